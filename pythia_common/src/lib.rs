@@ -14,6 +14,7 @@ extern crate lazy_static;
 
 mod budget;
 pub mod osprofiler;
+mod request_type;
 
 use std::error::Error;
 use std::fmt;
@@ -21,9 +22,11 @@ use std::fmt;
 pub use crate::osprofiler::AnnotationEnum;
 pub use crate::osprofiler::OSProfilerEnum;
 pub use crate::osprofiler::OSProfilerSpan;
-pub use crate::osprofiler::RequestType;
-pub use crate::osprofiler::REQUEST_TYPES;
-pub use crate::osprofiler::REQUEST_TYPE_REGEXES;
+pub use crate::request_type::read_request_types;
+pub use crate::request_type::RequestType;
+pub use crate::request_type::RequestTypeDefinition;
+pub use crate::request_type::RequestTypeRule;
+pub use crate::request_type::RequestTypeRules;
 
 pub use crate::budget::NodeStats;
 
@@ -9,77 +9,13 @@ All rights reserved.
 /// Stuff related to working with osprofiler
 ///
 use std::collections::HashMap;
-use std::fmt;
 
 use chrono::NaiveDateTime;
-use regex::RegexSet;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::PythiaError;
 
-/// Type of a request.
-///
-/// It's defined here because for now we only use them for OpenStack.
-#[derive(Serialize, Deserialize, Debug, Copy, Eq, PartialEq, Hash, Clone)]
-pub enum RequestType {
-    ServerCreate,
-    ServerDelete,
-    ServerList,
-    FloatingIPCreate,
-    FloatingIPDelete,
-    FloatingIPList,
-    UsageList,
-    Unknown,
-}
-
-lazy_static! {
-    // The ordering of the below two structures should match each other. If any
-    // tracepoint id in the trace matches any of these regexes, we set the
-    // request type accordingly.
-    pub static ref REQUEST_TYPE_REGEXES: RegexSet = RegexSet::new(&[
-        r"openstackclient\.compute\.v2\.server\.CreateServer\.take_action",
-        r"openstackclient\.compute\.v2\.server\.ListServer\.take_action",
-        r"openstackclient\.compute\.v2\.server\.DeleteServer\.take_action",
-        r"openstackclient\.network\.v2\.floating_ip\.CreateFloatingIP\.take_action_network",
-        r"openstackclient\.network\.v2\.floating_ip\.ListFloatingIP\.take_action_network",
-        r"openstackclient\.network\.v2\.floating_ip\.DeleteFloatingIP\.take_action_network",
-        r"novaclient\.v2\.usage\.UsageManager\.list",
-    ])
-    .unwrap();
-    pub static ref REQUEST_TYPES: Vec<RequestType> = vec![
-        RequestType::ServerCreate,
-        RequestType::ServerList,
-        RequestType::ServerDelete,
-        RequestType::FloatingIPCreate,
-        RequestType::FloatingIPList,
-        RequestType::FloatingIPDelete,
-        RequestType::UsageList,
-    ];
-}
-
-impl RequestType {
-    pub fn from_str(typ: &str) -> Result<RequestType, &str> {
-        match typ {
-            "ServerCreate" => Ok(RequestType::ServerCreate),
-            "ServerDelete" => Ok(RequestType::ServerDelete),
-            "ServerList" => Ok(RequestType::ServerList),
-            "FloatingIPCreate" => Ok(RequestType::FloatingIPCreate),
-            "FloatingIPDelete" => Ok(RequestType::FloatingIPDelete),
-            "FloatingIPList" => Ok(RequestType::FloatingIPList),
-            "UsageList" => Ok(RequestType::UsageList),
-            "Unknown" => Ok(RequestType::Unknown),
-            _ => Err("Unknown request type"),
-        }
-    }
-}
-
-impl fmt::Display for RequestType {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:?}", self)
-    }
-}
-
 impl OSProfilerSpan {
     /// We need this method because span endings do not have tracepoint IDs in OSProfiler.
     ///
@@ -110,6 +46,24 @@ impl OSProfilerSpan {
             },
         })
     }
+
+    /// Host that recorded the event
+    pub fn host(&self) -> &str {
+        match &self.info {
+            OSProfilerEnum::FunctionEntry(i) => &i.host,
+            OSProfilerEnum::RequestEntry(i) => &i.host,
+            OSProfilerEnum::Exit(ExitEnum::Normal(i)) => &i.host,
+            OSProfilerEnum::Exit(ExitEnum::Error(i)) => &i.host,
+            OSProfilerEnum::Annotation(AnnotationEnum::WaitFor(i)) => &i.host,
+            OSProfilerEnum::Annotation(AnnotationEnum::Child(i)) => &i.host,
+            OSProfilerEnum::Annotation(AnnotationEnum::Plain(i)) => &i.host,
+            OSProfilerEnum::Annotation(AnnotationEnum::Log(i)) => &i.host,
+            OSProfilerEnum::Annotation(AnnotationEnum::KeyValue(i)) => &i.host,
+            OSProfilerEnum::Annotation(AnnotationEnum::Args(i)) => &i.host,
+            OSProfilerEnum::Annotation(AnnotationEnum::Results(i)) => &i.host,
+            OSProfilerEnum::Annotation(AnnotationEnum::WaitForKeyValue(i)) => &i.host,
+        }
+    }
 }
 
 /// What an OSProfiler event json has.
@@ -121,10 +75,10 @@ impl OSProfilerSpan {
 pub struct OSProfilerSpan {
     pub trace_id: Uuid,
     pub parent_id: Uuid,
-    project: String,
+    pub project: String,
     pub name: String,
     pub base_id: Uuid,
-    service: String,
+    pub service: String,
     pub tracepoint_id: String,
     #[serde(with = "serde_timestamp")]
     pub timestamp: NaiveDateTime,
@@ -157,22 +111,22 @@ pub enum AnnotationEnum {
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct WaitForKeyValueAnnotation {
-    wait_for: Uuid,
-    function: ArgsKeyValueFunction,
-    tracepoint_id: String,
-    host: String,
-    thread_id: u64,
-    pid: u64,
+    pub wait_for: Uuid,
+    pub function: ArgsKeyValueFunction,
+    pub tracepoint_id: String,
+    pub host: String,
+    pub thread_id: u64,
+    pub pid: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct ArgsKeyValueInfo {
-    function: ArgsKeyValueFunction,
-    tracepoint_id: String,
-    host: String,
-    thread_id: u64,
-    pid: u64,
+    pub function: ArgsKeyValueFunction,
+    pub tracepoint_id: String,
+    pub host: String,
+    pub thread_id: u64,
+    pub pid: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
@@ -186,83 +140,83 @@ pub struct ArgsKeyValueFunction {
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct ResultKeyValueInfo {
-    function: ResultFunction,
-    host: String,
+    pub function: ResultFunction,
+    pub host: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct ResultFunction {
-    result: String,
+    pub result: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct KeyValueAnnotationInfo {
     pub value: u64,
-    tracepoint_id: String,
+    pub tracepoint_id: String,
     pub host: String,
-    thread_id: u64,
-    pid: u64,
+    pub thread_id: u64,
+    pub pid: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct WaitAnnotationInfo {
-    function: FunctionEntryFunction,
-    thread_id: u64,
+    pub function: FunctionEntryFunction,
+    pub thread_id: u64,
     pub host: String,
-    tracepoint_id: String,
-    pid: u64,
+    pub tracepoint_id: String,
+    pub pid: u64,
     pub wait_for: Uuid,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct LogAnnotationInfo {
-    thread_id: u64,
+    pub thread_id: u64,
     pub host: String,
-    tracepoint_id: String,
-    pid: u64,
-    msg: String,
+    pub tracepoint_id: String,
+    pub pid: u64,
+    pub msg: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct PlainAnnotationInfo {
-    thread_id: u64,
+    pub thread_id: u64,
     pub host: String,
-    tracepoint_id: String,
-    pid: u64,
+    pub tracepoint_id: String,
+    pub pid: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct ChildAnnotationInfo {
-    thread_id: u64,
+    pub thread_id: u64,
     pub host: String,
-    tracepoint_id: String,
+    pub tracepoint_id: String,
     pub child_id: Uuid,
-    pid: u64,
+    pub pid: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct RequestEntryInfo {
-    request: RequestEntryRequest,
-    thread_id: u64,
+    pub request: RequestEntryRequest,
+    pub thread_id: u64,
     pub host: String,
-    tracepoint_id: String,
-    pid: u64,
+    pub tracepoint_id: String,
+    pub pid: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
-struct RequestEntryRequest {
-    path: String,
-    scheme: String,
-    method: String,
-    query: String,
+pub struct RequestEntryRequest {
+    pub path: String,
+    pub scheme: String,
+    pub method: String,
+    pub query: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
@@ -281,25 +235,25 @@ pub struct NormalExitInfo {
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct ErrorExitInfo {
-    etype: String,
-    message: String,
+    pub etype: String,
+    pub message: String,
     pub host: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct FunctionEntryInfo {
-    function: FunctionEntryFunction,
-    thread_id: u64,
+    pub function: FunctionEntryFunction,
+    pub thread_id: u64,
     pub host: String,
-    tracepoint_id: String,
-    pid: u64,
+    pub tracepoint_id: String,
+    pub pid: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
 #[serde(deny_unknown_fields)]
-struct FunctionEntryFunction {
-    name: String,
+pub struct FunctionEntryFunction {
+    pub name: String,
 }
 
 pub mod serde_timestamp {
@@ -0,0 +1,425 @@
+/*
+This source code is licensed under the BSD-style license found in the
+LICENSE file in the root directory of this source tree.
+
+Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
+All rights reserved.
+*/
+
+//! Request types and the rules that assign them to traces.
+//!
+//! Request types are defined in configuration, each with a name and the conditions a trace has
+//! to meet: a tracepoint id in the trace, the tracepoint id of the root of the trace, or values
+//! of keys in its events. The first definition whose conditions all hold is used.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use config::{Config, ConfigError, File, FileFormat};
+use regex::Regex;
+use serde::{de, ser, Deserialize, Serialize};
+
+use crate::PythiaError;
+
+lazy_static! {
+    static ref REQUEST_TYPE_NAMES: Mutex<RequestTypeNames> = Mutex::new(RequestTypeNames::new());
+}
+
+struct RequestTypeNames {
+    names: Vec<String>,
+    ids: HashMap<String, usize>,
+}
+
+impl RequestTypeNames {
+    fn new() -> Self {
+        let mut result = RequestTypeNames {
+            names: Vec::new(),
+            ids: HashMap::new(),
+        };
+        result.get_or_insert(UNKNOWN_NAME);
+        result
+    }
+
+    fn get_or_insert(&mut self, name: &str) -> usize {
+        match self.ids.get(name) {
+            Some(&id) => id,
+            None => {
+                let id = self.names.len();
+                self.names.push(name.to_string());
+                self.ids.insert(name.to_string(), id);
+                id
+            }
+        }
+    }
+}
+
+const UNKNOWN_NAME: &str = "Unknown";
+/// Request types found by clustering traces are named `Inferred0`, `Inferred1`, ...
+const INFERRED_PREFIX: &str = "Inferred";
+
+/// Type of a request.
+///
+/// Like tracepoint ids, request types are kept as `usize`s and their names are looked up when
+/// needed. A name is known once it's defined in the configuration or deserialized.
+#[derive(Hash, Clone, Copy, Eq, PartialEq)]
+pub struct RequestType {
+    id: usize,
+}
+
+impl RequestType {
+    /// Requests that don't match any definition
+    pub const UNKNOWN: RequestType = RequestType { id: 0 };
+
+    /// Returns the request type with this name, defining it if it's new
+    pub fn new(name: &str) -> Self {
+        RequestType {
+            id: REQUEST_TYPE_NAMES.lock().unwrap().get_or_insert(name),
+        }
+    }
+
+    /// Returns the request type with this name if it's already defined
+    pub fn from_str(typ: &str) -> Result<RequestType, &str> {
+        match REQUEST_TYPE_NAMES.lock().unwrap().ids.get(typ) {
+            Some(&id) => Ok(RequestType { id }),
+            None => Err("Unknown request type"),
+        }
+    }
+
+    /// The request type of the `i`th cluster of traces when request types are inferred
+    pub fn inferred(i: usize) -> Self {
+        RequestType::new(&format!("{}{}", INFERRED_PREFIX, i))
+    }
+
+    /// Whether the name is one of an inferred request type. These are not in the configuration,
+    /// so they may not be defined yet, e.g., in the server after a restart.
+    pub fn is_inferred_name(name: &str) -> bool {
+        name.starts_with(INFERRED_PREFIX) && name[INFERRED_PREFIX.len()..].parse::<usize>().is_ok()
+    }
+
+    pub fn name(&self) -> String {
+        REQUEST_TYPE_NAMES.lock().unwrap().names[self.id].clone()
+    }
+}
+
+impl fmt::Display for RequestType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Debug for RequestType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Serialize for RequestType {
+    fn serialize<S>(&self, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        s.serialize_str(&self.name())
+    }
+}
+
+struct RequestTypeVisitor;
+
+impl<'de> de::Visitor<'de> for RequestTypeVisitor {
+    type Value = RequestType;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a string representing a request type")
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(RequestType::new(s))
+    }
+}
+
+impl<'de> Deserialize<'de> for RequestType {
+    fn deserialize<D>(d: D) -> Result<RequestType, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        d.deserialize_str(RequestTypeVisitor)
+    }
+}
+
+/// A request type as it's written in the configuration. Conditions that are not given always
+/// hold.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RequestTypeDefinition {
+    pub name: String,
+    /// Regex that one of the tracepoint ids in the trace should match
+    pub tracepoint: Option<String>,
+    /// Regex that the tracepoint id of the first event in the trace should match
+    pub root: Option<String>,
+    /// Regexes that the value of these keys should match in one of the events
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestTypeRule {
+    pub request_type: RequestType,
+    pub tracepoint: Option<Regex>,
+    pub root: Option<Regex>,
+    pub tags: Vec<(String, Regex)>,
+}
+
+impl RequestTypeRule {
+    /// Whether the trace meets the conditions. `tracepoints` has all tracepoint ids in the trace,
+    /// `root` is the first one, and `tag` returns the values of a key in the events.
+    pub fn is_match<'a, I, F, T>(&self, tracepoints: I, root: &str, tag: F) -> bool
+    where
+        I: IntoIterator<Item = &'a str>,
+        F: Fn(&str) -> T,
+        T: IntoIterator<Item = String>,
+    {
+        if let Some(r) = &self.root {
+            if !r.is_match(root) {
+                return false;
+            }
+        }
+        if let Some(r) = &self.tracepoint {
+            if !tracepoints.into_iter().any(|t| r.is_match(t)) {
+                return false;
+            }
+        }
+        self.tags
+            .iter()
+            .all(|(key, r)| tag(key).into_iter().any(|v| r.is_match(&v)))
+    }
+}
+
+/// Request type definitions, in the order they are tried
+#[derive(Debug, Clone)]
+pub struct RequestTypeRules {
+    rules: Vec<RequestTypeRule>,
+}
+
+impl RequestTypeRules {
+    pub fn from_definitions(
+        definitions: &[RequestTypeDefinition],
+    ) -> Result<RequestTypeRules, PythiaError> {
+        let regex = |name: &str, r: &str| {
+            Regex::new(r)
+                .map_err(|e| PythiaError(format!("Bad regex in request type {}: {}", name, e)))
+        };
+        let mut rules = Vec::new();
+        for def in definitions.iter() {
+            if def.name == UNKNOWN_NAME {
+                return Err(PythiaError(format!(
+                    "Request type name {} is reserved",
+                    UNKNOWN_NAME
+                )));
+            }
+            let mut tags = Vec::new();
+            for (key, r) in def.tags.iter() {
+                tags.push((key.clone(), regex(&def.name, r)?));
+            }
+            rules.push(RequestTypeRule {
+                request_type: RequestType::new(&def.name),
+                tracepoint: def
+                    .tracepoint
+                    .as_ref()
+                    .map(|r| regex(&def.name, r))
+                    .transpose()?,
+                root: def.root.as_ref().map(|r| regex(&def.name, r)).transpose()?,
+                tags,
+            });
+        }
+        Ok(RequestTypeRules { rules })
+    }
+
+    /// The OpenStack operations Pythia was first used with, for when no definitions are
+    /// configured
+    pub fn openstack() -> RequestTypeRules {
+        let definitions: Vec<RequestTypeDefinition> = [
+            (
+                "ServerCreate",
+                r"openstackclient\.compute\.v2\.server\.CreateServer\.take_action",
+            ),
+            (
+                "ServerList",
+                r"openstackclient\.compute\.v2\.server\.ListServer\.take_action",
+            ),
+            (
+                "ServerDelete",
+                r"openstackclient\.compute\.v2\.server\.DeleteServer\.take_action",
+            ),
+            (
+                "FloatingIPCreate",
+                r"openstackclient\.network\.v2\.floating_ip\.CreateFloatingIP\.take_action_network",
+            ),
+            (
+                "FloatingIPList",
+                r"openstackclient\.network\.v2\.floating_ip\.ListFloatingIP\.take_action_network",
+            ),
+            (
+                "FloatingIPDelete",
+                r"openstackclient\.network\.v2\.floating_ip\.DeleteFloatingIP\.take_action_network",
+            ),
+            ("UsageList", r"novaclient\.v2\.usage\.UsageManager\.list"),
+        ]
+        .iter()
+        .map(|(name, tracepoint)| RequestTypeDefinition {
+            name: name.to_string(),
+            tracepoint: Some(tracepoint.to_string()),
+            root: None,
+            tags: HashMap::new(),
+        })
+        .collect();
+        RequestTypeRules::from_definitions(&definitions).unwrap()
+    }
+
+    pub fn rules(&self) -> &[RequestTypeRule] {
+        &self.rules
+    }
+
+    /// Request types in the order they are defined
+    pub fn request_types(&self) -> Vec<RequestType> {
+        self.rules.iter().map(|r| r.request_type).collect()
+    }
+
+    /// Whether the tracepoint id is used to tell request types apart, so it has to be enabled
+    pub fn is_request_type_tracepoint(&self, tracepoint: &str) -> bool {
+        self.rules.iter().any(|r| {
+            r.tracepoint
+                .iter()
+                .chain(r.root.iter())
+                .any(|t| t.is_match(tracepoint))
+        })
+    }
+
+    /// The first request type whose conditions hold, see `RequestTypeRule::is_match`
+    pub fn classify<'a, I, F, T>(&self, tracepoints: I, root: &str, tag: F) -> RequestType
+    where
+        I: IntoIterator<Item = &'a str> + Clone,
+        F: Fn(&str) -> T,
+        T: IntoIterator<Item = String>,
+    {
+        self.rules
+            .iter()
+            .find(|r| r.is_match(tracepoints.clone(), root, &tag))
+            .map(|r| r.request_type)
+            .unwrap_or(RequestType::UNKNOWN)
+    }
+}
+
+/// Reads request type definitions from the `[[request_type]]` tables of a toml file. Without the
+/// file, the OpenStack request types are used.
+pub fn read_request_types(path: &str) -> RequestTypeRules {
+    if !std::path::Path::new(path).exists() {
+        return RequestTypeRules::openstack();
+    }
+    let mut settings = Config::default();
+    settings.merge(File::new(path, FileFormat::Toml)).unwrap();
+    let definitions = match settings.get::<Vec<RequestTypeDefinition>>("request_type") {
+        Ok(d) => d,
+        Err(ConfigError::NotFound(_)) => Vec::new(),
+        Err(e) => panic!("Couldn't read request types from {}: {}", path, e),
+    };
+    RequestTypeRules::from_definitions(&definitions).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn definition(
+        name: &str,
+        tracepoint: Option<&str>,
+        root: Option<&str>,
+    ) -> RequestTypeDefinition {
+        RequestTypeDefinition {
+            name: name.to_string(),
+            tracepoint: tracepoint.map(|t| t.to_string()),
+            root: root.map(|r| r.to_string()),
+            tags: HashMap::new(),
+        }
+    }
+
+    fn rules() -> RequestTypeRules {
+        let mut upload = definition("ImageUpload", None, Some(r"^glance/api:"));
+        upload
+            .tags
+            .insert("http.method".to_string(), "^(PUT|POST)$".to_string());
+        RequestTypeRules::from_definitions(&[
+            definition("ServerCreate", Some(r"compute/manager\.py:build"), None),
+            upload,
+            definition("ImageRequest", None, Some(r"^glance/api:")),
+        ])
+        .unwrap()
+    }
+
+    fn tags<'a>(pairs: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Vec<String> + 'a {
+        move |key: &str| {
+            pairs
+                .iter()
+                .filter(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn classifies_by_first_matching_rule() {
+        let rules = rules();
+        let create = ["nova/api:create", "nova/compute/manager.py:build"];
+        assert_eq!(
+            rules.classify(create.iter().cloned(), create[0], tags(&[])),
+            RequestType::new("ServerCreate")
+        );
+        let image = ["glance/api:images", "glance/store:write"];
+        assert_eq!(
+            rules.classify(
+                image.iter().cloned(),
+                image[0],
+                tags(&[("http.method", "PUT")])
+            ),
+            RequestType::new("ImageUpload")
+        );
+        assert_eq!(
+            rules.classify(
+                image.iter().cloned(),
+                image[0],
+                tags(&[("http.method", "GET")])
+            ),
+            RequestType::new("ImageRequest")
+        );
+        // The root has to match, not just any tracepoint
+        let other = ["keystone/api:token", "glance/api:images"];
+        assert_eq!(
+            rules.classify(other.iter().cloned(), other[0], tags(&[])),
+            RequestType::UNKNOWN
+        );
+    }
+
+    #[test]
+    fn finds_request_type_tracepoints() {
+        let rules = rules();
+        assert!(rules.is_request_type_tracepoint("nova/compute/manager.py:build"));
+        assert!(rules.is_request_type_tracepoint("glance/api:images"));
+        assert!(!rules.is_request_type_tracepoint("nova/api:create"));
+    }
+
+    #[test]
+    fn rejects_bad_definitions() {
+        assert!(RequestTypeRules::from_definitions(&[definition("Unknown", None, None)]).is_err());
+        assert!(RequestTypeRules::from_definitions(&[definition("Bad", Some("("), None)]).is_err());
+    }
+
+    #[test]
+    fn recognizes_inferred_names() {
+        assert_eq!(RequestType::inferred(3).name(), "Inferred3");
+        assert!(RequestType::is_inferred_name("Inferred12"));
+        assert!(!RequestType::is_inferred_name("Inferred"));
+        assert!(!RequestType::is_inferred_name("InferredX"));
+        assert!(!RequestType::is_inferred_name("ServerCreate"));
+    }
+}
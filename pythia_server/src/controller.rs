@@ -48,16 +48,7 @@ impl OSProfilerController {
             if path.is_dir() {
                 self.write_dir(&path, to_write);
             } else {
-                if RequestType::from_str(
-                    path.file_name()
-                        .unwrap()
-                        .to_string_lossy()
-                        .rsplit(":")
-                        .next()
-                        .unwrap(),
-                )
-                .is_ok()
-                {
+                if is_request_type_file(&path) {
                     std::fs::remove_file(path).ok();
                 } else {
                     let mut file = File::create(path).unwrap();
@@ -91,7 +82,7 @@ impl OSProfilerController {
         }
         match *request_type {
             Some(t) => {
-                if t != RequestType::Unknown {
+                if t != RequestType::UNKNOWN {
                     let mut newname = result.file_name().unwrap().to_os_string();
                     newname.push(":");
                     newname.push(t.to_string());
@@ -103,3 +94,14 @@ impl OSProfilerController {
         result
     }
 }
+
+/// Whether the file is for a single request type. Inferred request types are recognized even if
+/// they are not defined yet, since the controller may have inferred them before a restart.
+fn is_request_type_file(path: &Path) -> bool {
+    let name = path.file_name().unwrap().to_string_lossy();
+    let suffix = match name.rfind(':') {
+        Some(i) => &name[i + 1..],
+        None => return false,
+    };
+    RequestType::from_str(suffix).is_ok() || RequestType::is_inferred_name(suffix)
+}
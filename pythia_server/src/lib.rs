@@ -27,7 +27,8 @@ pub mod settings;
 
 use std::sync::{Arc, Mutex};
 
-use jsonrpc_core::{IoHandler, Result, Value};
+use chrono::Utc;
+use jsonrpc_core::{Error, IoHandler, Result, Value};
 use jsonrpc_derive::rpc;
 use jsonrpc_http_server::ServerBuilder;
 use serde_json;
@@ -66,6 +67,11 @@ pub trait PythiaAPI {
     /// Delete these keys from redis. Used to free up memory, but deleted any records of traces.
     #[rpc(name = "free_keys")]
     fn free_keys(&self, keys: Vec<String>) -> Result<()>;
+
+    /// Host name and local time in nanoseconds since the epoch, used to estimate the offset of
+    /// the local clock.
+    #[rpc(name = "get_clock")]
+    fn get_clock(&self) -> Result<(String, i64)>;
 }
 
 struct PythiaAPIImpl {
@@ -77,7 +83,10 @@ struct PythiaAPIImpl {
 impl PythiaAPI for PythiaAPIImpl {
     fn get_events(&self, trace_id: String) -> Result<Value> {
         eprintln!("Got request for {}", trace_id);
-        Ok(serde_json::to_value(self.reader.lock().unwrap().get_matches(&trace_id)).unwrap())
+        match self.reader.lock().unwrap().get_matches(&trace_id) {
+            Ok(spans) => Ok(serde_json::to_value(spans).unwrap()),
+            Err(e) => Err(Error::invalid_params(e)),
+        }
     }
 
     fn set_tracepoints(&self, settings: Vec<(String, Option<RequestType>, [u8; 1])>) -> Result<()> {
@@ -109,6 +118,13 @@ impl PythiaAPI for PythiaAPIImpl {
         self.reader.lock().unwrap().free_keys(keys);
         Ok(())
     }
+
+    fn get_clock(&self) -> Result<(String, i64)> {
+        // Same as the host name OSProfiler puts in events
+        let host = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .map_err(|_| Error::internal_error())?;
+        Ok((host.trim().to_string(), Utc::now().timestamp_nanos()))
+    }
 }
 
 /// Starts the server in port specified at the config file and waits for requests.
@@ -57,17 +57,20 @@ impl OSProfilerReader {
     }
 
     /// Public wrapper for get_matches_ that accepts string input and does not return RedisResult
-    pub fn get_matches(&mut self, span_id: &str) -> Vec<OSProfilerSpan> {
+    pub fn get_matches(&mut self, span_id: &str) -> Result<Vec<OSProfilerSpan>, String> {
         match Uuid::parse_str(span_id) {
-            Ok(uuid) => self.get_matches_(&uuid).unwrap(),
-            Err(_) => panic!("Malformed UUID as base id: {}", span_id),
+            Ok(uuid) => self
+                .get_matches_(&uuid)
+                .map_err(|e| format!("Could not get events of {}: {}", span_id, e)),
+            Err(_) => Err(format!("Malformed UUID as base id: {}", span_id)),
         }
     }
 
-    /// Get matching events from local redis instance
+    /// Get matching events from local redis instance. Events that can't be parsed are skipped.
     fn get_matches_(&mut self, span_id: &Uuid) -> redis::RedisResult<Vec<OSProfilerSpan>> {
         let mut trials = 0;
         let mut to_parse: Option<String> = None;
+        let mut last_error = None;
         while to_parse.is_none() && trials < 2 {
             to_parse = match self
                 .connection
@@ -77,7 +80,7 @@ impl OSProfilerReader {
                     Value::Nil => {
                         return Ok(Vec::new());
                     }
-                    Value::Data(_) => Some(FromRedisValue::from_redis_value(&to_parse).unwrap()),
+                    Value::Data(_) => Some(FromRedisValue::from_redis_value(&to_parse)?),
                     _ => {
                         eprintln!("Got {:?} as reply", to_parse);
                         return Ok(Vec::new());
@@ -86,19 +89,27 @@ impl OSProfilerReader {
                 Err(e) => {
                     self.restart_connection();
                     eprintln!("Got error {} for {}", e, span_id);
+                    last_error = Some(e);
                     None
                 }
             };
             trials += 1;
         }
         let mut result = Vec::new();
-        let to_parse = to_parse.unwrap();
+        let to_parse = match to_parse {
+            Some(s) => s,
+            None => return Err(last_error.unwrap()),
+        };
+        if to_parse.len() < 2 {
+            eprintln!("Got {:?} as reply", to_parse);
+            return Ok(result);
+        }
         for dict_string in to_parse[1..to_parse.len() - 1].split("}{") {
             match osprofiler::parse_field(&("{".to_string() + dict_string + "}")) {
                 Ok(span) => {
                     result.push(span);
                 }
-                Err(e) => panic!("Problem while parsing {}: {}", dict_string, e),
+                Err(e) => eprintln!("Skipping {}: {}", dict_string, e),
             }
         }
         Ok(result)
@@ -11,12 +11,19 @@ use std::path::PathBuf;
 
 use config::{Config, File, FileFormat};
 
+use pythia_common::read_request_types;
+use pythia_common::RequestTypeRules;
+
+const REQUEST_TYPES_PATH: &str = "/etc/pythia/request_types.toml";
+
 #[derive(Debug)]
 pub struct Settings {
     pub server_address: String,
     pub manifest_root: PathBuf,
     pub redis_url: String,
     pub network_interface: String,
+    /// Request types are only needed to recognize their tracepoint files in `manifest_root`
+    pub request_types: RequestTypeRules,
 }
 
 impl Settings {
@@ -31,6 +38,12 @@ impl Settings {
             redis_url: results.get("redis_url").unwrap().to_string(),
             manifest_root: PathBuf::from(results.get("manifest_root").unwrap()),
             network_interface: results.get("network_interface").unwrap().to_string(),
+            request_types: read_request_types(
+                results
+                    .get("request_types_file")
+                    .map(|x| x.as_str())
+                    .unwrap_or(REQUEST_TYPES_PATH),
+            ),
         }
     }
 }
@@ -6,14 +6,15 @@ Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
 All rights reserved.
 */
 
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg, ArgGroup, ArgMatches, SubCommand};
 use std::time::Instant;
 
+use pythia::filter::Filter;
 use pythia::{
     disable_all, disable_tracepoint, dump_traces, enable_all, enable_skeleton, get_crit,
-    get_manifest, get_trace, group_folder, group_from_ids, manifest_from_folder, manifest_stats,
-    measure_search_space_feasibility, read_trace_file, recent_traces, show_config,
-    show_key_value_pairs, show_manifest,
+    get_manifest, get_trace, group_folder, group_from_ids, import_traces, list_archive,
+    manifest_from_folder, manifest_stats, measure_search_space_feasibility, read_trace_file,
+    recent_traces, show_config, show_key_value_pairs, show_manifest,
 };
 
 fn main() {
@@ -34,15 +35,18 @@ fn main() {
         )
         .subcommand(
             SubCommand::with_name("manifest-folder")
-                .arg(Arg::with_name("trace-folder").required(true).index(1)),
+                .arg(Arg::with_name("trace-folder").required(true).index(1))
+                .arg(filter_option()),
         )
         .subcommand(
             SubCommand::with_name("group-folder")
-                .arg(Arg::with_name("trace-folder").required(true).index(1)),
+                .arg(Arg::with_name("trace-folder").required(true).index(1))
+                .arg(filter_option()),
         )
         .subcommand(
             SubCommand::with_name("group-ids")
-                .arg(Arg::with_name("traceid-file").required(true).index(1)),
+                .arg(Arg::with_name("traceid-file").required(true).index(1))
+                .arg(filter_option()),
         )
         .subcommand(
             SubCommand::with_name("read-file")
@@ -50,7 +54,36 @@ fn main() {
         )
         .subcommand(
             SubCommand::with_name("dump-traces")
-                .arg(Arg::with_name("trace-file").required(true).index(1)),
+                .arg(Arg::with_name("trace-file").required(true).index(1))
+                .arg(filter_option()),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .arg(Arg::with_name("archive").required(true).index(1))
+                .arg(
+                    Arg::with_name("trace-folder")
+                        .long("trace-folder")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("traceid-file")
+                        .long("traceid-file")
+                        .takes_value(true),
+                )
+                .group(
+                    ArgGroup::with_name("source")
+                        .args(&["trace-folder", "traceid-file"])
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("list-archive")
+                .arg(Arg::with_name("archive").required(true).index(1))
+                .arg(
+                    Arg::with_name("request-type")
+                        .long("request-type")
+                        .takes_value(true),
+                ),
         )
         .subcommand(
             SubCommand::with_name("get-crit")
@@ -90,13 +123,22 @@ fn main() {
             );
         }
         ("manifest-folder", Some(matches)) => {
-            manifest_from_folder(matches.value_of("trace-folder").unwrap());
+            manifest_from_folder(
+                matches.value_of("trace-folder").unwrap(),
+                filter_arg(matches).as_ref(),
+            );
         }
         ("group-folder", Some(matches)) => {
-            group_folder(matches.value_of("trace-folder").unwrap());
+            group_folder(
+                matches.value_of("trace-folder").unwrap(),
+                filter_arg(matches).as_ref(),
+            );
         }
         ("group-ids", Some(matches)) => {
-            group_from_ids(matches.value_of("traceid-file").unwrap());
+            group_from_ids(
+                matches.value_of("traceid-file").unwrap(),
+                filter_arg(matches).as_ref(),
+            );
         }
         ("read-file", Some(matches)) => {
             read_trace_file(matches.value_of("trace-file").unwrap());
@@ -108,7 +150,23 @@ fn main() {
             show_manifest(matches.value_of("request-type").unwrap());
         }
         ("dump-traces", Some(matches)) => {
-            dump_traces(matches.value_of("trace-file").unwrap());
+            dump_traces(
+                matches.value_of("trace-file").unwrap(),
+                filter_arg(matches).as_ref(),
+            );
+        }
+        ("import", Some(matches)) => {
+            import_traces(
+                matches.value_of("archive").unwrap(),
+                matches.value_of("trace-folder"),
+                matches.value_of("traceid-file"),
+            );
+        }
+        ("list-archive", Some(matches)) => {
+            list_archive(
+                matches.value_of("archive").unwrap(),
+                matches.value_of("request-type"),
+            );
         }
         ("get-trace", Some(matches)) => {
             get_trace(
@@ -148,3 +206,21 @@ fn main() {
     };
     eprintln!("Overall Pythia took {}us", now.elapsed().as_micros());
 }
+
+fn filter_option<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("filter")
+        .long("filter")
+        .takes_value(true)
+        .help("Only use traces that match the filter expression, see the filter module docs")
+}
+
+/// Parses the `--filter` argument, exiting on a malformed filter
+fn filter_arg(matches: &ArgMatches) -> Option<Filter> {
+    matches.value_of("filter").map(|f| match Filter::parse(f) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    })
+}
@@ -25,6 +25,8 @@ use pythia::controller::controller_from_settings;
 use pythia::controller::Controller;
 use pythia::critical::CriticalPath;
 use pythia::critical::Path;
+use pythia::grouping::detector_from_settings;
+use pythia::grouping::MultimodalEdgeDetector;
 use pythia::grouping::GroupManager;
 use pythia::manifest::Manifest;
 use pythia::reader::reader_from_settings;
@@ -50,7 +52,9 @@ fn main() {
     let now = Instant::now();
     let strategy = get_strategy(&SETTINGS, &MANIFEST, &CONTROLLER);
     let mut budget_manager = BudgetManager::from_settings(&SETTINGS);
-    let mut groups = GroupManager::new();
+    let mut groups = GroupManager::from_settings(&SETTINGS);
+    let detector = detector_from_settings(&SETTINGS);
+    let edge_detector = MultimodalEdgeDetector::from_settings(&SETTINGS);
     let mut last_decision = Instant::now();
     let mut last_gc = Instant::now();
 
@@ -98,9 +102,14 @@ fn main() {
         pool.execute(move || {
             let mut reader = reader_from_settings(&SETTINGS);
             loop {
-                for trace in reader.get_recent_traces() {
-                    tx.send(CriticalPath::from_trace(&trace).unwrap())
-                        .expect("channel will be there waiting for the pool");
+                for mut trace in reader.get_recent_traces() {
+                    MANIFEST.classify(&mut trace);
+                    match CriticalPath::from_trace(&trace) {
+                        Ok(path) => tx
+                            .send(path)
+                            .expect("channel will be there waiting for the pool"),
+                        Err(e) => eprintln!("Skipping trace {}: {}", trace.base_id, e),
+                    }
                 }
                 sleep(SETTINGS.jiffy);
             }
@@ -150,7 +159,7 @@ fn main() {
                 // let keep_count =
                 //     (enabled_tracepoints.len() as f32 * (1.0 - SETTINGS.disable_ratio)) as usize;
                 // let mut to_keep = HashSet::new();
-                // for g in groups.problem_groups() {
+                // for g in groups.problem_groups(&*detector) {
                 //     let mut nidx = g.start_node;
                 //     while nidx != g.end_node {
                 //         if enabled_tracepoints
@@ -202,48 +211,75 @@ fn main() {
             
             // Make decision
             let mut budget = SETTINGS.tracepoints_per_epoch;
-            // let problem_groups = groups.problem_groups();
-            
-            let problem_groups = groups.problem_groups_cv(0.05); // tsl: problem groups takes now 
-            // println!("*CV Groups: {:?}", problem_groups);
-
-            //comment-in below line for consistently slow analysis
-            // let problem_groups_slow = groups.problem_groups_slow(95.0); // tsl: problem groups takes now 
-            // println!("*SLOW Groups: {:?}", problem_groups_slow);
+            let problem_groups = groups.problem_groups(&*detector);
 
             let mut used_groups = Vec::new();
 
             //tsl ; get problematic group types to disable tps for non-problematic ones
             let mut problematic_req_types = Vec::new();
             
+            for compliance in groups.slo_compliance() {
+                println!("SLO {}", compliance);
+            }
             println!("Making decision. Top 10 problem groups:");
             for g in problem_groups.iter().take(10) {
                 println!("{}", g);
+                println!("    Time: {}", g.wait_times());
+                if let Some(change) = &g.change_point {
+                    println!("    Latency change: {}", change);
+                }
+                for outlier in g.outliers(SETTINGS.outlier_threshold) {
+                    println!("    Outlier: {}", outlier);
+                }
+                if let Some(&edge) = g.error_edges().first() {
+                    let endpoints = g.g.edge_endpoints(edge).unwrap();
+                    println!(
+                        "    Errors start at ({} -> {}): {}",
+                        g.g[endpoints.0],
+                        g.g[endpoints.1],
+                        g.g[endpoints.1]
+                            .key_value_pair
+                            .get("error_type")
+                            .map(ToString::to_string)
+                            .unwrap_or_default()
+                    );
+                }
+                if let Some(parent) = groups.parent(g) {
+                    println!("    All values: {}", parent);
+                }
                 // for enabled in &g.enabled_tps{
                 //     println!("Enabled: {:?} ", enabled);
                 // }
             }
-
-            //comment-in below line for consistently slow analysis
-            // println!("Making decision. Top 10 slow problem groups:");
-            // for g in problem_groups_slow.iter().take(10) {
-            //     println!("{}", g);
-            //     // for enabled in &g.enabled_tps{
-            //     //     println!("Enabled: {:?} ", enabled);
-            //     // }
-            // }
             for g in problem_groups {
                 problematic_req_types.push(g.request_type);
 
-                let problem_edges = g.problem_edges();
+                let problem_edges = g.problem_edges(edge_detector.as_ref());
+                let decomposition = g.variance_decomposition();
 
                 println!("Top 10 edges of group {}:", g);
+                println!("    Variance: {}", decomposition);
+                for (a, b, covariance) in decomposition.covariances.iter().take(3) {
+                    println!("    Covariance of {:?} and {:?}: {:?}", a, b, covariance);
+                }
                 for edge in problem_edges.iter().take(10) {
                     let endpoints = g.g.edge_endpoints(*edge).unwrap();
                     println!(
                         "({} -> {}): {}",
                         g.g[endpoints.0], g.g[endpoints.1], g.g[*edge]
                     );
+                    println!(
+                        "    {:.1}% of the variance",
+                        decomposition.share(*edge) * 100.0
+                    );
+                    println!("    Time: {}", g.g[*edge].wait);
+                    for a in g.attribute_edge(*edge, SETTINGS.attribution_threshold) {
+                        println!("    {}", a);
+                    }
+                    let modes = g.g[*edge].modes(SETTINGS.mode_min_share);
+                    if modes.len() >= 2 {
+                        println!("    Modes: {:?}", modes);
+                    }
                 }
                 for &edge in problem_edges.iter() {
                     if budget <= 0 {
@@ -276,7 +312,7 @@ fn main() {
                     writeln!(output_file, "Enabled {}", decisions.len()).ok();
                     writeln!(output_file, "Enabled {:?}", decisions).ok();
                     if decisions.len() > 0 {
-                        used_groups.push(g.hash().to_string());
+                        used_groups.push((g.request_type, g.hash().to_string()));
                     }
                     // // tsl: record enabled tracepoints per group
                     // g.update_enabled_tracepoints(&decisions);
@@ -289,8 +325,8 @@ fn main() {
             for item in problematic_req_types{
                 println!("{:?}, ", item)
             }
-            for g in used_groups {
-                groups.used(&g);
+            for (request_type, g) in used_groups {
+                groups.used(request_type, &g);
             }
 
             //tsl : for groups that stopped being problematic; just disable tracepoints, which are enabled so far
@@ -42,7 +42,20 @@ pub fn controller_from_settings(settings: &Settings) -> Box<dyn Controller> {
         ApplicationType::OpenStack => Box::new(OSProfilerController::from_settings(settings)),
         ApplicationType::HDFS => Box::new(HDFSController::from_settings(settings)),
         ApplicationType::DEATHSTAR => Box::new(HDFSController::from_settings(settings)),
+        ApplicationType::XTrace => Box::new(HDFSController::from_settings(settings)),
+        // These can't turn tracepoints on or off, so when traces are polled from Jaeger or
+        // dropped into a watched directory, decisions are only logged
+        ApplicationType::Uber if settings.jaeger_url.is_some() || settings.watch_dir.is_some() => {
+            Box::new(TestController::new())
+        }
+        ApplicationType::OpenTelemetry | ApplicationType::Zipkin
+            if settings.watch_dir.is_some() =>
+        {
+            Box::new(TestController::new())
+        }
         ApplicationType::Uber => panic!("Can't control uber"),
+        ApplicationType::OpenTelemetry => panic!("Can't control OpenTelemetry"),
+        ApplicationType::Zipkin => panic!("Can't control Zipkin"),
     }
 }
 
@@ -29,7 +29,7 @@ impl Controller for OSProfilerController {
         eprintln!("Enabling {:?}", points);
         let mut enabled_tracepoints = self.enabled_tracepoints.lock().unwrap();
         for p in points {
-            if p.1 == Some(RequestType::Unknown) {
+            if p.1 == Some(RequestType::UNKNOWN) {
                 enabled_tracepoints.insert((p.0, None));
             } else {
                 enabled_tracepoints.insert(p.clone());
@@ -42,7 +42,7 @@ impl Controller for OSProfilerController {
         eprintln!("Disabling {:?}", points);
         let mut enabled_tracepoints = self.enabled_tracepoints.lock().unwrap();
         for p in points {
-            if p.1 == Some(RequestType::Unknown) {
+            if p.1 == Some(RequestType::UNKNOWN) {
                 enabled_tracepoints.remove(&(p.0, None));
             } else {
                 enabled_tracepoints.remove(p);
@@ -12,11 +12,16 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::time::Duration;
 
+use chrono::NaiveDateTime;
 use crypto::digest::Digest;
 use crypto::sha2::Sha256;
 use genawaiter::{rc::gen, yield_};
 use petgraph::visit::EdgeRef;
-use petgraph::{dot::Dot, graph::NodeIndex, Direction};
+use petgraph::{
+    dot::Dot,
+    graph::{EdgeIndex, NodeIndex},
+    Direction,
+};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -30,6 +35,24 @@ use crate::trace::Trace;
 use crate::trace::TracepointID;
 use crate::PythiaError;
 
+/// What the request was doing during an edge of its critical path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum WaitKind {
+    /// Running code, including the code of async children that nothing waits for
+    Running,
+    /// Waiting on async children, i.e., handing off between a thread and a child, or a thread
+    /// being blocked while the children it waits for run
+    AsyncWait,
+    /// Waiting for a lock that others were queued on
+    LockWait,
+}
+
+/// Key of the events that report the number of waiters queued on a lock when it's acquired
+const LOCK_QUEUE_KEY: &str = "lock_queue";
+/// Key of the events that report how long their thread was blocked waiting for async children,
+/// e.g., the first event after an OSProfiler `asynch_wait` span
+const BLOCKED_KEY: &str = "blocked_ns";
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CriticalPath {
     /// This is the actual critical path
@@ -40,6 +63,9 @@ pub struct CriticalPath {
     /// A hypothetical critical path is just a path which wasn't critical
     pub is_hypothetical: bool,
     pub request_type: RequestType,
+    /// The request failed, even if the path doesn't include the events that report it
+    #[serde(default)]
+    pub is_error: bool,
     /// The hash is lazily calculated at first access
     hash: String,
 }
@@ -54,6 +80,7 @@ impl CriticalPath {
             is_hypothetical: false,
             hash: "".to_string(),
             request_type: dag.request_type,
+            is_error: dag.is_error,
         };
         let mut cur_node = dag.end_node;
         let mut end_nidx = path.g.g.add_node(dag.g[cur_node].clone());
@@ -129,6 +156,7 @@ impl CriticalPath {
                 is_hypothetical: true,
                 hash: "".to_string(),
                 request_type: dag.request_type,
+                is_error: dag.is_error,
             };
             let mut remaining_nodes = vec![(dag.start_node, dag.start_node, p.g.start_node, p)];
             while !remaining_nodes.is_empty() {
@@ -431,6 +459,77 @@ impl CriticalPath {
         unfinished
     }
 
+    /// When the request ended, i.e., the timestamp of the last event of the path
+    pub fn end_time(&self) -> NaiveDateTime {
+        self.g.g[self.end_node].timestamp
+    }
+
+    /// Splits the time of each edge of the path by what the request was doing. An edge that ends
+    /// where a lock was acquired behind other waiters, i.e., at an event with a nonzero
+    /// `lock_queue`, is a lock wait. `FollowsFrom` edges, which readers add where a request hands
+    /// off to or joins async work (OSProfiler `asynch_wait` spans, OpenTelemetry links), are async
+    /// waits. The rest of an edge is an async wait while a thread of the request was blocked on
+    /// async children, as reported by `blocked_ns` at the event that ends the wait, and running
+    /// otherwise.
+    pub fn wait_times(&self) -> HashMap<EdgeIndex, Vec<(WaitKind, Duration)>> {
+        let blocked = self.blocked_intervals();
+        let mut result = HashMap::new();
+        for edge in self.g.g.edge_indices() {
+            let (source, target) = self.g.g.edge_endpoints(edge).unwrap();
+            let duration = self.g.g[edge].duration;
+            let queued = self.g.g[target]
+                .key_value_pair
+                .get(LOCK_QUEUE_KEY)
+                .and_then(|v| v.as_f64())
+                .filter(|&waiters| waiters > 0.0)
+                .is_some();
+            let times = if queued {
+                vec![(WaitKind::LockWait, duration)]
+            } else if self.g.g[edge].variant == EdgeType::FollowsFrom {
+                vec![(WaitKind::AsyncWait, duration)]
+            } else {
+                let (from, to) = (self.g.g[source].timestamp, self.g.g[target].timestamp);
+                let waiting = blocked
+                    .iter()
+                    .filter(|&&(start, end)| start < to && end > from)
+                    .map(|&(start, end)| (end.min(to) - start.max(from)).to_std().unwrap())
+                    .sum::<Duration>()
+                    .min(duration);
+                vec![
+                    (WaitKind::Running, duration - waiting),
+                    (WaitKind::AsyncWait, waiting),
+                ]
+            };
+            result.insert(edge, times);
+        }
+        result
+    }
+
+    /// The disjoint intervals in which a thread of the request was blocked on async children,
+    /// sorted by time
+    fn blocked_intervals(&self) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+        let mut intervals = self
+            .g
+            .g
+            .node_indices()
+            .filter_map(|nidx| {
+                let event = &self.g.g[nidx];
+                let blocked = event.key_value_pair.get(BLOCKED_KEY)?.as_f64()?;
+                let start = event.timestamp - chrono::Duration::nanoseconds(blocked as i64);
+                Some((start, event.timestamp))
+            })
+            .collect::<Vec<_>>();
+        intervals.sort();
+        let mut result: Vec<(NaiveDateTime, NaiveDateTime)> = Vec::new();
+        for (start, end) in intervals {
+            match result.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => result.push((start, end)),
+            }
+        }
+        result
+    }
+
     /// This is not used
     pub fn next_real_node(&self, nidx: NodeIndex) -> Option<NodeIndex> {
         let mut result;
@@ -508,6 +607,7 @@ impl CriticalPath {
             timestamp: self.g.g[after].timestamp + chrono::Duration::nanoseconds(1),
             is_synthetic: true,
             key_value_pair: HashMap::new(),
+            is_error: false,
         });
         self.g.g.add_edge(
             after,
@@ -628,3 +728,141 @@ impl Path for CriticalPath {
         self.g.g.node_count()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::trace::Value;
+
+    /// A request that hands off to an async child at 10ms, which waits 20ms for a lock with two
+    /// waiters queued and exits at 70ms. The parent resumes at 75ms after having been blocked for
+    /// `blocked` milliseconds, and the request ends at 100ms.
+    fn waiting_path(blocked: u64) -> CriticalPath {
+        let (parent, child) = (Uuid::new_v4(), Uuid::new_v4());
+        let events = [
+            (parent, EventType::Entry, 0, None),
+            (child, EventType::Entry, 10, None),
+            (child, EventType::Annotation, 30, Some((LOCK_QUEUE_KEY, 2))),
+            (child, EventType::Exit, 70, None),
+            (
+                parent,
+                EventType::Annotation,
+                75,
+                Some((BLOCKED_KEY, blocked * 1_000_000)),
+            ),
+            (parent, EventType::Exit, 100, None),
+        ];
+        let start = NaiveDateTime::from_timestamp_opt(0, 0).unwrap();
+        let mut trace = Trace::new(&Uuid::new_v4());
+        for (i, &(span, variant, millis, value)) in events.iter().enumerate() {
+            let mut event = Event {
+                trace_id: span,
+                tracepoint_id: TracepointID::from_str(&format!("t{}", i)),
+                timestamp: start + chrono::Duration::milliseconds(millis),
+                is_synthetic: false,
+                variant,
+                key_value_pair: HashMap::new(),
+                is_error: false,
+            };
+            if let Some((key, value)) = value {
+                event
+                    .key_value_pair
+                    .insert(key.to_string(), Value::UnsignedInt(value));
+            }
+            let node = trace.g.add_node(event);
+            if i == 0 {
+                trace.start_node = node;
+            } else {
+                let from = trace.g[trace.end_node].timestamp;
+                let edge = DAGEdge {
+                    duration: (trace.g[node].timestamp - from).to_std().unwrap(),
+                    // The hand-offs to and from the child
+                    variant: if i == 1 || i == 4 {
+                        EdgeType::FollowsFrom
+                    } else {
+                        EdgeType::ChildOf
+                    },
+                };
+                trace.g.add_edge(trace.end_node, node, edge);
+            }
+            trace.end_node = node;
+        }
+        CriticalPath::from_trace(&trace).unwrap()
+    }
+
+    /// The times of the edges in path order
+    fn times(path: &CriticalPath) -> Vec<Vec<(WaitKind, Duration)>> {
+        let wait = path.wait_times();
+        let mut result = Vec::new();
+        let mut nidx = path.start_node;
+        while let Some(next) = path.next_node(nidx) {
+            let edge = path.g.g.find_edge(nidx, next).unwrap();
+            result.push(
+                wait[&edge]
+                    .iter()
+                    .filter(|(_, time)| *time > Duration::new(0, 0))
+                    .cloned()
+                    .collect(),
+            );
+            nidx = next;
+        }
+        result
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn classifies_running_async_and_lock_waits() {
+        use WaitKind::*;
+        // Blocked from 25ms, before the child's lock wait ended
+        assert_eq!(
+            times(&waiting_path(50)),
+            vec![
+                vec![(AsyncWait, ms(10))],
+                vec![(LockWait, ms(20))],
+                vec![(AsyncWait, ms(40))],
+                vec![(AsyncWait, ms(5))],
+                vec![(Running, ms(25))],
+            ]
+        );
+        // Blocked from 55ms, so the child ran on its own for a while
+        assert_eq!(
+            times(&waiting_path(20))[2],
+            vec![(Running, ms(25)), (AsyncWait, ms(15))]
+        );
+    }
+
+    #[test]
+    fn splits_the_whole_path() {
+        for &blocked in [0, 20, 50, 100].iter() {
+            let path = waiting_path(blocked);
+            let total: Duration = path
+                .wait_times()
+                .values()
+                .flat_map(|times| times.iter().map(|&(_, time)| time))
+                .sum();
+            assert_eq!(total, path.duration);
+        }
+    }
+
+    #[test]
+    fn merges_overlapping_waits() {
+        let mut path = waiting_path(50);
+        // Another thread was blocked from 20ms to 70ms, overlapping the parent's wait
+        let exit = path
+            .prev_node(path.prev_node(path.end_node).unwrap())
+            .unwrap();
+        path.g.g[exit]
+            .key_value_pair
+            .insert(BLOCKED_KEY.to_string(), Value::UnsignedInt(50_000_000));
+        assert_eq!(
+            path.blocked_intervals(),
+            vec![(
+                NaiveDateTime::from_timestamp_opt(0, 20_000_000).unwrap(),
+                NaiveDateTime::from_timestamp_opt(0, 75_000_000).unwrap()
+            )]
+        );
+    }
+}
@@ -0,0 +1,563 @@
+/*
+This source code is licensed under the BSD-style license found in the
+LICENSE file in the root directory of this source tree.
+
+Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
+All rights reserved.
+*/
+
+//! Filter expressions to select traces, for example
+//! `request_type == ServerCreate && duration > 2s && contains("nova/compute/manager.py")`.
+//!
+//! An expression is made of comparisons and functions, joined with `&&`, `||`, `!` and
+//! parentheses. The comparisons are:
+//! * `request_type` with `==` or `!=` and a request type name
+//! * `duration` with `==`, `!=`, `<`, `<=`, `>` or `>=` and a duration such as `2s`, `1.5s`,
+//!   `150ms`, `20us`, `100ns`, `5m` or `1h`
+//! * `events`, the number of events, with the same operators and a number
+//!
+//! The functions are:
+//! * `contains("...")`, true if a tracepoint id contains the string
+//! * `matches("...")`, true if a tracepoint id matches the regex
+//!
+//! Filters can be used on both traces and critical paths. For a critical path, `events`,
+//! `contains` and `matches` only look at the events on the path.
+
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::time::Duration;
+
+use regex::Regex;
+
+use pythia_common::RequestType;
+
+use crate::critical::CriticalPath;
+use crate::trace::Trace;
+use crate::PythiaError;
+
+/// Something a filter can select
+pub trait Filterable {
+    fn request_type(&self) -> RequestType;
+    fn duration(&self) -> Duration;
+    /// The events that `events`, `contains` and `matches` look at
+    fn events(&self) -> &Trace;
+}
+
+impl Filterable for Trace {
+    fn request_type(&self) -> RequestType {
+        self.request_type
+    }
+
+    fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    fn events(&self) -> &Trace {
+        self
+    }
+}
+
+impl Filterable for CriticalPath {
+    fn request_type(&self) -> RequestType {
+        self.request_type
+    }
+
+    fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    fn events(&self) -> &Trace {
+        &self.g
+    }
+}
+
+#[derive(Debug)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn parse(s: &str) -> Result<Filter, PythiaError> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(Filter { expr }),
+            Some(t) => Err(PythiaError(format!("Unexpected {} in filter", t))),
+        }
+    }
+
+    pub fn matches<T: Filterable>(&self, t: &T) -> bool {
+        self.expr.eval(t)
+    }
+}
+
+#[derive(Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    /// Whether the request type is (true) or is not (false) the given one
+    RequestType(bool, RequestType),
+    Duration(Comparison, Duration),
+    Events(Comparison, usize),
+    Contains(String),
+    Matches(Regex),
+}
+
+impl Expr {
+    fn eval<T: Filterable>(&self, t: &T) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(t) && b.eval(t),
+            Expr::Or(a, b) => a.eval(t) || b.eval(t),
+            Expr::Not(a) => !a.eval(t),
+            Expr::RequestType(equal, r) => (t.request_type() == *r) == *equal,
+            Expr::Duration(c, d) => c.apply(&t.duration(), d),
+            Expr::Events(c, n) => c.apply(&t.events().g.node_count(), n),
+            Expr::Contains(s) => {
+                let g = &t.events().g;
+                g.node_indices()
+                    .any(|n| g[n].tracepoint_id.to_string().contains(s.as_str()))
+            }
+            Expr::Matches(re) => {
+                let g = &t.events().g;
+                g.node_indices()
+                    .any(|n| re.is_match(&g[n].tracepoint_id.to_string()))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    fn apply<T: PartialOrd>(self, a: &T, b: &T) -> bool {
+        match self {
+            Comparison::Eq => a == b,
+            Comparison::Ne => a != b,
+            Comparison::Lt => a < b,
+            Comparison::Le => a <= b,
+            Comparison::Gt => a > b,
+            Comparison::Ge => a >= b,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    /// A number, possibly followed by a unit
+    Number(String),
+    Compare(Comparison),
+    And,
+    Or,
+    Not,
+    LeftParen,
+    RightParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(s) | Token::Number(s) => write!(f, "{}", s),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Compare(c) => write!(
+                f,
+                "{}",
+                match c {
+                    Comparison::Eq => "==",
+                    Comparison::Ne => "!=",
+                    Comparison::Lt => "<",
+                    Comparison::Le => "<=",
+                    Comparison::Gt => ">",
+                    Comparison::Ge => ">=",
+                }
+            ),
+            Token::And => write!(f, "&&"),
+            Token::Or => write!(f, "||"),
+            Token::Not => write!(f, "!"),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, PythiaError> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let token = match c {
+            '(' => {
+                chars.next();
+                Token::LeftParen
+            }
+            ')' => {
+                chars.next();
+                Token::RightParen
+            }
+            '"' => {
+                chars.next();
+                Token::Str(string_literal(&mut chars)?)
+            }
+            '&' | '|' => {
+                chars.next();
+                if chars.next() != Some(c) {
+                    return Err(PythiaError(format!("Expected {}{} in filter", c, c)));
+                }
+                if c == '&' {
+                    Token::And
+                } else {
+                    Token::Or
+                }
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let followed_by_eq = chars.peek() == Some(&'=');
+                if followed_by_eq {
+                    chars.next();
+                }
+                match (c, followed_by_eq) {
+                    ('=', true) => Token::Compare(Comparison::Eq),
+                    ('!', true) => Token::Compare(Comparison::Ne),
+                    ('<', true) => Token::Compare(Comparison::Le),
+                    ('>', true) => Token::Compare(Comparison::Ge),
+                    ('<', false) => Token::Compare(Comparison::Lt),
+                    ('>', false) => Token::Compare(Comparison::Gt),
+                    ('!', false) => Token::Not,
+                    _ => return Err(PythiaError("Expected == in filter".to_string())),
+                }
+            }
+            c if c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '.') {
+                        break;
+                    }
+                    number.push(c);
+                    chars.next();
+                }
+                Token::Number(number)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                }
+                Token::Ident(ident)
+            }
+            c => return Err(PythiaError(format!("Unexpected {} in filter", c))),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Reads a string up to the closing quote, with `\"` and `\\` as escapes
+fn string_literal(chars: &mut Peekable<Chars>) -> Result<String, PythiaError> {
+    let mut result = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(result),
+            Some('\\') => match chars.next() {
+                Some(c) => result.push(c),
+                None => break,
+            },
+            Some(c) => result.push(c),
+            None => break,
+        }
+    }
+    Err(PythiaError("Unterminated string in filter".to_string()))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, PythiaError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| PythiaError("Unexpected end of filter".to_string()))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), PythiaError> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(PythiaError(format!(
+                "Expected {} but got {} in filter",
+                expected, token
+            )));
+        }
+        Ok(())
+    }
+
+    fn or(&mut self) -> Result<Expr, PythiaError> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, PythiaError> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, PythiaError> {
+        match self.next()? {
+            Token::Not => Ok(Expr::Not(Box::new(self.unary()?))),
+            Token::LeftParen => {
+                let expr = self.or()?;
+                self.expect(Token::RightParen)?;
+                Ok(expr)
+            }
+            Token::Ident(name) => self.predicate(&name),
+            t => Err(PythiaError(format!("Unexpected {} in filter", t))),
+        }
+    }
+
+    fn predicate(&mut self, name: &str) -> Result<Expr, PythiaError> {
+        match name {
+            "contains" | "matches" => {
+                self.expect(Token::LeftParen)?;
+                let arg = match self.next()? {
+                    Token::Str(s) => s,
+                    t => {
+                        return Err(PythiaError(format!(
+                            "Expected a string in {}() but got {}",
+                            name, t
+                        )))
+                    }
+                };
+                self.expect(Token::RightParen)?;
+                if name == "contains" {
+                    Ok(Expr::Contains(arg))
+                } else {
+                    Regex::new(&arg)
+                        .map(Expr::Matches)
+                        .map_err(|e| PythiaError(e.to_string()))
+                }
+            }
+            "request_type" => {
+                let comparison = self.comparison()?;
+                if comparison != Comparison::Eq && comparison != Comparison::Ne {
+                    return Err(PythiaError(
+                        "request_type can only be compared with == or !=".to_string(),
+                    ));
+                }
+                let request_type = match self.next()? {
+                    // Request types are defined in the configuration, which may not be read yet
+                    Token::Ident(s) | Token::Str(s) => RequestType::new(&s),
+                    t => {
+                        return Err(PythiaError(format!(
+                            "Expected a request type but got {}",
+                            t
+                        )))
+                    }
+                };
+                Ok(Expr::RequestType(
+                    comparison == Comparison::Eq,
+                    request_type,
+                ))
+            }
+            "duration" => {
+                let comparison = self.comparison()?;
+                match self.next()? {
+                    Token::Number(s) => Ok(Expr::Duration(comparison, parse_duration(&s)?)),
+                    t => Err(PythiaError(format!("Expected a duration but got {}", t))),
+                }
+            }
+            "events" => {
+                let comparison = self.comparison()?;
+                match self.next()? {
+                    Token::Number(s) => Ok(Expr::Events(
+                        comparison,
+                        s.parse()
+                            .map_err(|_| PythiaError(format!("Invalid number {}", s)))?,
+                    )),
+                    t => Err(PythiaError(format!("Expected a number but got {}", t))),
+                }
+            }
+            _ => Err(PythiaError(format!("Unknown field {} in filter", name))),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Comparison, PythiaError> {
+        match self.next()? {
+            Token::Compare(c) => Ok(c),
+            t => Err(PythiaError(format!(
+                "Expected a comparison but got {} in filter",
+                t
+            ))),
+        }
+    }
+}
+
+/// Parses durations such as `2s`, `1.5s` or `150ms`
+fn parse_duration(s: &str) -> Result<Duration, PythiaError> {
+    let invalid = || PythiaError(format!("Invalid duration {}", s));
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .ok_or_else(invalid)?;
+    let value: f64 = s[..split].parse().map_err(|_| invalid())?;
+    let nanos_per_unit = match &s[split..] {
+        "ns" => 1.0,
+        "us" => 1e3,
+        "ms" => 1e6,
+        "s" => 1e9,
+        "m" => 60e9,
+        "h" => 3600e9,
+        _ => return Err(invalid()),
+    };
+    Ok(Duration::from_nanos((value * nanos_per_unit).round() as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use chrono::NaiveDateTime;
+    use uuid::Uuid;
+
+    use crate::trace::{Event, EventType, TracepointID};
+
+    /// A trace of `ServerCreate` with one event per tracepoint
+    fn trace(tracepoints: &[&str], duration: Duration) -> Trace {
+        let mut trace = Trace::new(&Uuid::new_v4());
+        for tracepoint in tracepoints {
+            trace.g.add_node(Event {
+                trace_id: Uuid::new_v4(),
+                tracepoint_id: TracepointID::from_str(tracepoint),
+                timestamp: NaiveDateTime::from_timestamp_opt(0, 0).unwrap(),
+                is_synthetic: false,
+                variant: EventType::Annotation,
+                key_value_pair: HashMap::new(),
+                is_error: false,
+            });
+        }
+        trace.request_type = RequestType::new("ServerCreate");
+        trace.duration = duration;
+        trace
+    }
+
+    fn matches(filter: &str, trace: &Trace) -> bool {
+        Filter::parse(filter).unwrap().matches(trace)
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("2s").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_duration("1.5s").unwrap(), Duration::from_millis(1500));
+        assert_eq!(parse_duration("150ms").unwrap(), Duration::from_millis(150));
+        assert_eq!(parse_duration("20us").unwrap(), Duration::from_micros(20));
+        assert_eq!(parse_duration("100ns").unwrap(), Duration::from_nanos(100));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("1h").unwrap(), Duration::from_secs(3600));
+        assert!(parse_duration("2").is_err());
+        assert!(parse_duration("2days").is_err());
+        assert!(parse_duration("ms").is_err());
+    }
+
+    #[test]
+    fn parses_precedence() {
+        let filter = Filter::parse("events > 1 || duration < 1s && !contains(\"x\")").unwrap();
+        match filter.expr {
+            Expr::Or(a, b) => {
+                assert!(matches!(*a, Expr::Events(Comparison::Gt, 1)));
+                match *b {
+                    Expr::And(c, d) => {
+                        assert!(matches!(*c, Expr::Duration(Comparison::Lt, _)));
+                        assert!(matches!(*d, Expr::Not(_)));
+                    }
+                    e => panic!("Expected && but got {:?}", e),
+                }
+            }
+            e => panic!("Expected || but got {:?}", e),
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        for filter in &[
+            "",
+            "duration >",
+            "duration > 2",
+            "request_type < ServerCreate",
+            "events == many",
+            "colour == red",
+            "contains(foo)",
+            "matches(\"(\")",
+            "(duration > 2s",
+            "duration > 2s)",
+            "contains(\"unterminated)",
+        ] {
+            assert!(
+                Filter::parse(filter).is_err(),
+                "{} should not parse",
+                filter
+            );
+        }
+    }
+
+    #[test]
+    fn evaluates_traces() {
+        let t = trace(
+            &[
+                "nova/compute/manager.py:build",
+                "nova/api/servers.py:create",
+            ],
+            Duration::from_millis(2500),
+        );
+        assert!(matches("request_type == ServerCreate", &t));
+        assert!(matches("request_type != \"ServerDelete\"", &t));
+        assert!(!matches("request_type == ServerDelete", &t));
+        assert!(matches("duration > 2s && duration <= 2.5s", &t));
+        assert!(!matches("duration >= 3s", &t));
+        assert!(matches("events == 2", &t));
+        assert!(matches("contains(\"nova/compute/manager.py\")", &t));
+        assert!(!matches("contains(\"glance\")", &t));
+        assert!(matches("matches(\"api/.*:create$\")", &t));
+        assert!(matches(
+            "!(events < 2) && (contains(\"glance\") || duration != 1s)",
+            &t
+        ));
+        assert!(!matches("!contains(\"nova\")", &t));
+    }
+}
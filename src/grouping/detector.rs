@@ -0,0 +1,422 @@
+/*
+This source code is licensed under the BSD-style license found in the
+LICENSE file in the root directory of this source tree.
+
+Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
+All rights reserved.
+*/
+
+//! Problem detectors choose the groups to diagnose.
+//!
+//! Each detector looks for one kind of problem. The detectors named in the configuration are
+//! combined, either flagging groups that any of them flags or only those all of them flag.
+
+use std::cmp::Ordering;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use histogram::Histogram;
+use petgraph::graph::EdgeIndex;
+
+use pythia_common::RequestType;
+
+use crate::critical::Path;
+use crate::grouping::Group;
+use crate::settings::Settings;
+use crate::slo::SLOTracker;
+
+pub trait ProblemDetector {
+    /// The problematic groups among `groups`, most problematic first. `slos` has the latency
+    /// objectives of request types and how the groups meet them.
+    fn detect<'a>(&self, groups: &[&'a Group], slos: &SLOTracker) -> Vec<&'a Group>;
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ProblemDetectorType {
+    Variance,
+    CV,
+    Slow,
+    SLO,
+    Multimodal,
+    Change,
+    Errors,
+}
+
+/// How the groups flagged by multiple detectors are combined
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DetectorComposition {
+    Any,
+    All,
+}
+
+/// Groups with nonzero variance, highest variance first
+pub struct VarianceDetector;
+
+impl ProblemDetector for VarianceDetector {
+    fn detect<'a>(&self, groups: &[&'a Group], _slos: &SLOTracker) -> Vec<&'a Group> {
+        let mut result: Vec<&Group> = groups
+            .iter()
+            .cloned()
+            .filter(|g| g.variance != 0.0)
+            .collect();
+        sort_by_variance(&mut result);
+        result
+    }
+}
+
+/// Groups whose coefficient of variation is above a threshold, highest variance first
+pub struct CVDetector {
+    threshold: f64,
+}
+
+impl ProblemDetector for CVDetector {
+    fn detect<'a>(&self, groups: &[&'a Group], _slos: &SLOTracker) -> Vec<&'a Group> {
+        let mut result: Vec<&Group> = groups
+            .iter()
+            .cloned()
+            .filter(|g| g.variance != 0.0)
+            .filter(|g| g.variance.sqrt() / g.mean > self.threshold)
+            .collect();
+        sort_by_variance(&mut result);
+        result
+    }
+}
+
+/// Consistently slow groups: those whose mean is above a percentile of the means of all groups,
+/// slowest first
+pub struct SlowDetector {
+    percentile: f64,
+}
+
+impl ProblemDetector for SlowDetector {
+    fn detect<'a>(&self, groups: &[&'a Group], _slos: &SLOTracker) -> Vec<&'a Group> {
+        let mut histogram = Histogram::new();
+        for g in groups.iter() {
+            histogram.increment(g.mean.round() as u64).ok();
+        }
+        let threshold = match histogram.percentile(self.percentile) {
+            Ok(t) => t as f64,
+            Err(_) => return Vec::new(),
+        };
+        let mut result: Vec<&Group> = groups
+            .iter()
+            .cloned()
+            .filter(|g| g.mean > threshold)
+            .collect();
+        result.sort_by(|a, b| b.mean.partial_cmp(&a.mean).unwrap_or(Ordering::Equal));
+        result
+    }
+}
+
+/// Groups with requests over the latency objective of their request type while it's violated,
+/// largest share of the violating requests first
+pub struct SLODetector;
+
+impl ProblemDetector for SLODetector {
+    fn detect<'a>(&self, groups: &[&'a Group], slos: &SLOTracker) -> Vec<&'a Group> {
+        let mut result: Vec<(&Group, f64)> = groups
+            .iter()
+            .map(|g| (*g, slos.tail_share(g.request_type, g.get_hash())))
+            .filter(|(_, share)| *share > 0.0)
+            .collect();
+        result.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        result.into_iter().map(|(g, _)| g).collect()
+    }
+}
+
+/// Groups whose durations have two or more modes, e.g., cache hits and misses, most modes first
+pub struct MultimodalDetector {
+    /// Share of the traces a mode needs to have
+    min_share: f64,
+}
+
+impl ProblemDetector for MultimodalDetector {
+    fn detect<'a>(&self, groups: &[&'a Group], _slos: &SLOTracker) -> Vec<&'a Group> {
+        let mut result: Vec<(&Group, usize)> = groups
+            .iter()
+            .map(|g| (*g, g.stats.modes(self.min_share).len()))
+            .filter(|(_, modes)| *modes >= 2)
+            .collect();
+        result.sort_by(|a, b| {
+            b.1.cmp(&a.1).then(
+                b.0.variance
+                    .partial_cmp(&a.0.variance)
+                    .unwrap_or(Ordering::Equal),
+            )
+        });
+        result.into_iter().map(|(g, _)| g).collect()
+    }
+}
+
+/// Groups whose mean or tail latency increased since they were last used, largest relative
+/// increase first
+pub struct ChangeDetector;
+
+impl ProblemDetector for ChangeDetector {
+    fn detect<'a>(&self, groups: &[&'a Group], _slos: &SLOTracker) -> Vec<&'a Group> {
+        let mut result: Vec<(&Group, f64)> = groups
+            .iter()
+            .filter_map(|g| g.change_point.as_ref().map(|c| (*g, c.ratio())))
+            .collect();
+        result.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        result.into_iter().map(|(g, _)| g).collect()
+    }
+}
+
+/// Groups of failed requests, most traces first
+pub struct ErrorDetector;
+
+impl ProblemDetector for ErrorDetector {
+    fn detect<'a>(&self, groups: &[&'a Group], _slos: &SLOTracker) -> Vec<&'a Group> {
+        let mut result: Vec<&Group> = groups.iter().cloned().filter(|g| g.is_error).collect();
+        result.sort_by_key(|g| Reverse(g.trace_count()));
+        result
+    }
+}
+
+/// Modes of the latency of an edge with their shares of the traces
+type Modes = Vec<(Duration, f64)>;
+
+/// Finds edges whose latency has two or more modes, e.g., cache hits and misses, or requests
+/// with and without retries. Such edges are often the problem even when their variance is modest.
+pub struct MultimodalEdgeDetector {
+    /// Share of the traces a mode needs to have
+    min_share: f64,
+}
+
+impl MultimodalEdgeDetector {
+    /// The detector, or `None` if multimodal edges are not ranked first
+    pub fn from_settings(s: &Settings) -> Option<Self> {
+        if s.multimodal_edges {
+            Some(MultimodalEdgeDetector {
+                min_share: s.mode_min_share,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The multimodal edges among `edges` of the group with their modes, the edges whose modes
+    /// are furthest apart first
+    pub fn detect(&self, group: &Group, edges: &[EdgeIndex]) -> Vec<(EdgeIndex, Modes)> {
+        let mut result: Vec<(EdgeIndex, Modes, f64)> = edges
+            .iter()
+            .map(|&edge| (edge, group.g[edge].modes(self.min_share)))
+            .filter(|(_, modes)| modes.len() >= 2)
+            .map(|(edge, modes)| {
+                let separation = between_mode_variance(&modes);
+                (edge, modes, separation)
+            })
+            .collect();
+        result.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(Ordering::Equal));
+        result
+            .into_iter()
+            .map(|(edge, modes, _)| (edge, modes))
+            .collect()
+    }
+}
+
+/// Variance of the latency if every duration was at the peak of its mode, i.e., how far apart the
+/// modes are, weighted by how many durations they have
+fn between_mode_variance(modes: &[(Duration, f64)]) -> f64 {
+    let total: f64 = modes.iter().map(|(_, share)| share).sum();
+    let mean = modes
+        .iter()
+        .map(|(d, share)| d.as_secs_f64() * share)
+        .sum::<f64>()
+        / total;
+    modes
+        .iter()
+        .map(|(d, share)| share * (d.as_secs_f64() - mean).powi(2))
+        .sum::<f64>()
+        / total
+}
+
+/// Combines detectors; groups keep the order of the first detector that flags them
+pub struct CompositeDetector {
+    detectors: Vec<Box<dyn ProblemDetector>>,
+    composition: DetectorComposition,
+}
+
+impl ProblemDetector for CompositeDetector {
+    fn detect<'a>(&self, groups: &[&'a Group], slos: &SLOTracker) -> Vec<&'a Group> {
+        let detected: Vec<Vec<&Group>> = self
+            .detectors
+            .iter()
+            .map(|d| d.detect(groups, slos))
+            .collect();
+        let mut result = Vec::new();
+        let mut seen = HashSet::new();
+        for g in detected.iter().flatten() {
+            if !seen.insert(key(g)) {
+                continue;
+            }
+            let flagged_by_all = detected
+                .iter()
+                .all(|d| d.iter().any(|other| key(other) == key(g)));
+            if self.composition == DetectorComposition::Any || flagged_by_all {
+                result.push(*g);
+            }
+        }
+        result
+    }
+}
+
+fn key(group: &Group) -> (RequestType, &str) {
+    (group.request_type, group.get_hash())
+}
+
+fn sort_by_variance(groups: &mut Vec<&Group>) {
+    groups.sort_by(|a, b| {
+        b.variance
+            .partial_cmp(&a.variance)
+            .unwrap_or(Ordering::Equal)
+    });
+}
+
+/// Constructor for the problem detector of the configuration
+pub fn detector_from_settings(s: &Settings) -> Box<dyn ProblemDetector> {
+    let detectors = s
+        .problem_detectors
+        .iter()
+        .map(|t| -> Box<dyn ProblemDetector> {
+            match t {
+                ProblemDetectorType::Variance => Box::new(VarianceDetector),
+                ProblemDetectorType::CV => Box::new(CVDetector {
+                    threshold: s.cv_threshold,
+                }),
+                ProblemDetectorType::Slow => Box::new(SlowDetector {
+                    percentile: s.slow_percentile,
+                }),
+                ProblemDetectorType::SLO => Box::new(SLODetector),
+                ProblemDetectorType::Multimodal => Box::new(MultimodalDetector {
+                    min_share: s.mode_min_share,
+                }),
+                ProblemDetectorType::Change => Box::new(ChangeDetector),
+                ProblemDetectorType::Errors => Box::new(ErrorDetector),
+            }
+        })
+        .collect();
+    Box::new(CompositeDetector {
+        detectors,
+        composition: s.detector_composition,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grouping::tests::{group, path};
+
+    fn slos() -> SLOTracker {
+        SLOTracker::new(&[], 0.99, Duration::from_secs(5), Duration::from_secs(60))
+    }
+
+    /// Groups of three request types: steady at 30ms, noisy around 50ms and slow at about 300ms
+    /// with little variance
+    fn groups() -> Vec<Group> {
+        let steady = RequestType::new("DetectSteady");
+        let noisy = RequestType::new("DetectNoisy");
+        let slow = RequestType::new("DetectSlow");
+        vec![
+            group(
+                &(0..5)
+                    .map(|i| path(steady, &[10, 20], i, &[], false))
+                    .collect::<Vec<_>>(),
+            ),
+            group(
+                &(0..5)
+                    .map(|i| path(noisy, &[10, 20 + 10 * i as u64], i, &[], false))
+                    .collect::<Vec<_>>(),
+            ),
+            group(
+                &(0..5)
+                    .map(|i| path(slow, &[100, 200 + i as u64], i, &[], false))
+                    .collect::<Vec<_>>(),
+            ),
+        ]
+    }
+
+    fn names(groups: &[&Group]) -> Vec<String> {
+        groups.iter().map(|g| g.request_type.to_string()).collect()
+    }
+
+    #[test]
+    fn detects_variance_cv_and_slow_groups() {
+        let groups = groups();
+        let groups: Vec<&Group> = groups.iter().collect();
+        assert_eq!(
+            names(&VarianceDetector.detect(&groups, &slos())),
+            vec!["DetectNoisy", "DetectSlow"]
+        );
+        let cv = CVDetector { threshold: 0.05 };
+        assert_eq!(names(&cv.detect(&groups, &slos())), vec!["DetectNoisy"]);
+        // The 40th percentile of the three means is the mean of the noisy group
+        let slow = SlowDetector { percentile: 40.0 };
+        assert_eq!(names(&slow.detect(&groups, &slos())), vec!["DetectSlow"]);
+        assert!(slow.detect(&[], &slos()).is_empty());
+    }
+
+    #[test]
+    fn composes_detectors() {
+        let groups = groups();
+        let groups: Vec<&Group> = groups.iter().collect();
+        let composite = |composition, detectors: Vec<Box<dyn ProblemDetector>>| {
+            CompositeDetector {
+                detectors,
+                composition,
+            }
+            .detect(&groups, &slos())
+        };
+
+        // Groups are in the order of the first detector that flags them, once
+        let any = composite(
+            DetectorComposition::Any,
+            vec![
+                Box::new(SlowDetector { percentile: 40.0 }),
+                Box::new(VarianceDetector),
+                Box::new(CVDetector { threshold: 0.05 }),
+            ],
+        );
+        assert_eq!(names(&any), vec!["DetectSlow", "DetectNoisy"]);
+
+        let all = composite(
+            DetectorComposition::All,
+            vec![
+                Box::new(VarianceDetector),
+                Box::new(SlowDetector { percentile: 40.0 }),
+            ],
+        );
+        assert_eq!(names(&all), vec!["DetectSlow"]);
+        let all = composite(
+            DetectorComposition::All,
+            vec![
+                Box::new(CVDetector { threshold: 0.05 }),
+                Box::new(SlowDetector { percentile: 40.0 }),
+            ],
+        );
+        assert!(all.is_empty());
+    }
+
+    #[test]
+    fn flags_failed_groups() {
+        let ok = RequestType::new("DetectOk");
+        let failed = RequestType::new("DetectFailed");
+        let often = RequestType::new("DetectOftenFailed");
+        let groups: Vec<Group> = [(ok, false, 5), (failed, true, 2), (often, true, 3)]
+            .iter()
+            .map(|&(request_type, is_error, count)| {
+                let paths: Vec<_> = (0..count)
+                    .map(|i| path(request_type, &[10, 20], i, &[], is_error))
+                    .collect();
+                group(&paths)
+            })
+            .collect();
+        let groups: Vec<&Group> = groups.iter().collect();
+        assert_eq!(
+            names(&ErrorDetector.detect(&groups, &slos())),
+            vec!["DetectOftenFailed", "DetectFailed"]
+        );
+    }
+}
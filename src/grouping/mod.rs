@@ -0,0 +1,1667 @@
+/*
+This source code is licensed under the BSD-style license found in the
+LICENSE file in the root directory of this source tree.
+
+Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
+All rights reserved.
+*/
+
+//! Code related to grouping critical paths
+
+mod detector;
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Display;
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use petgraph::dot::Dot;
+use petgraph::graph::EdgeIndex;
+use petgraph::graph::NodeIndex;
+use petgraph::stable_graph::StableGraph;
+use petgraph::Direction;
+use uuid::Uuid;
+
+use pythia_common::RequestType;
+
+use crate::critical::CriticalPath;
+use crate::critical::Path;
+use crate::critical::WaitKind;
+use crate::settings::Settings;
+use crate::slo::{SLOCompliance, SLOTracker};
+use crate::streaming::{
+    ChangePoint, ChangeTracker, Reservoir, RunningCovariance, RunningStats, Shift, StatsConfig,
+    TimedCoMoments, TimedStats, TimedSumCovariance,
+};
+use crate::trace::Event;
+use crate::trace::TraceNode;
+//use crate::trace::TraceNode::key_value_pair;
+use crate::trace::TracepointID;
+use crate::trace::Value;
+
+pub use crate::grouping::detector::{
+    detector_from_settings, DetectorComposition, MultimodalEdgeDetector, ProblemDetector,
+    ProblemDetectorType,
+};
+
+/// A group of critical paths
+#[derive(Clone, Debug)]
+pub struct Group {
+    /// Representative path and the relevant latency etc. statistics
+    pub g: StableGraph<TraceNode, GroupEdge>,
+    hash: String,
+    pub start_node: NodeIndex,
+    pub end_node: NodeIndex,
+    pub request_type: RequestType,
+    /// The group has the paths of failed requests, which are kept apart from successful ones
+    pub is_error: bool,
+    /// Durations of the critical paths that this group was constructed from
+    pub stats: TimedStats,
+    /// Base ids of a sample of the critical paths, to look up examples of the group
+    pub exemplars: Reservoir<Uuid>,
+    /// Co-moments of the durations of the edges, in the order of the path
+    covariance: TimedSumCovariance,
+    /// Positions in the path of the edges whose pairwise covariances are kept, see `track_pairs`
+    paired: Vec<usize>,
+    /// Co-moments of the durations of the paired edges
+    pairs: TimedCoMoments,
+    /// The paths with the highest robust z-scores since the group was last used
+    outliers: Vec<TraceOutlier>,
+    /// When groups are split on key-values, the hash of the group with the paths of all values
+    pub parent: Option<String>,
+    /// Change-point detection on the durations of the critical paths
+    changes: ChangeTracker,
+    /// The latest increase of the latency since the group was last used
+    pub change_point: Option<ChangePoint>,
+    pub variance: f64,
+    // pub key_value_pairs: HashMap<String, Vec<Value>>,
+    // tsl: Group means to calculate CVs
+    pub mean: f64,
+    pub is_used: bool,
+    //   //tsl: Disable strategy - if a groups stops being problematic, disable all the tracepoints for that
+    // pub enabled_tps : Vec<(TracepointID, Option<RequestType>)>,
+
+    // tsl: Group coefficient of variance
+    // pub cv: f64,
+}
+
+/// Number of base ids kept as examples of each group
+const EXEMPLARS: usize = 10;
+/// Number of outlier paths kept by each group
+const OUTLIERS: usize = 10;
+/// Paths are compared to the group only once it has this many
+const MIN_OUTLIER_TRACES: usize = 20;
+/// Number of edges with the highest robust z-scores kept for each outlier
+const OUTLIER_EDGES: usize = 3;
+/// Number of edges whose pairwise covariances are kept. Longer paths keep them for the edges
+/// that contribute the most to the variance.
+const PAIR_EDGES: usize = 8;
+
+/// A critical path that was slow compared to the other paths of its group when it was added,
+/// in total or on some edges. Slowness is measured with robust z-scores: the distance above the
+/// median in median absolute deviations, scaled like a z-score, so the outliers themselves don't
+/// hide how unusual they are.
+#[derive(Debug, Clone)]
+pub struct TraceOutlier {
+    pub base_id: Uuid,
+    pub duration: Duration,
+    /// Robust z-score of the duration of the path, `None` if the durations of the group didn't vary
+    pub z_score: Option<f64>,
+    /// Edges with the highest robust z-scores, with their durations in the path
+    pub edges: Vec<(EdgeIndex, Duration, f64)>,
+}
+
+impl TraceOutlier {
+    /// The highest robust z-score of the path or its edges
+    pub fn score(&self) -> f64 {
+        self.edges
+            .iter()
+            .map(|e| e.2)
+            .chain(self.z_score)
+            .fold(f64::NEG_INFINITY, f64::max)
+    }
+}
+
+impl Display for TraceOutlier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Trace {} took {:?}", self.base_id, self.duration)?;
+        if let Some(z) = self.z_score {
+            write!(f, " (robust z-score {:.1})", z)?;
+        }
+        for (edge, duration, z) in self.edges.iter() {
+            write!(f, ", edge {:?} {:?} ({:.1})", edge, duration, z)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GroupEdge {
+    /// These are the durations of the individual paths.
+    pub duration: TimedStats,
+    /// Latency for the values of each key of the events at the ends of the edge
+    attributes: HashMap<(EdgeEndpoint, String), AttributeStats>,
+    /// Time on the edge by what the requests were doing
+    pub wait: WaitTimes,
+}
+
+impl GroupEdge {
+    fn new(stats: &StatsConfig) -> Self {
+        GroupEdge {
+            duration: TimedStats::new(stats),
+            attributes: HashMap::new(),
+            wait: WaitTimes::default(),
+        }
+    }
+
+    /// Adds the duration of the edge in a path, what the request was doing during it, and the
+    /// events at its ends in that path
+    fn add(
+        &mut self,
+        duration: Duration,
+        wait: &[(WaitKind, Duration)],
+        source: &Event,
+        target: &Event,
+    ) {
+        self.duration.add(target.timestamp, duration);
+        for &(kind, time) in wait.iter() {
+            self.wait.add(kind, time);
+        }
+        let latency = duration.as_secs_f64();
+        for &(endpoint, event) in [
+            (EdgeEndpoint::Source, source),
+            (EdgeEndpoint::Target, target),
+        ]
+        .iter()
+        {
+            for (key, value) in event.key_value_pair.iter() {
+                self.attributes
+                    .entry((endpoint, key.clone()))
+                    .or_insert_with(AttributeStats::new)
+                    .add(value, latency);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.duration.clear();
+        self.attributes.clear();
+        self.wait.clear();
+    }
+
+    /// Durations at the peaks of the latency distribution of the edge and their shares, see
+    /// `QuantileSketch::modes`
+    pub fn modes(&self, min_share: f64) -> Vec<(Duration, f64)> {
+        self.duration.modes(min_share)
+    }
+
+    /// Attributes that explain at least `threshold` of the variance of the latency, most
+    /// explanatory first. Numeric attributes are fit with a linear regression, and the others are
+    /// compared like in a one-way ANOVA.
+    pub fn attributions(&self, threshold: f64) -> Vec<EdgeAttribution> {
+        let mut result = Vec::new();
+        for ((endpoint, key), stats) in self.attributes.iter() {
+            if let Some((explained, effect)) = stats.analyze() {
+                if explained >= threshold {
+                    result.push(EdgeAttribution {
+                        key: key.clone(),
+                        endpoint: *endpoint,
+                        explained,
+                        samples: stats.count,
+                        effect,
+                    });
+                }
+            }
+        }
+        result.sort_by(|a, b| {
+            b.explained
+                .partial_cmp(&a.explained)
+                .unwrap_or(Ordering::Equal)
+        });
+        result
+    }
+}
+
+impl Display for GroupEdge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Edge({} elements, {:?} min, {:?} max, {:?} variance, {:?} p50, {:?} p95, {:?} p99)",
+            self.duration.count(),
+            self.duration.min().unwrap_or_default(),
+            self.duration.max().unwrap_or_default(),
+            self.duration.variance(),
+            self.duration.quantile(0.5),
+            self.duration.quantile(0.95),
+            self.duration.quantile(0.99),
+        )
+    }
+}
+
+/// Time on critical paths by what the requests were doing, see `CriticalPath::wait_times`. Much
+/// time waiting on locks or async children points to contention rather than slow code. It covers
+/// the paths since the group was created or last used.
+#[derive(Debug, Clone, Default)]
+pub struct WaitTimes {
+    pub running: Duration,
+    pub async_wait: Duration,
+    pub lock_wait: Duration,
+}
+
+impl WaitTimes {
+    fn add(&mut self, kind: WaitKind, duration: Duration) {
+        match kind {
+            WaitKind::Running => self.running += duration,
+            WaitKind::AsyncWait => self.async_wait += duration,
+            WaitKind::LockWait => self.lock_wait += duration,
+        }
+    }
+
+    fn merge(&mut self, other: &WaitTimes) {
+        self.running += other.running;
+        self.async_wait += other.async_wait;
+        self.lock_wait += other.lock_wait;
+    }
+
+    fn clear(&mut self) {
+        *self = WaitTimes::default();
+    }
+
+    pub fn total(&self) -> Duration {
+        self.running + self.async_wait + self.lock_wait
+    }
+
+    /// Share of the time of this kind, 0 if there's no time
+    pub fn share(&self, kind: WaitKind) -> f64 {
+        let total = self.total().as_secs_f64();
+        if total == 0.0 {
+            return 0.0;
+        }
+        let time = match kind {
+            WaitKind::Running => self.running,
+            WaitKind::AsyncWait => self.async_wait,
+            WaitKind::LockWait => self.lock_wait,
+        };
+        time.as_secs_f64() / total
+    }
+}
+
+impl Display for WaitTimes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:?} total, {:.1}% running, {:.1}% waiting on async children, {:.1}% on locks",
+            self.total(),
+            self.share(WaitKind::Running) * 100.0,
+            self.share(WaitKind::AsyncWait) * 100.0,
+            self.share(WaitKind::LockWait) * 100.0
+        )
+    }
+}
+
+/// Which end of an edge an attribute is observed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EdgeEndpoint {
+    Source,
+    Target,
+}
+
+/// How the latency of an edge changes with an attribute
+#[derive(Debug, Clone)]
+pub enum AttributeEffect {
+    /// Seconds of latency per unit of a numeric attribute
+    Slope(f64),
+    /// The value of a categorical attribute with the highest mean latency
+    Slowest { value: String, mean: Duration },
+}
+
+/// An attribute of the events at the ends of an edge that explains part of its latency variance
+#[derive(Debug, Clone)]
+pub struct EdgeAttribution {
+    pub key: String,
+    pub endpoint: EdgeEndpoint,
+    /// Share of the variance explained, adjusted for the number of values the attribute takes so
+    /// that ids and other unique values don't explain everything
+    pub explained: f64,
+    /// Number of traces that had the attribute
+    pub samples: usize,
+    pub effect: AttributeEffect,
+}
+
+impl Display for EdgeAttribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at {:?} explains {:.0}% of variance in {} traces, ",
+            self.key,
+            self.endpoint,
+            self.explained * 100.0,
+            self.samples
+        )?;
+        match &self.effect {
+            AttributeEffect::Slope(slope) => write!(f, "{:+.6}s per unit", slope),
+            AttributeEffect::Slowest { value, mean } => {
+                write!(f, "slowest with {} ({:?} mean)", value, mean)
+            }
+        }
+    }
+}
+
+impl Group {
+    pub fn dot(&self) -> String {
+        format!("{}", Dot::new(&self.g))
+    }
+
+    /// tsl: add enabled tracepoints for the groups
+    // pub fn update_enabled_tracepoints(&mut self, decisions: &Vec<(TracepointID, Option<RequestType>)>) {
+
+    //     for decision in decisions {
+    //         self.enabled_tps.push(&decision);
+    //     }
+    // }
+
+    /// `node_keys` are the key-values kept in the nodes of the groups. If there are `split_keys`,
+    /// each group also has a child group for every combination of values of those keys.
+    pub fn from_critical_paths(
+        paths: Vec<CriticalPath>,
+        node_keys: &[String],
+        split_keys: &[String],
+        stats: &StatsConfig,
+    ) -> Vec<Group> {
+        let mut hash_map = HashMap::<(RequestType, String), Group>::new();
+        for path in paths.iter() {
+            for (key, parent) in group_keys(path, split_keys) {
+                match hash_map.get_mut(&key) {
+                    Some(v) => v.add_trace(path),
+                    None => {
+                        let group = Group::with_hash(path, node_keys, stats, &key.1, parent);
+                        hash_map.insert(key, group);
+                    }
+                }
+            }
+        }
+        // The edges of long paths whose pairwise covariances are kept are only known once all
+        // paths are in, so those paths are added to the pairs again
+        let mut paired = HashSet::new();
+        for (key, group) in hash_map.iter_mut() {
+            if group.track_pairs() {
+                paired.insert(key.clone());
+            }
+        }
+        if !paired.is_empty() {
+            for path in paths.iter() {
+                let durations = edge_durations(path);
+                for (key, _) in group_keys(path, split_keys) {
+                    if paired.contains(&key) {
+                        let group = hash_map.get_mut(&key).unwrap();
+                        group.add_pairs(path.end_time(), &durations);
+                    }
+                }
+            }
+        }
+        let mut zeros = 0;
+        for (_, group) in hash_map.iter_mut() {
+            group.calculate_variance();
+            group.calculate_mean();
+            if group.variance == 0.0 {
+                zeros += 1;
+            }
+        }
+        println!("{} groups had 0 variance", zeros);
+        hash_map.values().cloned().collect::<Vec<Group>>()
+    }
+
+    fn new(path: &CriticalPath, node_keys: &[String], stats: &StatsConfig) -> Group {
+        let mut dag = StableGraph::<TraceNode, GroupEdge>::new();
+        let mut cur_node = path.start_node;
+        let mut prev_node = None;
+        let mut prev_dag_nidx = None;
+        let mut start_node = None;
+        let mut end_node;
+        let mut durations = Vec::new();
+        let wait = path.wait_times();
+        loop {
+            let dag_nidx = dag.add_node(TraceNode::from_event(&path.g.g[cur_node], node_keys));
+            end_node = dag_nidx;
+            if prev_node.is_none() {
+                start_node = Some(dag_nidx);
+            } else {
+                match path.g.g.find_edge(prev_node.unwrap(), cur_node) {
+                    Some(edge) => {
+                        let mut group_edge = GroupEdge::new(stats);
+                        durations.push(path.g.g[edge].duration.as_nanos() as f64);
+                        group_edge.add(
+                            path.g.g[edge].duration,
+                            &wait[&edge],
+                            &path.g.g[prev_node.unwrap()],
+                            &path.g.g[cur_node],
+                        );
+                        dag.add_edge(prev_dag_nidx.unwrap(), dag_nidx, group_edge);
+                    }
+                    None => panic!("No edge?"),
+                }
+            }
+            prev_dag_nidx = Some(dag_nidx);
+            prev_node = Some(cur_node);
+            cur_node = match path.next_node(cur_node) {
+                Some(node) => node,
+                None => break,
+            };
+        }
+        let mut changes = ChangeTracker::new(&stats.change);
+        changes.add(path.end_time(), path.duration);
+        let mut covariance = TimedSumCovariance::new(durations.len(), stats);
+        covariance.add(path.end_time(), &durations);
+        let paired = if durations.len() <= PAIR_EDGES {
+            (0..durations.len()).collect()
+        } else {
+            Vec::new()
+        };
+        let pairs = TimedCoMoments::new(durations.len().min(PAIR_EDGES), stats);
+        let mut stats = TimedStats::new(stats);
+        stats.add(path.end_time(), path.duration);
+        let mut exemplars = Reservoir::new(EXEMPLARS);
+        exemplars.add(path.g.base_id);
+        let mut group = Group {
+            g: dag,
+            start_node: start_node.unwrap(),
+            end_node: end_node,
+            hash: path.hash().to_string(),
+            request_type: path.request_type,
+            is_error: path.is_error,
+            stats,
+            exemplars,
+            covariance,
+            paired,
+            pairs,
+            outliers: Vec::new(),
+            parent: None,
+            changes,
+            change_point: None,
+            variance: 0.0,
+            mean: 0.0,
+            is_used: false,
+            // enabled_tps: Vec<(TracepointID, Option<RequestType>)> = Vec::new(),
+            //cv: 0.0,
+            //  key_value_pairs: TraceNode::get_key_values(),
+        };
+        group.add_pairs(path.end_time(), &durations);
+        group
+    }
+
+    /// A group with its own hash instead of the hash of the path, for child groups
+    fn with_hash(
+        path: &CriticalPath,
+        node_keys: &[String],
+        stats: &StatsConfig,
+        hash: &str,
+        parent: Option<String>,
+    ) -> Group {
+        let mut group = Group::new(path, node_keys, stats);
+        group.hash = hash.to_string();
+        group.parent = parent;
+        group
+    }
+
+    /// After we use a group for diagnosis, we reset the statistics of the group and its edges, so
+    /// it's evaluated again only with traces that come after the decision.
+    pub fn used(&mut self) {
+        self.stats.clear();
+        for edge in self.g.edge_indices().collect::<Vec<_>>() {
+            self.g[edge].clear();
+        }
+        for node in self.g.node_indices().collect::<Vec<_>>() {
+            self.g[node].clear_values();
+        }
+        self.exemplars.clear();
+        self.covariance.clear();
+        self.pairs.clear();
+        self.outliers.clear();
+        self.changes.clear();
+        self.change_point = None;
+        self.variance = 0.0;
+        self.is_used = true;
+    }
+
+    /// Moves the windows of the statistics of the group and its edges to end at `now`. Returns
+    /// whether durations left the window of the group.
+    fn expire(&mut self, now: NaiveDateTime) -> bool {
+        for edge in self.g.edge_indices().collect::<Vec<_>>() {
+            self.g[edge].duration.expire(now);
+        }
+        self.covariance.expire(now);
+        self.pairs.expire(now);
+        self.stats.expire(now)
+    }
+
+    /// Adds the durations of the paired edges of a path that ended at `time`, given the
+    /// durations of all its edges
+    fn add_pairs(&mut self, time: NaiveDateTime, durations: &[f64]) {
+        if self.paired.is_empty() {
+            return;
+        }
+        let paired: Vec<f64> = self.paired.iter().map(|&i| durations[i]).collect();
+        self.pairs.add(time, &paired);
+    }
+
+    /// Chooses the edges whose pairwise covariances are kept in paths with more than
+    /// `PAIR_EDGES` edges: those that contribute the most to the variance. Since the co-moments
+    /// start over when the edges change, an edge replaces one that is kept only if it contributes
+    /// more than twice as much. Returns whether the edges changed.
+    fn track_pairs(&mut self) -> bool {
+        let contributions = self.covariance.covariances();
+        if contributions.len() <= PAIR_EDGES {
+            return false;
+        }
+        let mut ranked: Vec<usize> = (0..contributions.len()).collect();
+        ranked.sort_by(|&a, &b| {
+            contributions[b]
+                .partial_cmp(&contributions[a])
+                .unwrap_or(Ordering::Equal)
+        });
+        ranked.truncate(PAIR_EDGES);
+        if !self.paired.is_empty() {
+            let weakest = self
+                .paired
+                .iter()
+                .map(|&i| contributions[i])
+                .fold(f64::INFINITY, f64::min);
+            let strongest = ranked
+                .iter()
+                .filter(|i| !self.paired.contains(i))
+                .map(|&i| contributions[i])
+                .fold(f64::NEG_INFINITY, f64::max);
+            if strongest <= 2.0 * weakest {
+                return false;
+            }
+        }
+        ranked.sort_unstable();
+        self.paired = ranked;
+        self.pairs.clear();
+        true
+    }
+
+    /// Edges of the path, from the start
+    fn path_edges(&self) -> Vec<EdgeIndex> {
+        let mut result = Vec::new();
+        let mut cur_node = self.start_node;
+        while let Some(next) = self.next_node(cur_node) {
+            match self.g.find_edge(cur_node, next) {
+                Some(edge) => result.push(edge),
+                None => panic!("No edge?"),
+            }
+            cur_node = next;
+        }
+        result
+    }
+
+    /// Splits the variance of the end-to-end latency among the edges, see
+    /// `VarianceDecomposition`. It covers the paths in the scope of the statistics since the group
+    /// was created or last used.
+    pub fn variance_decomposition(&self) -> VarianceDecomposition {
+        let path_edges = self.path_edges();
+        let mut covariances = Vec::new();
+        for (a, &i) in self.paired.iter().enumerate() {
+            for (b, &j) in self.paired.iter().enumerate().skip(a + 1) {
+                let covariance = self.pairs.covariance(a, b);
+                if covariance != 0.0 {
+                    covariances.push((path_edges[i], path_edges[j], covariance));
+                }
+            }
+        }
+        covariances.sort_by(|a, b| b.2.abs().partial_cmp(&a.2.abs()).unwrap_or(Ordering::Equal));
+        let mut edges: Vec<EdgeVariance> = path_edges
+            .into_iter()
+            .zip(self.covariance.covariances())
+            .map(|(edge, contribution)| EdgeVariance {
+                edge,
+                variance: self.g[edge].duration.variance(),
+                contribution,
+            })
+            .collect();
+        edges.sort_by(|a, b| {
+            b.contribution
+                .partial_cmp(&a.contribution)
+                .unwrap_or(Ordering::Equal)
+        });
+        VarianceDecomposition {
+            total: edges.iter().map(|e| e.contribution).sum(),
+            edges,
+            covariances,
+        }
+    }
+
+    /// Time on the paths of the group by what the requests were doing, the sum over its edges
+    pub fn wait_times(&self) -> WaitTimes {
+        let mut result = WaitTimes::default();
+        for edge in self.g.edge_indices() {
+            result.merge(&self.g[edge].wait);
+        }
+        result
+    }
+
+    /// Edges of the path that end at an event that reported an error, from the start. In a group
+    /// of failed requests, the first one is where errors start, unless the events that reported
+    /// them are not on the critical path.
+    pub fn error_edges(&self) -> Vec<EdgeIndex> {
+        self.path_edges()
+            .into_iter()
+            .filter(|&e| self.g[self.g.edge_endpoints(e).unwrap().1].is_error)
+            .collect()
+    }
+
+    /// Returns all edges sorted by their contribution to the variance of the end-to-end latency,
+    /// see `variance_decomposition`. With a multimodal edge detector, the edges it flags come
+    /// next, in its order, and in groups of failed requests, the edges where errors are reported
+    /// come before all others.
+    pub fn problem_edges(&self, multimodal: Option<&MultimodalEdgeDetector>) -> Vec<EdgeIndex> {
+        let edges: Vec<EdgeIndex> = self
+            .variance_decomposition()
+            .edges
+            .iter()
+            .map(|e| e.edge)
+            .collect();
+        let edges = match multimodal {
+            Some(detector) => {
+                let mut flagged: Vec<EdgeIndex> = detector
+                    .detect(self, &edges)
+                    .into_iter()
+                    .map(|(edge, _)| edge)
+                    .collect();
+                for edge in edges {
+                    if !flagged.contains(&edge) {
+                        flagged.push(edge);
+                    }
+                }
+                flagged
+            }
+            None => edges,
+        };
+        if !self.is_error {
+            return edges;
+        }
+        let mut result = self.error_edges();
+        for edge in edges {
+            if !result.contains(&edge) {
+                result.push(edge);
+            }
+        }
+        result
+    }
+
+    /// Attributes of the events at the ends of the edge that explain at least `threshold` of the
+    /// variance of its latency, see `GroupEdge::attributions`
+    pub fn attribute_edge(&self, edge: EdgeIndex, threshold: f64) -> Vec<EdgeAttribution> {
+        self.g[edge].attributions(threshold)
+    }
+
+    pub fn trace_count(&self) -> usize {
+        self.stats.count()
+    }
+
+    fn add_trace(&mut self, path: &CriticalPath) {
+        self.check_outlier(path);
+        self.stats.add(path.end_time(), path.duration);
+        self.exemplars.add(path.g.base_id);
+        for change in self.changes.add(path.end_time(), path.duration) {
+            if change.shift == Shift::Increase {
+                self.change_point = Some(change);
+            }
+        }
+        let mut cur_node = path.start_node;
+        let mut prev_node = None;
+        let mut cur_dag_nidx = self.start_node;
+        let mut prev_dag_nidx = None;
+        let mut durations = Vec::new();
+        let wait = path.wait_times();
+        loop {
+            self.g[cur_dag_nidx].add_event(&path.g.g[cur_node]);
+            if !prev_dag_nidx.is_none() {
+                match path.g.g.find_edge(prev_node.unwrap(), cur_node) {
+                    Some(edge) => {
+                        let dag_edge = self
+                            .g
+                            .find_edge(prev_dag_nidx.unwrap(), cur_dag_nidx)
+                            .unwrap();
+                        durations.push(path.g.g[edge].duration.as_nanos() as f64);
+                        self.g[dag_edge].add(
+                            path.g.g[edge].duration,
+                            &wait[&edge],
+                            &path.g.g[prev_node.unwrap()],
+                            &path.g.g[cur_node],
+                        );
+                    }
+                    None => panic!("No edge?"),
+                }
+            }
+            prev_dag_nidx = Some(cur_dag_nidx);
+            prev_node = Some(cur_node);
+            cur_node = match path.next_node(cur_node) {
+                Some(node) => node,
+                None => break,
+            };
+            cur_dag_nidx = self.next_node(cur_dag_nidx).unwrap();
+        }
+        self.covariance.add(path.end_time(), &durations);
+        self.add_pairs(path.end_time(), &durations);
+    }
+    /// Compares the path to the paths already in the group, keeping it if it's among the most
+    /// unusual
+    fn check_outlier(&mut self, path: &CriticalPath) {
+        if self.trace_count() < MIN_OUTLIER_TRACES {
+            return;
+        }
+        let mut edges = Vec::new();
+        let mut cur_node = path.start_node;
+        for edge in self.path_edges() {
+            let next = path.next_node(cur_node).unwrap();
+            let duration = path.g.g[path.g.g.find_edge(cur_node, next).unwrap()].duration;
+            if let Some(z) = self.g[edge].duration.robust_z_score(duration) {
+                if z > 0.0 {
+                    edges.push((edge, duration, z));
+                }
+            }
+            cur_node = next;
+        }
+        edges.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(Ordering::Equal));
+        edges.truncate(OUTLIER_EDGES);
+        let outlier = TraceOutlier {
+            base_id: path.g.base_id,
+            duration: path.duration,
+            z_score: self.stats.robust_z_score(path.duration),
+            edges,
+        };
+        let score = outlier.score();
+        if score <= 0.0 {
+            return;
+        }
+        if self.outliers.len() < OUTLIERS {
+            self.outliers.push(outlier);
+        } else if let Some(least) = self
+            .outliers
+            .iter_mut()
+            .min_by(|a, b| a.score().partial_cmp(&b.score()).unwrap_or(Ordering::Equal))
+        {
+            if least.score() < score {
+                *least = outlier;
+            }
+        }
+    }
+
+    /// Paths since the group was last used whose robust z-score, in total or on an edge, was at
+    /// least `threshold` when they were added, most unusual first. Only the most unusual few are
+    /// kept, and paths are compared only after the group has enough of them.
+    pub fn outliers(&self, threshold: f64) -> Vec<&TraceOutlier> {
+        let mut result: Vec<&TraceOutlier> = self
+            .outliers
+            .iter()
+            .filter(|o| o.score() >= threshold)
+            .collect();
+        result.sort_by(|a, b| b.score().partial_cmp(&a.score()).unwrap_or(Ordering::Equal));
+        result
+    }
+
+    // tsl: calculate mean of the group
+    fn calculate_mean(&mut self) {
+        // change below variance to mean
+        self.mean = self.stats.mean();
+    }
+    fn calculate_variance(&mut self) {
+        self.variance = self.stats.variance();
+    }
+}
+
+/// Durations of the edges of the path in nanoseconds, from the start
+fn edge_durations(path: &CriticalPath) -> Vec<f64> {
+    let mut result = Vec::new();
+    let mut cur_node = path.start_node;
+    while let Some(next) = path.next_node(cur_node) {
+        let edge = path.g.g.find_edge(cur_node, next).unwrap();
+        result.push(path.g.g[edge].duration.as_nanos() as f64);
+        cur_node = next;
+    }
+    result
+}
+
+/// Keys of the groups the path belongs to, with the hash of their parent. Besides the group of
+/// its hash, a path belongs to a child group for the values it has of `split_keys`. Values are
+/// collected from all events of the path, so a path through two hosts gets `host=a+b`. Paths of
+/// failed requests get their own groups, as if their hash was different.
+fn group_keys(
+    path: &CriticalPath,
+    split_keys: &[String],
+) -> Vec<((RequestType, String), Option<String>)> {
+    let hash = if path.is_error {
+        format!("{}/error", path.hash())
+    } else {
+        path.hash().to_string()
+    };
+    let mut result = vec![((path.request_type, hash.clone()), None)];
+    if split_keys.is_empty() {
+        return result;
+    }
+    let values = split_keys
+        .iter()
+        .map(|key| {
+            let mut values = path
+                .g
+                .g
+                .node_indices()
+                .filter_map(|n| path.g.g[n].key_value_pair.get(key))
+                .map(|v| v.to_string())
+                .collect::<Vec<_>>();
+            values.sort();
+            values.dedup();
+            format!("{}={}", key, values.join("+"))
+        })
+        .collect::<Vec<_>>();
+    let child = format!("{}/{}", hash, values.join(","));
+    result.push(((path.request_type, child), Some(hash)));
+    result
+}
+
+/// Too few traces can't tell an attribute's effect apart from noise
+const MIN_ATTRIBUTION_SAMPLES: usize = 4;
+
+/// Latency of an edge for the values of one key
+#[derive(Debug, Clone)]
+struct AttributeStats {
+    count: usize,
+    /// Values and latencies, as long as all values are numbers
+    numeric: Option<RunningCovariance>,
+    /// Latency by value, until there are too many values to compare
+    categories: Option<HashMap<String, RunningStats>>,
+}
+
+/// Keys with more values than this, e.g., ids, are not compared by value
+const MAX_CATEGORIES: usize = 64;
+
+impl AttributeStats {
+    fn new() -> Self {
+        AttributeStats {
+            count: 0,
+            numeric: Some(RunningCovariance::new()),
+            categories: Some(HashMap::new()),
+        }
+    }
+
+    fn add(&mut self, value: &Value, latency: f64) {
+        self.count += 1;
+        let number = match value {
+            Value::Str(_) | Value::Bool(_) | Value::List(_) => None,
+            _ => value.as_f64(),
+        };
+        match (number, self.numeric.as_mut()) {
+            (Some(x), Some(numeric)) => numeric.add(x, latency),
+            _ => self.numeric = None,
+        }
+        if let Some(categories) = self.categories.as_mut() {
+            let name = value.to_string();
+            if categories.len() == MAX_CATEGORIES && !categories.contains_key(&name) {
+                self.categories = None;
+            } else {
+                categories.entry(name).or_default().add(latency);
+            }
+        }
+    }
+
+    /// Share of the variance explained and the effect of the key, if there is enough data
+    fn analyze(&self) -> Option<(f64, AttributeEffect)> {
+        match (&self.numeric, &self.categories) {
+            (Some(numeric), _) => regression(numeric),
+            (None, Some(categories)) => anova(categories),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Adjusted R² and slope of the least-squares line of latency on the value
+fn regression(points: &RunningCovariance) -> Option<(f64, AttributeEffect)> {
+    let n = points.count();
+    if n < MIN_ATTRIBUTION_SAMPLES {
+        return None;
+    }
+    let r_squared = points.r_squared()?;
+    let adjusted = 1.0 - (1.0 - r_squared) * (n - 1) as f64 / (n - 2) as f64;
+    Some((adjusted, AttributeEffect::Slope(points.slope()?)))
+}
+
+/// Omega squared of the latencies grouped by value, and the value with the highest mean
+fn anova(categories: &HashMap<String, RunningStats>) -> Option<(f64, AttributeEffect)> {
+    let n: usize = categories.values().map(|c| c.count()).sum();
+    let k = categories.len();
+    if n < MIN_ATTRIBUTION_SAMPLES || k < 2 || k == n {
+        return None;
+    }
+    let grand_mean = categories
+        .values()
+        .map(|c| c.count() as f64 * c.mean())
+        .sum::<f64>()
+        / n as f64;
+    let mut between = 0.0;
+    let mut within = 0.0;
+    let mut slowest: Option<(&String, f64)> = None;
+    for (value, latencies) in categories.iter() {
+        between += latencies.count() as f64 * (latencies.mean() - grand_mean).powi(2);
+        within += latencies.sum_of_squares();
+        if slowest.iter().all(|&(_, m)| latencies.mean() > m) {
+            slowest = Some((value, latencies.mean()));
+        }
+    }
+    let total = between + within;
+    if total == 0.0 {
+        return None;
+    }
+    let within_mean_square = within / (n - k) as f64;
+    let omega_squared =
+        (between - (k - 1) as f64 * within_mean_square) / (total + within_mean_square);
+    let (value, slowest_mean) = slowest.unwrap();
+    Some((
+        omega_squared,
+        AttributeEffect::Slowest {
+            value: value.clone(),
+            mean: Duration::from_secs_f64(slowest_mean),
+        },
+    ))
+}
+
+// # key value = hostname = client | server  ---> Append trace_id 0000>
+// 1231-123_hostname = "client" , 1233331-123_hostname = "client"
+
+// 2222-123_hostname = "client"
+
+impl Path for Group {
+    fn get_hash(&self) -> &str {
+        &self.hash
+    }
+
+    fn set_hash(&mut self, hash: &str) {
+        self.hash = hash.to_string()
+    }
+
+    fn start_node(&self) -> NodeIndex {
+        self.start_node
+    }
+
+    fn at(&self, idx: NodeIndex) -> TracepointID {
+        self.g[idx].tracepoint_id
+    }
+
+    fn next_node(&self, nidx: NodeIndex) -> Option<NodeIndex> {
+        let mut matches = self.g.neighbors_directed(nidx, Direction::Outgoing);
+        let result = matches.next();
+        assert!(matches.next().is_none());
+        result
+    }
+
+    fn prev_node(&self, nidx: NodeIndex) -> Option<NodeIndex> {
+        let mut matches = self.g.neighbors_directed(nidx, Direction::Incoming);
+        let result = matches.next();
+        assert!(matches.next().is_none());
+        result
+    }
+
+    fn len(&self) -> usize {
+        self.g.node_count()
+    }
+}
+
+/// An edge's part of the variance of the end-to-end latency of a group
+#[derive(Debug, Clone)]
+pub struct EdgeVariance {
+    pub edge: EdgeIndex,
+    /// Variance of the duration of the edge
+    pub variance: f64,
+    /// Covariance of the duration of the edge with the end-to-end latency, i.e., its variance
+    /// plus its covariances with the other edges. These sum to the end-to-end variance.
+    pub contribution: f64,
+}
+
+/// The variance of the end-to-end latency of a group, which is the sum of the variances of the
+/// edges and twice the covariances of all pairs of edges. Edges whose durations move together
+/// add to each other's contributions, and edges that compensate for each other, e.g., a cache hit
+/// followed by more work, take from them, so an edge with a high variance of its own is not
+/// necessarily what drives the variance of the request. Variances are in nanoseconds squared.
+#[derive(Debug, Clone)]
+pub struct VarianceDecomposition {
+    pub total: f64,
+    /// Edges by contribution, largest first
+    pub edges: Vec<EdgeVariance>,
+    /// Nonzero covariances of pairs of edges, largest magnitude first. In paths with more than
+    /// `PAIR_EDGES` edges, only pairs of the edges that contribute the most are kept.
+    pub covariances: Vec<(EdgeIndex, EdgeIndex, f64)>,
+}
+
+impl VarianceDecomposition {
+    /// Share of the end-to-end variance that comes from the edge, 0 if there is no variance
+    pub fn share(&self, edge: EdgeIndex) -> f64 {
+        if self.total == 0.0 {
+            return 0.0;
+        }
+        self.edges
+            .iter()
+            .find(|e| e.edge == edge)
+            .map_or(0.0, |e| e.contribution / self.total)
+    }
+}
+
+impl Display for VarianceDecomposition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let variances: f64 = self.edges.iter().map(|e| e.variance).sum();
+        write!(
+            f,
+            "total variance {:?}, edge variances {:?}, covariances {:?}",
+            self.total,
+            variances,
+            self.total - variances
+        )
+    }
+}
+
+/// This manages the grouping etc. and stores a collection of groups
+#[derive(Debug)]
+pub struct GroupManager {
+    /// Groups by request type and critical path hash, so requests of different types that take
+    /// the same path are not mixed
+    groups: HashMap<(RequestType, String), Group>,
+    /// Key-values kept in the nodes of the groups
+    node_keys: Vec<String>,
+    /// Key-values whose combinations get child groups
+    split_keys: Vec<String>,
+    stats: StatsConfig,
+    /// Groups with this many traces or fewer are not problem groups
+    min_traces: usize,
+    /// Latency objectives of request types and the requests of each group over them
+    slos: SLOTracker,
+}
+
+impl GroupManager {
+    pub fn from_settings(settings: &Settings) -> Self {
+        GroupManager {
+            groups: HashMap::new(),
+            node_keys: settings.node_keys.clone(),
+            split_keys: settings.group_split_keys.clone(),
+            stats: StatsConfig::from_settings(settings),
+            min_traces: settings.min_group_traces,
+            slos: SLOTracker::from_settings(settings),
+        }
+    }
+
+    /// Add new paths to the appropriate groups
+    pub fn update(&mut self, paths: &Vec<CriticalPath>) {
+        let mut updated_groups = Vec::new();
+        for path in paths {
+            let keys = group_keys(path, &self.split_keys);
+            let hashes: Vec<String> = keys.iter().map(|(key, _)| key.1.clone()).collect();
+            self.slos
+                .add(path.request_type, &hashes, path.end_time(), path.duration);
+            for (key, parent) in keys {
+                match self.groups.get_mut(&key) {
+                    Some(v) => v.add_trace(path),
+                    None => {
+                        let group =
+                            Group::with_hash(path, &self.node_keys, &self.stats, &key.1, parent);
+                        self.groups.insert(key.clone(), group);
+                    }
+                }
+                updated_groups.push(key);
+            }
+        }
+        // Groups without new paths can still have old ones leave their window
+        if let Some(now) = paths.iter().map(|p| p.end_time()).max() {
+            self.slos.expire(now);
+            for (key, group) in self.groups.iter_mut() {
+                if group.expire(now) {
+                    updated_groups.push(key.clone());
+                }
+            }
+        }
+        for key in updated_groups {
+            let group = self.groups.get_mut(&key).unwrap();
+            group.calculate_variance();
+            group.calculate_mean();
+            group.track_pairs();
+        }
+    }
+
+    /// Groups with enough traces that the detector finds problematic, most problematic first.
+    /// With split keys, the child groups are ranked, and their parent only keeps the statistics
+    /// of all values combined, see `parent`.
+    pub fn problem_groups(&self, detector: &dyn ProblemDetector) -> Vec<&Group> {
+        let groups: Vec<&Group> = self
+            .groups
+            .values()
+            .filter(|&g| g.parent.is_some() || self.split_keys.is_empty())
+            .filter(|&g| g.trace_count() > self.min_traces)
+            .collect();
+        detector.detect(&groups, &self.slos)
+    }
+
+    /// How well each request type with recent requests meets its latency objective, least
+    /// compliant first
+    pub fn slo_compliance(&self) -> Vec<SLOCompliance> {
+        self.slos.compliance()
+    }
+
+    /// The group with the paths of all values of the split keys, for child groups
+    pub fn parent(&self, group: &Group) -> Option<&Group> {
+        let hash = group.parent.as_ref()?;
+        self.groups.get(&(group.request_type, hash.clone()))
+    }
+
+    /// Groups split from the group on the values of the split keys
+    pub fn children(&self, group: &Group) -> Vec<&Group> {
+        self.groups
+            .values()
+            .filter(|g| g.request_type == group.request_type)
+            .filter(|g| g.parent.as_deref() == Some(group.hash()))
+            .collect()
+    }
+
+    /// Mark a group as "used": reset its performance data. A parent group and its children are
+    /// reset together, since they have the same paths.
+    pub fn used(&mut self, request_type: RequestType, group: &str) {
+        let parent = match &self.groups[&(request_type, group.to_string())].parent {
+            Some(p) => p.clone(),
+            None => group.to_string(),
+        };
+        for ((r, hash), g) in self.groups.iter_mut() {
+            if *r == request_type && (*hash == parent || g.parent.as_ref() == Some(&parent)) {
+                g.used();
+                self.slos.reset_group(request_type, hash);
+            }
+        }
+    }
+}
+
+impl Display for Group {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Group<{} {:?} traces, mean: {:?}, var: {:?}, cv:{:?}, hash: {:?}>",
+            self.trace_count(),
+            self.request_type,
+            self.mean / 1000000.0,
+            self.variance,
+            self.variance.sqrt() / self.mean,
+            self.hash
+        )
+    }
+}
+
+impl Display for GroupManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut groups: Vec<&Group> = self
+            .groups
+            .values()
+            .filter(|&g| g.trace_count() != 0)
+            .collect();
+        groups.sort_by(|a, b| b.variance.partial_cmp(&a.variance).unwrap());
+        for g in &groups {
+            write!(f, "{}, ", g)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::streaming::{ChangeConfig, StatsScope};
+    use crate::trace::{DAGEdge, EdgeType, EventType, Trace};
+
+    pub(super) fn config() -> StatsConfig {
+        StatsConfig {
+            scope: StatsScope::All,
+            window: Duration::from_secs(60),
+            half_life: Duration::from_secs(60),
+            change: ChangeConfig {
+                warmup: 20,
+                threshold: 10.0,
+                tail_quantile: 0.9,
+            },
+        }
+    }
+
+    /// A request on one span through tracepoints `t0`, `t1`, ... with edges of `millis`
+    /// milliseconds, starting `start` seconds into trace time. `values` are key-values of its
+    /// first event, and a failed request reports the error at its last event.
+    pub(super) fn path(
+        request_type: RequestType,
+        millis: &[u64],
+        start: i64,
+        values: &[(&str, &str)],
+        is_error: bool,
+    ) -> CriticalPath {
+        let span = Uuid::new_v4();
+        let mut trace = Trace::new(&Uuid::new_v4());
+        trace.request_type = request_type;
+        let mut time = NaiveDateTime::from_timestamp_opt(start, 0).unwrap();
+        for i in 0..=millis.len() {
+            let variant = if i == 0 {
+                EventType::Entry
+            } else if i == millis.len() {
+                EventType::Exit
+            } else {
+                EventType::Annotation
+            };
+            let mut event = Event {
+                trace_id: span,
+                tracepoint_id: TracepointID::from_str(&format!("t{}", i)),
+                timestamp: time,
+                is_synthetic: false,
+                variant,
+                key_value_pair: HashMap::new(),
+                is_error: is_error && i == millis.len(),
+            };
+            if i == 0 {
+                for (key, value) in values.iter() {
+                    event
+                        .key_value_pair
+                        .insert(key.to_string(), Value::Str(value.to_string()));
+                }
+            }
+            let node = trace.g.add_node(event);
+            if i == 0 {
+                trace.start_node = node;
+            } else {
+                let duration = Duration::from_millis(millis[i - 1]);
+                let edge = DAGEdge {
+                    duration,
+                    variant: EdgeType::ChildOf,
+                };
+                trace.g.add_edge(trace.end_node, node, edge);
+                trace.duration += duration;
+            }
+            trace.end_node = node;
+            if i < millis.len() {
+                time += chrono::Duration::milliseconds(millis[i] as i64);
+            }
+        }
+        trace.mark_errors();
+        CriticalPath::from_trace(&trace).unwrap()
+    }
+
+    /// A group of the paths, which have to go through the same tracepoints
+    pub(super) fn group(paths: &[CriticalPath]) -> Group {
+        let mut group = Group::new(&paths[0], &["host".to_string()], &config());
+        for path in paths[1..].iter() {
+            group.add_trace(path);
+        }
+        group.calculate_variance();
+        group.calculate_mean();
+        group
+    }
+
+    fn manager(split_keys: &[&str]) -> GroupManager {
+        GroupManager {
+            groups: HashMap::new(),
+            node_keys: vec!["host".to_string()],
+            split_keys: split_keys.iter().map(|k| k.to_string()).collect(),
+            stats: config(),
+            min_traces: 0,
+            slos: SLOTracker::new(
+                &[],
+                0.9,
+                Duration::from_millis(100),
+                Duration::from_secs(60),
+            ),
+        }
+    }
+
+    #[test]
+    fn keys_groups_by_path_and_split_values() {
+        let request_type = RequestType::new("GroupKeys");
+        let one = path(request_type, &[10, 20], 0, &[("host", "a")], false);
+        let keys = group_keys(&one, &[]);
+        assert_eq!(keys, vec![((request_type, one.hash().to_string()), None)]);
+
+        let split = vec!["host".to_string(), "zone".to_string()];
+        let keys = group_keys(&one, &split);
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0], ((request_type, one.hash().to_string()), None));
+        assert_eq!(keys[1].0 .1, format!("{}/host=a,zone=", one.hash()));
+        assert_eq!(keys[1].1.as_deref(), Some(one.hash()));
+
+        // Paths through the same tracepoints with other values only share the parent
+        let other = path(request_type, &[30, 40], 0, &[("host", "b")], false);
+        let other_keys = group_keys(&other, &split);
+        assert_eq!(other_keys[0], keys[0]);
+        assert_ne!(other_keys[1].0, keys[1].0);
+        assert_eq!(other_keys[1].1, keys[1].1);
+    }
+
+    #[test]
+    fn ranks_child_groups() {
+        let request_type = RequestType::new("RankChildren");
+        let mut groups = manager(&["host"]);
+        let mut paths = Vec::new();
+        for i in 0..10 {
+            paths.push(path(request_type, &[10, 20], i, &[("host", "a")], false));
+            paths.push(path(
+                request_type,
+                &[10, 20 + 50 * i as u64],
+                i,
+                &[("host", "b")],
+                false,
+            ));
+        }
+        groups.update(&paths);
+
+        let problems = groups.problem_groups(&detector::VarianceDetector);
+        assert_eq!(problems.len(), 1);
+        let slow = problems[0];
+        assert!(slow.hash().ends_with("/host=b"));
+        assert_eq!(slow.trace_count(), 10);
+        let parent = groups.parent(slow).unwrap();
+        assert_eq!(Some(parent.hash()), slow.parent.as_deref());
+        assert_eq!(parent.trace_count(), 20);
+        assert!(groups.parent(parent).is_none());
+        assert_eq!(groups.children(parent).len(), 2);
+
+        // Without split keys, there are only parents to rank
+        let mut groups = manager(&[]);
+        groups.update(&paths);
+        let problems = groups.problem_groups(&detector::VarianceDetector);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].trace_count(), 20);
+    }
+
+    #[test]
+    fn resets_parent_and_child_groups() {
+        let request_type = RequestType::new("ResetGroups");
+        let other_type = RequestType::new("ResetOtherGroups");
+        let mut groups = manager(&["host"]);
+        let paths = vec![
+            path(request_type, &[10, 20], 0, &[("host", "a")], false),
+            path(request_type, &[10, 30], 1, &[("host", "b")], false),
+            path(other_type, &[10, 20], 1, &[("host", "a")], false),
+        ];
+        groups.update(&paths);
+        let count = |groups: &GroupManager| {
+            groups
+                .groups
+                .values()
+                .filter(|g| g.request_type == request_type && g.trace_count() != 0)
+                .count()
+        };
+        assert_eq!(count(&groups), 3);
+
+        // Using a child resets its parent and the other children
+        let child = groups
+            .groups
+            .keys()
+            .find(|(r, hash)| *r == request_type && hash.ends_with("/host=a"))
+            .unwrap()
+            .1
+            .clone();
+        groups.used(request_type, &child);
+        assert_eq!(count(&groups), 0);
+        assert!(groups
+            .groups
+            .values()
+            .filter(|g| g.request_type == request_type)
+            .all(|g| g.is_used));
+        assert!(groups
+            .groups
+            .values()
+            .filter(|g| g.request_type == other_type)
+            .all(|g| !g.is_used && g.trace_count() == 1));
+
+        // And using a parent resets its children
+        groups.update(&paths);
+        assert_eq!(count(&groups), 3);
+        let parent = paths[0].hash().to_string();
+        groups.used(request_type, &parent);
+        assert_eq!(count(&groups), 0);
+    }
+
+    #[test]
+    fn keeps_failed_requests_apart() {
+        let request_type = RequestType::new("FailedGroups");
+        let ok = path(request_type, &[10, 20, 30], 0, &[("host", "a")], false);
+        let failed = path(request_type, &[10, 20, 30], 0, &[("host", "a")], true);
+        assert_eq!(ok.hash(), failed.hash());
+        let hash = format!("{}/error", failed.hash());
+        let keys = group_keys(&failed, &["host".to_string()]);
+        assert_eq!(keys[0], ((request_type, hash.clone()), None));
+        assert_eq!(keys[1].0 .1, format!("{}/host=a", hash));
+        assert_eq!(keys[1].1, Some(hash.clone()));
+
+        let mut groups = manager(&[]);
+        groups.update(&vec![ok, failed.clone(), failed]);
+        assert_eq!(groups.groups.len(), 2);
+        let failed = &groups.groups[&(request_type, hash)];
+        assert!(failed.is_error);
+        assert_eq!(failed.trace_count(), 2);
+        let ok = groups.groups.values().find(|g| !g.is_error).unwrap();
+        assert_eq!(ok.trace_count(), 1);
+        assert!(ok.error_edges().is_empty());
+    }
+
+    #[test]
+    fn finds_where_errors_start() {
+        let request_type = RequestType::new("ErrorEdges");
+        let mut failed = path(request_type, &[10, 20, 30], 0, &[], true);
+        // The error is reported at `t1` and then at the exit of the span
+        let nodes: Vec<NodeIndex> = failed.g.g.node_indices().collect();
+        for n in nodes {
+            if failed.g.g[n].tracepoint_id == TracepointID::from_str("t1") {
+                failed.g.g[n].is_error = true;
+            }
+        }
+        let group = group(&[failed]);
+        let edges = group.path_edges();
+        assert_eq!(group.error_edges(), vec![edges[0], edges[2]]);
+        // In groups of failed requests, the edges where errors are reported come first
+        let problem_edges = group.problem_edges(None);
+        assert_eq!(problem_edges[..2], [edges[0], edges[2]]);
+        assert_eq!(problem_edges.len(), 3);
+    }
+
+    #[test]
+    fn decomposes_variance_with_pair_covariances() {
+        let request_type = RequestType::new("DecomposeVariance");
+        let paths: Vec<CriticalPath> = (0..10)
+            .map(|i| {
+                path(
+                    request_type,
+                    &[10 + i, 30 - 2 * i, 5 + 3 * i],
+                    i as i64,
+                    &[],
+                    false,
+                )
+            })
+            .collect();
+        let group = group(&paths);
+        let decomposition = group.variance_decomposition();
+        let edges = group.path_edges();
+        // The variance of `i` is 8.25ms²
+        let ms = 1e12;
+        let expected = vec![
+            (edges[1], edges[2], -6.0 * 8.25 * ms),
+            (edges[0], edges[2], 3.0 * 8.25 * ms),
+            (edges[0], edges[1], -2.0 * 8.25 * ms),
+        ];
+        assert_eq!(decomposition.covariances.len(), 3);
+        for (&(a, b, covariance), (x, y, z)) in decomposition.covariances.iter().zip(expected) {
+            assert_eq!((a, b), (x, y));
+            assert!((covariance - z).abs() < 1e-6 * ms);
+        }
+        // The end-to-end variance is the variances of the edges and twice their covariances
+        let variances: f64 = decomposition.edges.iter().map(|e| e.variance).sum();
+        let covariances: f64 = decomposition.covariances.iter().map(|c| c.2).sum();
+        assert!((decomposition.total - group.variance).abs() < 1e-6 * ms);
+        assert!((variances + 2.0 * covariances - group.variance).abs() < 1e-6 * ms);
+    }
+
+    #[test]
+    fn pairs_edges_that_contribute_most() {
+        let request_type = RequestType::new("PairLongPaths");
+        let paths: Vec<CriticalPath> = (0..10)
+            .map(|i| {
+                let mut millis = vec![1; 10];
+                millis[3] = 10 + i;
+                millis[7] = 10 + 2 * i;
+                path(request_type, &millis, i as i64, &[], false)
+            })
+            .collect();
+        let groups = Group::from_critical_paths(paths, &[], &[], &config());
+        assert_eq!(groups.len(), 1);
+        let edges = groups[0].path_edges();
+        let covariances = groups[0].variance_decomposition().covariances;
+        assert_eq!(covariances.len(), 1);
+        assert_eq!((covariances[0].0, covariances[0].1), (edges[3], edges[7]));
+        assert!((covariances[0].2 - 2.0 * 8.25e12).abs() < 1.0);
+
+        // Other edges only replace the paired ones if they contribute much more
+        let mut group = groups[0].clone();
+        assert!(!group.track_pairs());
+        assert!(group.paired.contains(&3) && group.paired.contains(&7));
+    }
+
+    fn attribute(pairs: &[(Value, f64)]) -> AttributeStats {
+        let mut stats = AttributeStats::new();
+        for (value, latency) in pairs.iter() {
+            stats.add(value, *latency);
+        }
+        stats
+    }
+
+    fn host(name: &str) -> Value {
+        Value::Str(name.to_string())
+    }
+
+    #[test]
+    fn regression_fits_numeric_attributes() {
+        let exact: Vec<(Value, f64)> = (1..=10)
+            .map(|x| (Value::UnsignedInt(x), 0.5 + 0.002 * x as f64))
+            .collect();
+        match attribute(&exact).analyze() {
+            Some((explained, AttributeEffect::Slope(slope))) => {
+                assert!((explained - 1.0).abs() < 1e-9);
+                assert!((slope - 0.002).abs() < 1e-9);
+            }
+            other => panic!("Expected a slope but got {:?}", other),
+        }
+
+        // The adjusted R² is below the plain one, and can be negative for unrelated values
+        let mut points = RunningCovariance::new();
+        for &(x, y) in [(1.0, 2.0), (2.0, 1.0), (3.0, 3.0), (4.0, 2.0)].iter() {
+            points.add(x, y);
+        }
+        let (adjusted, _) = regression(&points).unwrap();
+        assert!(adjusted < points.r_squared().unwrap());
+        assert!((points.r_squared().unwrap() - 0.1).abs() < 1e-9);
+        assert!((adjusted - -0.35).abs() < 1e-9);
+
+        let mut few = RunningCovariance::new();
+        for x in 0..MIN_ATTRIBUTION_SAMPLES - 1 {
+            few.add(x as f64, x as f64);
+        }
+        assert!(regression(&few).is_none());
+    }
+
+    #[test]
+    fn anova_compares_categorical_attributes() {
+        let pairs = vec![
+            (host("a"), 1.0),
+            (host("a"), 1.1),
+            (host("a"), 0.9),
+            (host("b"), 3.0),
+            (host("b"), 3.1),
+            (host("b"), 2.9),
+        ];
+        match attribute(&pairs).analyze() {
+            Some((explained, AttributeEffect::Slowest { value, mean })) => {
+                // Between groups: 6 * 1² = 6, within: 4 * 0.01 = 0.04
+                let within_mean_square = 0.04 / 4.0;
+                let expected = (6.0 - within_mean_square) / (6.04 + within_mean_square);
+                assert!((explained - expected).abs() < 1e-9);
+                assert_eq!(value, "b");
+                assert!((mean.as_secs_f64() - 3.0).abs() < 1e-6);
+            }
+            other => panic!("Expected the slowest value but got {:?}", other),
+        }
+
+        // Values that don't matter explain nothing
+        let pairs = vec![
+            (host("a"), 1.0),
+            (host("a"), 2.0),
+            (host("b"), 1.0),
+            (host("b"), 2.0),
+        ];
+        let (explained, _) = attribute(&pairs).analyze().unwrap();
+        assert!(explained < 0.0);
+
+        // A value per trace can't be told apart from noise
+        let unique: Vec<(Value, f64)> = (0..5).map(|i| (host(&i.to_string()), i as f64)).collect();
+        assert!(attribute(&unique).analyze().is_none());
+    }
+
+    #[test]
+    fn stops_comparing_many_values() {
+        let ids: Vec<(Value, f64)> = (0..=MAX_CATEGORIES)
+            .map(|i| (host(&format!("req-{}", i)), (i % 2) as f64))
+            .collect();
+        let stats = attribute(&ids);
+        assert_eq!(stats.count, MAX_CATEGORIES + 1);
+        assert!(stats.categories.is_none());
+        assert!(stats.analyze().is_none());
+
+        // Mixed values are compared by value
+        let mixed = vec![
+            (Value::UnsignedInt(1), 1.0),
+            (host("x"), 2.0),
+            (Value::UnsignedInt(1), 1.0),
+            (host("x"), 2.0),
+            (host("x"), 2.1),
+        ];
+        let stats = attribute(&mixed);
+        assert!(stats.numeric.is_none());
+        assert!(stats.analyze().is_some());
+    }
+
+    #[test]
+    fn sums_wait_times() {
+        let mut wait = WaitTimes::default();
+        assert_eq!(wait.share(WaitKind::Running), 0.0);
+        wait.add(WaitKind::Running, Duration::from_millis(30));
+        wait.add(WaitKind::AsyncWait, Duration::from_millis(10));
+        wait.add(WaitKind::Running, Duration::from_millis(20));
+        let mut other = WaitTimes::default();
+        other.add(WaitKind::LockWait, Duration::from_millis(40));
+        wait.merge(&other);
+        assert_eq!(wait.total(), Duration::from_millis(100));
+        for &(kind, share) in [
+            (WaitKind::Running, 0.5),
+            (WaitKind::AsyncWait, 0.1),
+            (WaitKind::LockWait, 0.4),
+        ]
+        .iter()
+        {
+            assert!((wait.share(kind) - share).abs() < 1e-9);
+        }
+        wait.clear();
+        assert_eq!(wait.total(), Duration::new(0, 0));
+
+        // Edges sum the time of their paths
+        let group = group(&[
+            path(RequestType::UNKNOWN, &[10, 20], 0, &[], false),
+            path(RequestType::UNKNOWN, &[30, 40], 1, &[], false),
+        ]);
+        let totals = group
+            .g
+            .edge_indices()
+            .map(|edge| group.g[edge].wait.total())
+            .collect::<Vec<_>>();
+        assert_eq!(totals.iter().sum::<Duration>(), Duration::from_millis(100));
+        assert!(group
+            .g
+            .edge_indices()
+            .all(|edge| group.g[edge].wait.share(WaitKind::Running) == 1.0));
+    }
+}
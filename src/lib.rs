@@ -37,6 +37,9 @@ All rights reserved.
 //! 2. This script generates a list of trace_ids in the file `~/offline_profiling.txt`.
 //! 3. Use `cargo run manifest <path/to/trace/ids>` to generate the manifest. It is stored in
 //!    `/opt/stack/manifest.json`.
+//! 4. Request types are defined in `request_types.toml`. For applications whose traces can't be
+//!    told apart that way, set `infer_request_types` and the manifest gets request types inferred
+//!    from the structure of the traces, which the controller then uses too.
 //!
 //! # Using Pythia utils
 //! There are a bunch of functions defined in this file, they are used from `cargo run`. Try
@@ -45,6 +48,10 @@ All rights reserved.
 //! * `pythia get-trace <trace_id>` read a single trace and print the dot file
 //! * `pythia [enable|disable]-all` to enable/disable all tracepoints
 //! * `pythia manifest-stats` construct a manifest and print all the stats used for the paper.
+//! * `pythia group-folder <folder> --filter 'duration > 2s'` group the critical paths of some
+//!   traces, see [`filter`](filter/index.html) for the filter syntax
+//! * `pythia import <archive> --trace-folder <folder>` append traces to a trace archive, and
+//!   `pythia list-archive <archive>` to see what is in it
 //!
 //! # Running Pythia loop
 //! 1. Make sure everything is configured correctly, read the comments in the toml files
@@ -67,12 +74,15 @@ extern crate lazy_static;
 pub mod budget;
 pub mod controller;
 pub mod critical;
+pub mod filter;
 pub mod grouping;
 pub mod manifest;
 pub mod reader;
 pub mod rpclib;
 pub mod search;
 pub mod settings;
+pub mod slo;
+pub mod streaming;
 pub mod trace;
 
 use std::collections::HashSet;
@@ -81,7 +91,7 @@ use std::fmt;
 use std::fs::File;
 use std::io::stdin;
 use std::io::{self, BufRead};
-use std::path::PathBuf;
+use std::path::Path;
 use std::process::Command;
 use std::time::Duration;
 use std::time::Instant;
@@ -93,12 +103,17 @@ use pythia_common::RequestType;
 
 use crate::controller::controller_from_settings;
 use crate::critical::CriticalPath;
+use crate::filter::Filter;
 use crate::grouping::Group;
+use crate::grouping::MultimodalEdgeDetector;
 use crate::manifest::Manifest;
+use crate::manifest::RequestTypeClassifier;
 use crate::reader::reader_from_settings;
 use crate::settings::ApplicationType;
 use crate::settings::Settings;
+use crate::streaming::{StatsConfig, StatsScope};
 use crate::trace::Trace;
+use crate::trace::{ArchiveQuery, ArchiveReader, ArchiveWriter};
 
 // use rand::seq::SliceRandom;
 // use crate::cct::CCT;
@@ -133,7 +148,7 @@ use crate::trace::Trace;
 //         .map(|t| CriticalPath::from_trace(t).unwrap())
 //         .collect();
 //     let now = Instant::now();
-//     let mut groups = Group::from_critical_paths(critical_paths);
+//     let mut groups = Group::from_critical_paths(critical_paths, &settings.node_keys);
 //     eprintln!(
 //         "Extracting critical paths took {}us",
 //         now.elapsed().as_micros()
@@ -269,12 +284,14 @@ pub fn manifest_stats(manfile: &str) {
         let settings = Settings::read();
         let mut reader = reader_from_settings(&settings);
         reader.for_searchspace();
-        let traces = reader.read_trace_file(manfile);
+        let traces = reader
+            .read_trace_file(manfile)
+            .expect("Couldn't read trace file");
         let now = Instant::now();
-        let manifest = Manifest::from_trace_list(&traces);
+        let manifest = Manifest::from_trace_list(&traces, &settings.request_types);
         let elapsed = now.elapsed();
         println!("Overwriting manifest file");
-        let manifest_file = settings.manifest_file;
+        let manifest_file = &settings.manifest_file;
         manifest.to_file(manifest_file.as_path());
         // let prev_stats = statm_self().unwrap();
         let manifest = Manifest::from_file(manifest_file.as_path())
@@ -284,7 +301,12 @@ pub fn manifest_stats(manfile: &str) {
             .iter()
             .filter_map(|t| CriticalPath::from_trace(t).ok())
             .collect::<Vec<CriticalPath>>();
-        let groups = Group::from_critical_paths(critical_paths);
+        let groups = Group::from_critical_paths(
+            critical_paths,
+            &settings.node_keys,
+            &settings.group_split_keys,
+            &all_time_stats(&settings),
+        );
 
         // Start outputting stats
         eprintln!(
@@ -399,10 +421,16 @@ pub fn show_manifest(request_type: &str) {
     );
 }
 
-pub fn dump_traces(tracefile: &str) {
+pub fn dump_traces(tracefile: &str, filter: Option<&Filter>) {
     let settings = Settings::read();
     let mut reader = reader_from_settings(&settings);
-    for trace in reader.read_trace_file(tracefile) {
+    for trace in reader
+        .read_trace_file(tracefile)
+        .expect("Couldn't read trace file")
+    {
+        if !filter.iter().all(|f| f.matches(&trace)) {
+            continue;
+        }
         let mut outfile = dirs::home_dir().unwrap();
         outfile.push(trace.base_id.to_hyphenated().to_string());
         outfile.set_extension("json");
@@ -410,36 +438,104 @@ pub fn dump_traces(tracefile: &str) {
     }
 }
 
+/// Appends traces from a folder or a file of trace ids to the archive in `archive_dir`. Traces
+/// that are already in the archive are skipped.
+pub fn import_traces(archive_dir: &str, trace_folder: Option<&str>, traceid_file: Option<&str>) {
+    let settings = Settings::read();
+    let mut reader = reader_from_settings(&settings);
+    let traces = match (trace_folder, traceid_file) {
+        (Some(folder), _) => reader.read_dir(folder),
+        (None, Some(file)) => reader.read_trace_file(file),
+        (None, None) => panic!("Either a trace folder or a trace id file is needed"),
+    }
+    .expect("Couldn't read traces");
+    let mut archive =
+        ArchiveWriter::open(Path::new(archive_dir)).expect("Couldn't open trace archive");
+    let mut imported = 0;
+    for trace in traces.iter() {
+        if archive.append(trace).expect("Couldn't write to trace archive") {
+            imported += 1;
+        }
+    }
+    archive.flush().expect("Couldn't write to trace archive");
+    println!(
+        "Imported {} traces, {} were already archived",
+        imported,
+        traces.len() - imported
+    );
+}
+
+/// Lists the traces in the archive in `archive_dir` by start time
+pub fn list_archive(archive_dir: &str, request_type: Option<&str>) {
+    let archive = ArchiveReader::open(Path::new(archive_dir)).expect("Couldn't open trace archive");
+    let query = ArchiveQuery {
+        request_type: request_type.map(RequestType::new),
+        ..Default::default()
+    };
+    for entry in archive.query(&query) {
+        println!(
+            "{} {} {} {:?}",
+            entry.base_id, entry.request_type, entry.start_time, entry.duration
+        );
+    }
+}
+
 pub fn get_manifest(manfile: &str, overwrite: bool) {
     let settings = Settings::read();
     let mut reader = reader_from_settings(&settings);
     reader.for_searchspace();
-    let mut traces = reader.read_trace_file(manfile);
+    let mut traces = reader
+        .read_trace_file(manfile)
+        .expect("Couldn't read trace file");
     if settings.application == ApplicationType::HDFS {
         for trace in &mut traces {
             trace.prune();
         }
     }
-    manifest_from_traces(&traces, overwrite, &settings.manifest_file);
+    manifest_from_traces(&mut traces, overwrite, &settings);
 }
 
-pub fn manifest_from_folder(trace_folder: &str) {
+pub fn manifest_from_folder(trace_folder: &str, filter: Option<&Filter>) {
     let settings = Settings::read();
     let mut reader = reader_from_settings(&settings);
     reader.for_searchspace();
-    let mut traces = reader.read_dir(trace_folder);
+    let mut traces = reader
+        .read_dir(trace_folder)
+        .expect("Couldn't read trace folder");
     println!("Read {} traces", traces.len());
+    if let Some(filter) = filter {
+        traces.retain(|t| filter.matches(t));
+        println!("{} traces match the filter", traces.len());
+    }
     if settings.application == ApplicationType::HDFS {
         for trace in &mut traces {
             trace.prune();
         }
     }
-    manifest_from_traces(&traces, false, &settings.manifest_file);
+    manifest_from_traces(&mut traces, false, &settings);
 }
 
-fn manifest_from_traces(traces: &Vec<Trace>, overwrite: bool, manifest_file: &PathBuf) {
+fn manifest_from_traces(traces: &mut Vec<Trace>, overwrite: bool, settings: &Settings) {
+    let manifest_file = &settings.manifest_file;
     let now = Instant::now();
-    let manifest = Manifest::from_trace_list(&traces);
+    let classifier = if settings.infer_request_types {
+        let classifier = RequestTypeClassifier::from_traces(
+            traces
+                .iter()
+                .filter(|t| t.request_type == RequestType::UNKNOWN),
+            settings.inference_similarity,
+        );
+        for trace in traces.iter_mut() {
+            if trace.request_type == RequestType::UNKNOWN {
+                trace.request_type = classifier.classify(trace);
+            }
+        }
+        Some(classifier)
+    } else {
+        None
+    };
+    let mut manifest = Manifest::from_trace_list(&traces, &settings.request_types);
+    manifest.classifier = classifier;
     let elapsed = now.elapsed();
     println!("{}", manifest);
     if manifest_file.exists() {
@@ -464,23 +560,34 @@ pub fn measure_search_space_feasibility(trace_file: &str) {
     let settings = Settings::read();
     let mut reader = reader_from_settings(&settings);
     reader.for_searchspace();
-    let mut trace = reader.read_file(trace_file);
+    let mut trace = match reader.read_file(trace_file) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", trace_file, e);
+            return;
+        }
+    };
     if settings.application == ApplicationType::HDFS {
         trace.prune();
     }
-    println!("{}", Manifest::from_trace_list(&vec![trace]));
+    println!(
+        "{}",
+        Manifest::from_trace_list(&vec![trace], &settings.request_types)
+    );
 }
 
-pub fn group_folder(trace_folder: &str) {
+pub fn group_folder(trace_folder: &str, filter: Option<&Filter>) {
     let settings = Settings::read();
     let mut reader = reader_from_settings(&settings);
     // println!(trace_folder);
-    let traces = reader.read_dir(trace_folder);
+    let traces = reader
+        .read_dir(trace_folder)
+        .expect("Couldn't read trace folder");
     println!("Read {} traces", traces.len());
-    group_traces(traces);
+    group_traces(traces, filter, &settings);
 }
 
-pub fn group_from_ids(id_file: &str) {
+pub fn group_from_ids(id_file: &str, filter: Option<&Filter>) {
     let settings = Settings::read();
     let mut reader = reader_from_settings(&settings);
     println!("{}",id_file);
@@ -488,26 +595,58 @@ pub fn group_from_ids(id_file: &str) {
     // println!("mertiko");
     let traces = io::BufReader::new(file)
         .lines()
-        .map(|x| reader.get_trace_from_base_id(&x.unwrap()).unwrap())
+        .filter_map(|x| {
+            let id = x.unwrap();
+            match reader.get_trace_from_base_id(&id) {
+                Ok(t) => Some(t),
+                Err(e) => {
+                    eprintln!("Skipping trace {}: {}", id, e);
+                    None
+                }
+            }
+        })
         .collect::<Vec<_>>();
     println!("Read {} traces", traces.len());
-    group_traces(traces);
+    group_traces(traces, filter, &settings);
 }
 
-fn group_traces(traces: Vec<Trace>) {
+/// Statistics for analyzing a fixed set of traces as a whole, regardless of when they ended
+fn all_time_stats(settings: &Settings) -> StatsConfig {
+    StatsConfig {
+        scope: StatsScope::All,
+        ..StatsConfig::from_settings(settings)
+    }
+}
+
+/// The filter is applied to whole traces, as in the other subcommands, before their critical
+/// paths are computed
+fn group_traces(traces: Vec<Trace>, filter: Option<&Filter>, settings: &Settings) {
     let critical_paths = traces
         .iter()
+        .filter(|t| filter.iter().all(|f| f.matches(*t)))
         .filter_map(|t| CriticalPath::from_trace(t).ok())
         .collect::<Vec<CriticalPath>>();
     println!("Got {} paths", critical_paths.len());
-    let mut groups = Group::from_critical_paths(critical_paths);
+    if critical_paths.is_empty() {
+        match filter {
+            Some(_) => println!("No paths match the filter"),
+            None => println!("No paths to group"),
+        }
+        return;
+    }
+    let mut groups = Group::from_critical_paths(
+        critical_paths,
+        &settings.node_keys,
+        &settings.group_split_keys,
+        &all_time_stats(settings),
+    );
     println!("Got {} groups", groups.len());
-    groups.sort_by(|a, b| b.traces.len().partial_cmp(&a.traces.len()).unwrap()); // descending order
+    groups.sort_by(|a, b| b.trace_count().partial_cmp(&a.trace_count()).unwrap()); // descending order
     println!(
         "Trace count and variance of each group: {:?}",
         groups
             .iter()
-            .map(|x| (x.traces.len(), x.variance))
+            .map(|x| (x.trace_count(), x.variance))
             .collect::<Vec<_>>()
     );
     println!("Top 5 variance groups");
@@ -517,12 +656,17 @@ fn group_traces(traces: Vec<Trace>) {
             break;
         }
         println!(
-            "Group length {}, variance {}, each trace duration {:?}\nsample trace: {}",
-            i.traces.len(),
+            "Group length {}, variance {}, duration p50 {:?} p95 {:?} p99 {:?}\nsample trace: {}",
+            i.trace_count(),
             i.variance,
-            i.traces.iter().map(|x| x.duration).collect::<Vec<_>>(),
+            i.stats.quantile(0.5),
+            i.stats.quantile(0.95),
+            i.stats.quantile(0.99),
             i
         );
+        for outlier in i.outliers(settings.outlier_threshold) {
+            println!("Outlier: {}", outlier);
+        }
     }
     println!("Top 5 groups with longest traces");
     groups.sort_by(|a, b| b.g.node_count().partial_cmp(&a.g.node_count()).unwrap()); // descending order
@@ -531,10 +675,12 @@ fn group_traces(traces: Vec<Trace>) {
             break;
         }
         println!(
-            "Group length {}, variance {}, each trace duration {:?}\nsample trace: {}",
-            i.traces.len(),
+            "Group length {}, variance {}, duration p50 {:?} p95 {:?} p99 {:?}\nsample trace: {}",
+            i.trace_count(),
             i.variance,
-            i.traces.iter().map(|x| x.duration).collect::<Vec<_>>(),
+            i.stats.quantile(0.5),
+            i.stats.quantile(0.95),
+            i.stats.quantile(0.99),
             i
         );
     }
@@ -553,21 +699,35 @@ fn group_traces(traces: Vec<Trace>) {
                 //                           }
                 //     break;
                 //                                    }},
-                x.traces.len(),
+                x.trace_count(),
                 x.variance,
-                x.traces.iter().map(|x| x.g.base_id).collect::<Vec<_>>()
+                x.exemplars.items()
             ))
             .join("\n")
     );
     groups.sort_by(|a, b| b.variance.partial_cmp(&a.variance).unwrap()); // descending order
-    println!("\n\nEdges sorted by variance:\n");
-    let problem_edges = groups[0].problem_edges();
+    println!("\n\nEdges sorted by contribution to variance:\n");
+    let edge_detector = MultimodalEdgeDetector::from_settings(settings);
+    let problem_edges = groups[0].problem_edges(edge_detector.as_ref());
+    let decomposition = groups[0].variance_decomposition();
+    println!("Time: {}", groups[0].wait_times());
+    for (a, b, covariance) in decomposition.covariances.iter().take(3) {
+        println!("Covariance of {:?} and {:?}: {:?}", a, b, covariance);
+    }
     for edge in &problem_edges {
         let endpoints = groups[0].g.edge_endpoints(*edge).unwrap();
         println!(
             "({} -> {}): {}",
             groups[0].g[endpoints.0], groups[0].g[endpoints.1], groups[0].g[*edge]
         );
+        println!(
+            "    {:.1}% of the variance",
+            decomposition.share(*edge) * 100.0
+        );
+        println!("    Time: {}", groups[0].g[*edge].wait);
+        for a in groups[0].attribute_edge(*edge, settings.attribution_threshold) {
+            println!("    {}", a);
+        }
     }
 }
 
@@ -575,7 +735,13 @@ pub fn read_trace_file(trace_file: &str) {
     
     let settings = Settings::read();
     let mut reader = reader_from_settings(&settings);
-    let mut trace = reader.read_file(trace_file);
+    let mut trace = match reader.read_file(trace_file) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Couldn't read {}: {}", trace_file, e);
+            return;
+        }
+    };
     if settings.application == ApplicationType::HDFS {
         trace.prune();
     }
@@ -587,7 +753,13 @@ pub fn get_trace(trace_id: &str, to_file: bool, prune: bool) {
     let settings = Settings::read();
     let mut reader = reader_from_settings(&settings);
     
-    let mut trace = reader.get_trace_from_base_id(trace_id).unwrap();
+    let mut trace = match reader.get_trace_from_base_id(trace_id) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Couldn't get trace {}: {}", trace_id, e);
+            return;
+        }
+    };
     
     if prune {
         trace.prune();
@@ -606,7 +778,13 @@ pub fn get_trace(trace_id: &str, to_file: bool, prune: bool) {
 pub fn show_key_value_pairs(trace_id: &str) {
     let settings = Settings::read();
     let mut reader = reader_from_settings(&settings);
-    let trace = reader.get_trace_from_base_id(trace_id).unwrap();
+    let trace = match reader.get_trace_from_base_id(trace_id) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Couldn't get trace {}: {}", trace_id, e);
+            return;
+        }
+    };
     trace.get_keys();
 
     println!("{:?}", trace_id);
@@ -615,7 +793,13 @@ pub fn show_key_value_pairs(trace_id: &str) {
 pub fn get_crit(trace_id: &str) {
     let settings = Settings::read();
     let mut reader = reader_from_settings(&settings);
-    let trace = reader.get_trace_from_base_id(trace_id).unwrap();
+    let trace = match reader.get_trace_from_base_id(trace_id) {
+        Ok(t) => t,
+        Err(e) => {
+            eprintln!("Couldn't get trace {}: {}", trace_id, e);
+            return;
+        }
+    };
     let crit = CriticalPath::from_trace(&trace).unwrap();
     println!("{}", crit.g);
 }
@@ -0,0 +1,341 @@
+/*
+This source code is licensed under the BSD-style license found in the
+LICENSE file in the root directory of this source tree.
+
+Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
+All rights reserved.
+*/
+
+//! Request types inferred from the structure of traces, for applications whose traces don't say
+//! what request they are.
+//!
+//! The top of a trace's hierarchy is its first event and the tracepoints directly under it, i.e.,
+//! in the root span, or outside of any span if the trace doesn't start with one. Traces with the
+//! same first tracepoint are clustered by the Jaccard similarity of the tracepoints under it:
+//! starting from the most common structure, each structure joins the most similar cluster if it
+//! is similar enough, or starts a new cluster.
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Display;
+
+use petgraph::algo::toposort;
+use petgraph::Direction;
+use serde::{Deserialize, Serialize};
+
+use pythia_common::RequestType;
+
+use crate::trace::EventType;
+use crate::trace::Trace;
+use crate::trace::TracepointID;
+
+/// First tracepoint of a trace and the tracepoints directly under it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TopHierarchy {
+    pub root: TracepointID,
+    pub children: HashSet<TracepointID>,
+}
+
+impl TopHierarchy {
+    pub fn from_trace(trace: &Trace) -> Option<Self> {
+        let root = trace.g.node_weight(trace.start_node)?;
+        let order = toposort(&trace.g, None).ok()?;
+        // Number of spans that are open after each event
+        let mut open_after = HashMap::new();
+        let mut children = HashSet::new();
+        let top_level = if root.variant == EventType::Entry {
+            1
+        } else {
+            0
+        };
+        for nidx in order {
+            let open_before: i64 = trace
+                .g
+                .neighbors_directed(nidx, Direction::Incoming)
+                .filter_map(|p| open_after.get(&p))
+                .max()
+                .cloned()
+                .unwrap_or(0);
+            let event = &trace.g[nidx];
+            let open = match event.variant {
+                EventType::Entry => open_before + 1,
+                EventType::Exit => open_before - 1,
+                EventType::Annotation => open_before,
+            };
+            open_after.insert(nidx, open);
+            if nidx != trace.start_node
+                && event.variant != EventType::Exit
+                && open_before == top_level
+            {
+                children.insert(event.tracepoint_id);
+            }
+        }
+        Some(TopHierarchy {
+            root: root.tracepoint_id,
+            children,
+        })
+    }
+
+    /// Jaccard similarity of the tracepoints under the root, 0 if the roots are different
+    pub fn similarity(&self, other: &TopHierarchy) -> f64 {
+        if self.root != other.root {
+            return 0.0;
+        }
+        let union = self.children.union(&other.children).count();
+        if union == 0 {
+            return 1.0;
+        }
+        self.children.intersection(&other.children).count() as f64 / union as f64
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Cluster {
+    request_type: RequestType,
+    /// The most common structure in the cluster
+    representative: TopHierarchy,
+    /// Number of profiling traces in the cluster
+    size: usize,
+}
+
+/// Assigns inferred request types to traces. It's built from profiling traces and saved with the
+/// manifest, so traces seen later get the same request types.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RequestTypeClassifier {
+    clusters: Vec<Cluster>,
+    /// Structures less similar than this to every cluster are not classified
+    similarity: f64,
+}
+
+impl RequestTypeClassifier {
+    /// Clusters the traces, naming the request types `Inferred0`, `Inferred1`, ... from the
+    /// largest cluster to the smallest
+    pub fn from_traces<'a, I>(traces: I, similarity: f64) -> Self
+    where
+        I: IntoIterator<Item = &'a Trace>,
+    {
+        let mut structures: Vec<(TopHierarchy, usize)> = Vec::new();
+        for trace in traces {
+            let top = match TopHierarchy::from_trace(trace) {
+                Some(t) => t,
+                None => continue,
+            };
+            match structures.iter_mut().find(|(s, _)| *s == top) {
+                Some((_, count)) => *count += 1,
+                None => structures.push((top, 1)),
+            }
+        }
+        structures.sort_by_key(|s| Reverse(s.1));
+
+        let mut clusters: Vec<Cluster> = Vec::new();
+        for (top, count) in structures {
+            match best_match(&clusters, &top, similarity) {
+                Some(i) => clusters[i].size += count,
+                None => clusters.push(Cluster {
+                    request_type: RequestType::UNKNOWN,
+                    representative: top,
+                    size: count,
+                }),
+            }
+        }
+        clusters.sort_by_key(|c| Reverse(c.size));
+        for (i, cluster) in clusters.iter_mut().enumerate() {
+            cluster.request_type = RequestType::inferred(i);
+        }
+        RequestTypeClassifier {
+            clusters,
+            similarity,
+        }
+    }
+
+    /// Request type of the most similar cluster, or `Unknown` if none is similar enough
+    pub fn classify(&self, trace: &Trace) -> RequestType {
+        TopHierarchy::from_trace(trace)
+            .and_then(|top| best_match(&self.clusters, &top, self.similarity))
+            .map(|i| self.clusters[i].request_type)
+            .unwrap_or(RequestType::UNKNOWN)
+    }
+
+    pub fn request_types(&self) -> Vec<RequestType> {
+        self.clusters.iter().map(|c| c.request_type).collect()
+    }
+
+    /// Tracepoints that need to be enabled to classify traces
+    pub fn tracepoints(&self) -> HashSet<TracepointID> {
+        let mut result = HashSet::new();
+        for c in self.clusters.iter() {
+            result.insert(c.representative.root);
+            result.extend(c.representative.children.iter());
+        }
+        result
+    }
+}
+
+/// Index of the most similar cluster with the same root, the earliest one if there is a tie
+fn best_match(clusters: &[Cluster], top: &TopHierarchy, similarity: f64) -> Option<usize> {
+    let mut best: Option<(usize, f64)> = None;
+    for (i, c) in clusters.iter().enumerate() {
+        if c.representative.root != top.root {
+            continue;
+        }
+        let s = c.representative.similarity(top);
+        if s >= similarity && best.iter().all(|&(_, b)| s > b) {
+            best = Some((i, s));
+        }
+    }
+    best.map(|(i, _)| i)
+}
+
+impl Display for RequestTypeClassifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Inferred request types:")?;
+        for c in self.clusters.iter() {
+            writeln!(
+                f,
+                "{}: {} traces starting with {}, {} tracepoints under it",
+                c.request_type,
+                c.size,
+                c.representative.root,
+                c.representative.children.len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::NaiveDateTime;
+    use uuid::Uuid;
+
+    use crate::trace::{DAGEdge, EdgeType, Event};
+
+    /// A trace with one event after the other. The first and last events are the entry and exit
+    /// of the root span.
+    fn trace(root: &str, events: &[(&str, EventType)]) -> Trace {
+        let mut trace = Trace::new(&Uuid::new_v4());
+        let mut all = vec![(root, EventType::Entry)];
+        all.extend(events.iter().cloned());
+        all.push((root, EventType::Exit));
+        let mut previous = None;
+        for (tracepoint, variant) in all {
+            let node = trace.g.add_node(Event {
+                trace_id: Uuid::new_v4(),
+                tracepoint_id: TracepointID::from_str(tracepoint),
+                timestamp: NaiveDateTime::from_timestamp_opt(0, 0).unwrap(),
+                is_synthetic: false,
+                variant,
+                key_value_pair: HashMap::new(),
+                is_error: false,
+            });
+            match previous {
+                Some(p) => {
+                    trace.g.add_edge(
+                        p,
+                        node,
+                        DAGEdge {
+                            duration: Default::default(),
+                            variant: EdgeType::ChildOf,
+                        },
+                    );
+                }
+                None => trace.start_node = node,
+            }
+            previous = Some(node);
+        }
+        trace.end_node = previous.unwrap();
+        trace
+    }
+
+    /// A trace whose root span has the given spans directly under it
+    fn calls(root: &str, children: &[&str]) -> Trace {
+        let mut events = Vec::new();
+        for child in children {
+            events.push((*child, EventType::Entry));
+            events.push((*child, EventType::Exit));
+        }
+        trace(root, &events)
+    }
+
+    fn tracepoints(names: &[&str]) -> HashSet<TracepointID> {
+        names.iter().map(|n| TracepointID::from_str(n)).collect()
+    }
+
+    #[test]
+    fn finds_top_hierarchy() {
+        let t = trace(
+            "web:request",
+            &[
+                ("db:query", EventType::Entry),
+                ("db:lock", EventType::Annotation),
+                ("db:query", EventType::Exit),
+                ("web:render", EventType::Annotation),
+            ],
+        );
+        let top = TopHierarchy::from_trace(&t).unwrap();
+        assert_eq!(top.root, TracepointID::from_str("web:request"));
+        assert_eq!(top.children, tracepoints(&["db:query", "web:render"]));
+
+        let other = TopHierarchy::from_trace(&calls("web:request", &["db:query"])).unwrap();
+        assert!((top.similarity(&other) - 0.5).abs() < 1e-9);
+        let elsewhere = TopHierarchy::from_trace(&calls("cli:run", &["db:query"])).unwrap();
+        assert_eq!(other.similarity(&elsewhere), 0.0);
+    }
+
+    #[test]
+    fn clusters_similar_structures() {
+        let mut traces = Vec::new();
+        for _ in 0..3 {
+            traces.push(calls("web:request", &["auth:check", "db:query"]));
+        }
+        traces.push(calls(
+            "web:request",
+            &["auth:check", "db:query", "cache:get"],
+        ));
+        for _ in 0..2 {
+            traces.push(calls("web:request", &["upload:write"]));
+        }
+        traces.push(calls("cli:run", &["db:query"]));
+        let classifier = RequestTypeClassifier::from_traces(traces.iter(), 0.5);
+
+        // From the largest cluster to the smallest
+        assert_eq!(
+            classifier.request_types(),
+            vec![
+                RequestType::inferred(0),
+                RequestType::inferred(1),
+                RequestType::inferred(2)
+            ]
+        );
+        let sizes: Vec<usize> = classifier.clusters.iter().map(|c| c.size).collect();
+        assert_eq!(sizes, vec![4, 2, 1]);
+        assert_eq!(classifier.classify(&traces[3]), RequestType::inferred(0));
+        assert_eq!(classifier.classify(&traces[4]), RequestType::inferred(1));
+        assert_eq!(classifier.classify(&traces[6]), RequestType::inferred(2));
+        assert_eq!(
+            classifier.classify(&calls(
+                "web:request",
+                &["upload:write", "auth:check", "db:query"]
+            )),
+            RequestType::inferred(0)
+        );
+        assert_eq!(
+            classifier.classify(&calls("web:request", &["metrics:push"])),
+            RequestType::UNKNOWN
+        );
+        assert_eq!(
+            classifier.tracepoints(),
+            tracepoints(&[
+                "web:request",
+                "auth:check",
+                "db:query",
+                "upload:write",
+                "cli:run"
+            ])
+        );
+    }
+}
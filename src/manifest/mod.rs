@@ -10,6 +10,7 @@ All rights reserved.
 //!
 //! Manifest has one SearchSpace per request type, and mostly relays functions to the relevant
 //! SearchSpace.
+mod inference;
 mod searchspace;
 
 use std::collections::HashMap;
@@ -25,19 +26,24 @@ use petgraph::visit::NodeRef;
 use serde::{Deserialize, Serialize};
 
 use pythia_common::RequestType;
-use pythia_common::REQUEST_TYPE_REGEXES;
+use pythia_common::RequestTypeRules;
 
 use crate::grouping::Group;
 use crate::manifest::searchspace::SearchSpace;
 use crate::trace::Trace;
 use crate::trace::TracepointID;
 
+pub use crate::manifest::inference::RequestTypeClassifier;
+pub use crate::manifest::inference::TopHierarchy;
 pub use crate::manifest::searchspace::HierarchicalCriticalPath;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Manifest {
     pub per_request_type: HashMap<RequestType, SearchSpace>,
     pub request_type_tracepoints: Vec<TracepointID>,
+    /// Infers request types of traces that have none
+    #[serde(default)]
+    pub classifier: Option<RequestTypeClassifier>,
 }
 
 impl Manifest {
@@ -60,7 +66,7 @@ impl Manifest {
 
     pub fn find_matches<'a>(&'a self, group: &Group) -> Vec<&'a HierarchicalCriticalPath> {
         let now = Instant::now();
-        let matches = if group.request_type == RequestType::Unknown {
+        let matches = if group.request_type == RequestType::UNKNOWN {
             let mut result = Vec::new();
             for ss in self.per_request_type.values() {
                 result.extend(ss.find_matches(group, false).iter());
@@ -103,8 +109,9 @@ impl Manifest {
             .find_matches(group, true);
         if matches.len() == 0 {
             panic!(
-                "Found no match for {}:\n{}",
-                group.traces[0].g.base_id, group
+                "Found no match for {:?}:\n{}",
+                group.exemplars.items(),
+                group
             );
         }
         now.elapsed()
@@ -114,10 +121,11 @@ impl Manifest {
         Manifest {
             per_request_type: HashMap::new(),
             request_type_tracepoints: Vec::new(),
+            classifier: None,
         }
     }
 
-    pub fn from_trace_list(traces: &Vec<Trace>) -> Manifest {
+    pub fn from_trace_list(traces: &Vec<Trace>, request_types: &RequestTypeRules) -> Manifest {
         let mut map = HashMap::<RequestType, SearchSpace>::new();
         for trace in traces {
             match map.get_mut(&trace.request_type) {
@@ -134,24 +142,39 @@ impl Manifest {
         let mut result = Manifest {
             per_request_type: map,
             request_type_tracepoints: Vec::new(),
+            classifier: None,
         };
-        result.add_request_type_tracepoints(traces);
+        result.add_request_type_tracepoints(traces, request_types);
         result
     }
 
-    fn add_request_type_tracepoints(&mut self, traces: &Vec<Trace>) {
+    fn add_request_type_tracepoints(
+        &mut self,
+        traces: &Vec<Trace>,
+        request_types: &RequestTypeRules,
+    ) {
         for trace in traces {
             self.request_type_tracepoints.extend(
                 trace
                     .g
                     .node_references()
                     .map(|x| x.weight().tracepoint_id.to_string())
-                    .filter(|x: &String| REQUEST_TYPE_REGEXES.is_match(x))
+                    .filter(|x: &String| request_types.is_request_type_tracepoint(x))
                     .map(|x| TracepointID::from_str(&x)),
             );
         }
     }
 
+    /// Sets the request type of a trace that has none using the classifier, if there is one
+    pub fn classify(&self, trace: &mut Trace) {
+        if trace.request_type != RequestType::UNKNOWN {
+            return;
+        }
+        if let Some(classifier) = &self.classifier {
+            trace.request_type = classifier.classify(trace);
+        }
+    }
+
     pub fn to_file(&self, file: &Path) {
         let writer = std::fs::File::create(file).unwrap();
         serde_json::to_writer(writer, self).ok();
@@ -178,6 +201,9 @@ impl Manifest {
             }
         }
         result.extend(self.request_type_tracepoints.iter());
+        if let Some(classifier) = &self.classifier {
+            result.extend(classifier.tracepoints());
+        }
         result.iter().cloned().collect()
     }
 }
@@ -188,6 +214,9 @@ impl Display for Manifest {
         for (request_type, inner) in &self.per_request_type {
             write!(f, "{:?} manifest:\n{}", request_type, inner).unwrap();
         }
+        if let Some(classifier) = &self.classifier {
+            write!(f, "{}", classifier).unwrap();
+        }
         Ok(())
     }
 }
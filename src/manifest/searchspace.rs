@@ -86,19 +86,20 @@ impl HierarchicalCriticalPath {
         CriticalPath::all_possible_paths(trace).map(|x| HierarchicalCriticalPath::from_path(&x))
     }
 
-    /// Copies critical path and then adds hierarchical edges
+    /// Copies critical path and then adds hierarchical edges. The nodes are only matched by
+    /// tracepoint, so they don't keep any key-values.
     pub fn from_path(path: &CriticalPath) -> Self {
         let mut g = StableGraph::new();
         // Add all nodes and happens before edges to the graph
         let mut prev_path_node = path.start_node;
-        let mut prev_node = g.add_node(TraceNode::from_event(&path.g.g[prev_path_node]));
+        let mut prev_node = g.add_node(TraceNode::from_event(&path.g.g[prev_path_node], &[]));
         let start_node = prev_node;
         loop {
             let cur_path_node = match path.next_node(prev_path_node) {
                 Some(node) => node,
                 None => break,
             };
-            let new_node = g.add_node(TraceNode::from_event(&path.g.g[cur_path_node]));
+            let new_node = g.add_node(TraceNode::from_event(&path.g.g[cur_path_node], &[]));
             g.add_edge(
                 prev_node,
                 new_node,
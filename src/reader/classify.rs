@@ -0,0 +1,106 @@
+/*
+This source code is licensed under the BSD-style license found in the
+LICENSE file in the root directory of this source tree.
+
+Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
+All rights reserved.
+*/
+
+//! A reader that sets the request type of the traces read by the reader of the application,
+//! using the request types defined in the configuration.
+
+use pythia_common::RequestType;
+use pythia_common::RequestTypeRules;
+
+use crate::reader::Reader;
+use crate::reader::ReaderError;
+use crate::settings::Settings;
+use crate::trace::Trace;
+
+pub struct RequestTypeReader {
+    reader: Box<dyn Reader>,
+    rules: RequestTypeRules,
+}
+
+impl RequestTypeReader {
+    pub fn from_settings(settings: &Settings, reader: Box<dyn Reader>) -> Self {
+        RequestTypeReader {
+            reader,
+            rules: settings.request_types.clone(),
+        }
+    }
+
+    fn classify(&self, trace: &mut Trace) {
+        let tracepoints: Vec<String> = trace
+            .g
+            .node_indices()
+            .map(|n| trace.g[n].tracepoint_id.to_string())
+            .collect();
+        let root = trace
+            .g
+            .node_weight(trace.start_node)
+            .map(|e| e.tracepoint_id.to_string())
+            .unwrap_or_default();
+        let g = &trace.g;
+        trace.request_type =
+            self.rules
+                .classify(tracepoints.iter().map(|t| t.as_str()), &root, |key| {
+                    g.node_indices()
+                        .filter_map(|n| g[n].key_value_pair.get(key))
+                        .map(|v| v.to_string())
+                        .collect::<Vec<_>>()
+                });
+    }
+
+    fn classify_all(&self, mut traces: Vec<Trace>) -> Vec<Trace> {
+        for trace in traces.iter_mut() {
+            self.classify(trace);
+        }
+        traces
+    }
+}
+
+impl Reader for RequestTypeReader {
+    fn for_searchspace(&mut self) {
+        self.reader.for_searchspace();
+    }
+
+    fn reset_state(&mut self) {
+        self.reader.reset_state();
+    }
+
+    fn read_file(&mut self, filename: &str) -> Result<Trace, ReaderError> {
+        let mut trace = self.reader.read_file(filename)?;
+        self.classify(&mut trace);
+        Ok(trace)
+    }
+
+    fn read_traces(&mut self, filename: &str) -> Result<Vec<Trace>, ReaderError> {
+        let traces = self.reader.read_traces(filename)?;
+        Ok(self.classify_all(traces))
+    }
+
+    fn read_dir(&mut self, foldername: &str) -> Result<Vec<Trace>, ReaderError> {
+        let traces = self.reader.read_dir(foldername)?;
+        Ok(self.classify_all(traces))
+    }
+
+    fn get_trace_from_base_id(&mut self, id: &str) -> Result<Trace, ReaderError> {
+        let mut trace = self.reader.get_trace_from_base_id(id)?;
+        self.classify(&mut trace);
+        if trace.request_type == RequestType::UNKNOWN && !self.rules.rules().is_empty() {
+            eprintln!("Warning: couldn't get type for request {}", id);
+        }
+        Ok(trace)
+    }
+
+    fn get_recent_traces(&mut self) -> Vec<Trace> {
+        let traces = self.reader.get_recent_traces();
+        self.classify_all(traces)
+    }
+
+    fn read_trace_file(&mut self, tracefile: &str) -> Result<Vec<Trace>, ReaderError> {
+        let traces = self.reader.read_trace_file(tracefile)?;
+        Ok(self.classify_all(traces))
+    }
+}
@@ -8,38 +8,150 @@ All rights reserved.
 
 //! This module contains a Reader trait, which reads traces.
 
-mod hdfs;
-mod deathstar;
+mod classify;
 mod osprofiler;
+mod otel;
+mod skew;
 mod uber;
+mod watch;
+mod xtrace;
+mod xtrace_client;
+mod zipkin;
 
 use std::error::Error;
 use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
 
+use chrono::NaiveDateTime;
+
+use futures::future;
+use futures::future::Future;
+use futures::stream::Stream;
+use futures::Async;
 use hex;
+use hyper::rt;
+use hyper::Client;
 use itertools::Itertools;
 use serde::de;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::reader::hdfs::HDFSReader;
-use crate::reader::deathstar::DEATHSTARReader;
+use crate::reader::classify::RequestTypeReader;
 use crate::reader::osprofiler::OSProfilerReader;
+use crate::reader::otel::OTelReader;
 use crate::reader::uber::UberReader;
+use crate::reader::watch::DirectoryReader;
+use crate::reader::xtrace::XTraceReader;
+use crate::reader::zipkin::ZipkinReader;
 use crate::settings::ApplicationType;
 use crate::settings::Settings;
 use crate::trace::Trace;
 
+/// Why a trace could not be read
+#[derive(Debug)]
+pub enum ReaderError {
+    /// Reading a file or talking to a server failed
+    Io(std::io::Error),
+    /// The input is not in a format the reader understands
+    Parse(String),
+    /// The trace is missing events, e.g., the parent of an event or the end of a span
+    Incomplete(String),
+    /// Parts of the trace are not connected to each other
+    Disjoint(String),
+}
+
+impl fmt::Display for ReaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReaderError::Io(e) => write!(f, "I/O error: {}", e),
+            ReaderError::Parse(s) => write!(f, "Parse error: {}", s),
+            ReaderError::Incomplete(s) => write!(f, "Incomplete trace: {}", s),
+            ReaderError::Disjoint(s) => write!(f, "Disjoint trace: {}", s),
+        }
+    }
+}
+
+impl Error for ReaderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReaderError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for ReaderError {
+    fn from(e: std::io::Error) -> Self {
+        ReaderError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for ReaderError {
+    fn from(e: serde_json::Error) -> Self {
+        if e.is_io() {
+            ReaderError::Io(e.into())
+        } else {
+            ReaderError::Parse(e.to_string())
+        }
+    }
+}
+
+impl From<hex::FromHexError> for ReaderError {
+    fn from(e: hex::FromHexError) -> Self {
+        ReaderError::Parse(e.to_string())
+    }
+}
+
+impl From<uuid::Error> for ReaderError {
+    fn from(e: uuid::Error) -> Self {
+        ReaderError::Parse(e.to_string())
+    }
+}
+
+impl From<std::num::ParseIntError> for ReaderError {
+    fn from(e: std::num::ParseIntError) -> Self {
+        ReaderError::Parse(e.to_string())
+    }
+}
+
+/// Time between two events of a trace, which fails if the trace goes back in time
+fn duration_between(from: NaiveDateTime, to: NaiveDateTime) -> Result<Duration, ReaderError> {
+    (to - from)
+        .to_std()
+        .map_err(|_| ReaderError::Parse(format!("Event at {} comes before {}", to, from)))
+}
+
 pub trait Reader {
     /// The file can contain a trace json, written by serde or by the tracing
     /// infrastructure
-    fn read_file(&mut self, filename: &str) -> Trace;
+    fn read_file(&mut self, filename: &str) -> Result<Trace, ReaderError>;
+    fn get_trace_from_base_id(&mut self, id: &str) -> Result<Trace, ReaderError>;
+    /// Reads all traces in a file. Readers whose files can hold more than one trace override
+    /// this.
+    fn read_traces(&mut self, filename: &str) -> Result<Vec<Trace>, ReaderError> {
+        Ok(vec![self.read_file(filename)?])
+    }
+
     /// The folder contains files that may include trace jsons, written by serde
-    /// or by the tracing infrastructure
-    fn read_dir(&mut self, foldername: &str) -> Vec<Trace>;
-    fn get_trace_from_base_id(&mut self, id: &str) -> Result<Trace, Box<dyn Error>>;
+    /// or by the tracing infrastructure. Files that can't be read are skipped.
+    fn read_dir(&mut self, foldername: &str) -> Result<Vec<Trace>, ReaderError> {
+        let mut results = Vec::new();
+        for entry in std::fs::read_dir(foldername)? {
+            let path = entry?.path();
+            eprintln!("Reading {}", path.to_string_lossy());
+            match self.read_traces(&path.to_string_lossy()) {
+                Ok(t) => results.extend(t),
+                Err(e) => {
+                    eprintln!("Skipping {}: {}", path.to_string_lossy(), e);
+                }
+            }
+        }
+        Ok(results)
+    }
 
-    /// This function collects new traces that have finished.
+    /// This function collects new traces that have finished. Traces that can't be read are
+    /// skipped.
     ///
     /// It is called multiple times for OpenStack, which collects traces in the first
     /// call and returns traces whose duration did not change in the second call.
@@ -52,9 +164,9 @@ pub trait Reader {
     /// this function indicates this Reader will be used for search space
     fn for_searchspace(&mut self);
 
-    /// Read a file with one request ID per line
-    fn read_trace_file(&mut self, tracefile: &str) -> Vec<Trace> {
-        let trace_ids = std::fs::read_to_string(tracefile).unwrap();
+    /// Read a file with one request ID per line. Requests that can't be read are skipped.
+    fn read_trace_file(&mut self, tracefile: &str) -> Result<Vec<Trace>, ReaderError> {
+        let trace_ids = std::fs::read_to_string(tracefile)?;
         let mut traces = Vec::new();
         for id in trace_ids.split('\n') {
             if id.len() <= 1 {
@@ -66,21 +178,87 @@ pub trait Reader {
                     traces.push(t);
                 }
                 Err(e) => {
-                    eprintln!("Failed with {:?}", e);
+                    eprintln!("Failed with {}", e);
                 }
             }
         }
-        traces
+        Ok(traces)
     }
 }
 
 /// Constructor for Reader
 pub fn reader_from_settings(settings: &Settings) -> Box<dyn Reader> {
-    match &settings.application {
+    let reader: Box<dyn Reader> = match &settings.application {
         ApplicationType::OpenStack => Box::new(OSProfilerReader::from_settings(settings)),
-        ApplicationType::HDFS => Box::new(HDFSReader::from_settings(settings)),
-        ApplicationType::DEATHSTAR => Box::new(DEATHSTARReader::from_settings(settings)),
+        ApplicationType::HDFS | ApplicationType::DEATHSTAR | ApplicationType::XTrace => {
+            Box::new(XTraceReader::from_settings(settings))
+        }
         ApplicationType::Uber => Box::new(UberReader::from_settings(settings)),
+        ApplicationType::OpenTelemetry => Box::new(OTelReader::from_settings(settings)),
+        ApplicationType::Zipkin => Box::new(ZipkinReader::from_settings(settings)),
+    };
+    let reader: Box<dyn Reader> = Box::new(RequestTypeReader::from_settings(settings, reader));
+    // Recent traces come from files dropped into a directory instead
+    match &settings.watch_dir {
+        Some(_) => Box::new(DirectoryReader::from_settings(settings, reader)),
+        None => reader,
+    }
+}
+
+/// Percent-encodes a path segment or query value of a URL, keeping only unreserved characters
+fn percent_encode(value: &str) -> String {
+    let mut result = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                result.push(byte as char)
+            }
+            _ => result.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    result
+}
+
+/// Blocking HTTP GET used by the readers that talk to a trace collector
+fn download_webpage(urn: String) -> Result<String, ReaderError> {
+    let uri: hyper::Uri = urn
+        .parse()
+        .map_err(|_| ReaderError::Parse(format!("Invalid URL {}", urn)))?;
+    let (tx, mut rx) = futures::sync::mpsc::unbounded();
+
+    let fut = future::lazy(move || {
+        let err_tx = tx.clone();
+        Client::new()
+            .get(uri)
+            .and_then(|res| res.into_body().concat2())
+            .map(move |body| {
+                tx.unbounded_send(Ok(String::from_utf8_lossy(&body).to_string()))
+                    .ok();
+            })
+            .map_err(move |e| {
+                err_tx.unbounded_send(Err(e.to_string())).ok();
+            })
+    });
+    rt::run(fut);
+    let failed = |reason: String| {
+        ReaderError::Io(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!("Could not get {}: {}", urn, reason),
+        ))
+    };
+    loop {
+        match rx.poll() {
+            Ok(Async::Ready(Some(Ok(s)))) => {
+                return Ok(s);
+            }
+            Ok(Async::Ready(Some(Err(e)))) => {
+                return Err(failed(e));
+            }
+            Ok(Async::NotReady) => {}
+            Ok(Async::Ready(None)) | Err(_) => {
+                return Err(failed("no response".into()));
+            }
+        }
     }
 }
 
@@ -105,13 +283,20 @@ impl HexID {
         format!("{:02x}", self.id.unwrap().iter().format(""))
     }
 
-    pub fn from_str(id: &str) -> Self {
-        let mut buf: [u8; 8] = [0; 8];
-        let decoded = hex::decode(id).unwrap();
-        for i in 0..8 {
-            buf[i] = decoded[i];
+}
+
+/// Ids longer than 64 bits are truncated
+impl FromStr for HexID {
+    type Err = ReaderError;
+
+    fn from_str(id: &str) -> Result<Self, Self::Err> {
+        let decoded = hex::decode(id)?;
+        if decoded.len() < 8 {
+            return Err(ReaderError::Parse(format!("Id {} is shorter than 64 bits", id)));
         }
-        HexID { id: Some(buf) }
+        let mut buf: [u8; 8] = [0; 8];
+        buf.copy_from_slice(&decoded[..8]);
+        Ok(HexID { id: Some(buf) })
     }
 }
 
@@ -140,10 +325,30 @@ impl<'de> de::Visitor<'de> for HexIDVisitor {
         if value == "0" {
             return Ok(HexID { id: None });
         }
-        let decoded = hex::decode(value).unwrap();
-        let mut result = [0; 8];
-        let decoded = &decoded[..result.len()];
-        result.copy_from_slice(decoded);
-        Ok(HexID { id: Some(result) })
+        HexID::from_str(value)
+            .map_err(|_| de::Error::invalid_value(de::Unexpected::Str(value), &self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hex_ids() {
+        let id = HexID::from_str("0123456789abcdef").unwrap();
+        assert_eq!(id.to_string(), "0123456789abcdef");
+        assert_eq!(HexID::from_str("0123456789abcdef0011").unwrap(), id);
+        assert!(HexID::from_str("0123").is_err());
+        assert!(HexID::from_str("not hex").is_err());
+        let unset: HexID = serde_json::from_str("\"0\"").unwrap();
+        assert_eq!(unset.to_uuid(), Uuid::nil());
+        assert!(serde_json::from_str::<HexID>("\"01\"").is_err());
+    }
+
+    #[test]
+    fn encodes_urls() {
+        assert_eq!(percent_encode("frontend-1.a_b~"), "frontend-1.a_b~");
+        assert_eq!(percent_encode("a b&c=d/é"), "a%20b%26c%3Dd%2F%C3%A9");
     }
 }
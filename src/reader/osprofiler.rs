@@ -11,7 +11,6 @@ All rights reserved.
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::error::Error;
 use std::time::Duration;
 
 use petgraph::graph::NodeIndex;
@@ -23,14 +22,15 @@ use pythia_common::osprofiler::ExitEnum;
 use pythia_common::AnnotationEnum;
 use pythia_common::OSProfilerEnum;
 use pythia_common::OSProfilerSpan;
-use pythia_common::RequestType;
-use pythia_common::REQUEST_TYPES;
-use pythia_common::REQUEST_TYPE_REGEXES;
 
 use crate::critical::CriticalPath;
+use crate::reader::duration_between;
+use crate::reader::skew::correct_skew;
 use crate::reader::Reader;
+use crate::reader::ReaderError;
 
 use crate::rpclib::free_keys;
+use crate::rpclib::get_clock_offset;
 use crate::rpclib::get_events_from_client;
 use crate::settings::Settings;
 use crate::trace::Event;
@@ -38,9 +38,7 @@ use crate::trace::EventType;
 use crate::trace::Trace;
 use crate::trace::TracepointID;
 use crate::trace::{DAGEdge, EdgeType};
-use crate::PythiaError;
 
-use crate::trace::Value::SignedInt;
 use crate::trace::Value::UnsignedInt;
 //use crate::trace::Value::float;
 use crate::trace::Value::Str;
@@ -52,6 +50,9 @@ pub struct OSProfilerReader {
     trace_error_count: HashMap<String, usize>,
     for_searchspace: bool,
     free_keys: bool,
+    skew_correction: bool,
+    /// Clock offsets reported by the agents of each host, in nanoseconds
+    clock_offsets: HashMap<String, i64>,
 }
 
 impl Reader for OSProfilerReader {
@@ -150,14 +151,10 @@ impl Reader for OSProfilerReader {
         traces
     }
 
-    fn read_file(&mut self, file: &str) -> Trace {
-        let reader = std::fs::File::open(file).unwrap();
-        let t: Vec<OSProfilerSpan> = serde_json::from_reader(reader).unwrap();
-        self.from_event_list(Uuid::nil(), t).unwrap()
-    }
-
-    fn read_dir(&mut self, _foldername: &str) -> Vec<Trace> {
-        Vec::new()
+    fn read_file(&mut self, file: &str) -> Result<Trace, ReaderError> {
+        let reader = std::fs::File::open(file)?;
+        let t: Vec<OSProfilerSpan> = serde_json::from_reader(reader)?;
+        self.from_event_list(Uuid::nil(), t)
     }
     /*
 
@@ -264,30 +261,29 @@ impl Reader for OSProfilerReader {
         result
     }*/
 
-    fn get_trace_from_base_id(&mut self, id: &str) -> Result<Trace, Box<dyn Error>> {
+    fn get_trace_from_base_id(&mut self, id: &str) -> Result<Trace, ReaderError> {
         eprintln!("Working on {}", id);
-        let mut result = match Uuid::parse_str(id) {
-            Ok(uuid) => {
-                let event_list = self.get_all_matches(&uuid);
-                if event_list.len() == 0 {
-                    return Err(Box::new(PythiaError(
-                        format!("No traces match the uuid {}", uuid).into(),
-                    )));
-                }
-                let dag = self.from_event_list(Uuid::parse_str(id).unwrap(), event_list)?;
-                dag
-            }
+        let uuid = match Uuid::parse_str(id) {
+            Ok(uuid) => uuid,
             Err(_) => {
-                panic!("Malformed UUID received as base ID: {}", id);
+                return Err(ReaderError::Parse(format!(
+                    "Malformed UUID received as base ID: {}",
+                    id
+                )));
             }
         };
-        if result.request_type == RequestType::Unknown {
-            eprintln!("Warning: couldn't get type for request {}", id);
+        let event_list = self.get_all_matches(&uuid);
+        if event_list.len() == 0 {
+            return Err(ReaderError::Incomplete(format!(
+                "No traces match the uuid {}",
+                uuid
+            )));
         }
-        result.duration = (result.g[result.end_node].timestamp
-            - result.g[result.start_node].timestamp)
-            .to_std()
-            .unwrap();
+        let mut result = self.from_event_list(uuid, event_list)?;
+        result.duration = duration_between(
+            result.g[result.start_node].timestamp,
+            result.g[result.end_node].timestamp,
+        )?;
         Ok(result)
     }
 }
@@ -297,6 +293,16 @@ impl OSProfilerReader {
         let redis_url = &settings.redis_url;
         let client = redis::Client::open(&redis_url[..]).unwrap();
         let con = client.get_connection().unwrap();
+        // Offsets are measured once, clocks don't drift much during a run
+        let clock_offsets = if settings.agent_clock_offsets {
+            settings
+                .pythia_clients
+                .iter()
+                .filter_map(|c| get_clock_offset(c))
+                .collect()
+        } else {
+            HashMap::new()
+        };
         OSProfilerReader {
             connection: con,
             client_list: settings.pythia_clients.clone(),
@@ -304,6 +310,8 @@ impl OSProfilerReader {
             trace_error_count: HashMap::new(),
             for_searchspace: false,
             free_keys: settings.free_keys,
+            skew_correction: settings.skew_correction,
+            clock_offsets,
         }
     }
 
@@ -320,18 +328,44 @@ impl OSProfilerReader {
         &mut self,
         id: Uuid,
         mut event_list: Vec<OSProfilerSpan>,
-    ) -> Result<Trace, Box<dyn Error>> {
+    ) -> Result<Trace, ReaderError> {
         let mut mydag = Trace::new(&id);
-        self.add_events(&mut mydag, &mut event_list, None)?;
+        let mut async_events = self.get_async_events(&event_list);
+        if self.skew_correction {
+            correct_skew(&mut event_list, &mut async_events, &self.clock_offsets);
+        }
+        self.add_events(&mut mydag, &mut event_list, None, &mut async_events)?;
+        mydag.mark_errors();
         Ok(mydag)
     }
 
+    /// Events of the asynchronous traces that the events start, and that those start in turn,
+    /// by the id of the trace
+    fn get_async_events(
+        &mut self,
+        event_list: &[OSProfilerSpan],
+    ) -> HashMap<Uuid, Vec<OSProfilerSpan>> {
+        let mut result = HashMap::new();
+        let mut to_fetch = async_children(event_list);
+        while let Some(id) = to_fetch.pop() {
+            if result.contains_key(&id) {
+                continue;
+            }
+            let events = self.get_all_matches(&id);
+            to_fetch.extend(async_children(&events));
+            result.insert(id, events);
+        }
+        result
+    }
+
+    /// `async_events` has the events of the asynchronous traces, see `get_async_events`
     fn add_events(
         &mut self,
         mut dag: &mut Trace,
         event_list: &mut Vec<OSProfilerSpan>,
         mut parent_of_trace: Option<NodeIndex>,
-    ) -> Result<Option<NodeIndex>, Box<dyn Error>> {
+        async_events: &mut HashMap<Uuid, Vec<OSProfilerSpan>>,
+    ) -> Result<Option<NodeIndex>, ReaderError> {
         if event_list.len() == 0 {
             return Ok(None);
         }
@@ -352,30 +386,40 @@ impl OSProfilerReader {
         let mut wait_spans = HashSet::<Uuid>::new();
         let mut add_next_to_waiters = false;
         let mut wait_for = Vec::<Uuid>::new();
+        // When the waiter started waiting for the traces in `wait_for`
+        let mut wait_start = None;
         let mut nidx = None;
         let mut prev_nidx = None;
         let mut prev_time = start_time;
         for (idx, event) in event_list.iter().enumerate() {
-            assert!(event.base_id == base_id);
-            assert!(prev_time <= event.timestamp);
+            if event.base_id != base_id {
+                return Err(ReaderError::Parse(format!(
+                    "Event {} has base id {}, expected {}",
+                    event.trace_id, event.base_id, base_id
+                )));
+            }
+            if prev_time > event.timestamp {
+                return Err(ReaderError::Parse(format!(
+                    "Events of {} are not sorted",
+                    base_id
+                )));
+            }
             prev_time = event.timestamp;
             let mut mynode = Event::from_osp_span(event);
-            let current_tracepoint_id = event.get_tracepoint_id(&mut tracepoint_id_map)?;
+            let current_tracepoint_id = event
+                .get_tracepoint_id(&mut tracepoint_id_map)
+                .map_err(|e| ReaderError::Incomplete(e.to_string()))?;
             mynode.tracepoint_id = TracepointID::from_str(&current_tracepoint_id);
-            if mynode.variant == EventType::Entry {
-                let matches: Vec<usize> = REQUEST_TYPE_REGEXES
-                    .matches(&current_tracepoint_id)
-                    .iter()
-                    .collect();
-                if matches.len() > 0 {
-                    assert!(matches.len() == 1);
-                    dag.request_type = REQUEST_TYPES[matches[0]];
-                }
-            }
             // Don't add asynch_wait into the DAGs
             nidx = match &event.info {
                 OSProfilerEnum::Annotation(AnnotationEnum::WaitFor(w)) => {
                     wait_for.push(w.wait_for);
+                    wait_start.get_or_insert(event.timestamp);
+                    None
+                }
+                OSProfilerEnum::Annotation(AnnotationEnum::WaitForKeyValue(w)) => {
+                    wait_for.push(w.wait_for);
+                    wait_start.get_or_insert(event.timestamp);
                     None
                 }
                 OSProfilerEnum::Annotation(AnnotationEnum::Child(_)) => None,
@@ -403,7 +447,7 @@ impl OSProfilerReader {
             }
             if let OSProfilerEnum::Annotation(s) = &event.info {
                 match &s {
-                    AnnotationEnum::WaitFor(_) => {
+                    AnnotationEnum::WaitFor(_) | AnnotationEnum::WaitForKeyValue(_) => {
                         wait_spans.insert(event.trace_id);
                     }
                     AnnotationEnum::Child(c) => {
@@ -414,9 +458,9 @@ impl OSProfilerReader {
                                 None => match parent_of_trace {
                                     Some(nidx) => nidx,
                                     None => {
-                                        return Err(Box::new(PythiaError(
+                                        return Err(ReaderError::Incomplete(
                                             "No parent, start with annotation".into(),
-                                        )))
+                                        ))
                                     }
                                 },
                             },
@@ -430,9 +474,10 @@ impl OSProfilerReader {
                     parent_of_trace.unwrap(),
                     nidx.unwrap(),
                     DAGEdge {
-                        duration: (event.timestamp - dag.g[parent_of_trace.unwrap()].timestamp)
-                            .to_std()
-                            .unwrap(),
+                        duration: duration_between(
+                            dag.g[parent_of_trace.unwrap()].timestamp,
+                            event.timestamp,
+                        )?,
                         variant: EdgeType::FollowsFrom,
                     },
                 );
@@ -442,6 +487,14 @@ impl OSProfilerReader {
                 for waiter in wait_for.iter() {
                     waiters.insert(*waiter, nidx.unwrap());
                 }
+                // The waiter reports how long it was blocked, see `CriticalPath::wait_times`
+                if let (Some(start), Some(waiter)) = (wait_start.take(), nidx) {
+                    let blocked = duration_between(start, event.timestamp)?;
+                    dag.g[waiter].key_value_pair.insert(
+                        "blocked_ns".to_string(),
+                        UnsignedInt(blocked.as_nanos() as u64),
+                    );
+                }
                 wait_for = vec![];
                 add_next_to_waiters = false;
             }
@@ -450,17 +503,17 @@ impl OSProfilerReader {
                     active_spans.insert(event.trace_id, nidx.unwrap());
                     children_per_parent.insert(event.trace_id, None);
                     if event.parent_id == event.base_id {
-                        match children_per_parent.get(&event.parent_id).unwrap() {
+                        match parent_state(&children_per_parent, &event.parent_id)? {
                             Some(sibling_id) => {
-                                let sibling_node = id_map.get(sibling_id).unwrap();
+                                let sibling_node = &node_of(&id_map, &sibling_id)?;
                                 dag.g.add_edge(
                                     *sibling_node,
                                     nidx.unwrap(),
                                     DAGEdge {
-                                        duration: (event.timestamp
-                                            - dag.g[*sibling_node].timestamp)
-                                            .to_std()
-                                            .unwrap(),
+                                        duration: duration_between(
+                                            dag.g[*sibling_node].timestamp,
+                                            event.timestamp,
+                                        )?,
                                         variant: EdgeType::ChildOf,
                                     },
                                 );
@@ -471,29 +524,29 @@ impl OSProfilerReader {
                         match children_per_parent.get(&event.parent_id) {
                             Some(result) => match result {
                                 Some(sibling_id) => {
-                                    let sibling_node = id_map.get(sibling_id).unwrap();
+                                    let sibling_node = &node_of(&id_map, &sibling_id)?;
                                     dag.g.add_edge(
                                         *sibling_node,
                                         nidx.unwrap(),
                                         DAGEdge {
-                                            duration: (event.timestamp
-                                                - dag.g[*sibling_node].timestamp)
-                                                .to_std()
-                                                .unwrap(),
+                                            duration: duration_between(
+                                                dag.g[*sibling_node].timestamp,
+                                                event.timestamp,
+                                            )?,
                                             variant: EdgeType::ChildOf,
                                         },
                                     );
                                 }
                                 None => {
-                                    let parent_node = id_map.get(&event.parent_id).unwrap();
+                                    let parent_node = &node_of(&id_map, &event.parent_id)?;
                                     dag.g.add_edge(
                                         *parent_node,
                                         nidx.unwrap(),
                                         DAGEdge {
-                                            duration: (event.timestamp
-                                                - dag.g[*parent_node].timestamp)
-                                                .to_std()
-                                                .unwrap(),
+                                            duration: duration_between(
+                                                dag.g[*parent_node].timestamp,
+                                                event.timestamp,
+                                            )?,
                                             variant: EdgeType::ChildOf,
                                         },
                                     );
@@ -501,14 +554,10 @@ impl OSProfilerReader {
                             },
                             None => {
                                 // Parent has finished execution before child starts - shouldn't happen
-                                let parent_node = &dag.g[match id_map.get(&event.parent_id) {
-                                    Some(&nidx) => nidx,
-                                    None => {
-                                        panic!("Warning: Parent of node {:?} not found. Silently ignoring this event", event);
-                                    }
-                                }];
-                                assert!(event.timestamp > parent_node.timestamp);
-                                panic!("Parent of node {:?} not found: {:?}", event, parent_node);
+                                return Err(ReaderError::Incomplete(format!(
+                                    "Parent of {} is not active",
+                                    event.trace_id
+                                )));
                             }
                         }
                     }
@@ -518,17 +567,17 @@ impl OSProfilerReader {
                         None => {
                             // Don't add wait for annotations
                         }
-                        Some(nidx) => match children_per_parent.get(&event.parent_id).unwrap() {
+                        Some(nidx) => match parent_state(&children_per_parent, &event.parent_id)? {
                             Some(sibling_id) => {
-                                let sibling_node = id_map.get(sibling_id).unwrap();
+                                let sibling_node = &node_of(&id_map, &sibling_id)?;
                                 dag.g.add_edge(
                                     *sibling_node,
                                     nidx,
                                     DAGEdge {
-                                        duration: (event.timestamp
-                                            - dag.g[*sibling_node].timestamp)
-                                            .to_std()
-                                            .unwrap(),
+                                        duration: duration_between(
+                                            dag.g[*sibling_node].timestamp,
+                                            event.timestamp,
+                                        )?,
                                         variant: EdgeType::ChildOf,
                                     },
                                 );
@@ -537,15 +586,15 @@ impl OSProfilerReader {
                                 // If idx == 0, annotation is the first node and the edge is added in
                                 // add_async
                                 if idx != 0 {
-                                    let parent_node = id_map.get(&event.parent_id).unwrap();
+                                    let parent_node = &node_of(&id_map, &event.parent_id)?;
                                     dag.g.add_edge(
                                         *parent_node,
                                         nidx,
                                         DAGEdge {
-                                            duration: (event.timestamp
-                                                - dag.g[*parent_node].timestamp)
-                                                .to_std()
-                                                .unwrap(),
+                                            duration: duration_between(
+                                                dag.g[*parent_node].timestamp,
+                                                event.timestamp,
+                                            )?,
                                             variant: EdgeType::ChildOf,
                                         },
                                     );
@@ -558,17 +607,28 @@ impl OSProfilerReader {
                     if nidx.is_none() {
                         add_next_to_waiters = true;
                     } else {
-                        let start_span = active_spans.remove(&event.trace_id).unwrap();
-                        match children_per_parent.remove(&event.trace_id).unwrap() {
+                        let start_span = match active_spans.remove(&event.trace_id) {
+                            Some(nidx) => nidx,
+                            None => {
+                                return Err(ReaderError::Incomplete(format!(
+                                    "Span {} ends without starting",
+                                    event.trace_id
+                                )));
+                            }
+                        };
+                        let last_child = parent_state(&children_per_parent, &event.trace_id)?;
+                        children_per_parent.remove(&event.trace_id);
+                        match last_child {
                             Some(child_id) => {
-                                let child_node = id_map.get(&child_id).unwrap();
+                                let child_node = &node_of(&id_map, &child_id)?;
                                 dag.g.add_edge(
                                     *child_node,
                                     nidx.unwrap(),
                                     DAGEdge {
-                                        duration: (event.timestamp - dag.g[*child_node].timestamp)
-                                            .to_std()
-                                            .unwrap(),
+                                        duration: duration_between(
+                                            dag.g[*child_node].timestamp,
+                                            event.timestamp,
+                                        )?,
                                         variant: EdgeType::ChildOf,
                                     },
                                 );
@@ -578,9 +638,10 @@ impl OSProfilerReader {
                                     start_span,
                                     nidx.unwrap(),
                                     DAGEdge {
-                                        duration: (event.timestamp - dag.g[start_span].timestamp)
-                                            .to_std()
-                                            .unwrap(),
+                                        duration: duration_between(
+                                            dag.g[start_span].timestamp,
+                                            event.timestamp,
+                                        )?,
                                         variant: EdgeType::ChildOf,
                                     },
                                 );
@@ -597,7 +658,7 @@ impl OSProfilerReader {
             }
         }
         for (trace_id, parent) in async_traces.iter() {
-            let last_node = self.add_asynch(&mut dag, trace_id, *parent)?;
+            let last_node = self.add_asynch(&mut dag, trace_id, *parent, async_events)?;
             if last_node.is_none() {
                 continue;
             }
@@ -608,9 +669,10 @@ impl OSProfilerReader {
                         last_node,
                         **parent,
                         DAGEdge {
-                            duration: (dag.g[**parent].timestamp - dag.g[last_node].timestamp)
-                                .to_std()
-                                .unwrap(),
+                            duration: duration_between(
+                                dag.g[last_node].timestamp,
+                                dag.g[**parent].timestamp,
+                            )?,
                             variant: EdgeType::FollowsFrom,
                         },
                     );
@@ -626,12 +688,46 @@ impl OSProfilerReader {
         mut dag: &mut Trace,
         trace_id: &Uuid,
         parent: NodeIndex,
-    ) -> Result<Option<NodeIndex>, Box<dyn Error>> {
-        let mut event_list = self.get_all_matches(trace_id);
+        async_events: &mut HashMap<Uuid, Vec<OSProfilerSpan>>,
+    ) -> Result<Option<NodeIndex>, ReaderError> {
+        let mut event_list = async_events.remove(trace_id).unwrap_or_default();
         if event_list.len() == 0 {
             return Ok(None);
         }
-        self.add_events(&mut dag, &mut event_list, Some(parent))
+        self.add_events(&mut dag, &mut event_list, Some(parent), async_events)
+    }
+}
+
+/// Ids of the asynchronous traces that the events start
+fn async_children(event_list: &[OSProfilerSpan]) -> Vec<Uuid> {
+    event_list
+        .iter()
+        .filter_map(|e| match &e.info {
+            OSProfilerEnum::Annotation(AnnotationEnum::Child(c)) => Some(c.child_id),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Node of an event that is already added, which is missing if the trace is incomplete
+fn node_of(id_map: &HashMap<Uuid, NodeIndex>, id: &Uuid) -> Result<NodeIndex, ReaderError> {
+    match id_map.get(id) {
+        Some(&nidx) => Ok(nidx),
+        None => Err(ReaderError::Incomplete(format!("No event for {}", id))),
+    }
+}
+
+/// Latest finished child of an active span
+fn parent_state(
+    children_per_parent: &HashMap<Uuid, Option<Uuid>>,
+    id: &Uuid,
+) -> Result<Option<Uuid>, ReaderError> {
+    match children_per_parent.get(id) {
+        Some(&child) => Ok(child),
+        None => Err(ReaderError::Incomplete(format!(
+            "Span {} is not active",
+            id
+        ))),
     }
 }
 
@@ -652,51 +748,67 @@ fn sort_event_list(event_list: &mut Vec<OSProfilerSpan>) {
 }
 
 impl Event {
-    //adding key-value pairs to Event as a HashMap depending on the EventType and which pairs are available
+    /// Keeps everything OSProfiler reports about the event as key-value pairs, except ids
     fn from_osp_span(event: &OSProfilerSpan) -> Event {
         let mut map = HashMap::new();
+        map.insert("host".to_string(), Str(event.host().to_string()));
+        let mut thread = |thread_id: u64, pid: u64| {
+            map.insert("thread_id".to_string(), UnsignedInt(thread_id));
+            map.insert("pid".to_string(), UnsignedInt(pid));
+        };
         match &event.info {
-            OSProfilerEnum::FunctionEntry(function_entry_info) => {
-                let host = Str((&function_entry_info.host).to_string());
-                map.insert("host".to_string(), host);
+            OSProfilerEnum::FunctionEntry(i) => {
+                thread(i.thread_id, i.pid);
+                map.insert("function".to_string(), Str(i.function.name.clone()));
+            }
+            OSProfilerEnum::RequestEntry(i) => {
+                thread(i.thread_id, i.pid);
+                map.insert("path".to_string(), Str(i.request.path.clone()));
+                map.insert("scheme".to_string(), Str(i.request.scheme.clone()));
+                map.insert("method".to_string(), Str(i.request.method.clone()));
+                map.insert("query".to_string(), Str(i.request.query.clone()));
             }
-            OSProfilerEnum::RequestEntry(request_entry_info) => {
-                let host = Str((&request_entry_info.host).to_string());
-                map.insert("host".to_string(), host);
+            OSProfilerEnum::Exit(ExitEnum::Normal(_)) => {}
+            OSProfilerEnum::Exit(ExitEnum::Error(i)) => {
+                map.insert("error_type".to_string(), Str(i.etype.clone()));
+                map.insert("error_message".to_string(), Str(i.message.clone()));
             }
-            OSProfilerEnum::Exit(ExitEnum::Normal(normal_entry_info)) => {
-                let host = Str((&normal_entry_info.host).to_string());
-                map.insert("host".to_string(), host);
+            OSProfilerEnum::Annotation(AnnotationEnum::KeyValue(i)) => {
+                thread(i.thread_id, i.pid);
+                map.insert("lock_queue".to_string(), UnsignedInt(i.value));
             }
-            OSProfilerEnum::Exit(ExitEnum::Error(error_entry_info)) => {
-                let host = Str((&error_entry_info.host).to_string());
-                map.insert("host".to_string(), host);
+            OSProfilerEnum::Annotation(AnnotationEnum::WaitFor(i)) => {
+                thread(i.thread_id, i.pid);
+                map.insert("function".to_string(), Str(i.function.name.clone()));
+                map.insert("wait_for".to_string(), Str(i.wait_for.to_string()));
             }
-            OSProfilerEnum::Annotation(AnnotationEnum::KeyValue(key_value_annotation_info)) => {
-                let host = Str((&key_value_annotation_info.host).to_string());
-                let val = UnsignedInt(key_value_annotation_info.value);
-                map.insert("lock_queue".to_string(), val);
-                map.insert("host".to_string(), host);
+            OSProfilerEnum::Annotation(AnnotationEnum::Child(i)) => {
+                thread(i.thread_id, i.pid);
+                map.insert("child_id".to_string(), Str(i.child_id.to_string()));
             }
-            OSProfilerEnum::Annotation(AnnotationEnum::WaitFor(wait_for_annotation_info)) => {
-                let host = Str((&wait_for_annotation_info.host).to_string());
-                map.insert("host".to_string(), host);
+            OSProfilerEnum::Annotation(AnnotationEnum::Plain(i)) => {
+                thread(i.thread_id, i.pid);
             }
-            OSProfilerEnum::Annotation(AnnotationEnum::Child(child_annotation_info)) => {
-                let host = Str((&child_annotation_info.host).to_string());
-                map.insert("host".to_string(), host);
+            OSProfilerEnum::Annotation(AnnotationEnum::Log(i)) => {
+                thread(i.thread_id, i.pid);
+                map.insert("msg".to_string(), Str(i.msg.clone()));
             }
-            OSProfilerEnum::Annotation(AnnotationEnum::Plain(plain_annotation_info)) => {
-                let host = Str((&plain_annotation_info.host).to_string());
-                map.insert("host".to_string(), host);
+            OSProfilerEnum::Annotation(AnnotationEnum::Args(i)) => {
+                thread(i.thread_id, i.pid);
+                map.insert("function".to_string(), Str(i.function.name.clone()));
+                map.insert("args".to_string(), Str(i.function.args.clone()));
+                map.insert("kwargs".to_string(), Str(i.function.kwargs.clone()));
             }
-            OSProfilerEnum::Annotation(AnnotationEnum::Log(log_annotation_info)) => {
-                let host = Str((&log_annotation_info.host).to_string());
-                map.insert("host".to_string(), host);
+            OSProfilerEnum::Annotation(AnnotationEnum::Results(i)) => {
+                map.insert("result".to_string(), Str(i.function.result.clone()));
+            }
+            OSProfilerEnum::Annotation(AnnotationEnum::WaitForKeyValue(i)) => {
+                thread(i.thread_id, i.pid);
+                map.insert("wait_for".to_string(), Str(i.wait_for.to_string()));
+                map.insert("function".to_string(), Str(i.function.name.clone()));
+                map.insert("args".to_string(), Str(i.function.args.clone()));
+                map.insert("kwargs".to_string(), Str(i.function.kwargs.clone()));
             }
-            OSProfilerEnum::Annotation(AnnotationEnum::Results(_result_info)) => {}
-            OSProfilerEnum::Annotation(AnnotationEnum::Args(_args_info)) => {}
-            OSProfilerEnum::Annotation(AnnotationEnum::WaitForKeyValue(_wait_for_kv_info)) => {}
         }
         Event {
             trace_id: event.trace_id,
@@ -711,6 +823,10 @@ impl Event {
             },
             is_synthetic: false,
             key_value_pair: map,
+            is_error: match event.info {
+                OSProfilerEnum::Exit(ExitEnum::Error(_)) => true,
+                _ => false,
+            },
         }
     }
 }
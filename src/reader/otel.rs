@@ -0,0 +1,1017 @@
+/*
+This source code is licensed under the BSD-style license found in the
+LICENSE file in the root directory of this source tree.
+
+Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
+All rights reserved.
+*/
+
+//! Reader for OpenTelemetry trace exports.
+//!
+//! The files are OTLP exports, either OTLP/JSON (one `ExportTraceServiceRequest` per line, as
+//! written by the collector's file exporter) or a binary protobuf `ExportTraceServiceRequest`.
+//! A single export can contain spans of many traces, so spans are grouped by trace id first.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::path::Path;
+use std::path::PathBuf;
+
+use byteorder::BigEndian;
+use byteorder::ByteOrder;
+use chrono::NaiveDateTime;
+use petgraph::graph::NodeIndex;
+use prost::Message;
+use serde::de;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::reader::duration_between;
+use crate::reader::HexID;
+use crate::reader::Reader;
+use crate::reader::ReaderError;
+use crate::settings::Settings;
+use crate::trace::Event;
+use crate::trace::EventType;
+use crate::trace::Trace;
+use crate::trace::TracepointID;
+use crate::trace::Value;
+use crate::trace::{DAGEdge, EdgeType};
+
+/// File extensions that are read as binary protobuf, everything else is read as JSON
+const PROTOBUF_EXTENSIONS: [&str; 3] = ["pb", "binpb", "protobuf"];
+
+pub struct OTelReader {
+    otel_trace_dir: PathBuf,
+}
+
+impl Reader for OTelReader {
+    fn for_searchspace(&mut self) {}
+    fn reset_state(&mut self) {}
+
+    fn read_file(&mut self, filename: &str) -> Result<Trace, ReaderError> {
+        let mut traces = self.read_traces(filename)?;
+        if traces.len() != 1 {
+            return Err(ReaderError::Parse(format!(
+                "Expected one trace in {}, found {}",
+                filename,
+                traces.len()
+            )));
+        }
+        Ok(traces.remove(0))
+    }
+
+    /// Looks for `<id>.json` or `<id>.pb` in the trace directory
+    fn get_trace_from_base_id(&mut self, id: &str) -> Result<Trace, ReaderError> {
+        for extension in ["json", PROTOBUF_EXTENSIONS[0]].iter() {
+            let mut path = self.otel_trace_dir.clone();
+            path.push(id);
+            path.set_extension(extension);
+            if path.exists() {
+                eprintln!("Reading {}", path.to_string_lossy());
+                return self.read_file(&path.to_string_lossy());
+            }
+        }
+        Err(ReaderError::Io(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("No trace file for {} in {:?}", id, self.otel_trace_dir),
+        )))
+    }
+
+    fn get_recent_traces(&mut self) -> Vec<Trace> {
+        Vec::new()
+    }
+
+    /// Unlike other readers, a single file can produce multiple traces here
+    fn read_traces(&mut self, filename: &str) -> Result<Vec<Trace>, ReaderError> {
+        let path = Path::new(filename);
+        let bytes = std::fs::read(path)?;
+        let is_protobuf = match path.extension() {
+            Some(ext) => PROTOBUF_EXTENSIONS
+                .iter()
+                .any(|&p| ext.to_string_lossy() == p),
+            None => false,
+        };
+        let spans = if is_protobuf {
+            spans_from_protobuf(&bytes)?
+        } else {
+            // We either have a saved file, or an OTLP export
+            if let Ok(t) = serde_json::from_slice::<Trace>(&bytes) {
+                return Ok(vec![t]);
+            }
+            spans_from_json(&bytes)?
+        };
+        let mut per_trace = BTreeMap::<[u8; 16], Vec<OTelSpan>>::new();
+        for span in spans {
+            per_trace.entry(span.trace_id).or_default().push(span);
+        }
+        let mut traces = Vec::new();
+        for (trace_id, spans) in per_trace.iter() {
+            match self.trace_from_spans(trace_id, spans) {
+                Ok(t) => traces.push(t),
+                // A file with a single trace is bad, so the caller gets the error
+                Err(e) if per_trace.len() == 1 => return Err(e),
+                Err(e) => {
+                    eprintln!("Skipping trace {}: {}", Uuid::from_bytes(*trace_id), e);
+                }
+            }
+        }
+        Ok(traces)
+    }
+}
+
+impl OTelReader {
+    pub fn from_settings(settings: &Settings) -> Self {
+        OTelReader {
+            otel_trace_dir: settings.otel_trace_dir.clone(),
+        }
+    }
+
+    /// Convert spans to entry/exit events, and span events to annotations
+    fn to_events(&self, spans: &[OTelSpan]) -> Vec<OTelEvent> {
+        let span_ids = spans.iter().map(|s| s.span_id).collect::<HashSet<_>>();
+        let parents = spans
+            .iter()
+            .map(|s| (s.span_id, s.parent_span_id))
+            .collect::<HashMap<_, _>>();
+        let mut events = Vec::new();
+        for span in spans {
+            // Remote parents that were not exported with this trace are ignored
+            let parent = match span.parent_span_id {
+                Some(p) if span_ids.contains(&p) => Some(span_id_to_uuid(p)),
+                _ => None,
+            };
+            let mut depth = 0;
+            let mut cur = span.parent_span_id;
+            while let Some(p) = cur {
+                if depth > spans.len() {
+                    break;
+                }
+                depth += 1;
+                cur = parents.get(&p).cloned().flatten();
+            }
+            let tracepoint_id = TracepointID::from_str(&span.tracepoint_name());
+            let mut entry_kv = span.attributes.clone();
+            entry_kv.extend(span.resource.iter().map(|(k, v)| (k.clone(), v.clone())));
+            events.push(OTelEvent {
+                e: Event {
+                    trace_id: span_id_to_uuid(span.span_id),
+                    tracepoint_id,
+                    timestamp: convert_otel_timestamp(span.start_time),
+                    is_synthetic: false,
+                    variant: EventType::Entry,
+                    key_value_pair: entry_kv,
+                    is_error: false,
+                },
+                span_id: span_id_to_uuid(span.span_id),
+                parent_id: parent,
+                links: span
+                    .links
+                    .iter()
+                    .filter(|l| span_ids.contains(*l))
+                    .map(|&l| span_id_to_uuid(l))
+                    .collect(),
+                depth,
+            });
+            for (idx, span_event) in span.events.iter().enumerate() {
+                let mut kv = span_event.attributes.clone();
+                kv.extend(span.resource.iter().map(|(k, v)| (k.clone(), v.clone())));
+                events.push(OTelEvent {
+                    e: Event {
+                        trace_id: span_event_to_uuid(span.span_id, idx),
+                        tracepoint_id: TracepointID::from_str(&format!(
+                            "{}:{}",
+                            span.tracepoint_name(),
+                            span_event.name
+                        )),
+                        timestamp: convert_otel_timestamp(span_event.time),
+                        is_synthetic: false,
+                        variant: EventType::Annotation,
+                        key_value_pair: kv,
+                        is_error: false,
+                    },
+                    span_id: span_id_to_uuid(span.span_id),
+                    parent_id: parent,
+                    links: Vec::new(),
+                    depth,
+                });
+            }
+            let mut exit_kv = span.resource.clone();
+            if let Some(message) = &span.error_message {
+                exit_kv.insert("error_message".to_string(), Value::Str(message.clone()));
+            }
+            events.push(OTelEvent {
+                e: Event {
+                    trace_id: span_id_to_uuid(span.span_id),
+                    tracepoint_id,
+                    timestamp: convert_otel_timestamp(span.end_time),
+                    is_synthetic: false,
+                    variant: EventType::Exit,
+                    key_value_pair: exit_kv,
+                    is_error: span.is_error,
+                },
+                span_id: span_id_to_uuid(span.span_id),
+                parent_id: parent,
+                links: Vec::new(),
+                depth,
+            });
+        }
+        events
+    }
+
+    /// Async spans, e.g., `CONSUMER` spans, can start after their parent ended. They follow from
+    /// the exit of the parent.
+    fn trace_from_spans(
+        &self,
+        trace_id: &[u8; 16],
+        spans: &[OTelSpan],
+    ) -> Result<Trace, ReaderError> {
+        let mut mydag = Trace::new(&Uuid::from_bytes(*trace_id));
+        let mut event_list = self.to_events(spans);
+        sort_event_list(&mut event_list);
+        // Latest node in the context of each active span: its entry, last annotation, or the
+        // exit of its last finished child
+        let mut latest_in_span = HashMap::<Uuid, NodeIndex>::new();
+        // Latest node of each span we saw, used as the source of links
+        let mut span_nodes = HashMap::<Uuid, NodeIndex>::new();
+        let mut last_nidx: Option<NodeIndex> = None;
+        for event in event_list.iter() {
+            let (prev_nidx, variant) = match event.e.variant {
+                EventType::Entry => match &event.parent_id {
+                    Some(p) => match (latest_in_span.get(p), span_nodes.get(p)) {
+                        (Some(&nidx), _) => (Some(nidx), EdgeType::ChildOf),
+                        (None, Some(&exit)) => (Some(exit), EdgeType::FollowsFrom),
+                        (None, None) => {
+                            return Err(ReaderError::Incomplete(format!(
+                                "Span {} starts before its parent",
+                                event.span_id
+                            )));
+                        }
+                    },
+                    None => (last_nidx, EdgeType::ChildOf),
+                },
+                EventType::Annotation | EventType::Exit => {
+                    match latest_in_span.get(&event.span_id) {
+                        Some(&nidx) => (Some(nidx), EdgeType::ChildOf),
+                        None => {
+                            return Err(ReaderError::Parse(format!(
+                                "Span {} has events outside of its duration",
+                                event.span_id
+                            )));
+                        }
+                    }
+                }
+            };
+            let nidx = mydag.g.add_node(event.e.clone());
+            if let Some(p) = prev_nidx {
+                mydag.g.add_edge(
+                    p,
+                    nidx,
+                    DAGEdge {
+                        duration: duration_between(mydag.g[p].timestamp, event.e.timestamp)?,
+                        variant,
+                    },
+                );
+            }
+            for link in event.links.iter() {
+                let &linked = match span_nodes.get(link) {
+                    Some(l) => l,
+                    None => continue,
+                };
+                if Some(linked) == prev_nidx || mydag.g[linked].timestamp > event.e.timestamp {
+                    continue;
+                }
+                mydag.g.add_edge(
+                    linked,
+                    nidx,
+                    DAGEdge {
+                        duration: duration_between(mydag.g[linked].timestamp, event.e.timestamp)?,
+                        variant: EdgeType::FollowsFrom,
+                    },
+                );
+            }
+            match event.e.variant {
+                EventType::Entry | EventType::Annotation => {
+                    latest_in_span.insert(event.span_id, nidx);
+                }
+                EventType::Exit => {
+                    latest_in_span.remove(&event.span_id);
+                    if let Some(p) = &event.parent_id {
+                        if latest_in_span.contains_key(p) {
+                            latest_in_span.insert(*p, nidx);
+                        }
+                    }
+                }
+            }
+            span_nodes.insert(event.span_id, nidx);
+            if last_nidx.is_none() {
+                mydag.start_node = nidx;
+            }
+            last_nidx = Some(nidx);
+        }
+        mydag.end_node = match last_nidx {
+            Some(nidx) => nidx,
+            None => return Err(ReaderError::Incomplete("Trace has no spans".into())),
+        };
+        mydag.duration = duration_between(
+            mydag.g[mydag.start_node].timestamp,
+            mydag.g[mydag.end_node].timestamp,
+        )?;
+        mydag.mark_errors();
+        Ok(mydag)
+    }
+}
+
+/// Sorts events by timestamp. On ties, entries come first (outer spans before inner ones),
+/// then annotations, then exits (inner spans before outer ones).
+fn sort_event_list(event_list: &mut [OTelEvent]) {
+    event_list.sort_by_key(|e| {
+        let (rank, depth) = match e.e.variant {
+            EventType::Entry => (0, e.depth as i64),
+            EventType::Annotation => (1, 0),
+            EventType::Exit => (2, -(e.depth as i64)),
+        };
+        (e.e.timestamp, rank, depth)
+    });
+}
+
+fn convert_otel_timestamp(nanos: u64) -> NaiveDateTime {
+    NaiveDateTime::from_timestamp(
+        (nanos / 1_000_000_000) as i64,
+        (nanos % 1_000_000_000) as u32,
+    )
+}
+
+fn span_id_to_uuid(span_id: [u8; 8]) -> Uuid {
+    HexID { id: Some(span_id) }.to_uuid()
+}
+
+/// Span events don't have ids, so we make one from the span id and the event's position
+fn span_event_to_uuid(span_id: [u8; 8], idx: usize) -> Uuid {
+    let mut buf = [0; 16];
+    buf[..8].copy_from_slice(&span_id);
+    BigEndian::write_u64(&mut buf[8..], idx as u64 + 1);
+    Uuid::from_bytes(buf)
+}
+
+/// Empty and all-zero ids mean that the id is not set
+fn parse_id<T>(bytes: &[u8]) -> Result<Option<T>, ReaderError>
+where
+    for<'a> &'a [u8]: TryInto<T>,
+{
+    if bytes.iter().all(|&b| b == 0) {
+        return Ok(None);
+    }
+    match bytes.try_into() {
+        Ok(id) => Ok(Some(id)),
+        Err(_) => Err(ReaderError::Parse(format!(
+            "Id has wrong length: {}",
+            hex::encode(bytes)
+        ))),
+    }
+}
+
+fn parse_hex_id<T>(id: &str) -> Result<Option<T>, ReaderError>
+where
+    for<'a> &'a [u8]: TryInto<T>,
+{
+    parse_id(&hex::decode(id)?)
+}
+
+/// Span representation shared by the JSON and protobuf formats
+#[derive(Debug, Clone)]
+struct OTelSpan {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+    parent_span_id: Option<[u8; 8]>,
+    name: String,
+    service: Option<String>,
+    start_time: u64,
+    end_time: u64,
+    attributes: HashMap<String, Value>,
+    /// Resource attributes that we keep on every event of the span
+    resource: HashMap<String, Value>,
+    events: Vec<OTelSpanEvent>,
+    /// Linked spans in the same trace
+    links: Vec<[u8; 8]>,
+    /// The status of the span is an error, with its message if there is one
+    is_error: bool,
+    error_message: Option<String>,
+}
+
+impl OTelSpan {
+    fn tracepoint_name(&self) -> String {
+        match &self.service {
+            Some(service) => format!("{}/{}", service, self.name),
+            None => self.name.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct OTelSpanEvent {
+    time: u64,
+    name: String,
+    attributes: HashMap<String, Value>,
+}
+
+#[derive(Debug, Clone)]
+struct OTelEvent {
+    e: Event,
+    span_id: Uuid,
+    parent_id: Option<Uuid>,
+    links: Vec<Uuid>,
+    /// Depth of the span in the hierarchy, used to order events with the same timestamp
+    depth: usize,
+}
+
+/// Resource attributes we keep, and the key we store them with
+fn resource_attributes(
+    attributes: HashMap<String, Value>,
+) -> (Option<String>, HashMap<String, Value>) {
+    let service = match attributes.get("service.name") {
+        Some(Value::Str(s)) => Some(s.clone()),
+        _ => None,
+    };
+    let mut result = HashMap::new();
+    if let Some(host) = attributes.get("host.name") {
+        result.insert("host".to_string(), host.clone());
+    }
+    if let Some(s) = &service {
+        result.insert("service".to_string(), Value::Str(s.clone()));
+    }
+    (service, result)
+}
+
+fn spans_from_json(bytes: &[u8]) -> Result<Vec<OTelSpan>, ReaderError> {
+    let mut result = Vec::new();
+    for request in serde_json::Deserializer::from_slice(bytes).into_iter::<OTelExport>() {
+        for resource_spans in request?.resource_spans {
+            let (service, resource) = resource_attributes(match &resource_spans.resource {
+                Some(r) => json_attributes(&r.attributes),
+                None => HashMap::new(),
+            });
+            for scope_spans in resource_spans.scope_spans {
+                for span in scope_spans.spans {
+                    result.push(OTelSpan {
+                        trace_id: parse_hex_id(&span.trace_id)?
+                            .ok_or_else(|| ReaderError::Parse("Span without a trace id".into()))?,
+                        span_id: parse_hex_id(&span.span_id)?
+                            .ok_or_else(|| ReaderError::Parse("Span without a span id".into()))?,
+                        parent_span_id: parse_hex_id(&span.parent_span_id)?,
+                        name: span.name,
+                        service: service.clone(),
+                        start_time: span.start_time_unix_nano,
+                        end_time: span.end_time_unix_nano,
+                        attributes: json_attributes(&span.attributes),
+                        resource: resource.clone(),
+                        events: span
+                            .events
+                            .iter()
+                            .map(|e| OTelSpanEvent {
+                                time: e.time_unix_nano,
+                                name: e.name.clone(),
+                                attributes: json_attributes(&e.attributes),
+                            })
+                            .collect(),
+                        links: span
+                            .links
+                            .iter()
+                            .map(|l| parse_hex_id(&l.span_id))
+                            .collect::<Result<Vec<Option<[u8; 8]>>, _>>()?
+                            .into_iter()
+                            .flatten()
+                            .collect(),
+                        is_error: span.status.code == STATUS_CODE_ERROR,
+                        error_message: Some(span.status.message).filter(|m| !m.is_empty()),
+                    });
+                }
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn json_attributes(attributes: &[OTelKeyValue]) -> HashMap<String, Value> {
+    attributes
+        .iter()
+        .filter_map(|kv| match &kv.value {
+            OTelAnyValue {
+                string_value: Some(s),
+                ..
+            } => Some((kv.key.clone(), Value::Str(s.clone()))),
+            OTelAnyValue {
+                int_value: Some(i), ..
+            } => Some((kv.key.clone(), Value::SignedInt(*i))),
+            OTelAnyValue {
+                bool_value: Some(b),
+                ..
+            } => Some((kv.key.clone(), Value::Bool(*b))),
+            OTelAnyValue {
+                double_value: Some(d),
+                ..
+            } => Some((kv.key.clone(), Value::Float(*d))),
+            _ => None,
+        })
+        .collect()
+}
+
+fn spans_from_protobuf(bytes: &[u8]) -> Result<Vec<OTelSpan>, ReaderError> {
+    let request = proto::ExportTraceServiceRequest::decode(bytes)
+        .map_err(|e| ReaderError::Parse(e.to_string()))?;
+    let mut result = Vec::new();
+    for resource_spans in request.resource_spans {
+        let (service, resource) = resource_attributes(match &resource_spans.resource {
+            Some(r) => proto_attributes(&r.attributes),
+            None => HashMap::new(),
+        });
+        for scope_spans in resource_spans.scope_spans {
+            for span in scope_spans.spans {
+                result.push(OTelSpan {
+                    trace_id: parse_id(&span.trace_id)?
+                        .ok_or_else(|| ReaderError::Parse("Span without a trace id".into()))?,
+                    span_id: parse_id(&span.span_id)?
+                        .ok_or_else(|| ReaderError::Parse("Span without a span id".into()))?,
+                    parent_span_id: parse_id(&span.parent_span_id)?,
+                    name: span.name,
+                    service: service.clone(),
+                    start_time: span.start_time_unix_nano,
+                    end_time: span.end_time_unix_nano,
+                    attributes: proto_attributes(&span.attributes),
+                    resource: resource.clone(),
+                    events: span
+                        .events
+                        .iter()
+                        .map(|e| OTelSpanEvent {
+                            time: e.time_unix_nano,
+                            name: e.name.clone(),
+                            attributes: proto_attributes(&e.attributes),
+                        })
+                        .collect(),
+                    links: span
+                        .links
+                        .iter()
+                        .map(|l| parse_id(&l.span_id))
+                        .collect::<Result<Vec<Option<[u8; 8]>>, _>>()?
+                        .into_iter()
+                        .flatten()
+                        .collect(),
+                    is_error: span.status.as_ref().map(|s| s.code) == Some(STATUS_CODE_ERROR),
+                    error_message: span.status.map(|s| s.message).filter(|m| !m.is_empty()),
+                });
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn proto_attributes(attributes: &[proto::KeyValue]) -> HashMap<String, Value> {
+    use proto::any_value::Value as V;
+    attributes
+        .iter()
+        .filter_map(|kv| {
+            let value = match kv.value.as_ref().and_then(|v| v.value.as_ref()) {
+                Some(V::StringValue(s)) => Value::Str(s.clone()),
+                Some(V::IntValue(i)) => Value::SignedInt(*i),
+                Some(V::BoolValue(b)) => Value::Bool(*b),
+                Some(V::DoubleValue(d)) => Value::Float(*d),
+                _ => return None,
+            };
+            Some((kv.key.clone(), value))
+        })
+        .collect()
+}
+
+/// OTLP/JSON encodes 64-bit integers as strings, but some exporters write numbers
+fn deserialize_u64<'de, D>(d: D) -> Result<u64, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    match StringOrInt::deserialize(d)? {
+        StringOrInt::Int(i) => Ok(i as u64),
+        StringOrInt::Str(s) => s.parse().map_err(de::Error::custom),
+    }
+}
+
+fn deserialize_i64<'de, D>(d: D) -> Result<Option<i64>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    match StringOrInt::deserialize(d)? {
+        StringOrInt::Int(i) => Ok(Some(i)),
+        StringOrInt::Str(s) => s.parse().map(Some).map_err(de::Error::custom),
+    }
+}
+
+/// `Status.code` of failed spans
+const STATUS_CODE_ERROR: i32 = 2;
+
+/// OTLP/JSON encodes enums as numbers, but some exporters write their names
+fn deserialize_status_code<'de, D>(d: D) -> Result<i32, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    match StringOrInt::deserialize(d)? {
+        StringOrInt::Int(i) => Ok(i as i32),
+        StringOrInt::Str(s) => match s.as_str() {
+            "STATUS_CODE_UNSET" => Ok(0),
+            "STATUS_CODE_OK" => Ok(1),
+            "STATUS_CODE_ERROR" => Ok(STATUS_CODE_ERROR),
+            _ => Err(de::Error::custom(format!("Unknown status code {}", s))),
+        },
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StringOrInt {
+    Int(i64),
+    Str(String),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OTelExport {
+    #[serde(default)]
+    resource_spans: Vec<OTelResourceSpans>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OTelResourceSpans {
+    resource: Option<OTelResource>,
+    /// Older exporters call these `instrumentationLibrarySpans`
+    #[serde(default, alias = "instrumentationLibrarySpans")]
+    scope_spans: Vec<OTelScopeSpans>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OTelResource {
+    #[serde(default)]
+    attributes: Vec<OTelKeyValue>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OTelScopeSpans {
+    #[serde(default)]
+    spans: Vec<OTelJsonSpan>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OTelJsonSpan {
+    trace_id: String,
+    span_id: String,
+    #[serde(default)]
+    parent_span_id: String,
+    name: String,
+    #[serde(deserialize_with = "deserialize_u64")]
+    start_time_unix_nano: u64,
+    #[serde(deserialize_with = "deserialize_u64")]
+    end_time_unix_nano: u64,
+    #[serde(default)]
+    attributes: Vec<OTelKeyValue>,
+    #[serde(default)]
+    events: Vec<OTelJsonSpanEvent>,
+    #[serde(default)]
+    links: Vec<OTelJsonLink>,
+    #[serde(default)]
+    status: OTelJsonStatus,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct OTelJsonStatus {
+    #[serde(default)]
+    message: String,
+    #[serde(default, deserialize_with = "deserialize_status_code")]
+    code: i32,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OTelJsonSpanEvent {
+    #[serde(deserialize_with = "deserialize_u64")]
+    time_unix_nano: u64,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    attributes: Vec<OTelKeyValue>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OTelJsonLink {
+    span_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct OTelKeyValue {
+    key: String,
+    value: OTelAnyValue,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OTelAnyValue {
+    string_value: Option<String>,
+    bool_value: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_i64")]
+    int_value: Option<i64>,
+    double_value: Option<f64>,
+}
+
+/// The subset of the OTLP protobuf definitions we need, see
+/// `opentelemetry/proto/collector/trace/v1/trace_service.proto`
+mod proto {
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ExportTraceServiceRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub resource_spans: Vec<ResourceSpans>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ResourceSpans {
+        #[prost(message, optional, tag = "1")]
+        pub resource: Option<Resource>,
+        /// Also matches the deprecated `instrumentation_library_spans`, which has the same tag
+        #[prost(message, repeated, tag = "2")]
+        pub scope_spans: Vec<ScopeSpans>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Resource {
+        #[prost(message, repeated, tag = "1")]
+        pub attributes: Vec<KeyValue>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct ScopeSpans {
+        #[prost(message, repeated, tag = "2")]
+        pub spans: Vec<Span>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Span {
+        #[prost(bytes, tag = "1")]
+        pub trace_id: Vec<u8>,
+        #[prost(bytes, tag = "2")]
+        pub span_id: Vec<u8>,
+        #[prost(bytes, tag = "4")]
+        pub parent_span_id: Vec<u8>,
+        #[prost(string, tag = "5")]
+        pub name: String,
+        #[prost(fixed64, tag = "7")]
+        pub start_time_unix_nano: u64,
+        #[prost(fixed64, tag = "8")]
+        pub end_time_unix_nano: u64,
+        #[prost(message, repeated, tag = "9")]
+        pub attributes: Vec<KeyValue>,
+        #[prost(message, repeated, tag = "11")]
+        pub events: Vec<SpanEvent>,
+        #[prost(message, repeated, tag = "13")]
+        pub links: Vec<Link>,
+        #[prost(message, optional, tag = "15")]
+        pub status: Option<Status>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Status {
+        #[prost(string, tag = "2")]
+        pub message: String,
+        #[prost(int32, tag = "3")]
+        pub code: i32,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct SpanEvent {
+        #[prost(fixed64, tag = "1")]
+        pub time_unix_nano: u64,
+        #[prost(string, tag = "2")]
+        pub name: String,
+        #[prost(message, repeated, tag = "3")]
+        pub attributes: Vec<KeyValue>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct Link {
+        #[prost(bytes, tag = "1")]
+        pub trace_id: Vec<u8>,
+        #[prost(bytes, tag = "2")]
+        pub span_id: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct KeyValue {
+        #[prost(string, tag = "1")]
+        pub key: String,
+        #[prost(message, optional, tag = "2")]
+        pub value: Option<AnyValue>,
+    }
+
+    #[derive(Clone, PartialEq, ::prost::Message)]
+    pub struct AnyValue {
+        #[prost(oneof = "any_value::Value", tags = "1, 2, 3, 4")]
+        pub value: Option<any_value::Value>,
+    }
+
+    pub mod any_value {
+        /// Named like the protobuf `oneof`
+        #[allow(clippy::enum_variant_names)]
+        #[derive(Clone, PartialEq, ::prost::Oneof)]
+        pub enum Value {
+            #[prost(string, tag = "1")]
+            StringValue(String),
+            #[prost(bool, tag = "2")]
+            BoolValue(bool),
+            #[prost(int64, tag = "3")]
+            IntValue(i64),
+            #[prost(double, tag = "4")]
+            DoubleValue(f64),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACE_ID: &str = "5b8efff798038103d269b633813fc60c";
+
+    fn reader() -> OTelReader {
+        OTelReader {
+            otel_trace_dir: PathBuf::new(),
+        }
+    }
+
+    fn json_span(span_id: &str, parent: &str, name: &str, start: u64, end: u64) -> String {
+        format!(
+            r#"{{"traceId": "{}", "spanId": "{}", "parentSpanId": "{}", "name": "{}",
+                "startTimeUnixNano": "{}", "endTimeUnixNano": "{}"}}"#,
+            TRACE_ID, span_id, parent, name, start, end
+        )
+    }
+
+    fn json_export(spans: &[String]) -> String {
+        format!(
+            r#"{{"resourceSpans": [{{
+                "resource": {{"attributes": [
+                    {{"key": "service.name", "value": {{"stringValue": "nova"}}}},
+                    {{"key": "host.name", "value": {{"stringValue": "node1"}}}}
+                ]}},
+                "scopeSpans": [{{"spans": [{}]}}]
+            }}]}}"#,
+            spans.join(",")
+        )
+    }
+
+    #[test]
+    fn parses_json() {
+        let root = r#"{"traceId": "5b8efff798038103d269b633813fc60c",
+            "spanId": "eee19b7ec3c1b174", "name": "create",
+            "startTimeUnixNano": "1000000000", "endTimeUnixNano": 1500000000,
+            "attributes": [
+                {"key": "http.status_code", "value": {"intValue": "500"}},
+                {"key": "retry", "value": {"boolValue": true}}
+            ],
+            "events": [{"timeUnixNano": "1100000000", "name": "queued"}],
+            "status": {"code": "STATUS_CODE_ERROR", "message": "boom"}}"#;
+        let export = json_export(&[root.to_string()]);
+        let spans = spans_from_json(export.as_bytes()).unwrap();
+        assert_eq!(spans.len(), 1);
+        let span = &spans[0];
+        assert_eq!(hex::encode(span.trace_id), TRACE_ID);
+        assert_eq!(hex::encode(span.span_id), "eee19b7ec3c1b174");
+        assert_eq!(span.parent_span_id, None);
+        assert_eq!(span.tracepoint_name(), "nova/create");
+        assert_eq!(span.start_time, 1_000_000_000);
+        assert_eq!(span.end_time, 1_500_000_000);
+        assert_eq!(
+            span.attributes.get("http.status_code"),
+            Some(&Value::SignedInt(500))
+        );
+        assert_eq!(span.attributes.get("retry"), Some(&Value::Bool(true)));
+        assert_eq!(
+            span.resource.get("host"),
+            Some(&Value::Str("node1".to_string()))
+        );
+        assert_eq!(span.events.len(), 1);
+        assert!(span.is_error);
+        assert_eq!(span.error_message.as_deref(), Some("boom"));
+    }
+
+    #[test]
+    fn builds_json_trace() {
+        let export = json_export(&[
+            json_span("0000000000000001", "", "root", 1000, 5000),
+            json_span("0000000000000002", "0000000000000001", "child", 2000, 3000),
+        ]);
+        let spans = spans_from_json(export.as_bytes()).unwrap();
+        let trace = reader()
+            .trace_from_spans(&spans[0].trace_id, &spans)
+            .unwrap();
+        assert_eq!(trace.g.node_count(), 4);
+        assert_eq!(trace.g.edge_count(), 3);
+        assert_eq!(trace.duration, std::time::Duration::from_nanos(4000));
+        assert_eq!(trace.g[trace.start_node].variant, EventType::Entry);
+        assert_eq!(trace.g[trace.end_node].variant, EventType::Exit);
+    }
+
+    #[test]
+    fn async_span_follows_from_parent_exit() {
+        let export = json_export(&[
+            json_span("0000000000000001", "", "producer", 1000, 2000),
+            json_span(
+                "0000000000000002",
+                "0000000000000001",
+                "consumer",
+                3000,
+                4000,
+            ),
+        ]);
+        let spans = spans_from_json(export.as_bytes()).unwrap();
+        let trace = reader()
+            .trace_from_spans(&spans[0].trace_id, &spans)
+            .unwrap();
+        let follows: Vec<_> = trace
+            .g
+            .edge_indices()
+            .filter(|&e| trace.g[e].variant == EdgeType::FollowsFrom)
+            .collect();
+        assert_eq!(follows.len(), 1);
+        let (source, target) = trace.g.edge_endpoints(follows[0]).unwrap();
+        assert_eq!(trace.g[source].variant, EventType::Exit);
+        assert_eq!(trace.g[target].variant, EventType::Entry);
+        assert_eq!(trace.duration, std::time::Duration::from_nanos(3000));
+    }
+
+    #[test]
+    fn parses_protobuf() {
+        let string_value = |s: &str| proto::AnyValue {
+            value: Some(proto::any_value::Value::StringValue(s.to_string())),
+        };
+        let span = |id: u8, parent: u8, name: &str, start: u64, end: u64| proto::Span {
+            trace_id: hex::decode(TRACE_ID).unwrap(),
+            span_id: vec![0, 0, 0, 0, 0, 0, 0, id],
+            parent_span_id: if parent == 0 {
+                Vec::new()
+            } else {
+                vec![0, 0, 0, 0, 0, 0, 0, parent]
+            },
+            name: name.to_string(),
+            start_time_unix_nano: start,
+            end_time_unix_nano: end,
+            attributes: vec![proto::KeyValue {
+                key: "db.rows".to_string(),
+                value: Some(proto::AnyValue {
+                    value: Some(proto::any_value::Value::IntValue(7)),
+                }),
+            }],
+            events: Vec::new(),
+            links: Vec::new(),
+            status: None,
+        };
+        let mut child = span(2, 1, "query", 2000, 3000);
+        child.status = Some(proto::Status {
+            message: String::new(),
+            code: STATUS_CODE_ERROR,
+        });
+        let request = proto::ExportTraceServiceRequest {
+            resource_spans: vec![proto::ResourceSpans {
+                resource: Some(proto::Resource {
+                    attributes: vec![proto::KeyValue {
+                        key: "service.name".to_string(),
+                        value: Some(string_value("db")),
+                    }],
+                }),
+                scope_spans: vec![proto::ScopeSpans {
+                    spans: vec![span(1, 0, "request", 1000, 5000), child],
+                }],
+            }],
+        };
+        let mut bytes = Vec::new();
+        request.encode(&mut bytes).unwrap();
+        let spans = spans_from_protobuf(&bytes).unwrap();
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[1].parent_span_id, Some([0, 0, 0, 0, 0, 0, 0, 1]));
+        assert_eq!(spans[1].tracepoint_name(), "db/query");
+        assert_eq!(
+            spans[1].attributes.get("db.rows"),
+            Some(&Value::SignedInt(7))
+        );
+        assert!(!spans[0].is_error);
+        assert!(spans[1].is_error);
+        let trace = reader()
+            .trace_from_spans(&spans[0].trace_id, &spans)
+            .unwrap();
+        assert!(trace.is_error);
+        assert_eq!(trace.g.node_count(), 4);
+    }
+
+    #[test]
+    fn rejects_bad_ids() {
+        let export = json_export(&[json_span("0001", "", "root", 1000, 2000)]);
+        assert!(spans_from_json(export.as_bytes()).is_err());
+    }
+}
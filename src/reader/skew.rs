@@ -0,0 +1,389 @@
+/*
+This source code is licensed under the BSD-style license found in the
+LICENSE file in the root directory of this source tree.
+
+Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
+All rights reserved.
+*/
+
+//! Clock skew correction for OSProfiler events collected from different hosts.
+//!
+//! When a span calls into another host, the callee starts after the caller starts and ends
+//! before the caller ends. Each such pair bounds the offset between the two clocks. Starting
+//! from the host where the request starts, we pick for every other host the offset that is within
+//! the bounds and closest to the offset reported by its agent (or zero if there is none), so
+//! hosts whose events are already consistent are left alone.
+//!
+//! Asynchronous traces that a request starts are fetched separately, but their hosts are
+//! shifted by the same offsets as the request's, so they are estimated together. An asynchronous
+//! span starts after the span or annotation that started it, but it can end after it.
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use chrono::Duration;
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+
+use pythia_common::AnnotationEnum;
+use pythia_common::OSProfilerEnum;
+use pythia_common::OSProfilerSpan;
+
+/// OSProfiler timestamps are in microseconds. A callee is moved at least this much after its
+/// caller, so sorting by timestamp keeps them in order.
+const RESOLUTION: i64 = 1000;
+
+/// Bounds of the offset of one host's clock relative to another's, in nanoseconds
+#[derive(Debug, Clone, Copy)]
+struct Bounds {
+    lower: i64,
+    upper: i64,
+}
+
+impl Bounds {
+    fn new() -> Self {
+        Bounds {
+            lower: i64::MIN,
+            upper: i64::MAX,
+        }
+    }
+
+    fn reversed(&self) -> Self {
+        Bounds {
+            lower: self.upper.saturating_neg(),
+            upper: self.lower.saturating_neg(),
+        }
+    }
+
+    /// The offset within the bounds closest to `preferred`. If the bounds contradict each
+    /// other, e.g., because the clocks drifted during the trace, the middle is used.
+    fn pick(&self, preferred: i64) -> i64 {
+        if self.lower <= self.upper {
+            preferred.max(self.lower).min(self.upper)
+        } else {
+            ((self.lower as i128 + self.upper as i128) / 2) as i64
+        }
+    }
+}
+
+/// Start and end of a span, and where it ran
+struct SpanTimes<'a> {
+    host: &'a str,
+    parent_id: Uuid,
+    start: Option<NaiveDateTime>,
+    end: Option<NaiveDateTime>,
+    /// Index of the list of events of the span, 0 for the request and more for its
+    /// asynchronous traces
+    list: usize,
+}
+
+/// Bounds of (offset of second host - offset of first host), with the hosts ordered by name
+#[derive(Default)]
+struct HostBounds<'a> {
+    bounds: HashMap<(&'a str, &'a str), Bounds>,
+}
+
+impl<'a> HostBounds<'a> {
+    /// Narrows the bounds of the offset of `callee` relative to `caller`
+    fn add(&mut self, caller: &'a str, callee: &'a str, b: Bounds) {
+        if caller == callee {
+            return;
+        }
+        let (key, b) = if caller < callee {
+            ((caller, callee), b)
+        } else {
+            ((callee, caller), b.reversed())
+        };
+        let pair = self.bounds.entry(key).or_insert_with(Bounds::new);
+        pair.lower = pair.lower.max(b.lower);
+        pair.upper = pair.upper.min(b.upper);
+    }
+}
+
+/// Estimates how much to add to the timestamps of each host, in nanoseconds, from the events of
+/// a request in `lists[0]` and of its asynchronous traces in the other lists. `reported` has the
+/// offsets reported by agents, which are used where the events don't say otherwise.
+fn estimate_offsets(
+    lists: &[&[OSProfilerSpan]],
+    reported: &HashMap<String, i64>,
+) -> HashMap<String, i64> {
+    let mut spans: HashMap<Uuid, SpanTimes> = HashMap::new();
+    // Annotations that started asynchronous traces, by the id of the trace
+    let mut started: HashMap<Uuid, (&str, NaiveDateTime)> = HashMap::new();
+    for (list, events) in lists.iter().enumerate() {
+        for event in events.iter() {
+            let span = spans.entry(event.trace_id).or_insert(SpanTimes {
+                host: event.host(),
+                parent_id: event.parent_id,
+                start: None,
+                end: None,
+                list,
+            });
+            match &event.info {
+                OSProfilerEnum::FunctionEntry(_) | OSProfilerEnum::RequestEntry(_) => {
+                    span.host = event.host();
+                    span.start = Some(event.timestamp);
+                }
+                OSProfilerEnum::Exit(_) => {
+                    span.end = Some(event.timestamp);
+                }
+                OSProfilerEnum::Annotation(AnnotationEnum::Child(c)) => {
+                    started.insert(c.child_id, (event.host(), event.timestamp));
+                }
+                OSProfilerEnum::Annotation(_) => {}
+            }
+        }
+    }
+
+    let mut bounds = HostBounds::default();
+    for (id, child) in spans.iter() {
+        if let Some(parent) = spans.get(&child.parent_id) {
+            let mut b = Bounds::new();
+            if let (Some(parent_start), Some(child_start)) = (parent.start, child.start) {
+                b.lower = nanos(parent_start - child_start).saturating_add(RESOLUTION);
+            }
+            // Asynchronous spans can outlive their parents
+            if let (Some(parent_end), Some(child_end), true) =
+                (parent.end, child.end, parent.list == child.list)
+            {
+                b.upper = nanos(parent_end - child_end).saturating_sub(RESOLUTION);
+            }
+            bounds.add(parent.host, child.host, b);
+        }
+        if let (Some(&(host, time)), Some(child_start)) = (started.get(id), child.start) {
+            let mut b = Bounds::new();
+            b.lower = nanos(time - child_start).saturating_add(RESOLUTION);
+            bounds.add(host, child.host, b);
+        }
+    }
+
+    let mut neighbors: HashMap<&str, Vec<(&str, Bounds)>> = HashMap::new();
+    for (&(a, b), bound) in bounds.bounds.iter() {
+        neighbors.entry(a).or_default().push((b, *bound));
+        neighbors.entry(b).or_default().push((a, bound.reversed()));
+    }
+    let reported_offset = |host: &str| *reported.get(host).unwrap_or(&0);
+    let mut offsets: HashMap<String, i64> = HashMap::new();
+    // The host where the request starts keeps its clock
+    let root = spans
+        .values()
+        .filter(|s| s.list == 0 && s.start.is_some() && !spans.contains_key(&s.parent_id))
+        .min_by_key(|s| s.start);
+    let first = lists
+        .iter()
+        .flat_map(|l| l.iter())
+        .min_by_key(|e| e.timestamp);
+    let reference = match (root, first) {
+        (Some(span), _) => span.host,
+        (None, Some(e)) => e.host(),
+        (None, None) => return offsets,
+    };
+    offsets.insert(reference.to_string(), reported_offset(reference));
+    let mut to_visit = VecDeque::new();
+    to_visit.push_back(reference);
+    while let Some(host) = to_visit.pop_front() {
+        let offset = offsets[host];
+        for (neighbor, bound) in neighbors.get(host).into_iter().flatten() {
+            if offsets.contains_key(*neighbor) {
+                continue;
+            }
+            let preferred = reported_offset(neighbor) - reported_offset(host);
+            offsets.insert(
+                neighbor.to_string(),
+                offset.saturating_add(bound.pick(preferred)),
+            );
+            to_visit.push_back(*neighbor);
+        }
+    }
+    // Hosts without calls to or from the others only have what their agents report
+    for span in spans.values() {
+        if !offsets.contains_key(span.host) {
+            offsets.insert(span.host.to_string(), reported_offset(span.host));
+        }
+    }
+    offsets
+}
+
+/// Shifts the timestamps of the events of a request, and of the asynchronous traces it started
+/// by their ids, by the estimated offsets of their hosts
+pub fn correct_skew(
+    events: &mut [OSProfilerSpan],
+    async_events: &mut HashMap<Uuid, Vec<OSProfilerSpan>>,
+    reported: &HashMap<String, i64>,
+) {
+    let offsets = {
+        let mut lists: Vec<&[OSProfilerSpan]> = vec![events];
+        lists.extend(async_events.values().map(|l| l.as_slice()));
+        estimate_offsets(&lists, reported)
+    };
+    for event in events
+        .iter_mut()
+        .chain(async_events.values_mut().flat_map(|l| l.iter_mut()))
+    {
+        match offsets.get(event.host()) {
+            Some(&offset) if offset != 0 => {
+                event.timestamp += Duration::nanoseconds(offset);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn nanos(d: Duration) -> i64 {
+    d.num_nanoseconds().unwrap_or(if d < Duration::zero() {
+        i64::MIN
+    } else {
+        i64::MAX
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pythia_common::osprofiler::{
+        ChildAnnotationInfo, ExitEnum, FunctionEntryFunction, FunctionEntryInfo, NormalExitInfo,
+    };
+
+    const MILLI: i64 = 1_000_000;
+
+    fn event(span: Uuid, parent: Uuid, millis: i64, info: OSProfilerEnum) -> OSProfilerSpan {
+        OSProfilerSpan {
+            trace_id: span,
+            parent_id: parent,
+            project: "nova".to_string(),
+            name: "rpc".to_string(),
+            base_id: Uuid::nil(),
+            service: "api".to_string(),
+            tracepoint_id: "nova:rpc".to_string(),
+            timestamp: NaiveDateTime::from_timestamp_opt(1_600_000_000, 0).unwrap()
+                + Duration::milliseconds(millis),
+            info,
+        }
+    }
+
+    /// Entry and exit of a span on `host`, with timestamps from that host's clock
+    fn span(span: Uuid, parent: Uuid, host: &str, start: i64, end: i64) -> Vec<OSProfilerSpan> {
+        let entry = OSProfilerEnum::FunctionEntry(FunctionEntryInfo {
+            function: FunctionEntryFunction {
+                name: "rpc".to_string(),
+            },
+            thread_id: 1,
+            host: host.to_string(),
+            tracepoint_id: "nova:rpc".to_string(),
+            pid: 1,
+        });
+        let exit = OSProfilerEnum::Exit(ExitEnum::Normal(NormalExitInfo {
+            host: host.to_string(),
+        }));
+        vec![
+            event(span, parent, start, entry),
+            event(span, parent, end, exit),
+        ]
+    }
+
+    /// A request on `api` from 0 to 100ms that calls `compute` from 10ms to 90ms and `network`
+    /// from 20ms to 30ms. The clock of `compute` is 50ms behind, the one of `network` is right.
+    fn request() -> Vec<OSProfilerSpan> {
+        let root = Uuid::new_v4();
+        let mut events = span(root, Uuid::new_v4(), "api", 0, 100);
+        events.extend(span(Uuid::new_v4(), root, "compute", -40, 40));
+        events.extend(span(Uuid::new_v4(), root, "network", 20, 30));
+        events
+    }
+
+    #[test]
+    fn bounds_offsets() {
+        let offsets = estimate_offsets(&[&request()], &HashMap::new());
+        assert_eq!(offsets["api"], 0);
+        assert_eq!(offsets["network"], 0);
+        // The callee must start after 0ms and end before 100ms, so the offset is within
+        // (40ms, 60ms). Without a reported offset, the one closest to zero is used.
+        assert_eq!(offsets["compute"], 40 * MILLI + RESOLUTION);
+    }
+
+    #[test]
+    fn prefers_reported_offsets() {
+        let mut reported = HashMap::new();
+        reported.insert("compute".to_string(), 50 * MILLI);
+        let offsets = estimate_offsets(&[&request()], &reported);
+        assert_eq!(offsets["compute"], 50 * MILLI);
+        // Reported offsets outside the bounds are clamped
+        reported.insert("compute".to_string(), 80 * MILLI);
+        let offsets = estimate_offsets(&[&request()], &reported);
+        assert_eq!(offsets["compute"], 60 * MILLI - RESOLUTION);
+    }
+
+    #[test]
+    fn corrects_timestamps() {
+        let mut events = request();
+        correct_skew(&mut events, &mut HashMap::new(), &HashMap::new());
+        let start = events[0].timestamp;
+        let times: Vec<i64> = events.iter().map(|e| nanos(e.timestamp - start)).collect();
+        assert_eq!(
+            times,
+            vec![
+                0,
+                100 * MILLI,
+                RESOLUTION,
+                80 * MILLI + RESOLUTION,
+                20 * MILLI,
+                30 * MILLI
+            ]
+        );
+    }
+
+    /// Annotation on `host` that starts the asynchronous trace `child`
+    fn child(span: Uuid, parent: Uuid, host: &str, millis: i64, child: Uuid) -> OSProfilerSpan {
+        let info = OSProfilerEnum::Annotation(AnnotationEnum::Child(ChildAnnotationInfo {
+            thread_id: 1,
+            host: host.to_string(),
+            tracepoint_id: "nova:cast".to_string(),
+            child_id: child,
+            pid: 1,
+        }));
+        event(span, parent, millis, info)
+    }
+
+    #[test]
+    fn corrects_async_traces() {
+        // The request on `api` from 0 to 100ms calls `compute` from 20ms to 40ms. At 10ms it
+        // starts a trace on `compute` that runs from 50ms to 150ms, after the request ends, and
+        // one on `worker` from 15ms to 25ms. The clock of `compute` is 50ms behind and the one
+        // of `worker` is 100ms behind.
+        let root = Uuid::new_v4();
+        let (on_compute, on_worker) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut events = span(root, Uuid::new_v4(), "api", 0, 100);
+        events.push(child(Uuid::new_v4(), root, "api", 10, on_compute));
+        events.push(child(Uuid::new_v4(), root, "api", 10, on_worker));
+        events.extend(span(Uuid::new_v4(), root, "compute", -30, -10));
+        let mut async_events = HashMap::new();
+        async_events.insert(on_compute, span(on_compute, root, "compute", 0, 100));
+        async_events.insert(
+            on_worker,
+            span(on_worker, Uuid::new_v4(), "worker", -85, -75),
+        );
+
+        let lists: Vec<&[OSProfilerSpan]> = vec![&events, &async_events[&on_compute]];
+        let offsets = estimate_offsets(&lists, &HashMap::new());
+        assert_eq!(offsets["compute"], 30 * MILLI + RESOLUTION);
+
+        let original = events.clone();
+        let original_async = async_events.clone();
+        correct_skew(&mut events, &mut async_events, &HashMap::new());
+        let shift = |before: &OSProfilerSpan, after: &OSProfilerSpan| {
+            nanos(after.timestamp - before.timestamp)
+        };
+        // Hosts are shifted the same in the request and in its asynchronous traces
+        assert_eq!(shift(&original[4], &events[4]), 30 * MILLI + RESOLUTION);
+        for (before, after) in original_async[&on_compute]
+            .iter()
+            .zip(async_events[&on_compute].iter())
+        {
+            assert_eq!(shift(before, after), 30 * MILLI + RESOLUTION);
+        }
+        // Asynchronous traces start after the annotation that starts them
+        let worker_start = async_events[&on_worker][0].timestamp;
+        assert_eq!(nanos(worker_start - events[3].timestamp), RESOLUTION);
+    }
+}
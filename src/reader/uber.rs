@@ -7,10 +7,11 @@ All rights reserved.
 */
 
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::convert::TryInto;
-use std::error::Error;
-use std::fmt;
 use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use chrono::Duration;
 use chrono::NaiveDateTime;
@@ -19,72 +20,178 @@ use petgraph::graph::{Graph, NodeIndex};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::critical::CriticalPath;
+use crate::reader::download_webpage;
+use crate::reader::percent_encode;
+use crate::reader::duration_between;
 use crate::reader::HexID;
 use crate::reader::Reader;
+use crate::reader::ReaderError;
 use crate::settings::Settings;
 use crate::trace::Event;
 use crate::trace::EventType;
 use crate::trace::Trace;
 use crate::trace::TracepointID;
+use crate::trace::Value;
 use crate::trace::{DAGEdge, EdgeType};
 
-#[derive(Debug)]
-struct UberParseError(String);
-
-impl fmt::Display for UberParseError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Uber error: {}", self.0)
-    }
-}
-
-impl Error for UberParseError {}
-
-fn raise(s: &str) -> Box<dyn Error> {
-    // panic!(s.to_string());
-    Box::new(UberParseError(s.into()))
-}
-
 pub struct UberReader {
     uber_trace_dir: PathBuf,
+    /// Jaeger query service, when set traces are polled from it instead of read from files
+    jaeger_url: Option<String>,
+    jaeger_services: Vec<String>,
+    jaeger_lookback: std::time::Duration,
+    jaeger_limit: usize,
+    /// Span count and duration of traces we saw in the last poll, but haven't returned yet
+    prev_traces: HashMap<String, (usize, std::time::Duration)>,
+    trace_error_count: HashMap<String, usize>,
+}
+
+lazy_static! {
+    /// Traces that are returned or skipped, and still within the lookback window. This is shared
+    /// because the controller polls with one reader per worker.
+    static ref PROCESSED_TRACES: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
 }
 
 impl Reader for UberReader {
     fn for_searchspace(&mut self) {}
-    fn reset_state(&mut self) {}
-    fn read_file(&mut self, filename: &str) -> Trace {
-        self.try_read_file(filename).unwrap()
+
+    /// In live mode, everything that is currently in the lookback window is considered old
+    fn reset_state(&mut self) {
+        if self.jaeger_url.is_none() {
+            return;
+        }
+        match self.poll_jaeger() {
+            Ok(traces) => {
+                *PROCESSED_TRACES.lock().unwrap() = traces.keys().cloned().collect();
+            }
+            Err(e) => {
+                eprintln!("Polling Jaeger failed with {:?}", e);
+            }
+        }
+        self.prev_traces.clear();
+        self.trace_error_count.clear();
+    }
+
+    fn read_file(&mut self, filename: &str) -> Result<Trace, ReaderError> {
+        let reader = std::fs::File::open(filename)?;
+        match serde_json::from_reader(reader) {
+            // We either have a saved file, or saved xtrace output
+            Ok(v) => Ok(v),
+            Err(_) => {
+                let reader = std::fs::File::open(filename)?;
+                match serde_json::from_reader::<_, UberTrace>(reader) {
+                    Ok(mut t) => self.from_json(&mut t),
+                    // ... or a trace downloaded from the Jaeger query service
+                    Err(_) => {
+                        let reader = std::fs::File::open(filename)?;
+                        let response: JaegerResponse = serde_json::from_reader(reader)?;
+                        match response.data {
+                            Some(data) if data.len() == 1 => self.from_jaeger(&data[0]),
+                            _ => Err(ReaderError::Parse("Expected exactly one trace".into())),
+                        }
+                    }
+                }
+                // trace.prune();
+            }
+        }
     }
 
-    fn get_trace_from_base_id(&mut self, id: &str) -> Result<Trace, Box<dyn Error>> {
+    fn get_trace_from_base_id(&mut self, id: &str) -> Result<Trace, ReaderError> {
+        if let Some(url) = &self.jaeger_url {
+            let result = download_webpage(format!("{}/api/traces/{}", url, id))?;
+            let response: JaegerResponse = serde_json::from_str(&result)?;
+            let data = response.data.unwrap_or_default();
+            if data.len() != 1 {
+                return Err(ReaderError::Parse(format!(
+                    "Got {} traces for {}",
+                    data.len(),
+                    id
+                )));
+            }
+            return self.from_jaeger(&data[0]);
+        }
         let mut path = self.uber_trace_dir.clone();
         path.push(id);
         path.set_extension("json");
-        eprintln!("Reading {}", path.to_str().unwrap());
-        self.try_read_file(&path.to_str().unwrap())
+        eprintln!("Reading {}", path.to_string_lossy());
+        self.read_file(&path.to_string_lossy())
     }
 
+    /// Only works in live mode. A trace is returned once its span count and duration are the
+    /// same in two consecutive polls, i.e., the request has finished and all spans are reported.
     fn get_recent_traces(&mut self) -> Vec<Trace> {
-        Vec::new()
-    }
+        if self.jaeger_url.is_none() {
+            return Vec::new();
+        }
+        let polled = match self.poll_jaeger() {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("Polling Jaeger failed with {}", e);
+                return Vec::new();
+            }
+        };
+        // Forget about traces that fell out of the lookback window
+        let ids: HashSet<String> = polled.keys().cloned().collect();
+        PROCESSED_TRACES
+            .lock()
+            .unwrap()
+            .retain(|id| ids.contains(id));
+        self.prev_traces.retain(|id, _| ids.contains(id));
+        self.trace_error_count.retain(|id, _| ids.contains(id));
 
-    fn read_dir(&mut self, foldername: &str) -> Vec<Trace> {
-        let mut results = Vec::new();
-        for entry in std::fs::read_dir(foldername).unwrap() {
-            let entry = entry.unwrap();
-            let path = entry.path();
-            eprintln!("Reading {}", path.to_str().unwrap());
-            match self.try_read_file(&path.to_str().unwrap()) {
-                Ok(t) => results.push(t),
+        let mut traces = Vec::new();
+        for (id, data) in polled.iter() {
+            if PROCESSED_TRACES.lock().unwrap().contains(id) {
+                continue;
+            }
+            if *self.trace_error_count.entry(id.clone()).or_insert(0) > 5 {
+                PROCESSED_TRACES.lock().unwrap().insert(id.clone());
+                self.prev_traces.remove(id);
+                eprintln!("Giving up on {}", id);
+                continue;
+            }
+            let t = match self.from_jaeger(data) {
+                Ok(t) => t,
                 Err(e) => {
-                    eprintln!("Parsing failed with {:?}", e);
+                    eprintln!("Could not read {}: {}", id, e);
+                    *self.trace_error_count.get_mut(id).unwrap() += 1;
+                    continue;
                 }
+            };
+            let current = (data.spans.len(), t.duration);
+            // Keep traces for one cycle, use them only when they stop changing
+            let stable = match self.prev_traces.get(id) {
+                Some(&prev) => prev == current,
+                None => false,
+            };
+            if stable {
+                match CriticalPath::from_trace(&t) {
+                    Ok(_) => {
+                        // Another worker might have returned it in the meantime
+                        if PROCESSED_TRACES.lock().unwrap().insert(id.clone()) {
+                            traces.push(t);
+                        }
+                        self.prev_traces.remove(id);
+                        self.trace_error_count.remove(id);
+                    }
+                    Err(_) => {
+                        *self.trace_error_count.get_mut(id).unwrap() += 1;
+                        self.prev_traces.insert(id.clone(), current);
+                    }
+                }
+            } else {
+                self.prev_traces.insert(id.clone(), current);
             }
         }
-        results
+        traces
     }
 }
 
-fn convert_uber_timestamp(start_time: u64, duration: i64) -> (NaiveDateTime, NaiveDateTime) {
+pub(super) fn convert_uber_timestamp(
+    start_time: u64,
+    duration: i64,
+) -> (NaiveDateTime, NaiveDateTime) {
     let duration = Duration::microseconds(duration);
 
     let seconds: i64 = (start_time / 1000000).try_into().unwrap();
@@ -93,6 +200,49 @@ fn convert_uber_timestamp(start_time: u64, duration: i64) -> (NaiveDateTime, Nai
     (start_time, start_time + duration)
 }
 
+/// Entry and exit events of a span. The key-value pairs are only added to the entry. Jaeger and
+/// Zipkin tag failed spans with `error`, which makes the exit an error.
+pub(super) fn span_to_events(
+    span_id: HexID,
+    parent: Option<HexID>,
+    tracepoint_id: TracepointID,
+    start_time: NaiveDateTime,
+    end_time: NaiveDateTime,
+    key_value_pair: HashMap<String, Value>,
+) -> Vec<UberEvent> {
+    let is_error = match key_value_pair.get("error") {
+        Some(Value::Bool(b)) => *b,
+        Some(Value::Str(s)) => s != "false",
+        _ => false,
+    };
+    vec![
+        UberEvent {
+            e: Event {
+                trace_id: span_id.to_uuid(),
+                tracepoint_id,
+                timestamp: start_time,
+                is_synthetic: false,
+                variant: EventType::Entry,
+                key_value_pair,
+                is_error: false,
+            },
+            parent_id: parent,
+        },
+        UberEvent {
+            e: Event {
+                trace_id: span_id.to_uuid(),
+                tracepoint_id,
+                timestamp: end_time,
+                is_synthetic: false,
+                variant: EventType::Exit,
+                key_value_pair: HashMap::new(),
+                is_error,
+            },
+            parent_id: parent,
+        },
+    ]
+}
+
 struct UberParsingState {
     nidx: NodeIndex,
     event: UberEvent,
@@ -105,30 +255,90 @@ impl UberReader {
     pub fn from_settings(settings: &Settings) -> Self {
         UberReader {
             uber_trace_dir: settings.uber_trace_dir.clone(),
+            jaeger_url: settings.jaeger_url.clone(),
+            jaeger_services: settings.jaeger_services.clone(),
+            jaeger_lookback: settings.jaeger_lookback,
+            jaeger_limit: settings.jaeger_limit,
+            prev_traces: HashMap::new(),
+            trace_error_count: HashMap::new(),
         }
     }
 
-    fn try_read_file(&mut self, filename: &str) -> Result<Trace, Box<dyn Error>> {
-        let reader = std::fs::File::open(filename).unwrap();
-        match serde_json::from_reader(reader) {
-            // We either have a saved file, or saved xtrace output
-            Ok(v) => Ok(v),
-            Err(_) => {
-                let reader = std::fs::File::open(filename).unwrap();
-                let mut t: UberTrace = serde_json::from_reader(reader).unwrap();
-                self.from_json(&mut t)
-                // trace.prune();
+    /// Gets the traces of all services in the lookback window, keyed by trace id
+    fn poll_jaeger(&self) -> Result<HashMap<String, JaegerData>, ReaderError> {
+        let url = self.jaeger_url.as_ref().unwrap();
+        let end = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let start = end - self.jaeger_lookback;
+        let mut result: HashMap<String, JaegerData> = HashMap::new();
+        for service in self.jaeger_services.iter() {
+            let page = download_webpage(format!(
+                "{}/api/traces?service={}&start={}&end={}&limit={}",
+                url,
+                percent_encode(service),
+                start.as_micros(),
+                end.as_micros(),
+                self.jaeger_limit
+            ))?;
+            let response: JaegerResponse = serde_json::from_str(&page)?;
+            for data in response.data.unwrap_or_default() {
+                // A trace crossing multiple services is returned for each of them, keep the
+                // most complete copy
+                let replace = match result.get(&data.trace_id) {
+                    Some(prev) => prev.spans.len() < data.spans.len(),
+                    None => true,
+                };
+                if replace {
+                    result.insert(data.trace_id.clone(), data);
+                }
             }
         }
+        Ok(result)
     }
 
-    fn to_events_edges(&self, spans: &Vec<UberSpan>) -> Result<Vec<UberEvent>, Box<dyn Error>> {
+    fn from_jaeger(&self, data: &JaegerData) -> Result<Trace, ReaderError> {
+        let span_ids = data
+            .spans
+            .iter()
+            .map(|s| s.span_id.as_str())
+            .collect::<HashSet<_>>();
+        let mut events = Vec::new();
+        for span in data.spans.iter() {
+            // Prefer CHILD_OF references, and ignore parents outside of the trace
+            let parent = span
+                .references
+                .iter()
+                .filter(|r| span_ids.contains(r.span_id.as_str()))
+                .min_by_key(|r| r.ref_type != "CHILD_OF")
+                .map(|r| jaeger_span_id(&r.span_id))
+                .transpose()?;
+            let mut key_value_pair = span.key_values();
+            let process = span.process_id.as_ref().and_then(|p| data.processes.get(p));
+            if let Some(process) = process {
+                // Tags of the span take precedence over the tags of its process
+                for (key, value) in process.key_values() {
+                    key_value_pair.entry(key).or_insert(value);
+                }
+            }
+            let (start_time, end_time) = convert_uber_timestamp(span.start_time, span.duration);
+            events.extend(span_to_events(
+                jaeger_span_id(&span.span_id)?,
+                parent,
+                TracepointID::from_str(&span.operation_name),
+                start_time,
+                end_time,
+                key_value_pair,
+            ));
+        }
+        UberReader::trace_from_events(&jaeger_trace_id(&data.trace_id)?, events)
+    }
+
+    fn to_events_edges(&self, spans: &Vec<UberSpan>) -> Result<Vec<UberEvent>, ReaderError> {
         let mut events = Vec::new();
         for span in spans {
             let parent = if span.references.len() == 1 {
                 let r = &span.references[0];
                 if r.trace_id != span.trace_id {
-                    return Err(raise(&format!(
+                    return Err(ReaderError::Parse(format!(
                         "Mismatch on trace ids {:?} and {:?}",
                         r.trace_id, span.trace_id
                     )));
@@ -137,40 +347,45 @@ impl UberReader {
             } else if span.references.len() == 0 {
                 None
             } else {
-                return Err(raise(&format!("Got {} references", span.references.len())));
+                return Err(ReaderError::Parse(format!(
+                    "Got {} references",
+                    span.references.len()
+                )));
             };
             let (start_time, end_time) = convert_uber_timestamp(span.start_time, span.duration);
-            events.push(UberEvent {
-                e: Event {
-                    trace_id: span.span_id.to_uuid(),
-                    tracepoint_id: TracepointID::from_str(&span.operation_name.to_string()),
-                    timestamp: start_time,
-                    is_synthetic: false,
-                    variant: EventType::Entry,
-                    key_value_pair: HashMap::new(),
-                },
-                parent_id: parent,
-            });
-            events.push(UberEvent {
-                e: Event {
-                    trace_id: span.span_id.to_uuid(),
-                    tracepoint_id: TracepointID::from_str(&span.operation_name.to_string()),
-                    timestamp: end_time,
-                    is_synthetic: false,
-                    variant: EventType::Exit,
-                    key_value_pair: HashMap::new(),
-                },
-                parent_id: parent,
-            });
+            events.extend(span_to_events(
+                span.span_id,
+                parent,
+                TracepointID::from_str(&span.operation_name.to_string()),
+                start_time,
+                end_time,
+                span.tags.iter().map(|t| t.key_value()).collect(),
+            ));
         }
         Ok(events)
     }
 
-    fn from_json(&self, data: &mut UberTrace) -> Result<Trace, Box<dyn Error>> {
-        assert!(data.data.len() == 1);
+    fn from_json(&self, data: &mut UberTrace) -> Result<Trace, ReaderError> {
+        if data.data.len() != 1 {
+            return Err(ReaderError::Parse(format!(
+                "Expected one trace, found {}",
+                data.data.len()
+            )));
+        }
         let trace = &data.data[0];
-        let mut mydag = Trace::new(&trace.trace_id.to_uuid());
-        let mut event_list = self.to_events_edges(&trace.spans)?;
+        let event_list = self.to_events_edges(&trace.spans)?;
+        UberReader::trace_from_events(&trace.trace_id.to_uuid(), event_list)
+    }
+
+    /// Builds the DAG from entry/exit events, also used by the other span-based readers
+    pub(super) fn trace_from_events(
+        trace_id: &Uuid,
+        mut event_list: Vec<UberEvent>,
+    ) -> Result<Trace, ReaderError> {
+        if event_list.is_empty() {
+            return Err(ReaderError::Incomplete("Trace has no spans".into()));
+        }
+        let mut mydag = Trace::new(trace_id);
         event_list.sort_by(|a, b| a.e.timestamp.cmp(&b.e.timestamp));
         let mut state = UberParsingState {
             active_spans: HashMap::new(),
@@ -187,7 +402,9 @@ impl UberReader {
                 let mut num_tries = deferred_events.len() + 1;
                 while deferred_events.len() != 0 {
                     if num_tries == 0 {
-                        return Err(raise("Could not add deferred nodes"));
+                        return Err(ReaderError::Incomplete(
+                            "Could not add deferred nodes".into(),
+                        ));
                     }
                     num_tries -= 1;
                     let e = deferred_events.pop().unwrap();
@@ -202,7 +419,7 @@ impl UberReader {
                     }
                     state.event = e;
                     state.nidx = mydag.g.add_node(event.e.clone());
-                    match self.try_add_node(&mut mydag, &mut state) {
+                    match UberReader::try_add_node(&mut mydag, &mut state) {
                         Ok(_) => {
                             state.last_nidx = Some(state.nidx);
                         }
@@ -224,7 +441,7 @@ impl UberReader {
                 _ => {}
             }
             state.nidx = mydag.g.add_node(event.e.clone());
-            match self.try_add_node(&mut mydag, &mut state) {
+            match UberReader::try_add_node(&mut mydag, &mut state) {
                 Ok(_) => {
                     state.last_nidx = Some(state.nidx);
                 }
@@ -235,27 +452,36 @@ impl UberReader {
                 }
             }
         }
-        mydag.end_node = state.last_nidx.unwrap();
-        mydag.duration = (mydag.g[mydag.end_node].timestamp - mydag.g[mydag.start_node].timestamp)
-            .to_std()
-            .unwrap();
+        mydag.end_node = match state.last_nidx {
+            Some(nidx) => nidx,
+            None => {
+                return Err(ReaderError::Incomplete(
+                    "No event could be added to the trace".into(),
+                ))
+            }
+        };
+        mydag.duration = duration_between(
+            mydag.g[mydag.start_node].timestamp,
+            mydag.g[mydag.end_node].timestamp,
+        )?;
+        mydag.mark_errors();
         let g: Graph<Event, DAGEdge> = mydag.g.clone().into();
         if connected_components(&g) > 1 {
-            Err(raise("Too many connected components"))
+            Err(ReaderError::Disjoint(
+                "Too many connected components".into(),
+            ))
         } else {
             Ok(mydag)
         }
     }
 
-    fn try_add_node(
-        &self,
-        mydag: &mut Trace,
-        s: &mut UberParsingState,
-    ) -> Result<(), Box<dyn Error>> {
+    fn try_add_node(mydag: &mut Trace, s: &mut UberParsingState) -> Result<(), ReaderError> {
         if mydag.g.node_count() <= 1 {
             mydag.start_node = s.nidx;
             if !s.event.parent_id.is_none() {
-                return Err(raise("Trace does not start with root span"));
+                return Err(ReaderError::Incomplete(
+                    "Trace does not start with root span".into(),
+                ));
             }
         }
         let prev_sibling = match &s.event.parent_id {
@@ -275,7 +501,7 @@ impl UberReader {
                     i,
                     s.nidx,
                     DAGEdge {
-                        duration: (s.event.e.timestamp - mydag.g[i].timestamp).to_std()?,
+                        duration: duration_between(mydag.g[i].timestamp, s.event.e.timestamp)?,
                         variant: EdgeType::ChildOf,
                     },
                 );
@@ -285,7 +511,9 @@ impl UberReader {
                     Some(p) => match s.active_spans.get(&p.to_uuid()) {
                         Some(&id) => Some(id),
                         None => {
-                            return Err(raise("Parent did not start before current node"));
+                            return Err(ReaderError::Incomplete(
+                                "Parent did not start before current node".into(),
+                            ));
                         }
                     },
                     None => {
@@ -295,8 +523,8 @@ impl UberReader {
                                 _ => {
                                     if mydag.g[s.last_nidx.unwrap()].trace_id != s.event.e.trace_id
                                     {
-                                        return Err(raise(
-                                            "Last node not exit and got parentless node",
+                                        return Err(ReaderError::Disjoint(
+                                            "Last node not exit and got parentless node".into(),
                                         ));
                                     }
                                 }
@@ -313,7 +541,10 @@ impl UberReader {
                             p,
                             s.nidx,
                             DAGEdge {
-                                duration: (s.event.e.timestamp - mydag.g[p].timestamp).to_std()?,
+                                duration: duration_between(
+                                    mydag.g[p].timestamp,
+                                    s.event.e.timestamp,
+                                )?,
                                 variant: EdgeType::ChildOf,
                             },
                         );
@@ -328,10 +559,58 @@ impl UberReader {
     }
 }
 
+/// Jaeger drops leading zeros from ids
+fn jaeger_span_id(id: &str) -> Result<HexID, ReaderError> {
+    let decoded = hex::decode(format!("{:0>16}", id))?;
+    if decoded.len() != 8 {
+        return Err(ReaderError::Parse(format!("Invalid span id {}", id)));
+    }
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&decoded);
+    Ok(HexID { id: Some(buf) })
+}
+
+fn jaeger_trace_id(id: &str) -> Result<Uuid, ReaderError> {
+    if id.len() <= 16 {
+        Ok(jaeger_span_id(id)?.to_uuid())
+    } else {
+        Ok(Uuid::from_slice(&hex::decode(format!("{:0>32}", id))?)?)
+    }
+}
+
+fn jaeger_tags_to_values(tags: &[JaegerTag]) -> HashMap<String, Value> {
+    tags.iter()
+        .map(|tag| {
+            let value = match &tag.type_of {
+                Some(type_of) => typed_value(type_of, &tag.value),
+                None => Value::from_json(&tag.value),
+            };
+            (tag.key.clone(), value)
+        })
+        .collect()
+}
+
+/// Parses a tag value by its declared Jaeger type, whether it's a JSON string as in files or a
+/// JSON value as from the query service, so the same tag gets the same `Value` from both. Values
+/// that don't parse are kept as strings.
+fn typed_value(type_of: &str, value: &serde_json::Value) -> Value {
+    let text = match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    let parsed = match type_of {
+        "bool" => text.parse().map(Value::Bool).ok(),
+        "int64" => text.parse().map(Value::SignedInt).ok(),
+        "float64" => text.parse().map(Value::Float).ok(),
+        _ => None,
+    };
+    parsed.unwrap_or(Value::Str(text))
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
-struct UberEvent {
-    e: Event,
-    parent_id: Option<HexID>,
+pub(super) struct UberEvent {
+    pub(super) e: Event,
+    pub(super) parent_id: Option<HexID>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Eq, PartialEq)]
@@ -402,3 +681,244 @@ pub struct UberTag {
     type_of: String,
     value: String,
 }
+
+impl UberTag {
+    /// Parses the value by its Jaeger type, keeping it as a string if it doesn't parse
+    fn key_value(&self) -> (String, Value) {
+        let value = serde_json::Value::String(self.value.clone());
+        (self.key.clone(), typed_value(&self.type_of, &value))
+    }
+}
+
+/// Response of the Jaeger query service, for both `/api/traces` and `/api/traces/{id}`
+#[derive(Deserialize, Debug, Clone)]
+struct JaegerResponse {
+    data: Option<Vec<JaegerData>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct JaegerData {
+    #[serde(rename = "traceID")]
+    trace_id: String,
+    spans: Vec<JaegerSpan>,
+    #[serde(default)]
+    processes: HashMap<String, JaegerProcess>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct JaegerSpan {
+    #[serde(rename = "spanID")]
+    span_id: String,
+    operation_name: String,
+    #[serde(default)]
+    references: Vec<JaegerReference>,
+    start_time: u64,
+    duration: i64,
+    #[serde(default)]
+    tags: Vec<JaegerTag>,
+    #[serde(rename = "processID")]
+    process_id: Option<String>,
+}
+
+impl JaegerSpan {
+    fn key_values(&self) -> HashMap<String, Value> {
+        jaeger_tags_to_values(&self.tags)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct JaegerReference {
+    ref_type: String,
+    #[serde(rename = "spanID")]
+    span_id: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct JaegerProcess {
+    service_name: String,
+    #[serde(default)]
+    tags: Vec<JaegerTag>,
+}
+
+impl JaegerProcess {
+    /// All process tags, and the service and host under the same keys as other readers
+    fn key_values(&self) -> HashMap<String, Value> {
+        let mut result = jaeger_tags_to_values(&self.tags);
+        if let Some(host) = result.get("hostname").or(result.get("ip")).cloned() {
+            result.insert("host".to_string(), host);
+        }
+        result.insert("service".to_string(), Value::Str(self.service_name.clone()));
+        result
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct JaegerTag {
+    key: String,
+    #[serde(rename = "type")]
+    type_of: Option<String>,
+    value: serde_json::Value,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    /// A Jaeger query service response with one trace of two spans, as recorded from Jaeger
+    const RECORDED: &str = r#"{"data": [{
+        "traceID": "6c1ddd3a2cb43dbe",
+        "spans": [
+            {"traceID": "6c1ddd3a2cb43dbe", "spanID": "6c1ddd3a2cb43dbe",
+             "operationName": "HTTP GET /hotels", "references": [],
+             "startTime": 1600000000000000, "duration": 5000,
+             "tags": [{"key": "http.status_code", "type": "int64", "value": 200}],
+             "processID": "p1"},
+            {"traceID": "6c1ddd3a2cb43dbe", "spanID": "e01f6ad5b9a4a7d",
+             "operationName": "SQL SELECT",
+             "references": [{"refType": "CHILD_OF", "traceID": "6c1ddd3a2cb43dbe",
+                             "spanID": "6c1ddd3a2cb43dbe"}],
+             "startTime": 1600000000001000, "duration": 2000, "processID": "p2"}
+        ],
+        "processes": {
+            "p1": {"serviceName": "frontend", "tags": [{"key": "hostname", "value": "web-1"}]},
+            "p2": {"serviceName": "mysql", "tags": []}
+        }
+    }], "total": 0, "limit": 0, "offset": 0, "errors": null}"#;
+
+    /// Serves `body` to every request on a local port, and records the request lines
+    fn stand_in(body: &'static str) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buf).unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..read]);
+                }
+                let request = String::from_utf8_lossy(&request).to_string();
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push(request.lines().next().unwrap_or("").to_string());
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        (address, requests)
+    }
+
+    fn reader(url: &str) -> UberReader {
+        UberReader {
+            uber_trace_dir: PathBuf::new(),
+            jaeger_url: Some(url.to_string()),
+            jaeger_services: vec!["front end".to_string()],
+            jaeger_lookback: std::time::Duration::from_secs(60),
+            jaeger_limit: 20,
+            prev_traces: HashMap::new(),
+            trace_error_count: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn polls_jaeger() {
+        let (url, requests) = stand_in(RECORDED);
+        let mut reader = reader(&url);
+        // Traces are returned once they stop changing between polls
+        assert!(reader.get_recent_traces().is_empty());
+        let traces = reader.get_recent_traces();
+        assert_eq!(traces.len(), 1);
+        let trace = &traces[0];
+        assert_eq!(trace.g.node_count(), 4);
+        assert_eq!(trace.duration, std::time::Duration::from_micros(5000));
+        let root = &trace.g[trace.start_node];
+        assert_eq!(
+            root.key_value_pair.get("host"),
+            Some(&Value::Str("web-1".to_string()))
+        );
+        assert_eq!(
+            root.key_value_pair.get("http.status_code"),
+            Some(&Value::SignedInt(200))
+        );
+        // Returned traces are not returned again
+        assert!(reader.get_recent_traces().is_empty());
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[0].starts_with("GET /api/traces?service=front%20end&start="));
+        assert!(requests[0].contains("&limit=20 "));
+    }
+
+    #[test]
+    fn parses_tags_by_type() {
+        let tags: Vec<JaegerTag> = serde_json::from_str(
+            r#"[{"key": "count", "type": "int64", "value": 3},
+                {"key": "ratio", "type": "float64", "value": 0.5},
+                {"key": "error", "type": "bool", "value": true},
+                {"key": "code", "type": "string", "value": "42"},
+                {"key": "size", "value": 7}]"#,
+        )
+        .unwrap();
+        let values = jaeger_tags_to_values(&tags);
+        assert_eq!(values["count"], Value::SignedInt(3));
+        assert_eq!(values["ratio"], Value::Float(0.5));
+        assert_eq!(values["error"], Value::Bool(true));
+        assert_eq!(values["code"], Value::Str("42".to_string()));
+        assert_eq!(values["size"], Value::UnsignedInt(7));
+
+        // Tags in files have the same values as polled ones
+        for (key, type_of, value) in [
+            ("count", "int64", "3"),
+            ("ratio", "float64", "0.5"),
+            ("error", "bool", "true"),
+            ("code", "string", "42"),
+        ]
+        .iter()
+        {
+            let tag = UberTag {
+                key: key.to_string(),
+                type_of: type_of.to_string(),
+                value: value.to_string(),
+            };
+            assert_eq!(tag.key_value(), (key.to_string(), values[*key].clone()));
+        }
+        let bad = UberTag {
+            key: "count".to_string(),
+            type_of: "int64".to_string(),
+            value: "many".to_string(),
+        };
+        assert_eq!(bad.key_value().1, Value::Str("many".to_string()));
+    }
+
+    #[test]
+    fn parses_jaeger_ids() {
+        // Leading zeros are dropped
+        assert_eq!(
+            jaeger_span_id("e01f6ad5b9a4a7d").unwrap(),
+            jaeger_span_id("0e01f6ad5b9a4a7d").unwrap()
+        );
+        assert!(jaeger_span_id("6c1ddd3a2cb43dbe6c1d").is_err());
+        assert!(jaeger_trace_id("463ac35c9f6413ad48485a3953bb6124").is_ok());
+    }
+}
@@ -0,0 +1,313 @@
+/*
+This source code is licensed under the BSD-style license found in the
+LICENSE file in the root directory of this source tree.
+
+Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
+All rights reserved.
+*/
+
+//! A reader that watches a directory for trace files, so traces that are collected offline can
+//! feed the controller loop. Parsing is done by the reader of the application.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use crate::reader::Reader;
+use crate::reader::ReaderError;
+use crate::settings::Settings;
+use crate::trace::Trace;
+
+lazy_static! {
+    /// Files that are already processed, shared between workers like the traces of `UberReader`
+    /// so that a file is returned by only one of them
+    static ref PROCESSED_FILES: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+pub struct DirectoryReader {
+    reader: Box<dyn Reader>,
+    watch_dir: PathBuf,
+    /// Processed files, so we don't process them again after a restart. Paths are kept as their
+    /// raw bytes, each followed by a NUL byte, since file names can contain any other byte.
+    record_file: PathBuf,
+    /// Files that can't be touched for this long are considered complete
+    settle_time: Duration,
+    /// Size and modification time of files that were still changing at the last poll
+    pending_files: HashMap<PathBuf, (u64, SystemTime)>,
+}
+
+impl Reader for DirectoryReader {
+    fn for_searchspace(&mut self) {
+        self.reader.for_searchspace();
+    }
+
+    fn reset_state(&mut self) {
+        self.reader.reset_state();
+        self.pending_files.clear();
+    }
+
+    fn read_file(&mut self, filename: &str) -> Result<Trace, ReaderError> {
+        self.reader.read_file(filename)
+    }
+
+    fn read_traces(&mut self, filename: &str) -> Result<Vec<Trace>, ReaderError> {
+        self.reader.read_traces(filename)
+    }
+
+    fn read_dir(&mut self, foldername: &str) -> Result<Vec<Trace>, ReaderError> {
+        self.reader.read_dir(foldername)
+    }
+
+    fn get_trace_from_base_id(&mut self, id: &str) -> Result<Trace, ReaderError> {
+        self.reader.get_trace_from_base_id(id)
+    }
+
+    /// Returns traces from files that appeared since the last call. A file is read once it is
+    /// not modified for `settle_time`, or once its size and modification time are the same
+    /// in two consecutive calls.
+    fn get_recent_traces(&mut self) -> Vec<Trace> {
+        let entries = match std::fs::read_dir(&self.watch_dir) {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!("Could not read {:?}: {:?}", self.watch_dir, e);
+                return Vec::new();
+            }
+        };
+        let mut ready = Vec::new();
+        let mut still_pending = HashMap::new();
+        for entry in entries {
+            let path = match entry {
+                Ok(e) => e.path(),
+                Err(_) => continue,
+            };
+            if !self.is_trace_file(&path) || PROCESSED_FILES.lock().unwrap().contains(&path) {
+                continue;
+            }
+            let metadata = match std::fs::metadata(&path) {
+                Ok(m) => m,
+                Err(_) => continue,
+            };
+            let modified = match metadata.modified() {
+                Ok(m) => m,
+                Err(_) => continue,
+            };
+            let current = (metadata.len(), modified);
+            let settled = match modified.elapsed() {
+                Ok(elapsed) => elapsed >= self.settle_time,
+                Err(_) => false,
+            };
+            if settled || self.pending_files.get(&path) == Some(&current) {
+                ready.push(path);
+            } else {
+                still_pending.insert(path, current);
+            }
+        }
+        self.pending_files = still_pending;
+
+        ready.sort();
+        let mut traces = Vec::new();
+        for path in ready {
+            if !self.claim(&path) {
+                continue;
+            }
+            eprintln!("Reading {}", path.to_string_lossy());
+            match self.reader.read_traces(&path.to_string_lossy()) {
+                Ok(t) => traces.extend(t),
+                Err(e) => {
+                    eprintln!("Skipping {}: {}", path.to_string_lossy(), e);
+                }
+            }
+        }
+        traces
+    }
+}
+
+impl DirectoryReader {
+    pub fn from_settings(settings: &Settings, reader: Box<dyn Reader>) -> Self {
+        let watch_dir = settings.watch_dir.clone().unwrap();
+        let record_file = match &settings.watch_record_file {
+            Some(f) => f.clone(),
+            None => watch_dir.join(".pythia_processed"),
+        };
+        let result = DirectoryReader {
+            reader,
+            watch_dir,
+            record_file,
+            settle_time: settings.watch_settle_time,
+            pending_files: HashMap::new(),
+        };
+        result.load_record();
+        result
+    }
+
+    /// Hidden and temporary files are skipped, so writers can write elsewhere and rename
+    fn is_trace_file(&self, path: &PathBuf) -> bool {
+        if !path.is_file() || *path == self.record_file {
+            return false;
+        }
+        let name = match path.file_name() {
+            Some(n) => n.to_string_lossy(),
+            None => return false,
+        };
+        !name.starts_with('.') && !name.ends_with(".tmp") && !name.ends_with(".part")
+    }
+
+    fn load_record(&self) {
+        let contents = match std::fs::read(&self.record_file) {
+            Ok(c) => c,
+            // No record yet
+            Err(_) => return,
+        };
+        let mut processed = PROCESSED_FILES.lock().unwrap();
+        for path in contents.split(|&b| b == 0) {
+            if !path.is_empty() {
+                processed.insert(PathBuf::from(OsStr::from_bytes(path)));
+            }
+        }
+    }
+
+    /// Marks the file as processed, returns false if it already was. Files that fail to parse
+    /// are also recorded, otherwise we would retry them forever.
+    fn claim(&self, path: &PathBuf) -> bool {
+        let mut processed = PROCESSED_FILES.lock().unwrap();
+        if !processed.insert(path.clone()) {
+            return false;
+        }
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.record_file)
+            .and_then(|mut f| {
+                f.write_all(path.as_os_str().as_bytes())?;
+                f.write_all(&[0])
+            });
+        if let Err(e) = written {
+            eprintln!("Could not record {:?} as processed: {:?}", path, e);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    /// Returns an empty trace per file and records the files it read
+    struct StubReader {
+        read: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Reader for StubReader {
+        fn read_file(&mut self, filename: &str) -> Result<Trace, ReaderError> {
+            self.read.lock().unwrap().push(filename.to_string());
+            Ok(Trace::new(&uuid::Uuid::new_v4()))
+        }
+
+        fn get_trace_from_base_id(&mut self, id: &str) -> Result<Trace, ReaderError> {
+            self.read_file(id)
+        }
+
+        fn get_recent_traces(&mut self) -> Vec<Trace> {
+            Vec::new()
+        }
+
+        fn reset_state(&mut self) {}
+
+        fn for_searchspace(&mut self) {}
+    }
+
+    /// A fresh directory to watch
+    fn watch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pythia-{}-{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn watcher(dir: &PathBuf, settle_time: Duration) -> (DirectoryReader, Arc<Mutex<Vec<String>>>) {
+        let read = Arc::new(Mutex::new(Vec::new()));
+        let reader = DirectoryReader {
+            reader: Box::new(StubReader { read: read.clone() }),
+            watch_dir: dir.clone(),
+            record_file: dir.join(".pythia_processed"),
+            settle_time,
+            pending_files: HashMap::new(),
+        };
+        reader.load_record();
+        (reader, read)
+    }
+
+    /// Forgets the files of `dir`, as if the process restarted
+    fn restart(dir: &PathBuf) {
+        PROCESSED_FILES
+            .lock()
+            .unwrap()
+            .retain(|p| !p.starts_with(dir));
+    }
+
+    #[test]
+    fn waits_for_files_to_settle() {
+        let dir = watch_dir("watch-settle");
+        let (mut reader, read) = watcher(&dir, Duration::from_secs(3600));
+        std::fs::write(dir.join("a.json"), "{}").unwrap();
+        std::fs::write(dir.join(".hidden.json"), "{}").unwrap();
+        std::fs::write(dir.join("b.json.part"), "{}").unwrap();
+        // Files are read once they didn't change between two polls
+        assert!(reader.get_recent_traces().is_empty());
+        std::fs::write(dir.join("c.json"), "{}").unwrap();
+        assert_eq!(reader.get_recent_traces().len(), 1);
+        std::fs::write(dir.join("c.json"), "{\"more\": 1}").unwrap();
+        assert!(reader.get_recent_traces().is_empty());
+        assert_eq!(reader.get_recent_traces().len(), 1);
+        // and only once
+        assert!(reader.get_recent_traces().is_empty());
+        assert_eq!(
+            *read.lock().unwrap(),
+            vec![
+                dir.join("a.json").to_string_lossy().to_string(),
+                dir.join("c.json").to_string_lossy().to_string()
+            ]
+        );
+
+        // Files that are not modified for the settle time are read right away
+        let (mut settled, _) = watcher(&dir, Duration::from_secs(0));
+        std::fs::write(dir.join("d.json"), "{}").unwrap();
+        assert_eq!(settled.get_recent_traces().len(), 1);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn remembers_processed_files() {
+        let dir = watch_dir("watch-record");
+        let odd = dir.join(OsStr::from_bytes(b"odd\xff\nname.json"));
+        std::fs::write(&odd, "{}").unwrap();
+        std::fs::write(dir.join("plain.json"), "{}").unwrap();
+        let (mut first, _) = watcher(&dir, Duration::from_secs(0));
+        assert_eq!(first.get_recent_traces().len(), 2);
+
+        restart(&dir);
+        std::fs::write(dir.join("new.json"), "{}").unwrap();
+        let (mut second, read) = watcher(&dir, Duration::from_secs(0));
+        assert_eq!(second.get_recent_traces().len(), 1);
+        assert_eq!(
+            *read.lock().unwrap(),
+            vec![dir.join("new.json").to_string_lossy().to_string()]
+        );
+
+        // Without the record, everything is read again
+        restart(&dir);
+        std::fs::remove_file(dir.join(".pythia_processed")).unwrap();
+        let (mut third, _) = watcher(&dir, Duration::from_secs(0));
+        assert_eq!(third.get_recent_traces().len(), 3);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
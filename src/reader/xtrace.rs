@@ -0,0 +1,673 @@
+/*
+This source code is licensed under the BSD-style license found in the
+LICENSE file in the root directory of this source tree.
+
+Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
+All rights reserved.
+*/
+
+//! Reader for X-Trace reports, used for HDFS, DeathStar and any other X-Trace-instrumented
+//! system. The differences between applications are kept in `XTraceOptions`.
+//!
+//! Every X-Trace report has the same core fields (`EventID`, `ParentEventID`, `HRT`, ...), while
+//! the rest of the fields depend on the instrumentation. Instead of a struct per report shape,
+//! the extra fields are collected into a map, and the options tell which of them to keep.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use byteorder::BigEndian;
+use byteorder::ByteOrder;
+use chrono::NaiveDateTime;
+use petgraph::graph::NodeIndex;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::reader::download_webpage;
+use crate::reader::duration_between;
+use crate::reader::xtrace_client::now_millis;
+use crate::reader::xtrace_client::XTraceClient;
+use crate::reader::HexID;
+use crate::reader::Reader;
+use crate::reader::ReaderError;
+use crate::settings::ApplicationType;
+use crate::settings::Settings;
+use crate::trace::Event;
+use crate::trace::EventType;
+use crate::trace::Trace;
+use crate::trace::TracepointID;
+use crate::trace::Value;
+use crate::trace::Value::Str;
+use crate::trace::{DAGEdge, EdgeType};
+
+/// Report fields that are bookkeeping of the X-Trace client rather than attributes of the event
+const IGNORED_FIELDS: [&str; 2] = ["X-Trace", "Timestamp"];
+
+/// How the `EventID`s of the reports are encoded
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum EventIDFormat {
+    /// Signed 64-bit decimal, used by the Java X-Trace client
+    Decimal,
+    /// Hex string, used by the C++ X-Trace client
+    Hex,
+}
+
+/// Per-application behavior of the X-Trace reader
+#[derive(Debug, Clone)]
+pub struct XTraceOptions {
+    pub event_ids: EventIDFormat,
+    /// Read traces from this directory instead of the X-Trace server when looking up by id
+    pub trace_dir: Option<PathBuf>,
+    /// Prune traces when reading them for the search space
+    pub prune: bool,
+    /// Whether to apply the skip rules below
+    pub simplify_trace: bool,
+    /// Tracepoints that are removed from the trace, their parents are connected to their children
+    pub skip_tracepoints: Vec<String>,
+    /// Labels that are removed from the trace, same as above
+    pub skip_labels: Vec<String>,
+    /// (child, parent) tracepoint pairs whose edges are removed
+    pub skip_edges: Vec<(String, String)>,
+    /// Report fields that are kept as key-value pairs, with the key they are stored under. For
+    /// lists, only the first element is kept.
+    pub key_fields: Vec<(String, String)>,
+    /// Reports without a source that have a `Tag` carry the shell command, as in `-put args`
+    pub command_from_tag: bool,
+    /// Listed tasks are only read if they have all of these tags
+    pub required_tags: Vec<String>,
+}
+
+impl XTraceOptions {
+    pub fn from_settings(settings: &Settings) -> Self {
+        match settings.application {
+            ApplicationType::HDFS => XTraceOptions::hdfs(),
+            ApplicationType::DEATHSTAR => XTraceOptions::deathstar(settings),
+            _ => XTraceOptions::generic(settings),
+        }
+    }
+
+    pub fn hdfs() -> Self {
+        XTraceOptions {
+            event_ids: EventIDFormat::Decimal,
+            trace_dir: None,
+            prune: true,
+            simplify_trace: false,
+            skip_tracepoints: to_strings(&["DFSOutputStream.java:387", "BlockReceiver.java:1280"]),
+            skip_labels: to_strings(&["waited"]),
+            skip_edges: to_string_pairs(&[
+                ("Client.java:1076", "Client.java:1044"),
+                ("DFSOutputStream.java:441", "DFSOutputStream.java:1669"),
+                ("BlockReceiver.java:1322", "BlockReceiver.java:903"),
+                ("DFSOutputStream.java:441", "SocketOutputStream.java:63"),
+                ("PacketHeader.java:164", "SocketInputStream.java:57"),
+                ("BlockReceiver.java:1322", "SocketOutputStream.java:63"),
+                ("PipelineAck.java:257", "SocketInputStream.java:57"),
+                ("DFSOutputStream.java:2271", "DFSOutputStream.java:1805"),
+            ]),
+            key_fields: to_string_pairs(&[
+                ("Name", "Name"),
+                ("Readsize", "Read Size"),
+                ("Writesize", "Write Size"),
+                ("Tag", "Tag"),
+                ("Replication", "Replication"),
+            ]),
+            command_from_tag: true,
+            // Only the tasks of the shell's main thread are complete requests
+            required_tags: to_strings(&["main"]),
+        }
+    }
+
+    pub fn deathstar(settings: &Settings) -> Self {
+        XTraceOptions {
+            event_ids: EventIDFormat::Hex,
+            trace_dir: Some(settings.DEATHSTAR_trace_dir.clone()),
+            prune: false,
+            simplify_trace: true,
+            skip_tracepoints: to_strings(&[
+                "/tmp/xtrace-cpp/src/lua_baggage.cpp:33",
+                "/tmp/xtrace-cpp/src/luaxtrace.cpp:49",
+            ]),
+            skip_labels: Vec::new(),
+            skip_edges: to_string_pairs(&[
+                (
+                    "/tmp/xtrace-cpp/src/lua_baggage.cpp:33",
+                    "/tmp/xtrace-cpp/src/lua_baggage.cpp:33",
+                ),
+                (
+                    "/tmp/xtrace-cpp/src/lua_baggage.cpp:33",
+                    "/tmp/xtrace-cpp/src/lua_baggage.cpp:43",
+                ),
+            ]),
+            key_fields: Vec::new(),
+            command_from_tag: false,
+            required_tags: Vec::new(),
+        }
+    }
+
+    /// For other X-Trace-instrumented systems, no simplification and all tags are kept
+    pub fn generic(settings: &Settings) -> Self {
+        XTraceOptions {
+            event_ids: if settings.xtrace_hex_ids {
+                EventIDFormat::Hex
+            } else {
+                EventIDFormat::Decimal
+            },
+            trace_dir: None,
+            prune: false,
+            simplify_trace: false,
+            skip_tracepoints: Vec::new(),
+            skip_labels: Vec::new(),
+            skip_edges: Vec::new(),
+            key_fields: to_string_pairs(&[("Tag", "Tag")]),
+            command_from_tag: false,
+            required_tags: Vec::new(),
+        }
+    }
+}
+
+fn to_strings(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+fn to_string_pairs(list: &[(&str, &str)]) -> Vec<(String, String)> {
+    list.iter()
+        .map(|(a, b)| (a.to_string(), b.to_string()))
+        .collect()
+}
+
+pub struct XTraceReader {
+    xtrace_url: String,
+    client: XTraceClient,
+    jiffy: Duration,
+    /// Tasks that are returned, with their last update time, until they leave the window
+    processed_traces: HashMap<String, u64>,
+    listed_once: bool,
+    for_searchspace: bool,
+    options: XTraceOptions,
+    skip_tracepoints: HashSet<TracepointID>,
+    skip_edges: HashSet<(TracepointID, TracepointID)>,
+}
+
+impl Reader for XTraceReader {
+    fn for_searchspace(&mut self) {
+        self.for_searchspace = true;
+    }
+
+    fn reset_state(&mut self) {}
+
+    /// Lists tasks with the configured tags, and returns those that are not updated for a jiffy.
+    /// Tasks that are already in the window at the first call are considered old.
+    fn get_recent_traces(&mut self) -> Vec<Trace> {
+        let tasks = match self.client.list_recent_tasks() {
+            Ok(t) => t,
+            Err(e) => {
+                eprintln!("Listing X-Trace tasks failed with {}", e);
+                return Vec::new();
+            }
+        };
+        let window_start = self.client.window_start();
+        self.processed_traces
+            .retain(|_, &mut last_updated| last_updated >= window_start);
+        let quiet_since = now_millis().saturating_sub(self.jiffy.as_millis() as u64);
+        let mut result = Vec::new();
+        for task in tasks {
+            if task.last_updated > quiet_since
+                || !self
+                    .options
+                    .required_tags
+                    .iter()
+                    .all(|t| task.tags.contains(t))
+                || self.processed_traces.contains_key(&task.task_id)
+            {
+                continue;
+            }
+            self.processed_traces
+                .insert(task.task_id.clone(), task.last_updated);
+            if self.listed_once {
+                result.push(task.task_id);
+            }
+        }
+        self.listed_once = true;
+        let mut traces = Vec::new();
+        for id in result {
+            match self.get_trace_from_base_id(&id) {
+                Ok(t) => traces.push(t),
+                Err(e) => {
+                    eprintln!("Skipping {}: {}", id, e);
+                }
+            }
+        }
+        traces
+    }
+
+    fn get_trace_from_base_id(&mut self, id: &str) -> Result<Trace, ReaderError> {
+        if id.is_empty() {
+            return Err(ReaderError::Parse("Empty task id".into()));
+        }
+        if let Some(dir) = &self.options.trace_dir {
+            let mut path = dir.clone();
+            path.push(id);
+            path.set_extension("json");
+            eprintln!("Reading {}", path.to_string_lossy());
+            return self.read_file(&path.to_string_lossy());
+        }
+        let urn: String = format!("{}/interactive/reports/{}", self.xtrace_url, id);
+        let result = download_webpage(urn)?;
+        let mut t: Vec<XTraceTrace> = serde_json::from_str(&result)?;
+        if t.len() != 1 {
+            return Err(ReaderError::Parse(format!(
+                "Got {} traces for {}",
+                t.len(),
+                id
+            )));
+        }
+        self.trace_from_json(&mut t[0])
+    }
+
+    fn read_file(&mut self, file: &str) -> Result<Trace, ReaderError> {
+        let reader = std::fs::File::open(file)?;
+        match serde_json::from_reader(reader) {
+            // We either have a saved file, or saved xtrace output
+            Ok(v) => Ok(v),
+            Err(_) => {
+                let reader = std::fs::File::open(file)?;
+                let mut t: Vec<XTraceTrace> = serde_json::from_reader(reader)?;
+                if t.len() != 1 {
+                    return Err(ReaderError::Parse(format!(
+                        "Expected one trace in {}, found {}",
+                        file,
+                        t.len()
+                    )));
+                }
+                self.trace_from_json(&mut t[0])
+            }
+        }
+    }
+}
+
+impl XTraceReader {
+    pub fn from_settings(settings: &Settings) -> Self {
+        XTraceReader::with_options(settings, XTraceOptions::from_settings(settings))
+    }
+
+    pub fn with_options(settings: &Settings, options: XTraceOptions) -> Self {
+        XTraceReader {
+            xtrace_url: settings.xtrace_url.clone(),
+            client: XTraceClient::from_settings(settings),
+            jiffy: settings.jiffy,
+            processed_traces: HashMap::new(),
+            listed_once: false,
+            for_searchspace: false,
+            skip_tracepoints: options
+                .skip_tracepoints
+                .iter()
+                .map(|t| TracepointID::from_str(t))
+                .collect(),
+            skip_edges: options
+                .skip_edges
+                .iter()
+                .map(|(a, b)| (TracepointID::from_str(a), TracepointID::from_str(b)))
+                .collect(),
+            options,
+        }
+    }
+
+    fn should_skip_edge(&self, mynode: &Event, parent: &Event) -> bool {
+        self.options.simplify_trace
+            && self
+                .skip_edges
+                .contains(&(mynode.tracepoint_id, parent.tracepoint_id))
+    }
+
+    fn should_skip_node(&self, node: &XTraceEvent, event: &Event) -> bool {
+        self.options.simplify_trace
+            && (self.options.skip_labels.contains(&node.label)
+                || self.skip_tracepoints.contains(&event.tracepoint_id))
+    }
+
+    fn trace_from_json(&self, data: &mut XTraceTrace) -> Result<Trace, ReaderError> {
+        let mut mydag = Trace::new(&data.id.to_uuid());
+        eprintln!("Working on {}", mydag.base_id);
+        let mut event_id_map = HashMap::new();
+        let mut nidx = NodeIndex::end();
+        let mut start_node = None;
+        let mut wait_parents: HashMap<String, Vec<String>> = HashMap::new();
+        sort_event_list(&mut data.reports);
+        for event in data.reports.iter() {
+            let mynode = self.to_event(event)?;
+            if self.should_skip_node(event, &mynode) {
+                let mut parents = Vec::new();
+                let mut potential_parents = event.parent_event_id.clone();
+                while let Some(p) = potential_parents.pop() {
+                    match event_id_map.get(&p) {
+                        None => match wait_parents.get(&p) {
+                            Some(p2) => potential_parents.extend(p2.iter().cloned()),
+                            None => {
+                                return Err(ReaderError::Incomplete(format!(
+                                    "Missing parent {}",
+                                    p
+                                )))
+                            }
+                        },
+                        Some(_) => {
+                            parents.push(p);
+                        }
+                    }
+                }
+                wait_parents.insert(event.event_id.clone(), parents);
+                continue;
+            }
+            nidx = mydag.g.add_node(mynode.clone());
+            event_id_map.insert(event.event_id.clone(), nidx);
+            if start_node.is_none() {
+                mydag.start_node = nidx;
+                start_node = Some(nidx);
+                continue;
+            }
+            for parent in event.parent_event_id.iter() {
+                let parent_nidxs = match event_id_map.get(parent) {
+                    Some(&parent_nidx) => vec![parent_nidx],
+                    // Must have deleted the parent
+                    None => match wait_parents.get(parent) {
+                        Some(p2) => p2.iter().map(|p| event_id_map[p]).collect(),
+                        None => {
+                            return Err(ReaderError::Incomplete(format!(
+                                "Missing parent {}",
+                                parent
+                            )))
+                        }
+                    },
+                };
+                for parent_nidx in parent_nidxs {
+                    // Skip this edge, since it's not used.
+                    if self.should_skip_edge(&mynode, &mydag.g[parent_nidx]) {
+                        continue;
+                    }
+                    mydag.g.add_edge(
+                        parent_nidx,
+                        nidx,
+                        DAGEdge {
+                            duration: duration_between(
+                                mydag.g[parent_nidx].timestamp,
+                                mynode.timestamp,
+                            )?,
+                            variant: EdgeType::ChildOf,
+                        },
+                    );
+                }
+            }
+        }
+        if start_node.is_none() {
+            return Err(ReaderError::Incomplete("Trace has no reports".into()));
+        }
+        mydag.end_node = nidx;
+        mydag.duration = duration_between(
+            mydag.g[mydag.start_node].timestamp,
+            mydag.g[mydag.end_node].timestamp,
+        )?;
+        if self.options.prune && self.for_searchspace {
+            mydag.prune();
+        }
+        Ok(mydag)
+    }
+
+    fn to_event(&self, event: &XTraceEvent) -> Result<Event, ReaderError> {
+        let mut map = HashMap::new();
+        map.insert("Agent".to_string(), Str(event.agent.to_string()));
+        map.insert(
+            "Process Name".to_string(),
+            Str(event.process_name.to_string()),
+        );
+        map.insert("Host".to_string(), Str(event.host.to_string()));
+        map.insert("Thread Name".to_string(), Str(event.process_id.to_string()));
+        // Only reports with a source are tracepoints, others are messages
+        if event.source.is_some() {
+            for (field, key) in self.options.key_fields.iter() {
+                let value = match event.fields.get(field) {
+                    Some(serde_json::Value::Array(list)) => list.first(),
+                    other => other,
+                };
+                match value {
+                    Some(v @ serde_json::Value::String(_))
+                    | Some(v @ serde_json::Value::Number(_)) => {
+                        map.insert(key.clone(), Value::from_json(v));
+                    }
+                    _ => {}
+                }
+            }
+            // The rest of the fields are kept under their own names
+            for (field, value) in event.fields.iter() {
+                if IGNORED_FIELDS.contains(&field.as_str())
+                    || self.options.key_fields.iter().any(|(f, _)| f == field)
+                {
+                    continue;
+                }
+                map.entry(field.clone())
+                    .or_insert_with(|| Value::from_json(value));
+            }
+        } else if self.options.command_from_tag {
+            if let Some(serde_json::Value::String(tag)) = event
+                .fields
+                .get("Tag")
+                .and_then(|t| t.as_array())
+                .and_then(|t| t.first())
+            {
+                if let Some(number) = tag.find(' ') {
+                    map.insert("Command".to_string(), Str(tag[1..number].to_string()));
+                }
+            }
+        }
+
+        Ok(Event {
+            trace_id: self.eventid_to_uuid(&event.event_id)?,
+            tracepoint_id: TracepointID::from_str(match &event.source {
+                Some(s) => s,
+                None => &event.label,
+            }),
+            timestamp: convert_xtrace_timestamp(event.hrt),
+            variant: EventType::Annotation,
+            is_synthetic: false,
+            key_value_pair: map,
+            is_error: false,
+        })
+    }
+
+    fn eventid_to_uuid(&self, id: &str) -> Result<Uuid, ReaderError> {
+        match self.options.event_ids {
+            EventIDFormat::Decimal => {
+                let id = id.parse::<i64>()?;
+                let mut buf = [0; 16];
+                BigEndian::write_i64(&mut buf, id);
+                Ok(Uuid::from_bytes(buf))
+            }
+            EventIDFormat::Hex => {
+                let decoded = hex::decode(id)?;
+                if decoded.len() < 8 {
+                    return Err(ReaderError::Parse(format!("Event id {} is too short", id)));
+                }
+                let mut buf: [u8; 8] = [0; 8];
+                buf.copy_from_slice(&decoded[..8]);
+                Ok(HexID { id: Some(buf) }.to_uuid())
+            }
+        }
+    }
+}
+
+fn convert_xtrace_timestamp(hrt: u64) -> NaiveDateTime {
+    let seconds: i64 = (hrt / 1000000000).try_into().unwrap();
+    let nanos: u32 = (hrt % 1000000000).try_into().unwrap();
+    NaiveDateTime::from_timestamp(seconds, nanos)
+}
+
+fn sort_event_list(event_list: &mut [XTraceEvent]) {
+    // Sorts events by timestamp
+    event_list.sort_by_key(|e| e.hrt);
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct XTraceTrace {
+    pub id: HexID,
+    pub reports: Vec<XTraceEvent>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct XTraceEvent {
+    agent: String,
+    process_name: String,
+    #[serde(rename = "ParentEventID")]
+    parent_event_id: Vec<String>,
+    label: String,
+    source: Option<String>,
+    host: String,
+    #[serde(rename = "HRT")]
+    hrt: u64,
+    #[serde(rename = "EventID")]
+    event_id: String,
+    #[serde(rename = "ProcessID")]
+    process_id: u64,
+    /// Everything else in the report, which depends on the instrumentation
+    #[serde(flatten)]
+    fields: HashMap<String, serde_json::Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader(event_ids: EventIDFormat) -> XTraceReader {
+        let options = XTraceOptions {
+            event_ids,
+            trace_dir: None,
+            prune: false,
+            simplify_trace: false,
+            skip_tracepoints: Vec::new(),
+            skip_labels: Vec::new(),
+            skip_edges: Vec::new(),
+            key_fields: to_string_pairs(&[("Tag", "Tag")]),
+            command_from_tag: false,
+            required_tags: Vec::new(),
+        };
+        XTraceReader {
+            xtrace_url: String::new(),
+            client: XTraceClient::new("", Vec::new(), 1, 1, Duration::from_secs(60)),
+            jiffy: Duration::from_secs(1),
+            processed_traces: HashMap::new(),
+            listed_once: false,
+            for_searchspace: false,
+            options,
+            skip_tracepoints: HashSet::new(),
+            skip_edges: HashSet::new(),
+        }
+    }
+
+    fn report(event_id: &str, parents: &[&str], hrt: u64, source: &str) -> serde_json::Value {
+        serde_json::json!({
+            "Agent": "DataNode",
+            "ProcessName": "DataNode",
+            "ParentEventID": parents,
+            "Label": "event",
+            "Source": source,
+            "Host": "dn-1",
+            "HRT": hrt,
+            "EventID": event_id,
+            "ProcessID": 42,
+            "Tag": ["main", "write"],
+            "Writesize": 4096,
+            "Timestamp": 1,
+        })
+    }
+
+    #[test]
+    fn parses_decimal_ids() {
+        let reader = reader(EventIDFormat::Decimal);
+        let id = reader.eventid_to_uuid("-2").unwrap();
+        assert_eq!(
+            &id.as_bytes()[..8],
+            &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe]
+        );
+        assert_eq!(&id.as_bytes()[8..], &[0; 8]);
+        assert_ne!(
+            reader.eventid_to_uuid("7036880926224436373").unwrap(),
+            reader.eventid_to_uuid("7036880926224436374").unwrap()
+        );
+        assert!(reader.eventid_to_uuid("0f1e2d3c4b5a6978").is_err());
+    }
+
+    #[test]
+    fn parses_hex_ids() {
+        let reader = reader(EventIDFormat::Hex);
+        let id = reader.eventid_to_uuid("0f1e2d3c4b5a6978").unwrap();
+        assert_eq!(
+            &id.as_bytes()[..8],
+            &[0x0f, 0x1e, 0x2d, 0x3c, 0x4b, 0x5a, 0x69, 0x78]
+        );
+        // Longer ids are truncated to 64 bits
+        assert_eq!(reader.eventid_to_uuid("0f1e2d3c4b5a6978aa").unwrap(), id);
+        assert!(reader.eventid_to_uuid("0f1e").is_err());
+        assert!(reader.eventid_to_uuid("-2").is_err());
+    }
+
+    #[test]
+    fn builds_trace() {
+        let mut data: XTraceTrace = serde_json::from_value(serde_json::json!({
+            "id": "0123456789abcdef",
+            "reports": [
+                report("3", &["2"], 3_000_000, "BlockReceiver.java:903"),
+                report("1", &[], 1_000_000, "DataXceiver.java:100"),
+                report("2", &["1"], 2_000_000, "BlockReceiver.java:500"),
+            ],
+        }))
+        .unwrap();
+        let trace = reader(EventIDFormat::Decimal)
+            .trace_from_json(&mut data)
+            .unwrap();
+        assert_eq!(trace.g.node_count(), 3);
+        assert_eq!(trace.g.edge_count(), 2);
+        assert_eq!(trace.duration, Duration::from_millis(2));
+        let start = &trace.g[trace.start_node];
+        assert_eq!(
+            start.tracepoint_id,
+            TracepointID::from_str("DataXceiver.java:100")
+        );
+        assert_eq!(
+            start.key_value_pair.get("Tag"),
+            Some(&Value::Str("main".to_string()))
+        );
+        assert_eq!(
+            start.key_value_pair.get("Writesize"),
+            Some(&Value::UnsignedInt(4096))
+        );
+        assert!(start.key_value_pair.get("Timestamp").is_none());
+    }
+
+    #[test]
+    fn rejects_empty_ids() {
+        match reader(EventIDFormat::Decimal).get_trace_from_base_id("") {
+            Err(ReaderError::Parse(_)) => {}
+            other => panic!("Expected a parse error, got {:?}", other.map(|t| t.base_id)),
+        }
+    }
+
+    #[test]
+    fn rejects_missing_parents() {
+        let mut data: XTraceTrace = serde_json::from_value(serde_json::json!({
+            "id": "0123456789abcdef",
+            "reports": [
+                report("1", &[], 1_000_000, "DataXceiver.java:100"),
+                report("3", &["2"], 3_000_000, "BlockReceiver.java:903"),
+            ],
+        }))
+        .unwrap();
+        match reader(EventIDFormat::Decimal).trace_from_json(&mut data) {
+            Err(ReaderError::Incomplete(_)) => {}
+            other => panic!(
+                "Expected an incomplete trace, got {:?}",
+                other.map(|t| t.base_id)
+            ),
+        }
+    }
+}
@@ -0,0 +1,280 @@
+/*
+This source code is licensed under the BSD-style license found in the
+LICENSE file in the root directory of this source tree.
+
+Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
+All rights reserved.
+*/
+
+//! Client for the task listings of the X-Trace server.
+//!
+//! The server lists the tasks of a tag at `/tag/{tag}`, most recently updated first, with
+//! `offset` and `length` for pagination. With `format=json`, the listing is a JSON array of task
+//! summaries instead of an HTML table.
+
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::reader::download_webpage;
+use crate::reader::percent_encode;
+use crate::reader::ReaderError;
+use crate::settings::Settings;
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskSummary {
+    #[serde(alias = "taskID", alias = "TaskID")]
+    pub task_id: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub num_reports: u64,
+    /// Milliseconds since the epoch
+    #[serde(deserialize_with = "millis_from_number_or_string")]
+    pub first_seen: u64,
+    /// Milliseconds since the epoch
+    #[serde(deserialize_with = "millis_from_number_or_string")]
+    pub last_updated: u64,
+}
+
+pub struct XTraceClient {
+    xtrace_url: String,
+    tags: Vec<String>,
+    page_size: usize,
+    max_pages: usize,
+    /// Only tasks updated within this window are listed
+    window: Duration,
+}
+
+impl XTraceClient {
+    pub fn from_settings(settings: &Settings) -> Self {
+        XTraceClient::new(
+            &settings.xtrace_url,
+            settings.xtrace_tags.clone(),
+            settings.xtrace_page_size,
+            settings.xtrace_max_pages,
+            settings.xtrace_window,
+        )
+    }
+
+    pub fn new(
+        xtrace_url: &str,
+        tags: Vec<String>,
+        page_size: usize,
+        max_pages: usize,
+        window: Duration,
+    ) -> Self {
+        XTraceClient {
+            xtrace_url: xtrace_url.to_string(),
+            tags,
+            page_size,
+            max_pages,
+            window,
+        }
+    }
+
+    /// Start of the listing window, in milliseconds since the epoch
+    pub fn window_start(&self) -> u64 {
+        now_millis().saturating_sub(self.window.as_millis() as u64)
+    }
+
+    /// Lists tasks with any of the configured tags that were updated within the window. A task
+    /// with more than one of the tags is listed once.
+    pub fn list_recent_tasks(&self) -> Result<Vec<TaskSummary>, ReaderError> {
+        let window_start = self.window_start();
+        let mut result: Vec<TaskSummary> = Vec::new();
+        for tag in self.tags.iter() {
+            for task in self.list_tag(tag, window_start)? {
+                if result.iter().all(|t| t.task_id != task.task_id) {
+                    result.push(task);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Goes through the pages of a tag until a page is short, reaches tasks older than
+    /// `window_start`, or `max_pages` pages are read
+    fn list_tag(&self, tag: &str, window_start: u64) -> Result<Vec<TaskSummary>, ReaderError> {
+        let mut result = Vec::new();
+        for page in 0..self.max_pages {
+            let url = format!(
+                "{}/tag/{}?format=json&offset={}&length={}",
+                self.xtrace_url,
+                percent_encode(tag),
+                page * self.page_size,
+                self.page_size
+            );
+            let tasks: Vec<TaskSummary> = serde_json::from_str(&download_webpage(url)?)?;
+            let page_len = tasks.len();
+            let mut reached_end = page_len < self.page_size;
+            for task in tasks {
+                if task.last_updated < window_start {
+                    reached_end = true;
+                    continue;
+                }
+                result.push(task);
+            }
+            if reached_end {
+                return Ok(result);
+            }
+        }
+        eprintln!(
+            "Listing of tag {} has more than {} pages, the rest is skipped",
+            tag, self.max_pages
+        );
+        Ok(result)
+    }
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+fn millis_from_number_or_string<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Millis {
+        Number(u64),
+        Str(String),
+    }
+    match Millis::deserialize(deserializer)? {
+        Millis::Number(n) => Ok(n),
+        Millis::Str(s) => s.parse().map_err(serde::de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Serves the body that `respond` gives for the path of each request on a local port, and
+    /// records the paths
+    fn stand_in<F>(respond: F) -> (String, Arc<Mutex<Vec<String>>>)
+    where
+        F: Fn(&str) -> String + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = stream.read(&mut buf).unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..read]);
+                }
+                let request = String::from_utf8_lossy(&request).to_string();
+                let path = request.split(' ').nth(1).unwrap_or("").to_string();
+                let body = respond(&path);
+                recorded.lock().unwrap().push(path);
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        (address, requests)
+    }
+
+    fn task(id: &str, last_updated: u64) -> String {
+        format!(
+            r#"{{"taskId": "{}", "title": "ls", "tags": ["FsShell"], "numReports": 3,
+                "firstSeen": "{}", "lastUpdated": {}}}"#,
+            id,
+            last_updated - 10,
+            last_updated
+        )
+    }
+
+    #[test]
+    fn parses_task_summaries() {
+        let tasks: Vec<TaskSummary> = serde_json::from_str(
+            r#"[{"taskID": "a1", "firstSeen": 1600000000000, "lastUpdated": "1600000000500"},
+                {"TaskID": "b2", "title": "put", "tags": ["FsShell", "NameNode"],
+                 "numReports": 7, "firstSeen": "1", "lastUpdated": 2}]"#,
+        )
+        .unwrap();
+        assert_eq!(tasks[0].task_id, "a1");
+        assert_eq!(tasks[0].first_seen, 1600000000000);
+        assert_eq!(tasks[0].last_updated, 1600000000500);
+        assert!(tasks[0].tags.is_empty());
+        assert_eq!(tasks[1].num_reports, 7);
+        assert_eq!((tasks[1].first_seen, tasks[1].last_updated), (1, 2));
+        assert!(serde_json::from_str::<Vec<TaskSummary>>(
+            r#"[{"taskId": "c", "firstSeen": "soon", "lastUpdated": 1}]"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn lists_recent_tasks() {
+        let recent = now_millis();
+        let old = recent - 3_600_000;
+        let (url, requests) = stand_in(move |path| {
+            let tasks = match path {
+                "/tag/FsShell?format=json&offset=0&length=2" => {
+                    vec![task("t1", recent), task("t2", recent)]
+                }
+                // The listing reaches tasks before the window
+                "/tag/FsShell?format=json&offset=2&length=2" => {
+                    vec![task("t3", recent), task("t4", old)]
+                }
+                // A short page ends the listing
+                "/tag/Name%20Node?format=json&offset=0&length=2" => {
+                    vec![task("t2", recent)]
+                }
+                _ => vec![task("busy", recent), task("busy", recent)],
+            };
+            format!("[{}]", tasks.join(","))
+        });
+        let client = XTraceClient::new(
+            &url,
+            vec!["FsShell".to_string(), "Name Node".to_string()],
+            2,
+            3,
+            Duration::from_secs(600),
+        );
+        let tasks = client.list_recent_tasks().unwrap();
+        let ids: Vec<&str> = tasks.iter().map(|t| t.task_id.as_str()).collect();
+        assert_eq!(ids, vec!["t1", "t2", "t3"]);
+        assert_eq!(requests.lock().unwrap().len(), 3);
+
+        // Listings stop after the last page
+        let busy = XTraceClient::new(
+            &url,
+            vec!["busy".to_string()],
+            2,
+            3,
+            Duration::from_secs(600),
+        );
+        assert_eq!(busy.list_tag("busy", busy.window_start()).unwrap().len(), 6);
+        assert_eq!(requests.lock().unwrap().len(), 6);
+    }
+}
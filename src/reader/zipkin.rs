@@ -0,0 +1,395 @@
+/*
+This source code is licensed under the BSD-style license found in the
+LICENSE file in the root directory of this source tree.
+
+Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
+All rights reserved.
+*/
+
+//! Reader for Zipkin v2 span lists, as returned by `/api/v2/trace/{traceId}`, or lists of such
+//! lists, as returned by `/api/v2/traces`.
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use byteorder::BigEndian;
+use byteorder::ByteOrder;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::reader::uber::convert_uber_timestamp;
+use crate::reader::uber::span_to_events;
+use crate::reader::uber::UberEvent;
+use crate::reader::uber::UberReader;
+use crate::reader::HexID;
+use crate::reader::Reader;
+use crate::reader::ReaderError;
+use crate::settings::Settings;
+use crate::trace::Event;
+use crate::trace::EventType;
+use crate::trace::Trace;
+use crate::trace::TracepointID;
+use crate::trace::Value;
+
+pub struct ZipkinReader {
+    zipkin_trace_dir: PathBuf,
+}
+
+impl Reader for ZipkinReader {
+    fn for_searchspace(&mut self) {}
+    fn reset_state(&mut self) {}
+    fn read_file(&mut self, filename: &str) -> Result<Trace, ReaderError> {
+        let mut traces = self.read_traces(filename)?;
+        if traces.len() != 1 {
+            return Err(ReaderError::Parse(format!(
+                "Expected one trace in {}, found {}",
+                filename,
+                traces.len()
+            )));
+        }
+        Ok(traces.remove(0))
+    }
+
+    fn get_trace_from_base_id(&mut self, id: &str) -> Result<Trace, ReaderError> {
+        let mut path = self.zipkin_trace_dir.clone();
+        path.push(id);
+        path.set_extension("json");
+        eprintln!("Reading {}", path.to_string_lossy());
+        self.read_file(&path.to_string_lossy())
+    }
+
+    fn get_recent_traces(&mut self) -> Vec<Trace> {
+        Vec::new()
+    }
+
+    /// Files from `/api/v2/traces` contain multiple traces, so one file can produce many traces
+    fn read_traces(&mut self, filename: &str) -> Result<Vec<Trace>, ReaderError> {
+        let bytes = std::fs::read(filename)?;
+        // We either have a saved file, or saved zipkin output
+        if let Ok(t) = serde_json::from_slice::<Trace>(&bytes) {
+            return Ok(vec![t]);
+        }
+        let spans: Vec<ZipkinSpan> = match serde_json::from_slice::<ZipkinDump>(&bytes)? {
+            ZipkinDump::Trace(spans) => spans,
+            ZipkinDump::Traces(traces) => traces.into_iter().flatten().collect(),
+        };
+        let mut per_trace = BTreeMap::<String, Vec<ZipkinSpan>>::new();
+        for span in spans {
+            per_trace
+                .entry(span.trace_id.clone())
+                .or_default()
+                .push(span);
+        }
+        let mut results = Vec::new();
+        for (trace_id, spans) in per_trace.iter() {
+            match self.trace_from_spans(trace_id, spans) {
+                Ok(t) => results.push(t),
+                // A file with a single trace is bad, so the caller gets the error
+                Err(e) if per_trace.len() == 1 => return Err(e),
+                Err(e) => {
+                    eprintln!("Skipping trace {}: {}", trace_id, e);
+                }
+            }
+        }
+        Ok(results)
+    }
+}
+
+impl ZipkinReader {
+    pub fn from_settings(settings: &Settings) -> Self {
+        ZipkinReader {
+            zipkin_trace_dir: settings.zipkin_trace_dir.clone(),
+        }
+    }
+
+    fn trace_from_spans(&self, trace_id: &str, spans: &[ZipkinSpan]) -> Result<Trace, ReaderError> {
+        let event_list = self.to_events_edges(spans)?;
+        UberReader::trace_from_events(&trace_id_to_uuid(trace_id)?, event_list)
+    }
+
+    fn to_events_edges(&self, spans: &[ZipkinSpan]) -> Result<Vec<UberEvent>, ReaderError> {
+        // Zipkin reports both sides of an RPC under the same span id, with the server side
+        // marked as shared. We keep the client side as the span, and fold the server side
+        // into it as annotations.
+        let mut spans_by_id: HashMap<&str, Vec<&ZipkinSpan>> = HashMap::new();
+        for span in spans {
+            spans_by_id.entry(&span.id).or_default().push(span);
+        }
+        let mut events = Vec::new();
+        for (id, mut parts) in spans_by_id.into_iter() {
+            parts.sort_by_key(|s| s.shared);
+            let span = parts[0];
+            let span_id = span_id_to_hexid(id)?;
+            let parent = match &span.parent_id {
+                Some(p) => Some(span_id_to_hexid(p)?),
+                None => None,
+            };
+            let duration = match span.duration {
+                Some(d) => d,
+                None => {
+                    return Err(ReaderError::Incomplete(format!(
+                        "Span {} has no duration",
+                        id
+                    )));
+                }
+            };
+            let timestamp = match span.timestamp {
+                Some(t) => t,
+                None => {
+                    return Err(ReaderError::Incomplete(format!(
+                        "Span {} has no timestamp",
+                        id
+                    )));
+                }
+            };
+            let (start_time, end_time) = convert_uber_timestamp(timestamp, duration as i64);
+            let tracepoint = span.tracepoint_name();
+            // The client side wins on conflicting keys, e.g., the service name
+            let mut key_value_pair = HashMap::new();
+            for part in parts.iter().rev() {
+                key_value_pair.extend(part.key_values());
+            }
+            events.extend(span_to_events(
+                span_id,
+                parent,
+                TracepointID::from_str(&tracepoint),
+                start_time,
+                end_time,
+                key_value_pair,
+            ));
+            let mut annotation_idx = 0;
+            for part in parts.iter() {
+                for annotation in part.annotations.iter() {
+                    annotation_idx += 1;
+                    events.push(UberEvent {
+                        e: Event {
+                            trace_id: annotation_to_uuid(&span_id, annotation_idx),
+                            tracepoint_id: TracepointID::from_str(&format!(
+                                "{}:{}",
+                                part.tracepoint_name(),
+                                annotation.value
+                            )),
+                            timestamp: convert_uber_timestamp(annotation.timestamp, 0).0,
+                            is_synthetic: false,
+                            variant: EventType::Annotation,
+                            key_value_pair: HashMap::new(),
+                            is_error: false,
+                        },
+                        // Annotations are placed inside their span
+                        parent_id: Some(span_id),
+                    });
+                }
+            }
+        }
+        // Keep the order deterministic for events with the same timestamp
+        events.sort_by(|a, b| {
+            (a.e.timestamp, variant_order(&a.e.variant))
+                .cmp(&(b.e.timestamp, variant_order(&b.e.variant)))
+        });
+        Ok(events)
+    }
+}
+
+fn variant_order(variant: &EventType) -> u8 {
+    match variant {
+        EventType::Entry => 0,
+        EventType::Annotation => 1,
+        EventType::Exit => 2,
+    }
+}
+
+fn span_id_to_hexid(span_id: &str) -> Result<HexID, ReaderError> {
+    let decoded = hex::decode(span_id)?;
+    if decoded.len() != 8 {
+        return Err(ReaderError::Parse(format!("Invalid span id {}", span_id)));
+    }
+    let mut buf = [0; 8];
+    buf.copy_from_slice(&decoded);
+    Ok(HexID { id: Some(buf) })
+}
+
+/// Zipkin trace ids are either 64 or 128 bits. 64-bit ids are padded the same way as HexID.
+fn trace_id_to_uuid(trace_id: &str) -> Result<Uuid, ReaderError> {
+    let decoded = hex::decode(trace_id)?;
+    match decoded.len() {
+        8 => {
+            let mut buf = [0; 8];
+            buf.copy_from_slice(&decoded);
+            Ok(HexID { id: Some(buf) }.to_uuid())
+        }
+        16 => Ok(Uuid::from_slice(&decoded)?),
+        _ => Err(ReaderError::Parse(format!("Invalid trace id {}", trace_id))),
+    }
+}
+
+/// Annotations don't have ids, so we make one from the span id and the annotation's position
+fn annotation_to_uuid(span_id: &HexID, idx: u64) -> Uuid {
+    let mut buf = *span_id.to_uuid().as_bytes();
+    BigEndian::write_u64(&mut buf[8..], idx);
+    Uuid::from_bytes(buf)
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ZipkinDump {
+    Trace(Vec<ZipkinSpan>),
+    Traces(Vec<Vec<ZipkinSpan>>),
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ZipkinSpan {
+    trace_id: String,
+    id: String,
+    parent_id: Option<String>,
+    name: Option<String>,
+    kind: Option<String>,
+    timestamp: Option<u64>,
+    duration: Option<u64>,
+    local_endpoint: Option<ZipkinEndpoint>,
+    #[serde(default)]
+    annotations: Vec<ZipkinAnnotation>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+    #[serde(default)]
+    shared: bool,
+}
+
+impl ZipkinSpan {
+    fn service_name(&self) -> Option<&str> {
+        self.local_endpoint
+            .as_ref()
+            .and_then(|e| e.service_name.as_deref())
+    }
+
+    fn tracepoint_name(&self) -> String {
+        let name = self.name.as_deref().unwrap_or("");
+        match self.service_name() {
+            Some(service) => format!("{}/{}", service, name),
+            None => name.to_string(),
+        }
+    }
+
+    fn key_values(&self) -> HashMap<String, Value> {
+        let mut result: HashMap<String, Value> = self
+            .tags
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::Str(v.clone())))
+            .collect();
+        if let Some(service) = self.service_name() {
+            result.insert("service".to_string(), Value::Str(service.to_string()));
+        }
+        if let Some(endpoint) = &self.local_endpoint {
+            if let Some(ip) = endpoint.ipv4.as_ref().or(endpoint.ipv6.as_ref()) {
+                result.insert("host".to_string(), Value::Str(ip.clone()));
+            }
+        }
+        if let Some(kind) = &self.kind {
+            result.insert("kind".to_string(), Value::Str(kind.clone()));
+        }
+        result
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct ZipkinEndpoint {
+    service_name: Option<String>,
+    ipv4: Option<String>,
+    ipv6: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct ZipkinAnnotation {
+    timestamp: u64,
+    value: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPANS: &str = r#"[
+        {"traceId": "463ac35c9f6413ad", "id": "a2fb4a1d1a96d312", "name": "get",
+         "kind": "CLIENT", "timestamp": 1000000, "duration": 400000,
+         "localEndpoint": {"serviceName": "frontend", "ipv4": "10.0.0.1"},
+         "tags": {"http.path": "/api"}},
+        {"traceId": "463ac35c9f6413ad", "id": "a2fb4a1d1a96d312", "name": "get",
+         "kind": "SERVER", "timestamp": 1100000, "duration": 200000, "shared": true,
+         "localEndpoint": {"serviceName": "backend", "ipv4": "10.0.0.2"},
+         "annotations": [{"timestamp": 1150000, "value": "cache miss"}],
+         "tags": {"db.rows": "3"}},
+        {"traceId": "463ac35c9f6413ad", "id": "b7ad6b7169203331", "name": "query",
+         "parentId": "a2fb4a1d1a96d312", "timestamp": 1200000, "duration": 50000,
+         "localEndpoint": {"serviceName": "backend"}}
+    ]"#;
+
+    fn reader() -> ZipkinReader {
+        ZipkinReader {
+            zipkin_trace_dir: PathBuf::new(),
+        }
+    }
+
+    fn spans() -> Vec<ZipkinSpan> {
+        serde_json::from_str(SPANS).unwrap()
+    }
+
+    #[test]
+    fn folds_shared_spans() {
+        let events = reader().to_events_edges(&spans()).unwrap();
+        let entries: Vec<&UberEvent> = events
+            .iter()
+            .filter(|e| e.e.variant == EventType::Entry)
+            .collect();
+        // The server side of the RPC doesn't get its own span
+        assert_eq!(entries.len(), 2);
+        let rpc = entries
+            .iter()
+            .find(|e| e.parent_id.is_none())
+            .expect("No root span");
+        assert_eq!(rpc.e.tracepoint_id, TracepointID::from_str("frontend/get"));
+        assert_eq!(
+            rpc.e.key_value_pair.get("service"),
+            Some(&Value::Str("frontend".to_string()))
+        );
+        assert_eq!(
+            rpc.e.key_value_pair.get("db.rows"),
+            Some(&Value::Str("3".to_string()))
+        );
+        assert_eq!(
+            rpc.e.key_value_pair.get("kind"),
+            Some(&Value::Str("CLIENT".to_string()))
+        );
+        let annotations: Vec<&UberEvent> = events
+            .iter()
+            .filter(|e| e.e.variant == EventType::Annotation)
+            .collect();
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(
+            annotations[0].e.tracepoint_id,
+            TracepointID::from_str("backend/get:cache miss")
+        );
+    }
+
+    #[test]
+    fn builds_trace() {
+        let trace = reader()
+            .trace_from_spans("463ac35c9f6413ad", &spans())
+            .unwrap();
+        // Entry and exit of both spans, and the annotation
+        assert_eq!(trace.g.node_count(), 5);
+        assert_eq!(trace.duration, std::time::Duration::from_millis(400));
+    }
+
+    #[test]
+    fn parses_ids() {
+        assert!(span_id_to_hexid("a2fb4a1d1a96d312").is_ok());
+        assert!(span_id_to_hexid("a2fb").is_err());
+        assert!(span_id_to_hexid("not hex!").is_err());
+        let short = trace_id_to_uuid("463ac35c9f6413ad").unwrap();
+        let long = trace_id_to_uuid("0000000000000000463ac35c9f6413ad").unwrap();
+        assert_ne!(short, long);
+        assert!(trace_id_to_uuid("463a").is_err());
+    }
+}
@@ -8,6 +8,7 @@ All rights reserved.
 
 //! Methods that talk to Pythia agents.
 
+use chrono::Utc;
 use futures::future::Future;
 use futures::stream::Stream;
 use futures::Async;
@@ -60,6 +61,10 @@ impl PythiaClient {
     fn free_keys(&self, keys: Vec<String>) -> impl Future<Item = (), Error = RpcError> {
         self.0.call_method("free_keys", "", (keys,))
     }
+
+    fn get_clock(&self) -> impl Future<Item = (String, i64), Error = RpcError> {
+        self.0.call_method("get_clock", "", ())
+    }
 }
 
 /// Read the overhead stats from the agent
@@ -114,15 +119,17 @@ pub fn get_events_from_client(client_uri: &str, trace_id: Uuid) -> Vec<OSProfile
             Ok(Async::Ready(Some(v))) => {
                 let traces = match v {
                     Value::Array(o) => o,
-                    _ => panic!("Got something weird from request {:?}", v),
+                    _ => {
+                        eprintln!("Got something weird from request {:?}", v);
+                        continue;
+                    }
                 };
-                final_result.extend(
-                    traces
-                        .iter()
-                        .map(|x| x.to_string())
-                        .map(|x: String| serde_json::from_str(&x).unwrap())
-                        .collect::<Vec<OSProfilerSpan>>(),
-                );
+                for x in traces {
+                    match serde_json::from_value::<OSProfilerSpan>(x) {
+                        Ok(span) => final_result.push(span),
+                        Err(e) => eprintln!("Skipping malformed span: {}", e),
+                    }
+                }
             }
             Ok(Async::NotReady) => {}
             Ok(Async::Ready(None)) => {
@@ -228,3 +235,36 @@ pub fn free_keys(client_uri: &str, keys: Vec<String>) {
         }
     }
 }
+
+/// Estimates how much to add to the agent's timestamps to match the local clock, assuming the
+/// agent read its clock halfway through the request. Returns the agent's host name and the
+/// offset in nanoseconds, or `None` if the agent can't be reached.
+pub fn get_clock_offset(client_uri: &str) -> Option<(String, i64)> {
+    let (tx, mut rx) = futures::sync::mpsc::unbounded();
+
+    let run = http::connect(client_uri)
+        .and_then(move |client: PythiaClient| {
+            let sent = Utc::now();
+            client.get_clock().and_then(move |(host, agent_time)| {
+                drop(client);
+                let received = Utc::now();
+                let _ = tx.unbounded_send((host, agent_time, sent, received));
+                Ok(())
+            })
+        })
+        .map_err(|e| eprintln!("RPC Client error: {:?}", e));
+
+    rt::run(run);
+    loop {
+        match rx.poll() {
+            Ok(Async::Ready(Some((host, agent_time, sent, received)))) => {
+                let midpoint = sent + (received - sent) / 2;
+                return Some((host, midpoint.timestamp_nanos() - agent_time));
+            }
+            Ok(Async::NotReady) => {}
+            Ok(Async::Ready(None)) | Err(_) => {
+                return None;
+            }
+        }
+    }
+}
@@ -171,7 +171,7 @@ mod tests {
         let mut manifest = MANIFEST.clone();
         let mut paths: Vec<HierarchicalCriticalPath> = manifest
             .per_request_type
-            .get_mut(&RequestType::ServerCreate)
+            .get_mut(&RequestType::new("ServerCreate"))
             .unwrap()
             .paths
             .values()
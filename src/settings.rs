@@ -12,11 +12,19 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 use std::time::Duration;
 
-use config::{Config, File, FileFormat};
+use config::{Config, ConfigError, File, FileFormat};
 
+use pythia_common::read_request_types;
+use pythia_common::RequestTypeRules;
+
+use crate::grouping::{DetectorComposition, ProblemDetectorType};
 use crate::search::SearchStrategyType;
+use crate::slo::{SLODefinition, SLO};
+use crate::streaming::StatsScope;
 
 const SETTINGS_PATH: &str = "/etc/pythia/controller.toml";
+const REQUEST_TYPES_PATH: &str = "/etc/pythia/request_types.toml";
+const SLOS_PATH: &str = "/etc/pythia/slos.toml";
 const DECISION_EPOCH: Duration = Duration::from_secs(120);
 const PYTHIA_JIFFY: Duration = Duration::from_secs(20);
 const GC_EPOCH: Duration = Duration::from_secs(120);
@@ -26,6 +34,36 @@ const DISABLE_RATIO: f32 = 0.1;
 const TRACE_SIZE_LIMIT: u32 = 100000000;
 const N_WORKERS: usize = 4;
 const FREE_KEYS: bool = false;
+const SKEW_CORRECTION: bool = true;
+const AGENT_CLOCK_OFFSETS: bool = false;
+const INFER_REQUEST_TYPES: bool = false;
+const INFERENCE_SIMILARITY: f64 = 0.5;
+const NODE_KEYS: &str = "host,lock_queue,error_type";
+const ATTRIBUTION_THRESHOLD: f64 = 0.3;
+const STATS_WINDOW: Duration = Duration::from_secs(600);
+const STATS_HALF_LIFE: Duration = Duration::from_secs(300);
+const PROBLEM_DETECTORS: &str = "CV";
+const MIN_GROUP_TRACES: usize = 3;
+const CV_THRESHOLD: f64 = 0.05;
+const SLOW_PERCENTILE: f64 = 95.0;
+const SLO_QUANTILE: f64 = 0.99;
+const SLO_LATENCY: Duration = Duration::from_secs(5);
+const SLO_WINDOW: Duration = Duration::from_secs(3600);
+const MODE_MIN_SHARE: f64 = 0.1;
+const MULTIMODAL_EDGES: bool = true;
+const CHANGE_WARMUP: usize = 50;
+const CHANGE_THRESHOLD: f64 = 8.0;
+const CHANGE_TAIL_QUANTILE: f64 = 0.95;
+const OUTLIER_THRESHOLD: f64 = 3.5;
+const OTEL_TRACE_DIR: &str = "/var/lib/pythia/otel";
+const ZIPKIN_TRACE_DIR: &str = "/var/lib/pythia/zipkin";
+const JAEGER_LOOKBACK: Duration = Duration::from_secs(300);
+const JAEGER_LIMIT: usize = 100;
+const WATCH_SETTLE_TIME: Duration = Duration::from_secs(10);
+const XTRACE_TAGS: &str = "FsShell";
+const XTRACE_PAGE_SIZE: usize = 100;
+const XTRACE_MAX_PAGES: usize = 10;
+const XTRACE_WINDOW: Duration = Duration::from_secs(600);
 
 #[derive(Debug)]
 pub struct Settings {
@@ -34,10 +72,19 @@ pub struct Settings {
     pub pythia_clients: Vec<String>,
     pub redis_url: String,
     pub xtrace_url: String,
+    pub xtrace_hex_ids: bool,
+    pub xtrace_tags: Vec<String>,
     pub uber_trace_dir: PathBuf,
     pub DEATHSTAR_trace_dir: PathBuf,
+    pub otel_trace_dir: PathBuf,
+    pub zipkin_trace_dir: PathBuf,
+    pub jaeger_url: Option<String>,
+    pub jaeger_services: Vec<String>,
+    pub watch_dir: Option<PathBuf>,
+    pub watch_record_file: Option<PathBuf>,
     pub hdfs_control_file: PathBuf,
     pub deathstar_control_file: PathBuf,
+    pub request_types: RequestTypeRules,
 
     pub search_strategy: SearchStrategyType,
     pub jiffy: Duration,
@@ -49,6 +96,59 @@ pub struct Settings {
     pub trace_size_limit: u32,
     pub n_workers: usize,
     pub free_keys: bool,
+    pub skew_correction: bool,
+    pub agent_clock_offsets: bool,
+    pub infer_request_types: bool,
+    /// Jaccard similarity for traces to get the same inferred request type
+    pub inference_similarity: f64,
+    /// Keys whose values are kept in the nodes of groups
+    pub node_keys: Vec<String>,
+    /// Keys whose values split groups into child groups, empty to group by path only
+    pub group_split_keys: Vec<String>,
+    /// Share of an edge's latency variance an attribute has to explain to be reported
+    pub attribution_threshold: f64,
+    /// Which durations the statistics of groups in the controller reflect
+    pub group_stats: StatsScope,
+    pub stats_window: Duration,
+    pub stats_half_life: Duration,
+    /// Detectors that choose problem groups, and how their choices are combined
+    pub problem_detectors: Vec<ProblemDetectorType>,
+    pub detector_composition: DetectorComposition,
+    /// Groups need more traces than this to be problem groups
+    pub min_group_traces: usize,
+    pub cv_threshold: f64,
+    /// Percentile of the means of all groups that slow groups are above
+    pub slow_percentile: f64,
+    /// Latency objectives of request types. Those without one should have `slo_quantile` of
+    /// their requests under `slo_latency`. Compliance is over a rolling window of `slo_window`,
+    /// unless the objective has its own.
+    pub slos: Vec<SLO>,
+    pub slo_quantile: f64,
+    pub slo_latency: Duration,
+    pub slo_window: Duration,
+    /// Share of the traces each mode of a multimodal group needs to have
+    pub mode_min_share: f64,
+    /// Whether problem edges with two or more latency modes come before the others
+    pub multimodal_edges: bool,
+    /// Change-point detection on the latency of groups: durations in the baseline, certainty
+    /// (log-likelihood ratio) of a change, and quantile of the baseline where the tail starts
+    pub change_warmup: usize,
+    pub change_threshold: f64,
+    pub change_tail_quantile: f64,
+    /// Robust z-score above which a trace is reported as an outlier of its group
+    pub outlier_threshold: f64,
+    /// Jaeger is polled for the traces of the last `jaeger_lookback`, at most `jaeger_limit` per
+    /// service
+    pub jaeger_lookback: Duration,
+    pub jaeger_limit: usize,
+    /// Watched files that are not modified for this long are read even if they were not seen
+    /// before
+    pub watch_settle_time: Duration,
+    /// X-Trace tasks are listed `xtrace_page_size` at a time, up to `xtrace_max_pages` pages, if
+    /// they were updated in the last `xtrace_window`
+    pub xtrace_page_size: usize,
+    pub xtrace_max_pages: usize,
+    pub xtrace_window: Duration,
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -56,7 +156,10 @@ pub enum ApplicationType {
     HDFS,
     OpenStack,
     Uber,
-    DEATHSTAR
+    DEATHSTAR,
+    OpenTelemetry,
+    Zipkin,
+    XTrace,
 }
 
 impl Settings {
@@ -75,22 +178,107 @@ impl Settings {
         } else {
             pythia_clients.split(",").map(|x| x.to_string()).collect()
         };
+        let xtrace_tags = results
+            .get("xtrace_tags")
+            .map(|x| x.as_str())
+            .unwrap_or(XTRACE_TAGS)
+            .split(',')
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+            .collect();
+        let request_types = read_request_types(
+            results
+                .get("request_types_file")
+                .map(|x| x.as_str())
+                .unwrap_or(REQUEST_TYPES_PATH),
+        );
+        let node_keys = split_keys(
+            results
+                .get("node_keys")
+                .map(|x| x.as_str())
+                .unwrap_or(NODE_KEYS),
+        );
+        let slo_window = results
+            .get("slo_window")
+            .map(|x| Duration::from_secs(x.parse().unwrap()))
+            .unwrap_or(SLO_WINDOW);
+        let slos = read_slos(
+            results
+                .get("slos_file")
+                .map(|x| x.as_str())
+                .unwrap_or(SLOS_PATH),
+            slo_window,
+        );
+        let group_split_keys = split_keys(
+            results
+                .get("group_split_keys")
+                .map(|x| x.as_str())
+                .unwrap_or(""),
+        );
+        let problem_detectors = results
+            .get("problem_detectors")
+            .map(|x| x.as_str())
+            .unwrap_or(PROBLEM_DETECTORS)
+            .split(",")
+            .map(|x| match x.trim() {
+                "Variance" => ProblemDetectorType::Variance,
+                "CV" => ProblemDetectorType::CV,
+                "Slow" => ProblemDetectorType::Slow,
+                "SLO" => ProblemDetectorType::SLO,
+                "Multimodal" => ProblemDetectorType::Multimodal,
+                "Change" => ProblemDetectorType::Change,
+                "Errors" => ProblemDetectorType::Errors,
+                other => panic!("Unknown problem detector {}", other),
+            })
+            .collect();
+        let jaeger_services = match results.get("jaeger_services") {
+            Some(s) if !s.is_empty() => s.split(",").map(|x| x.to_string()).collect(),
+            _ => Vec::new(),
+        };
         Settings {
             manifest_file,
             hdfs_control_file,
             deathstar_control_file,
+            request_types,
+            node_keys,
+            group_split_keys,
             pythia_clients,
             redis_url: results.get("redis_url").unwrap().to_string(),
             uber_trace_dir: PathBuf::from(results.get("uber_trace_dir").unwrap()),
             DEATHSTAR_trace_dir: PathBuf::from(results.get("DEATHSTAR_trace_dir").unwrap()),
+            otel_trace_dir: PathBuf::from(
+                results
+                    .get("otel_trace_dir")
+                    .map(|x| x.as_str())
+                    .unwrap_or(OTEL_TRACE_DIR),
+            ),
+            zipkin_trace_dir: PathBuf::from(
+                results
+                    .get("zipkin_trace_dir")
+                    .map(|x| x.as_str())
+                    .unwrap_or(ZIPKIN_TRACE_DIR),
+            ),
+            jaeger_url: results.get("jaeger_url").cloned(),
+            jaeger_services,
+            watch_dir: results.get("watch_dir").map(PathBuf::from),
+            watch_record_file: results.get("watch_record_file").map(PathBuf::from),
             application: match results.get("application").unwrap().as_str() {
                 "OpenStack" => ApplicationType::OpenStack,
                 "HDFS" => ApplicationType::HDFS,
                 "Uber" => ApplicationType::Uber,
                 "DEATHSTAR" => ApplicationType::DEATHSTAR,
+                "OpenTelemetry" => ApplicationType::OpenTelemetry,
+                "Zipkin" => ApplicationType::Zipkin,
+                "XTrace" => ApplicationType::XTrace,
                 _ => panic!("Unknown application type"),
             },
             xtrace_url: results.get("xtrace_url").unwrap().to_string(),
+            xtrace_hex_ids: match results.get("xtrace_event_ids").map(|x| x.as_str()) {
+                None | Some("decimal") => false,
+                Some("hex") => true,
+                _ => panic!("Unknown X-Trace event id format"),
+            },
+            xtrace_tags,
             decision_epoch: DECISION_EPOCH,
             search_strategy: match results.get("search_strategy").unwrap().as_str() {
                 "Flat" => SearchStrategyType::Flat,
@@ -106,6 +294,162 @@ impl Settings {
             trace_size_limit: TRACE_SIZE_LIMIT,
             n_workers: N_WORKERS,
             free_keys: FREE_KEYS,
+            skew_correction: results
+                .get("skew_correction")
+                .map(|x| x == "true")
+                .unwrap_or(SKEW_CORRECTION),
+            agent_clock_offsets: results
+                .get("agent_clock_offsets")
+                .map(|x| x == "true")
+                .unwrap_or(AGENT_CLOCK_OFFSETS),
+            infer_request_types: results
+                .get("infer_request_types")
+                .map(|x| x == "true")
+                .unwrap_or(INFER_REQUEST_TYPES),
+            inference_similarity: results
+                .get("inference_similarity")
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(INFERENCE_SIMILARITY),
+            attribution_threshold: results
+                .get("attribution_threshold")
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(ATTRIBUTION_THRESHOLD),
+            group_stats: match results.get("group_stats").map(|x| x.as_str()) {
+                None | Some("window") => StatsScope::Window,
+                Some("all") => StatsScope::All,
+                Some("decayed") => StatsScope::Decayed,
+                _ => panic!("Unknown group statistics scope"),
+            },
+            stats_window: results
+                .get("stats_window")
+                .map(|x| Duration::from_secs(x.parse().unwrap()))
+                .unwrap_or(STATS_WINDOW),
+            stats_half_life: results
+                .get("stats_half_life")
+                .map(|x| Duration::from_secs(x.parse().unwrap()))
+                .unwrap_or(STATS_HALF_LIFE),
+            problem_detectors,
+            detector_composition: match results.get("detector_composition").map(|x| x.as_str()) {
+                None | Some("Any") => DetectorComposition::Any,
+                Some("All") => DetectorComposition::All,
+                Some(other) => panic!("Unknown detector composition {}", other),
+            },
+            min_group_traces: results
+                .get("min_group_traces")
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(MIN_GROUP_TRACES),
+            cv_threshold: results
+                .get("cv_threshold")
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(CV_THRESHOLD),
+            slow_percentile: results
+                .get("slow_percentile")
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(SLOW_PERCENTILE),
+            slos,
+            slo_quantile: results
+                .get("slo_quantile")
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(SLO_QUANTILE),
+            slo_latency: results
+                .get("slo_latency")
+                .map(|x| Duration::from_secs_f64(x.parse().unwrap()))
+                .unwrap_or(SLO_LATENCY),
+            slo_window,
+            mode_min_share: results
+                .get("mode_min_share")
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(MODE_MIN_SHARE),
+            multimodal_edges: results
+                .get("multimodal_edges")
+                .map(|x| x == "true")
+                .unwrap_or(MULTIMODAL_EDGES),
+            change_warmup: results
+                .get("change_warmup")
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(CHANGE_WARMUP),
+            change_threshold: results
+                .get("change_threshold")
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(CHANGE_THRESHOLD),
+            change_tail_quantile: results
+                .get("change_tail_quantile")
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(CHANGE_TAIL_QUANTILE),
+            outlier_threshold: results
+                .get("outlier_threshold")
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(OUTLIER_THRESHOLD),
+            jaeger_lookback: results
+                .get("jaeger_lookback")
+                .map(|x| Duration::from_secs(x.parse().unwrap()))
+                .unwrap_or(JAEGER_LOOKBACK),
+            jaeger_limit: results
+                .get("jaeger_limit")
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(JAEGER_LIMIT),
+            watch_settle_time: results
+                .get("watch_settle_time")
+                .map(|x| Duration::from_secs(x.parse().unwrap()))
+                .unwrap_or(WATCH_SETTLE_TIME),
+            xtrace_page_size: results
+                .get("xtrace_page_size")
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(XTRACE_PAGE_SIZE),
+            xtrace_max_pages: results
+                .get("xtrace_max_pages")
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(XTRACE_MAX_PAGES),
+            xtrace_window: results
+                .get("xtrace_window")
+                .map(|x| Duration::from_secs(x.parse().unwrap()))
+                .unwrap_or(XTRACE_WINDOW),
         }
     }
 }
+
+/// Keys in a comma-separated list, without spaces around them or empty entries
+fn split_keys(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_string())
+        .collect()
+}
+
+/// Reads latency objectives from the `[[slo]]` tables of a toml file. Without the file, there are
+/// none.
+fn read_slos(path: &str, window: Duration) -> Vec<SLO> {
+    if !std::path::Path::new(path).exists() {
+        return Vec::new();
+    }
+    let mut settings = Config::default();
+    settings.merge(File::new(path, FileFormat::Toml)).unwrap();
+    let definitions = match settings.get::<Vec<SLODefinition>>("slo") {
+        Ok(d) => d,
+        Err(ConfigError::NotFound(_)) => Vec::new(),
+        Err(e) => panic!("Couldn't read SLOs from {}: {}", path, e),
+    };
+    definitions
+        .iter()
+        .map(|d| {
+            SLO::from_definition(d, window)
+                .unwrap_or_else(|e| panic!("Couldn't read SLOs from {}: {}", path, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_keys() {
+        assert_eq!(
+            split_keys(" host, lock_queue,,error_type ,"),
+            vec!["host", "lock_queue", "error_type"]
+        );
+        assert!(split_keys("").is_empty());
+        assert!(split_keys(" , ").is_empty());
+    }
+}
@@ -0,0 +1,419 @@
+/*
+This source code is licensed under the BSD-style license found in the
+LICENSE file in the root directory of this source tree.
+
+Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
+All rights reserved.
+*/
+
+//! Latency objectives (SLOs) per request type and how well requests meet them.
+//!
+//! An objective says that a quantile of the latency of a request type should be under a limit,
+//! e.g., 99% of `ServerCreate` requests should take less than 5 seconds. Compliance is the share
+//! of the requests in a rolling window of trace time that were under the limit, and the objective
+//! is violated while it's below the quantile. Each group keeps its own count of the requests over
+//! the limit, so groups can be ranked by how much of the violating tail they make up.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+
+use pythia_common::RequestType;
+
+use crate::settings::Settings;
+use crate::streaming::{Merge, Slices};
+use crate::PythiaError;
+
+/// An objective as it's written in the configuration
+#[derive(Deserialize, Debug, Clone)]
+pub struct SLODefinition {
+    pub request_type: String,
+    /// Share of the requests that should be under the latency, between 0 and 1
+    pub quantile: f64,
+    /// Latency in seconds
+    pub latency: f64,
+    /// Length of the rolling window in seconds, `slo_window` if not given
+    pub window: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SLO {
+    pub request_type: RequestType,
+    pub quantile: f64,
+    pub latency: Duration,
+    pub window: Duration,
+}
+
+impl SLO {
+    /// The objective of a definition, whose request type has to be defined already or be an
+    /// inferred one, so that a misspelled name isn't an objective no request meets
+    pub fn from_definition(
+        definition: &SLODefinition,
+        window: Duration,
+    ) -> Result<Self, PythiaError> {
+        let name = &definition.request_type;
+        let request_type = if RequestType::is_inferred_name(name) {
+            RequestType::new(name)
+        } else {
+            RequestType::from_str(name)
+                .map_err(|_| PythiaError(format!("Unknown request type {} in SLO", name)))?
+        };
+        Ok(SLO {
+            request_type,
+            quantile: definition.quantile,
+            latency: Duration::from_secs_f64(definition.latency),
+            window: definition.window.map(Duration::from_secs).unwrap_or(window),
+        })
+    }
+}
+
+/// Numbers of requests and requests over the latency of an objective
+#[derive(Debug, Clone, Copy, Default)]
+struct Counts {
+    requests: usize,
+    violating: usize,
+}
+
+impl Merge for Counts {
+    fn merge(&mut self, other: &Counts) {
+        self.requests += other.requests;
+        self.violating += other.violating;
+    }
+}
+
+/// Counts of the requests in a rolling window. Requests leave the window a slice at a time.
+#[derive(Debug, Clone)]
+struct SLOWindow {
+    slices: Slices<Counts>,
+}
+
+impl SLOWindow {
+    fn new(window: Duration) -> Self {
+        SLOWindow {
+            slices: Slices::new(window, Counts::default()),
+        }
+    }
+
+    fn add(&mut self, time: NaiveDateTime, violating: bool) {
+        self.slices.add(time, |counts| {
+            counts.requests += 1;
+            if violating {
+                counts.violating += 1;
+            }
+        });
+    }
+
+    fn expire(&mut self, now: NaiveDateTime) {
+        self.slices.expire(now);
+    }
+
+    fn requests(&self) -> usize {
+        self.slices.total().requests
+    }
+
+    fn violating(&self) -> usize {
+        self.slices.total().violating
+    }
+}
+
+/// How well the requests of a type meet their objective
+#[derive(Debug, Clone)]
+pub struct SLOCompliance {
+    pub slo: SLO,
+    /// Requests in the window, and those over the latency
+    pub requests: usize,
+    pub violating: usize,
+}
+
+impl SLOCompliance {
+    /// Share of the requests under the latency, 1 if there are none
+    pub fn compliance(&self) -> f64 {
+        if self.requests == 0 {
+            1.0
+        } else {
+            1.0 - self.violating as f64 / self.requests as f64
+        }
+    }
+
+    pub fn is_violated(&self) -> bool {
+        self.compliance() < self.slo.quantile
+    }
+}
+
+impl fmt::Display for SLOCompliance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:.2}% of {} requests under {:?} in the last {:?}, objective {:.2}%{}",
+            self.slo.request_type,
+            self.compliance() * 100.0,
+            self.requests,
+            self.slo.latency,
+            self.slo.window,
+            self.slo.quantile * 100.0,
+            if self.is_violated() { ", violated" } else { "" }
+        )
+    }
+}
+
+/// Tracks the objectives of all request types, and how many requests over the latency each
+/// group has
+#[derive(Debug, Clone)]
+pub struct SLOTracker {
+    slos: HashMap<RequestType, SLO>,
+    /// Quantile, latency and window of the objective of request types that don't have their own
+    default: (f64, Duration, Duration),
+    request_types: HashMap<RequestType, SLOWindow>,
+    groups: HashMap<(RequestType, String), SLOWindow>,
+}
+
+impl SLOTracker {
+    /// The objectives of the configuration. Request types without one get `slo_quantile` under
+    /// `slo_latency`.
+    pub fn from_settings(settings: &Settings) -> Self {
+        SLOTracker::new(
+            &settings.slos,
+            settings.slo_quantile,
+            settings.slo_latency,
+            settings.slo_window,
+        )
+    }
+
+    /// Tracks `slos`, and `quantile` under `latency` in a `window` for other request types
+    pub fn new(slos: &[SLO], quantile: f64, latency: Duration, window: Duration) -> Self {
+        SLOTracker {
+            slos: slos.iter().map(|slo| (slo.request_type, *slo)).collect(),
+            default: (quantile, latency, window),
+            request_types: HashMap::new(),
+            groups: HashMap::new(),
+        }
+    }
+
+    pub fn slo(&self, request_type: RequestType) -> SLO {
+        match self.slos.get(&request_type) {
+            Some(slo) => *slo,
+            None => {
+                let (quantile, latency, window) = self.default;
+                SLO {
+                    request_type,
+                    quantile,
+                    latency,
+                    window,
+                }
+            }
+        }
+    }
+
+    /// Adds a request of the type that ended at `time`, and that is in the groups with `hashes`
+    pub fn add(
+        &mut self,
+        request_type: RequestType,
+        hashes: &[String],
+        time: NaiveDateTime,
+        duration: Duration,
+    ) {
+        let slo = self.slo(request_type);
+        let violating = duration > slo.latency;
+        self.request_types
+            .entry(request_type)
+            .or_insert_with(|| SLOWindow::new(slo.window))
+            .add(time, violating);
+        for hash in hashes {
+            self.groups
+                .entry((request_type, hash.clone()))
+                .or_insert_with(|| SLOWindow::new(slo.window))
+                .add(time, violating);
+        }
+    }
+
+    /// Moves the windows to end at `now`
+    pub fn expire(&mut self, now: NaiveDateTime) {
+        for window in self.request_types.values_mut() {
+            window.expire(now);
+        }
+        for window in self.groups.values_mut() {
+            window.expire(now);
+        }
+    }
+
+    /// Forgets the requests of the group, e.g., after it's used for a decision
+    pub fn reset_group(&mut self, request_type: RequestType, hash: &str) {
+        self.groups.remove(&(request_type, hash.to_string()));
+    }
+
+    /// Compliance of the request types that had requests in their window
+    pub fn compliance(&self) -> Vec<SLOCompliance> {
+        let mut result: Vec<SLOCompliance> = self
+            .request_types
+            .iter()
+            .filter(|(_, window)| window.requests() != 0)
+            .map(|(&request_type, window)| SLOCompliance {
+                slo: self.slo(request_type),
+                requests: window.requests(),
+                violating: window.violating(),
+            })
+            .collect();
+        result.sort_by(|a, b| {
+            a.compliance()
+                .partial_cmp(&b.compliance())
+                .unwrap_or(Ordering::Equal)
+        });
+        result
+    }
+
+    /// Share of the requests over the latency of a violated objective that are in the group, 0 if
+    /// the objective of its request type is met
+    pub fn tail_share(&self, request_type: RequestType, hash: &str) -> f64 {
+        let window = match self.request_types.get(&request_type) {
+            Some(window) => window,
+            None => return 0.0,
+        };
+        let compliance = SLOCompliance {
+            slo: self.slo(request_type),
+            requests: window.requests(),
+            violating: window.violating(),
+        };
+        if !compliance.is_violated() {
+            return 0.0;
+        }
+        self.groups
+            .get(&(request_type, hash.to_string()))
+            .map_or(0.0, |g| g.violating() as f64 / compliance.violating as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(secs: i64) -> NaiveDateTime {
+        NaiveDateTime::from_timestamp_opt(secs, 0).unwrap()
+    }
+
+    fn definition(request_type: &str, window: Option<u64>) -> SLODefinition {
+        SLODefinition {
+            request_type: request_type.to_string(),
+            quantile: 0.99,
+            latency: 0.5,
+            window,
+        }
+    }
+
+    #[test]
+    fn reads_objectives_of_known_request_types() {
+        let known = RequestType::new("SLOKnown");
+        let window = Duration::from_secs(600);
+        let slo = SLO::from_definition(&definition("SLOKnown", None), window).unwrap();
+        assert!(slo.request_type == known);
+        assert_eq!(slo.latency, Duration::from_millis(500));
+        assert_eq!(slo.window, window);
+        let slo = SLO::from_definition(&definition("SLOKnown", Some(30)), window).unwrap();
+        assert_eq!(slo.window, Duration::from_secs(30));
+
+        assert!(SLO::from_definition(&definition("SLOMisspelled", None), window).is_err());
+        assert!(RequestType::from_str("SLOMisspelled").is_err());
+        // Inferred request types are only defined once traces are classified
+        assert!(RequestType::from_str("Inferred1000").is_err());
+        let slo = SLO::from_definition(&definition("Inferred1000", None), window).unwrap();
+        assert!(slo.request_type == RequestType::inferred(1000));
+    }
+
+    #[test]
+    fn counts_requests_in_the_window() {
+        let mut window = SLOWindow::new(Duration::from_secs(60));
+        window.add(at(0), true);
+        window.add(at(30), false);
+        window.add(at(31), true);
+        assert_eq!((window.requests(), window.violating()), (3, 2));
+
+        // The window is 60s in slices of 6s, so the slice of the first request ends at 6s
+        window.expire(at(59));
+        assert_eq!((window.requests(), window.violating()), (3, 2));
+        window.expire(at(65));
+        assert_eq!((window.requests(), window.violating()), (2, 1));
+        window.add(at(1), true);
+        assert_eq!((window.requests(), window.violating()), (2, 1));
+        window.expire(at(100));
+        assert_eq!((window.requests(), window.violating()), (0, 0));
+    }
+
+    #[test]
+    fn compliance() {
+        let slo = SLO {
+            request_type: RequestType::new("SLOCompliance"),
+            quantile: 0.99,
+            latency: Duration::from_secs(1),
+            window: Duration::from_secs(60),
+        };
+        let mut compliance = SLOCompliance {
+            slo,
+            requests: 100,
+            violating: 2,
+        };
+        assert!((compliance.compliance() - 0.98).abs() < 1e-12);
+        assert!(compliance.is_violated());
+        assert!(compliance.to_string().ends_with(", violated"));
+        compliance.slo.quantile = 0.95;
+        assert!(!compliance.is_violated());
+        compliance.requests = 0;
+        compliance.violating = 0;
+        assert_eq!(compliance.compliance(), 1.0);
+        assert!(!compliance.is_violated());
+    }
+
+    #[test]
+    fn ranks_groups_by_tail_share() {
+        let slow = RequestType::new("SLOTailSlow");
+        let fast = RequestType::new("SLOTailFast");
+        let objective = SLO {
+            request_type: fast,
+            quantile: 0.5,
+            latency: Duration::from_millis(100),
+            window: Duration::from_secs(60),
+        };
+        let mut slos = SLOTracker::new(
+            &[objective],
+            0.9,
+            Duration::from_millis(100),
+            Duration::from_secs(60),
+        );
+        let hashes = |hash: &str| vec![hash.to_string(), format!("{}/child", hash)];
+        for i in 0..10 {
+            let (hash, millis) = match i {
+                0..=3 => ("a", 200),
+                4 => ("b", 200),
+                _ => ("b", 50),
+            };
+            slos.add(slow, &hashes(hash), at(i), Duration::from_millis(millis));
+            // Two of ten fast requests are over the latency, which meets their objective
+            let millis = if i < 2 { 200 } else { 50 };
+            slos.add(fast, &hashes("a"), at(i), Duration::from_millis(millis));
+        }
+
+        let compliance = slos.compliance();
+        assert_eq!(compliance.len(), 2);
+        assert!(compliance[0].slo.request_type == slow);
+        assert_eq!((compliance[0].requests, compliance[0].violating), (10, 5));
+        assert!(compliance[0].is_violated() && !compliance[1].is_violated());
+
+        assert!((slos.tail_share(slow, "a") - 0.8).abs() < 1e-12);
+        assert!((slos.tail_share(slow, "a/child") - 0.8).abs() < 1e-12);
+        assert!((slos.tail_share(slow, "b") - 0.2).abs() < 1e-12);
+        assert_eq!(slos.tail_share(slow, "c"), 0.0);
+        assert_eq!(slos.tail_share(fast, "a"), 0.0);
+
+        // Resetting a group forgets its requests, but not those of its request type
+        slos.reset_group(slow, "a");
+        assert_eq!(slos.tail_share(slow, "a"), 0.0);
+        assert!((slos.tail_share(slow, "a/child") - 0.8).abs() < 1e-12);
+        assert_eq!(slos.compliance()[0].violating, 5);
+
+        slos.expire(at(100));
+        assert!(slos.compliance().is_empty());
+        assert_eq!(slos.tail_share(slow, "b"), 0.0);
+    }
+}
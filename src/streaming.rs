@@ -0,0 +1,1928 @@
+/*
+This source code is licensed under the BSD-style license found in the
+LICENSE file in the root directory of this source tree.
+
+Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
+All rights reserved.
+*/
+
+//! Streaming estimators for latency statistics.
+//!
+//! Groups see traces for as long as the controller runs, so instead of keeping every duration
+//! they keep estimators that take constant memory (or memory that grows with the log of the
+//! range of the values) and are updated in constant time.
+//!
+//! To reflect current behavior rather than everything since startup, durations are also kept in
+//! a sliding window and with exponential decay. Both go by the time the traces ended rather than
+//! the wall clock, so traces read from files are treated the same as live ones.
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::Duration;
+
+use chrono::NaiveDateTime;
+use rand::Rng;
+
+use crate::settings::Settings;
+
+/// Count, mean, variance, minimum and maximum with Welford's algorithm
+#[derive(Debug, Clone, Default)]
+pub struct RunningStats {
+    count: usize,
+    mean: f64,
+    m2: f64,
+    min: f64,
+    max: f64,
+}
+
+impl RunningStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+        if self.count == 1 || x < self.min {
+            self.min = x;
+        }
+        if self.count == 1 || x > self.max {
+            self.max = x;
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Population variance, like `stats::variance`
+    pub fn variance(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.m2 / self.count as f64
+        }
+    }
+
+    /// Sum of squared differences from the mean
+    pub fn sum_of_squares(&self) -> f64 {
+        self.m2
+    }
+
+    /// Adds the values seen by `other`, as if they were added one by one
+    pub fn merge(&mut self, other: &RunningStats) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other.clone();
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.m2 += other.m2 + delta * delta * (self.count * other.count) as f64 / count as f64;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.count = count;
+    }
+
+    pub fn min(&self) -> Option<f64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.min)
+        }
+    }
+
+    pub fn max(&self) -> Option<f64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.max)
+        }
+    }
+}
+
+/// Means, variances and covariance of pairs of values with Welford's algorithm
+#[derive(Debug, Clone, Default)]
+pub struct RunningCovariance {
+    count: usize,
+    mean_x: f64,
+    mean_y: f64,
+    m2_x: f64,
+    m2_y: f64,
+    comoment: f64,
+}
+
+impl RunningCovariance {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, x: f64, y: f64) {
+        self.count += 1;
+        let n = self.count as f64;
+        let delta_x = x - self.mean_x;
+        let delta_y = y - self.mean_y;
+        self.mean_x += delta_x / n;
+        self.mean_y += delta_y / n;
+        self.m2_x += delta_x * (x - self.mean_x);
+        self.m2_y += delta_y * (y - self.mean_y);
+        self.comoment += delta_x * (y - self.mean_y);
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Population covariance
+    pub fn covariance(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.comoment / self.count as f64
+        }
+    }
+
+    /// Share of the variance of y explained by a line fit on x, `None` if either is constant
+    pub fn r_squared(&self) -> Option<f64> {
+        if self.m2_x == 0.0 || self.m2_y == 0.0 {
+            return None;
+        }
+        Some(self.comoment * self.comoment / (self.m2_x * self.m2_y))
+    }
+
+    /// Slope of the least-squares line of y on x, `None` if x is constant
+    pub fn slope(&self) -> Option<f64> {
+        if self.m2_x == 0.0 {
+            None
+        } else {
+            Some(self.comoment / self.m2_x)
+        }
+    }
+}
+
+/// Weighted statistics of fixed-length vectors of values, e.g., the durations of the edges of a
+/// critical path. Weights let them be kept with exponential decay, and merging lets them be kept
+/// by slice of a sliding window, see `TimedMoments`.
+pub trait Moments: Merge + Clone {
+    fn new(len: usize) -> Self;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn add(&mut self, x: &[f64], weight: f64);
+    /// Multiplies the weights of the vectors so far by `factor`
+    fn scale(&mut self, factor: f64);
+}
+
+/// Weighted means of fixed-length vectors of values and of their sums, and the co-moment of each
+/// value with the sum, with Welford's algorithm. The covariances of the values with the sum add
+/// up to the variance of the sum, so they split it among the values with memory linear in their
+/// number rather than quadratic like all pairwise covariances.
+#[derive(Debug, Clone)]
+pub struct SumCovariance {
+    weight: f64,
+    means: Vec<f64>,
+    sum_mean: f64,
+    comoments: Vec<f64>,
+}
+
+impl SumCovariance {
+    /// Sum of the weights of the vectors
+    pub fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    /// Population covariance of each value with the sum
+    pub fn covariances(&self) -> Vec<f64> {
+        if self.weight == 0.0 {
+            return vec![0.0; self.len()];
+        }
+        self.comoments.iter().map(|c| c / self.weight).collect()
+    }
+}
+
+impl Moments for SumCovariance {
+    fn new(len: usize) -> Self {
+        SumCovariance {
+            weight: 0.0,
+            means: vec![0.0; len],
+            sum_mean: 0.0,
+            comoments: vec![0.0; len],
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.means.len()
+    }
+
+    fn add(&mut self, x: &[f64], weight: f64) {
+        assert_eq!(x.len(), self.len());
+        self.weight += weight;
+        let sum: f64 = x.iter().sum();
+        self.sum_mean += (sum - self.sum_mean) * weight / self.weight;
+        for ((x, mean), comoment) in x
+            .iter()
+            .zip(self.means.iter_mut())
+            .zip(self.comoments.iter_mut())
+        {
+            let delta = x - *mean;
+            *mean += delta * weight / self.weight;
+            *comoment += weight * delta * (sum - self.sum_mean);
+        }
+    }
+
+    fn scale(&mut self, factor: f64) {
+        self.weight *= factor;
+        for comoment in self.comoments.iter_mut() {
+            *comoment *= factor;
+        }
+    }
+}
+
+impl Merge for SumCovariance {
+    fn merge(&mut self, other: &SumCovariance) {
+        if other.weight == 0.0 {
+            return;
+        }
+        let weight = self.weight + other.weight;
+        let share = other.weight / weight;
+        let sum_delta = other.sum_mean - self.sum_mean;
+        for i in 0..self.len() {
+            let delta = other.means[i] - self.means[i];
+            self.comoments[i] += other.comoments[i] + delta * sum_delta * self.weight * share;
+            self.means[i] += delta * share;
+        }
+        self.sum_mean += sum_delta * share;
+        self.weight = weight;
+    }
+}
+
+/// Weighted means and co-moments of all pairs of values of fixed-length vectors, with Welford's
+/// algorithm generalized to co-moments. Memory is quadratic in the number of values, so it's
+/// meant for a few of them.
+#[derive(Debug, Clone)]
+pub struct CoMoments {
+    weight: f64,
+    means: Vec<f64>,
+    /// Co-moments of the pairs `(i, j)` with `i <= j`, row by row
+    comoments: Vec<f64>,
+}
+
+impl CoMoments {
+    fn index(&self, i: usize, j: usize) -> usize {
+        let (i, j) = if i <= j { (i, j) } else { (j, i) };
+        i * self.len() - i * (i + 1) / 2 + j
+    }
+
+    /// Sum of the weights of the vectors
+    pub fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    /// Population covariance of values `i` and `j`, their variance if `i == j`
+    pub fn covariance(&self, i: usize, j: usize) -> f64 {
+        if self.weight == 0.0 {
+            0.0
+        } else {
+            self.comoments[self.index(i, j)] / self.weight
+        }
+    }
+}
+
+impl Moments for CoMoments {
+    fn new(len: usize) -> Self {
+        CoMoments {
+            weight: 0.0,
+            means: vec![0.0; len],
+            comoments: vec![0.0; len * (len + 1) / 2],
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.means.len()
+    }
+
+    fn add(&mut self, x: &[f64], weight: f64) {
+        assert_eq!(x.len(), self.len());
+        self.weight += weight;
+        let deltas: Vec<f64> = x
+            .iter()
+            .zip(self.means.iter())
+            .map(|(x, m)| x - m)
+            .collect();
+        for (mean, delta) in self.means.iter_mut().zip(deltas.iter()) {
+            *mean += delta * weight / self.weight;
+        }
+        for (i, delta) in deltas.iter().enumerate() {
+            for (j, (x, mean)) in x.iter().zip(self.means.iter()).enumerate().skip(i) {
+                let index = self.index(i, j);
+                self.comoments[index] += weight * delta * (x - mean);
+            }
+        }
+    }
+
+    fn scale(&mut self, factor: f64) {
+        self.weight *= factor;
+        for comoment in self.comoments.iter_mut() {
+            *comoment *= factor;
+        }
+    }
+}
+
+impl Merge for CoMoments {
+    fn merge(&mut self, other: &CoMoments) {
+        if other.weight == 0.0 {
+            return;
+        }
+        let weight = self.weight + other.weight;
+        let share = other.weight / weight;
+        let deltas: Vec<f64> = other
+            .means
+            .iter()
+            .zip(self.means.iter())
+            .map(|(o, m)| o - m)
+            .collect();
+        for i in 0..self.len() {
+            for j in i..self.len() {
+                let index = self.index(i, j);
+                self.comoments[index] +=
+                    other.comoments[index] + deltas[i] * deltas[j] * self.weight * share;
+            }
+        }
+        for (mean, delta) in self.means.iter_mut().zip(deltas.iter()) {
+            *mean += delta * share;
+        }
+        self.weight = weight;
+    }
+}
+
+/// A peak of a distribution
+#[derive(Debug, Clone, Copy)]
+pub struct Mode {
+    /// The value at the peak
+    pub value: f64,
+    /// Share of the values around the peak, up to the valleys on either side
+    pub share: f64,
+}
+
+/// Fewer values than this are not enough to tell peaks from noise
+const MIN_MODE_SAMPLES: usize = 20;
+/// Buckets per bin for finding modes, so bins are about 20% wide
+const MODE_BIN_BUCKETS: i32 = 10;
+/// Two peaks are separate modes if the valley between them is below this share of the lower peak
+const MODE_VALLEY: f64 = 0.5;
+
+/// Relative error of the quantiles returned by `QuantileSketch`
+const RELATIVE_ACCURACY: f64 = 0.01;
+/// Median absolute deviation of the normal distribution in standard deviations, so robust
+/// z-scores are comparable to z-scores
+const MAD_SCALE: f64 = 0.6745;
+/// Smallest median absolute deviation relative to the median for robust z-scores, so durations
+/// that barely vary don't make small differences outliers
+const MIN_RELATIVE_MAD: f64 = 0.05;
+
+/// Quantiles of positive values with bounded relative error, from counts of buckets whose sizes
+/// grow exponentially (as in DDSketch). Memory grows with the log of the range of the values,
+/// not with their number.
+#[derive(Debug, Clone)]
+pub struct QuantileSketch {
+    /// Logarithm of the ratio between the bounds of each bucket
+    ln_gamma: f64,
+    buckets: BTreeMap<i32, usize>,
+    /// Values too small for the buckets, i.e., zeros
+    zeros: usize,
+    count: usize,
+}
+
+impl QuantileSketch {
+    pub fn new() -> Self {
+        let gamma = (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY);
+        QuantileSketch {
+            ln_gamma: gamma.ln(),
+            buckets: BTreeMap::new(),
+            zeros: 0,
+            count: 0,
+        }
+    }
+
+    pub fn add(&mut self, x: f64) {
+        self.count += 1;
+        if x < 1.0 {
+            self.zeros += 1;
+        } else {
+            *self.buckets.entry(self.index(x)).or_insert(0) += 1;
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Adds the values seen by `other`
+    pub fn merge(&mut self, other: &QuantileSketch) {
+        for (&index, &count) in other.buckets.iter() {
+            *self.buckets.entry(index).or_insert(0) += count;
+        }
+        self.zeros += other.zeros;
+        self.count += other.count;
+    }
+
+    /// The value at quantile `q` (between 0 and 1), 0 if there are no values
+    pub fn quantile(&self, q: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let rank = (q.clamp(0.0, 1.0) * (self.count - 1) as f64) as usize;
+        let mut seen = self.zeros;
+        if rank < seen {
+            return 0.0;
+        }
+        for (&index, &count) in self.buckets.iter() {
+            seen += count;
+            if rank < seen {
+                return self.value(index);
+            }
+        }
+        self.buckets
+            .keys()
+            .next_back()
+            .map_or(0.0, |&i| self.value(i))
+    }
+
+    /// Lower bound, upper bound and count of the non-empty buckets, from the smallest values to
+    /// the largest
+    pub fn buckets<'a>(&'a self) -> impl Iterator<Item = (f64, f64, usize)> + 'a {
+        let zeros = Some((0.0, 1.0, self.zeros)).filter(|b| b.2 != 0);
+        zeros
+            .into_iter()
+            .chain(self.buckets.iter().map(move |(&i, &c)| {
+                (
+                    (self.ln_gamma * (i - 1) as f64).exp(),
+                    (self.ln_gamma * i as f64).exp(),
+                    c,
+                )
+            }))
+    }
+
+    /// Peaks of the distribution that hold at least `min_share` of the values, from the smallest
+    /// values to the largest. The buckets are combined into wider bins and smoothed, and
+    /// neighboring peaks count as one unless the valley between them is deep enough.
+    pub fn modes(&self, min_share: f64) -> Vec<Mode> {
+        let (first, last) = match (self.buckets.keys().next(), self.buckets.keys().next_back()) {
+            (Some(&first), Some(&last)) if self.count >= MIN_MODE_SAMPLES => (first, last),
+            _ => return Vec::new(),
+        };
+        let bin = |index: i32| ((index - first) / MODE_BIN_BUCKETS) as usize;
+        let mut counts = vec![0.0; bin(last) + 1];
+        for (&index, &count) in self.buckets.iter() {
+            counts[bin(index)] += count as f64;
+        }
+        let smoothed: Vec<f64> = (0..counts.len())
+            .map(|i| {
+                let left = if i > 0 { counts[i - 1] } else { 0.0 };
+                let right = counts.get(i + 1).cloned().unwrap_or(0.0);
+                0.25 * left + 0.5 * counts[i] + 0.25 * right
+            })
+            .collect();
+        // Each segment is a peak and the bins around it, split at the lowest point between peaks
+        let mut segments: Vec<(usize, usize, usize)> = Vec::new();
+        for i in 0..smoothed.len() {
+            let left = if i > 0 { smoothed[i - 1] } else { 0.0 };
+            let right = smoothed.get(i + 1).cloned().unwrap_or(0.0);
+            if smoothed[i] == 0.0 || smoothed[i] < left || smoothed[i] <= right {
+                continue;
+            }
+            match segments.last_mut() {
+                Some(last) => {
+                    let valley = (last.1..i)
+                        .min_by(|&a, &b| {
+                            smoothed[a]
+                                .partial_cmp(&smoothed[b])
+                                .unwrap_or(Ordering::Equal)
+                        })
+                        .unwrap();
+                    let lower_peak = smoothed[last.1].min(smoothed[i]);
+                    if smoothed[valley] > MODE_VALLEY * lower_peak {
+                        if smoothed[i] > smoothed[last.1] {
+                            last.1 = i;
+                        }
+                    } else {
+                        last.2 = valley;
+                        segments.push((valley, i, smoothed.len()));
+                    }
+                }
+                None => segments.push((0, i, smoothed.len())),
+            }
+        }
+        let total = self.count as f64;
+        segments
+            .iter()
+            .map(|&(start, peak, end)| Mode {
+                value: self.value(first + (peak as i32) * MODE_BIN_BUCKETS + MODE_BIN_BUCKETS / 2),
+                share: counts[start..end].iter().sum::<f64>() / total,
+            })
+            .filter(|m| m.share >= min_share)
+            .collect()
+    }
+
+    /// Median of the distances of the values from the median
+    pub fn median_absolute_deviation(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let median = self.quantile(0.5);
+        let mut deviations: Vec<(f64, usize)> = Some((median, self.zeros))
+            .filter(|d| d.1 != 0)
+            .into_iter()
+            .chain(
+                self.buckets
+                    .iter()
+                    .map(|(&i, &c)| ((self.value(i) - median).abs(), c)),
+            )
+            .collect();
+        deviations.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+        let rank = (self.count - 1) / 2;
+        let mut seen = 0;
+        for (deviation, count) in deviations {
+            seen += count;
+            if rank < seen {
+                return deviation;
+            }
+        }
+        0.0
+    }
+
+    /// How far `x` is above the median in median absolute deviations, scaled like a z-score.
+    /// `None` if the values don't vary.
+    pub fn robust_z_score(&self, x: f64) -> Option<f64> {
+        let median = self.quantile(0.5);
+        let mad = self
+            .median_absolute_deviation()
+            .max(median * MIN_RELATIVE_MAD);
+        if mad == 0.0 {
+            None
+        } else {
+            Some(MAD_SCALE * (x - median) / mad)
+        }
+    }
+
+    fn index(&self, x: f64) -> i32 {
+        (x.ln() / self.ln_gamma).ceil() as i32
+    }
+
+    /// The value in the bucket with the lowest relative error to all others in it
+    fn value(&self, index: i32) -> f64 {
+        let gamma = self.ln_gamma.exp();
+        2.0 * (self.ln_gamma * index as f64).exp() / (gamma + 1.0)
+    }
+}
+
+impl Default for QuantileSketch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Running statistics and quantiles of durations, kept in nanoseconds
+#[derive(Debug, Clone)]
+pub struct DurationStats {
+    running: RunningStats,
+    quantiles: QuantileSketch,
+}
+
+impl DurationStats {
+    pub fn new() -> Self {
+        DurationStats {
+            running: RunningStats::new(),
+            quantiles: QuantileSketch::new(),
+        }
+    }
+
+    pub fn add(&mut self, duration: Duration) {
+        let nanos = duration.as_nanos() as f64;
+        self.running.add(nanos);
+        self.quantiles.add(nanos);
+    }
+
+    pub fn count(&self) -> usize {
+        self.running.count()
+    }
+
+    /// Mean in nanoseconds
+    pub fn mean(&self) -> f64 {
+        self.running.mean()
+    }
+
+    /// Variance in nanoseconds squared
+    pub fn variance(&self) -> f64 {
+        self.running.variance()
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.running.min().map(|x| Duration::from_nanos(x as u64))
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.running.max().map(|x| Duration::from_nanos(x as u64))
+    }
+
+    /// The duration at quantile `q` (between 0 and 1), within 1% of the real value
+    pub fn quantile(&self, q: f64) -> Duration {
+        Duration::from_nanos(self.quantiles.quantile(q) as u64)
+    }
+
+    pub fn sketch(&self) -> &QuantileSketch {
+        &self.quantiles
+    }
+
+    /// Robust z-score of the duration, see `QuantileSketch::robust_z_score`
+    pub fn robust_z_score(&self, duration: Duration) -> Option<f64> {
+        self.quantiles.robust_z_score(duration.as_nanos() as f64)
+    }
+
+    /// Durations at the peaks of the distribution and their shares, see `QuantileSketch::modes`
+    pub fn modes(&self, min_share: f64) -> Vec<(Duration, f64)> {
+        self.quantiles
+            .modes(min_share)
+            .iter()
+            .map(|m| (Duration::from_nanos(m.value as u64), m.share))
+            .collect()
+    }
+
+    pub fn merge(&mut self, other: &DurationStats) {
+        self.running.merge(&other.running);
+        self.quantiles.merge(&other.quantiles);
+    }
+}
+
+impl Merge for DurationStats {
+    fn merge(&mut self, other: &DurationStats) {
+        DurationStats::merge(self, other)
+    }
+}
+
+impl Default for DurationStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A uniform sample of at most `capacity` items of a stream (reservoir sampling)
+#[derive(Debug, Clone)]
+pub struct Reservoir<T> {
+    capacity: usize,
+    seen: usize,
+    items: Vec<T>,
+}
+
+impl<T> Reservoir<T> {
+    pub fn new(capacity: usize) -> Self {
+        Reservoir {
+            capacity,
+            seen: 0,
+            items: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, item: T) {
+        self.seen += 1;
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+        } else {
+            let i = rand::thread_rng().gen_range(0, self.seen);
+            if i < self.capacity {
+                self.items[i] = item;
+            }
+        }
+    }
+
+    pub fn items(&self) -> &[T] {
+        &self.items
+    }
+
+    pub fn clear(&mut self) {
+        self.seen = 0;
+        self.items.clear();
+    }
+}
+
+/// Number of slices a window is split into. Durations leave the window a slice at a time.
+const WINDOW_SLICES: i64 = 10;
+
+/// Statistics that can be combined, so windows can keep them by slice
+pub trait Merge {
+    fn merge(&mut self, other: &Self);
+}
+
+/// Statistics of each slice of a sliding window of trace time, and of all of them merged
+#[derive(Debug, Clone)]
+pub struct Slices<T> {
+    /// Length of a slice in milliseconds
+    slice: i64,
+    /// Statistics of each slice by its index, oldest first
+    slices: VecDeque<(i64, T)>,
+    /// Index of the newest slice the window ended at, even if it has no values
+    newest: Option<i64>,
+    /// All slices merged
+    total: T,
+    /// Statistics of no values, which new slices start from
+    empty: T,
+}
+
+impl<T: Merge + Clone> Slices<T> {
+    pub fn new(window: Duration, empty: T) -> Self {
+        Slices {
+            slice: (window.as_millis() as i64 / WINDOW_SLICES).max(1),
+            slices: VecDeque::new(),
+            newest: None,
+            total: empty.clone(),
+            empty,
+        }
+    }
+
+    /// Adds a value that ended at `time` to the statistics with `add`. Values older than the
+    /// window are ignored.
+    pub fn add<F: Fn(&mut T)>(&mut self, time: NaiveDateTime, add: F) {
+        let index = time.timestamp_millis().div_euclid(self.slice);
+        if let Some(newest) = self.newest {
+            if index <= newest - WINDOW_SLICES {
+                return;
+            }
+        }
+        let position = self.slices.iter().position(|(i, _)| *i >= index);
+        match position {
+            Some(p) if self.slices[p].0 == index => add(&mut self.slices[p].1),
+            _ => {
+                let mut stats = self.empty.clone();
+                add(&mut stats);
+                self.slices
+                    .insert(position.unwrap_or(self.slices.len()), (index, stats));
+            }
+        }
+        add(&mut self.total);
+        self.expire(time);
+    }
+
+    /// Drops the slices that are entirely before the window ending at `now`. Returns whether
+    /// anything was dropped.
+    pub fn expire(&mut self, now: NaiveDateTime) -> bool {
+        let index = now.timestamp_millis().div_euclid(self.slice);
+        let newest = self.newest.map_or(index, |n| n.max(index));
+        self.newest = Some(newest);
+        let mut expired = false;
+        while let Some(&(index, _)) = self.slices.front() {
+            if index > newest - WINDOW_SLICES {
+                break;
+            }
+            self.slices.pop_front();
+            expired = true;
+        }
+        if expired {
+            self.total = self.empty.clone();
+            for (_, stats) in self.slices.iter() {
+                self.total.merge(stats);
+            }
+        }
+        expired
+    }
+
+    /// The statistics of all slices merged
+    pub fn total(&self) -> &T {
+        &self.total
+    }
+
+    pub fn clear(&mut self) {
+        self.slices.clear();
+        self.newest = None;
+        self.total = self.empty.clone();
+    }
+}
+
+/// Statistics of the durations that ended in the last `window` of trace time
+#[derive(Debug, Clone)]
+pub struct WindowedStats {
+    slices: Slices<DurationStats>,
+}
+
+impl WindowedStats {
+    pub fn new(window: Duration) -> Self {
+        WindowedStats {
+            slices: Slices::new(window, DurationStats::new()),
+        }
+    }
+
+    /// Adds a duration that ended at `time`. Durations older than the window are ignored.
+    pub fn add(&mut self, time: NaiveDateTime, duration: Duration) {
+        self.slices.add(time, |stats| stats.add(duration));
+    }
+
+    /// Drops the durations that are entirely before the window ending at `now`, a slice at a
+    /// time. Returns whether anything was dropped.
+    pub fn expire(&mut self, now: NaiveDateTime) -> bool {
+        self.slices.expire(now)
+    }
+
+    pub fn stats(&self) -> &DurationStats {
+        &self.slices.total
+    }
+
+    pub fn clear(&mut self) {
+        self.slices.clear();
+    }
+}
+
+/// Weights of values that halve every `half_life` of trace time, with the newest value weighing 1
+#[derive(Debug, Clone, Copy)]
+struct Decay {
+    /// Half-life in milliseconds
+    half_life: f64,
+    /// Time of the newest value
+    newest: Option<NaiveDateTime>,
+}
+
+impl Decay {
+    fn new(half_life: Duration) -> Self {
+        Decay {
+            half_life: (half_life.as_millis() as f64).max(1.0),
+            newest: None,
+        }
+    }
+
+    /// Weight of a value that ended at `time`, and the factor by which the weights of the
+    /// values before it change, which is below 1 if it's the newest
+    fn weigh(&mut self, time: NaiveDateTime) -> (f64, f64) {
+        let newest = *self.newest.get_or_insert(time);
+        let age = (newest - time).num_milliseconds() as f64;
+        if age >= 0.0 {
+            (self.decay(age), 1.0)
+        } else {
+            self.newest = Some(time);
+            (1.0, self.decay(-age))
+        }
+    }
+
+    fn decay(&self, age: f64) -> f64 {
+        0.5f64.powf(age / self.half_life)
+    }
+
+    fn clear(&mut self) {
+        self.newest = None;
+    }
+}
+
+/// Mean and variance of values whose weights halve every `half_life` of trace time
+#[derive(Debug, Clone)]
+pub struct DecayedStats {
+    decay: Decay,
+    weight: f64,
+    mean: f64,
+    m2: f64,
+}
+
+impl DecayedStats {
+    pub fn new(half_life: Duration) -> Self {
+        DecayedStats {
+            decay: Decay::new(half_life),
+            weight: 0.0,
+            mean: 0.0,
+            m2: 0.0,
+        }
+    }
+
+    pub fn add(&mut self, time: NaiveDateTime, x: f64) {
+        // If the new value is the newest, the others get lighter
+        let (w, factor) = self.decay.weigh(time);
+        self.weight *= factor;
+        self.m2 *= factor;
+        self.weight += w;
+        let delta = x - self.mean;
+        self.mean += delta * w / self.weight;
+        self.m2 += w * delta * (x - self.mean);
+    }
+
+    /// Sum of the weights of the values, i.e., how many recent values the statistics reflect
+    pub fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    pub fn variance(&self) -> f64 {
+        if self.weight == 0.0 {
+            0.0
+        } else {
+            self.m2 / self.weight
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.decay.clear();
+        self.weight = 0.0;
+        self.mean = 0.0;
+        self.m2 = 0.0;
+    }
+}
+
+/// Which durations the statistics of groups reflect
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum StatsScope {
+    /// Everything since the group was created or used
+    All,
+    /// Durations in a sliding window of trace time
+    Window,
+    /// All durations, with older ones weighing exponentially less
+    Decayed,
+}
+
+/// How groups keep statistics of durations
+#[derive(Debug, Clone, Copy)]
+pub struct StatsConfig {
+    pub scope: StatsScope,
+    pub window: Duration,
+    pub half_life: Duration,
+    pub change: ChangeConfig,
+}
+
+impl StatsConfig {
+    pub fn from_settings(settings: &Settings) -> Self {
+        StatsConfig {
+            scope: settings.group_stats,
+            window: settings.stats_window,
+            half_life: settings.stats_half_life,
+            change: ChangeConfig::from_settings(settings),
+        }
+    }
+}
+
+/// Durations since the start, in a sliding window and with exponential decay. Queries answer
+/// from the configured scope; quantiles of decayed statistics come from the window.
+#[derive(Debug, Clone)]
+pub struct TimedStats {
+    scope: StatsScope,
+    all: DurationStats,
+    window: WindowedStats,
+    decayed: DecayedStats,
+}
+
+impl TimedStats {
+    pub fn new(config: &StatsConfig) -> Self {
+        TimedStats {
+            scope: config.scope,
+            all: DurationStats::new(),
+            window: WindowedStats::new(config.window),
+            decayed: DecayedStats::new(config.half_life),
+        }
+    }
+
+    /// Adds a duration that ended at `time`
+    pub fn add(&mut self, time: NaiveDateTime, duration: Duration) {
+        self.all.add(duration);
+        self.window.add(time, duration);
+        self.decayed.add(time, duration.as_nanos() as f64);
+    }
+
+    /// Moves the window to end at `now`. Returns whether durations left the window.
+    pub fn expire(&mut self, now: NaiveDateTime) -> bool {
+        self.window.expire(now)
+    }
+
+    pub fn clear(&mut self) {
+        self.all = DurationStats::new();
+        self.window.clear();
+        self.decayed.clear();
+    }
+
+    pub fn scope(&self) -> StatsScope {
+        self.scope
+    }
+
+    pub fn all(&self) -> &DurationStats {
+        &self.all
+    }
+
+    pub fn window(&self) -> &DurationStats {
+        self.window.stats()
+    }
+
+    pub fn decayed(&self) -> &DecayedStats {
+        &self.decayed
+    }
+
+    /// Durations of the scope, or of the window for decayed statistics
+    fn durations(&self) -> &DurationStats {
+        match self.scope {
+            StatsScope::All => &self.all,
+            StatsScope::Window | StatsScope::Decayed => self.window.stats(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.durations().count()
+    }
+
+    /// Mean in nanoseconds
+    pub fn mean(&self) -> f64 {
+        match self.scope {
+            StatsScope::Decayed => self.decayed.mean(),
+            _ => self.durations().mean(),
+        }
+    }
+
+    /// Variance in nanoseconds squared
+    pub fn variance(&self) -> f64 {
+        match self.scope {
+            StatsScope::Decayed => self.decayed.variance(),
+            _ => self.durations().variance(),
+        }
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.durations().min()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.durations().max()
+    }
+
+    pub fn quantile(&self, q: f64) -> Duration {
+        self.durations().quantile(q)
+    }
+
+    pub fn modes(&self, min_share: f64) -> Vec<(Duration, f64)> {
+        self.durations().modes(min_share)
+    }
+
+    pub fn robust_z_score(&self, duration: Duration) -> Option<f64> {
+        self.durations().robust_z_score(duration)
+    }
+}
+
+/// Moments of the durations of the parts of a request, e.g., the edges of a critical path, in
+/// the scope of the statistics like `TimedStats`. Durations are in nanoseconds.
+#[derive(Debug, Clone)]
+pub struct TimedMoments<T> {
+    scope: StatsScope,
+    all: T,
+    window: Slices<T>,
+    decay: Decay,
+    decayed: T,
+}
+
+/// Covariances of the duration of each part of a request with the end-to-end latency
+pub type TimedSumCovariance = TimedMoments<SumCovariance>;
+
+/// Covariances of all pairs of the durations of the parts of a request
+pub type TimedCoMoments = TimedMoments<CoMoments>;
+
+impl<T: Moments> TimedMoments<T> {
+    /// Moments of `len` parts
+    pub fn new(len: usize, config: &StatsConfig) -> Self {
+        TimedMoments {
+            scope: config.scope,
+            all: T::new(len),
+            window: Slices::new(config.window, T::new(len)),
+            decay: Decay::new(config.half_life),
+            decayed: T::new(len),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.all.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds the durations of the parts of a request that ended at `time`
+    pub fn add(&mut self, time: NaiveDateTime, durations: &[f64]) {
+        self.all.add(durations, 1.0);
+        self.window.add(time, |c| c.add(durations, 1.0));
+        let (w, factor) = self.decay.weigh(time);
+        self.decayed.scale(factor);
+        self.decayed.add(durations, w);
+    }
+
+    /// Moves the window to end at `now`. Returns whether durations left the window.
+    pub fn expire(&mut self, now: NaiveDateTime) -> bool {
+        self.window.expire(now)
+    }
+
+    pub fn clear(&mut self) {
+        let len = self.len();
+        self.all = T::new(len);
+        self.window.clear();
+        self.decay.clear();
+        self.decayed = T::new(len);
+    }
+
+    /// The moments of the durations in the scope
+    pub fn scoped(&self) -> &T {
+        match self.scope {
+            StatsScope::All => &self.all,
+            StatsScope::Window => &self.window.total,
+            StatsScope::Decayed => &self.decayed,
+        }
+    }
+}
+
+impl TimedSumCovariance {
+    /// Covariance of the duration of each part with the end-to-end latency in the scope, in
+    /// nanoseconds squared. These sum to the variance of the end-to-end latency.
+    pub fn covariances(&self) -> Vec<f64> {
+        self.scoped().covariances()
+    }
+}
+
+impl TimedCoMoments {
+    /// Covariance of the durations of parts `i` and `j` in the scope, in nanoseconds squared
+    pub fn covariance(&self, i: usize, j: usize) -> f64 {
+        self.scoped().covariance(i, j)
+    }
+}
+
+/// Direction of a change of the latency
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Shift {
+    Increase,
+    Decrease,
+}
+
+/// Two-sided CUSUM: sums of the log-likelihood ratios of the values under a shifted
+/// distribution versus the baseline, reset to zero when they turn negative. A sum above the
+/// threshold is a change in that direction.
+#[derive(Debug, Clone)]
+pub struct Cusum {
+    threshold: f64,
+    upper: f64,
+    lower: f64,
+    /// Values since each sum was last zero, i.e., since the change started
+    upper_run: RunningStats,
+    lower_run: RunningStats,
+}
+
+impl Cusum {
+    pub fn new(threshold: f64) -> Self {
+        Cusum {
+            threshold,
+            upper: 0.0,
+            lower: 0.0,
+            upper_run: RunningStats::new(),
+            lower_run: RunningStats::new(),
+        }
+    }
+
+    /// Adds value `x` with its log-likelihood ratios for an increase and for a decrease. Returns
+    /// the direction of a change and the mean of the values since it started, and starts over
+    /// if there is one.
+    pub fn add(&mut self, x: f64, up: f64, down: f64) -> Option<(Shift, f64)> {
+        self.upper = (self.upper + up).max(0.0);
+        self.lower = (self.lower + down).max(0.0);
+        if self.upper == 0.0 {
+            self.upper_run = RunningStats::new();
+        } else {
+            self.upper_run.add(x);
+        }
+        if self.lower == 0.0 {
+            self.lower_run = RunningStats::new();
+        } else {
+            self.lower_run.add(x);
+        }
+        let change = if self.upper > self.threshold {
+            Some((Shift::Increase, self.upper_run.mean()))
+        } else if self.lower > self.threshold {
+            Some((Shift::Decrease, self.lower_run.mean()))
+        } else {
+            None
+        };
+        if change.is_some() {
+            *self = Cusum::new(self.threshold);
+        }
+        change
+    }
+}
+
+/// Which statistic of the latency changed
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ChangeMetric {
+    Mean,
+    /// Share of the durations above the tail latency of the baseline
+    Tail,
+}
+
+/// A change of the latency of a group, found by `ChangeTracker`
+#[derive(Debug, Clone)]
+pub struct ChangePoint {
+    /// End time of the trace that made the change certain
+    pub time: NaiveDateTime,
+    pub metric: ChangeMetric,
+    pub shift: Shift,
+    /// Mean in nanoseconds for changes of the mean, share of the durations above the tail
+    /// latency for changes of the tail: in the baseline, and since the change started
+    pub before: f64,
+    pub after: f64,
+}
+
+impl ChangePoint {
+    /// How many times larger the statistic is after the change
+    pub fn ratio(&self) -> f64 {
+        self.after / self.before
+    }
+}
+
+impl fmt::Display for ChangePoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.metric {
+            ChangeMetric::Mean => write!(
+                f,
+                "mean {:?} at {}: {:?} -> {:?}",
+                self.shift,
+                self.time,
+                Duration::from_nanos(self.before as u64),
+                Duration::from_nanos(self.after as u64)
+            ),
+            ChangeMetric::Tail => write!(
+                f,
+                "tail {:?} at {}: {:.1}% -> {:.1}% of traces above the baseline tail latency",
+                self.shift,
+                self.time,
+                self.before * 100.0,
+                self.after * 100.0
+            ),
+        }
+    }
+}
+
+/// Shift of the mean the CUSUM of the mean looks for, in standard deviations of the baseline
+const MEAN_SHIFT: f64 = 1.0;
+/// Factor by which the share of durations above the tail latency changes in the shifted
+/// distributions of the CUSUM of the tail
+const TAIL_SHIFT: f64 = 2.0;
+/// Smallest standard deviation of the baseline, relative to its mean, so constant baselines
+/// don't make every difference a change
+const MIN_RELATIVE_STD_DEV: f64 = 0.01;
+
+/// How changes of the latency are detected
+#[derive(Debug, Clone, Copy)]
+pub struct ChangeConfig {
+    /// Number of durations in the baseline
+    pub warmup: usize,
+    /// Log-likelihood ratio at which a change is certain
+    pub threshold: f64,
+    /// Quantile of the baseline that separates the tail
+    pub tail_quantile: f64,
+}
+
+impl ChangeConfig {
+    pub fn from_settings(settings: &Settings) -> Self {
+        ChangeConfig {
+            warmup: settings.change_warmup,
+            threshold: settings.change_threshold,
+            tail_quantile: settings.change_tail_quantile,
+        }
+    }
+}
+
+/// Baseline learned from the first durations
+#[derive(Debug, Clone)]
+struct ChangeBaseline {
+    mean: f64,
+    std_dev: f64,
+    /// Tail latency in nanoseconds and the share of the baseline above it
+    tail: f64,
+    tail_share: f64,
+    mean_cusum: Cusum,
+    tail_cusum: Cusum,
+}
+
+/// Online change-point detection on the mean and the tail of the latency. The first durations
+/// are the baseline; later ones go into a CUSUM of the mean, assuming normal durations, and a
+/// CUSUM of how many durations are above the tail latency of the baseline. After a change, the
+/// next durations are the new baseline.
+#[derive(Debug, Clone)]
+pub struct ChangeTracker {
+    config: ChangeConfig,
+    warmup: DurationStats,
+    baseline: Option<ChangeBaseline>,
+}
+
+impl ChangeTracker {
+    pub fn new(config: &ChangeConfig) -> Self {
+        ChangeTracker {
+            config: *config,
+            warmup: DurationStats::new(),
+            baseline: None,
+        }
+    }
+
+    /// Adds a duration that ended at `time`, returning the changes it makes certain
+    pub fn add(&mut self, time: NaiveDateTime, duration: Duration) -> Vec<ChangePoint> {
+        let x = duration.as_nanos() as f64;
+        let baseline = match self.baseline.as_mut() {
+            Some(b) => b,
+            None => {
+                self.warmup.add(duration);
+                if self.warmup.count() >= self.config.warmup {
+                    self.baseline = Some(self.learn_baseline());
+                }
+                return Vec::new();
+            }
+        };
+        let mut result = Vec::new();
+        let z = (x - baseline.mean) / baseline.std_dev;
+        let change = baseline.mean_cusum.add(
+            x,
+            MEAN_SHIFT * (z - MEAN_SHIFT / 2.0),
+            MEAN_SHIFT * (-z - MEAN_SHIFT / 2.0),
+        );
+        if let Some((shift, after)) = change {
+            result.push(ChangePoint {
+                time,
+                metric: ChangeMetric::Mean,
+                shift,
+                before: baseline.mean,
+                after,
+            });
+        }
+        let p = baseline.tail_share;
+        let (up, down) = if x > baseline.tail {
+            (TAIL_SHIFT.ln(), -TAIL_SHIFT.ln())
+        } else {
+            (
+                ((1.0 - p * TAIL_SHIFT) / (1.0 - p)).ln(),
+                ((1.0 - p / TAIL_SHIFT) / (1.0 - p)).ln(),
+            )
+        };
+        let exceeds = if x > baseline.tail { 1.0 } else { 0.0 };
+        if let Some((shift, after)) = baseline.tail_cusum.add(exceeds, up, down) {
+            result.push(ChangePoint {
+                time,
+                metric: ChangeMetric::Tail,
+                shift,
+                before: p,
+                after,
+            });
+        }
+        if !result.is_empty() {
+            self.clear();
+        }
+        result
+    }
+
+    fn learn_baseline(&self) -> ChangeBaseline {
+        let mean = self.warmup.mean();
+        // Tail shares of 1/TAIL_SHIFT and above couldn't increase by TAIL_SHIFT
+        let tail_share = (1.0 - self.config.tail_quantile)
+            .max(1.0 / self.warmup.count() as f64)
+            .min(0.5 / TAIL_SHIFT);
+        ChangeBaseline {
+            mean,
+            std_dev: self
+                .warmup
+                .variance()
+                .sqrt()
+                .max(mean * MIN_RELATIVE_STD_DEV)
+                .max(1.0),
+            tail: self.warmup.quantile(1.0 - tail_share).as_nanos() as f64,
+            tail_share,
+            mean_cusum: Cusum::new(self.config.threshold),
+            tail_cusum: Cusum::new(self.config.threshold),
+        }
+    }
+
+    /// Starts over with a new baseline
+    pub fn clear(&mut self) {
+        self.warmup = DurationStats::new();
+        self.baseline = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() <= tolerance * b.abs().max(1.0)
+    }
+
+    #[test]
+    fn running_stats() {
+        let values = [4.0, 7.0, 13.0, 16.0, 1.0, 9.0];
+        let mut stats = RunningStats::new();
+        for &x in values.iter() {
+            stats.add(x);
+        }
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        let variance = values.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / values.len() as f64;
+        assert_eq!(stats.count(), 6);
+        assert!(close(stats.mean(), mean, 1e-12));
+        assert!(close(stats.variance(), variance, 1e-12));
+        assert_eq!(stats.min(), Some(1.0));
+        assert_eq!(stats.max(), Some(16.0));
+        assert_eq!(RunningStats::new().min(), None);
+    }
+
+    #[test]
+    fn merges_running_stats() {
+        let values: Vec<f64> = (0..100).map(|i| ((i * 37) % 101) as f64).collect();
+        let mut all = RunningStats::new();
+        let mut first = RunningStats::new();
+        let mut second = RunningStats::new();
+        for (i, &x) in values.iter().enumerate() {
+            all.add(x);
+            if i < 30 {
+                first.add(x);
+            } else {
+                second.add(x);
+            }
+        }
+        let mut merged = RunningStats::new();
+        merged.merge(&first);
+        merged.merge(&RunningStats::new());
+        merged.merge(&second);
+        assert_eq!(merged.count(), all.count());
+        assert!(close(merged.mean(), all.mean(), 1e-12));
+        assert!(close(merged.variance(), all.variance(), 1e-12));
+        assert!(close(merged.sum_of_squares(), all.sum_of_squares(), 1e-12));
+        assert_eq!(merged.min(), all.min());
+        assert_eq!(merged.max(), all.max());
+    }
+
+    #[test]
+    fn sketch_quantiles() {
+        let mut sketch = QuantileSketch::new();
+        assert_eq!(sketch.quantile(0.5), 0.0);
+        for x in 1..=10_000 {
+            sketch.add(x as f64);
+        }
+        assert_eq!(sketch.count(), 10_000);
+        for &q in [0.0, 0.1, 0.5, 0.9, 0.95, 0.99, 1.0].iter() {
+            let exact = 1.0 + (q * 9_999.0_f64).floor();
+            assert!(
+                close(sketch.quantile(q), exact, RELATIVE_ACCURACY),
+                "quantile {} is {}, not {}",
+                q,
+                sketch.quantile(q),
+                exact
+            );
+        }
+
+        // Values below 1, e.g., zero durations, are counted as zeros
+        let mut zeros = QuantileSketch::new();
+        for &x in [0.0, 0.0, 0.5, 100.0].iter() {
+            zeros.add(x);
+        }
+        assert_eq!(zeros.quantile(0.5), 0.0);
+        assert!(close(zeros.quantile(1.0), 100.0, RELATIVE_ACCURACY));
+    }
+
+    #[test]
+    fn merges_sketches() {
+        let mut all = QuantileSketch::new();
+        let mut low = QuantileSketch::new();
+        let mut high = QuantileSketch::new();
+        for x in 1..=1000 {
+            all.add(x as f64);
+            if x <= 500 {
+                low.add(x as f64);
+            } else {
+                high.add(x as f64);
+            }
+        }
+        low.merge(&high);
+        assert_eq!(low.count(), all.count());
+        for &q in [0.0, 0.25, 0.5, 0.75, 1.0].iter() {
+            assert_eq!(low.quantile(q), all.quantile(q));
+        }
+    }
+
+    #[test]
+    fn duration_stats() {
+        let mut stats = DurationStats::new();
+        for millis in 1..=100 {
+            stats.add(Duration::from_millis(millis));
+        }
+        assert_eq!(stats.count(), 100);
+        assert_eq!(stats.min(), Some(Duration::from_millis(1)));
+        assert_eq!(stats.max(), Some(Duration::from_millis(100)));
+        assert!(close(stats.mean(), 50.5e6, 1e-12));
+        let median = stats.quantile(0.5).as_nanos() as f64;
+        assert!(close(median, 50e6, RELATIVE_ACCURACY));
+    }
+
+    #[test]
+    fn finds_modes() {
+        let mut bimodal = QuantileSketch::new();
+        for i in 0..60 {
+            bimodal.add(10e6 + (i % 5) as f64 * 1e5);
+        }
+        for i in 0..40 {
+            bimodal.add(100e6 + (i % 5) as f64 * 1e6);
+        }
+        let modes = bimodal.modes(0.1);
+        assert_eq!(modes.len(), 2);
+        assert!(close(modes[0].value, 10e6, 0.25));
+        assert!(close(modes[0].share, 0.6, 1e-9));
+        assert!(close(modes[1].value, 100e6, 0.25));
+        assert!(close(modes[1].share, 0.4, 1e-9));
+        // Peaks with too few values are left out
+        assert_eq!(bimodal.modes(0.5).len(), 1);
+
+        let mut unimodal = QuantileSketch::new();
+        for i in 0..100 {
+            unimodal.add(50e6 + (i % 10) as f64 * 1e6);
+        }
+        let modes = unimodal.modes(0.1);
+        assert_eq!(modes.len(), 1);
+        assert!(close(modes[0].share, 1.0, 1e-9));
+
+        let mut few = QuantileSketch::new();
+        for _ in 0..MIN_MODE_SAMPLES - 1 {
+            few.add(1e6);
+        }
+        assert!(few.modes(0.0).is_empty());
+    }
+
+    #[test]
+    fn robust_z_scores() {
+        let mut sketch = QuantileSketch::new();
+        for millis in 1..=9 {
+            sketch.add(millis as f64 * 1e6);
+        }
+        // Deviations from the median of 5ms are 0 to 4ms
+        assert!(close(sketch.median_absolute_deviation(), 2e6, 0.1));
+        let z = sketch.robust_z_score(15e6).unwrap();
+        assert!(close(z, MAD_SCALE * 10.0 / 2.0, 0.1));
+        assert!(sketch.robust_z_score(1e6).unwrap() < 0.0);
+
+        // Durations that don't vary get the smallest deviation instead of none
+        let mut constant = QuantileSketch::new();
+        for _ in 0..10 {
+            constant.add(10e6);
+        }
+        assert_eq!(constant.median_absolute_deviation(), 0.0);
+        let median = constant.quantile(0.5);
+        let z = constant.robust_z_score(median * 1.1).unwrap();
+        assert!(close(z, MAD_SCALE * 0.1 / MIN_RELATIVE_MAD, 1e-9));
+
+        let mut zeros = QuantileSketch::new();
+        for _ in 0..10 {
+            zeros.add(0.0);
+        }
+        assert_eq!(zeros.median_absolute_deviation(), 0.0);
+        assert!(zeros.robust_z_score(1e6).is_none());
+        assert_eq!(QuantileSketch::new().median_absolute_deviation(), 0.0);
+    }
+
+    /// Population covariance of each value with the sum, the long way
+    fn sum_covariances(vectors: &[[f64; 3]]) -> Vec<f64> {
+        let n = vectors.len() as f64;
+        let sums: Vec<f64> = vectors.iter().map(|x| x.iter().sum()).collect();
+        let sum_mean = sums.iter().sum::<f64>() / n;
+        (0..3)
+            .map(|i| {
+                let mean = vectors.iter().map(|x| x[i]).sum::<f64>() / n;
+                vectors
+                    .iter()
+                    .zip(sums.iter())
+                    .map(|(x, sum)| (x[i] - mean) * (sum - sum_mean))
+                    .sum::<f64>()
+                    / n
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sum_covariances_add_up_to_variance() {
+        // The second value moves with the first and the third against it
+        let vectors: Vec<[f64; 3]> = (0..60)
+            .map(|i| {
+                let x = ((i * 37) % 23) as f64;
+                [x, 2.0 * x + (i % 3) as f64, 30.0 - x + (i % 5) as f64]
+            })
+            .collect();
+        let mut config = StatsConfig {
+            scope: StatsScope::All,
+            window: Duration::from_secs(10),
+            half_life: Duration::from_secs(5),
+            change: ChangeConfig {
+                warmup: 10,
+                threshold: 5.0,
+                tail_quantile: 0.95,
+            },
+        };
+        let mut sums = RunningStats::new();
+        let mut decayed_sums = DecayedStats::new(config.half_life);
+        let mut first = SumCovariance::new(3);
+        let mut second = SumCovariance::new(3);
+        let mut covariances = Vec::new();
+        for &scope in [StatsScope::All, StatsScope::Window, StatsScope::Decayed].iter() {
+            config.scope = scope;
+            covariances.push(TimedSumCovariance::new(3, &config));
+        }
+        for (i, x) in vectors.iter().enumerate() {
+            let sum = x.iter().sum();
+            sums.add(sum);
+            decayed_sums.add(at(i as i64), sum);
+            if i % 2 == 0 {
+                first.add(x, 1.0);
+            } else {
+                second.add(x, 1.0);
+            }
+            for c in covariances.iter_mut() {
+                c.add(at(i as i64), x);
+            }
+        }
+
+        let all = covariances[0].covariances();
+        for (c, expected) in all.iter().zip(sum_covariances(&vectors)) {
+            assert!(close(*c, expected, 1e-9));
+        }
+        assert!(close(all.iter().sum(), sums.variance(), 1e-9));
+        assert!(all[1] > all[0] && all[2] < 0.0);
+
+        first.merge(&second);
+        assert_eq!(first.weight(), 60.0);
+        for (c, expected) in first.covariances().iter().zip(all.iter()) {
+            assert!(close(*c, *expected, 1e-9));
+        }
+
+        // The window keeps the last 10 seconds
+        let window = covariances[1].covariances();
+        for (c, expected) in window.iter().zip(sum_covariances(&vectors[50..])) {
+            assert!(close(*c, expected, 1e-9));
+        }
+        assert!(!covariances[1].expire(at(59)));
+        assert!(covariances[1].expire(at(100)));
+        assert_eq!(covariances[1].covariances(), vec![0.0; 3]);
+
+        let decayed: f64 = covariances[2].covariances().iter().sum();
+        assert!(close(decayed, decayed_sums.variance(), 1e-9));
+
+        covariances[0].clear();
+        assert_eq!(covariances[0].covariances(), vec![0.0; 3]);
+    }
+
+    /// Population covariance of values `i` and `j` of the vectors
+    fn pair_covariance(vectors: &[[f64; 3]], i: usize, j: usize) -> f64 {
+        let n = vectors.len() as f64;
+        let mean = |k: usize| vectors.iter().map(|x| x[k]).sum::<f64>() / n;
+        let (mean_i, mean_j) = (mean(i), mean(j));
+        vectors
+            .iter()
+            .map(|x| (x[i] - mean_i) * (x[j] - mean_j))
+            .sum::<f64>()
+            / n
+    }
+
+    #[test]
+    fn co_moments_of_pairs() {
+        let vectors: Vec<[f64; 3]> = (0..60)
+            .map(|i| {
+                let x = ((i * 37) % 23) as f64;
+                [x, 2.0 * x + (i % 3) as f64, 30.0 - x + (i % 5) as f64]
+            })
+            .collect();
+        let mut config = stats_config(StatsScope::All);
+        config.window = Duration::from_secs(10);
+        let mut all = TimedCoMoments::new(3, &config);
+        config.scope = StatsScope::Window;
+        let mut window = TimedCoMoments::new(3, &config);
+        let mut first = CoMoments::new(3);
+        let mut second = CoMoments::new(3);
+        for (i, x) in vectors.iter().enumerate() {
+            all.add(at(i as i64), x);
+            window.add(at(i as i64), x);
+            if i < 25 {
+                first.add(x, 1.0);
+            } else {
+                second.add(x, 1.0);
+            }
+        }
+
+        for i in 0..3 {
+            for j in 0..3 {
+                let expected = pair_covariance(&vectors, i, j);
+                assert!(close(all.covariance(i, j), expected, 1e-9));
+                let expected = pair_covariance(&vectors[50..], i, j);
+                assert!(close(window.covariance(i, j), expected, 1e-9));
+            }
+        }
+        assert!(all.covariance(0, 1) > 0.0 && all.covariance(0, 2) < 0.0);
+
+        // The covariances of a value with all values add up to its covariance with the sum
+        for (i, expected) in sum_covariances(&vectors).into_iter().enumerate() {
+            let covariance: f64 = (0..3).map(|j| all.covariance(i, j)).sum();
+            assert!(close(covariance, expected, 1e-9));
+        }
+
+        first.merge(&second);
+        assert_eq!(first.weight(), 60.0);
+        for i in 0..3 {
+            for j in i..3 {
+                assert!(close(first.covariance(i, j), all.covariance(i, j), 1e-9));
+            }
+        }
+
+        assert!(window.expire(at(100)));
+        assert_eq!(window.covariance(0, 1), 0.0);
+        all.clear();
+        assert_eq!(all.covariance(0, 0), 0.0);
+        assert_eq!(all.len(), 3);
+    }
+
+    fn stats_config(scope: StatsScope) -> StatsConfig {
+        StatsConfig {
+            scope,
+            window: Duration::from_secs(60),
+            half_life: Duration::from_secs(10),
+            change: ChangeConfig {
+                warmup: 20,
+                threshold: 10.0,
+                tail_quantile: 0.9,
+            },
+        }
+    }
+
+    #[test]
+    fn old_durations_leave_the_window() {
+        let mut stats = TimedStats::new(&stats_config(StatsScope::Window));
+        stats.add(at(0), Duration::from_millis(100));
+        stats.add(at(30), Duration::from_millis(10));
+        assert_eq!(stats.count(), 2);
+        assert!(!stats.expire(at(59)));
+        assert_eq!(stats.count(), 2);
+
+        // The window is 60s in slices of 6s, so the slice of the first duration ends at 6s
+        assert!(stats.expire(at(65)));
+        assert_eq!(stats.count(), 1);
+        assert!(close(stats.mean(), 10e6, 1e-9));
+        assert_eq!(stats.max(), Some(Duration::from_millis(10)));
+        assert!(!stats.expire(at(66)));
+        assert_eq!(stats.all().count(), 2);
+
+        // Durations older than the window are ignored, and newer ones move it
+        stats.add(at(1), Duration::from_millis(100));
+        assert_eq!(stats.count(), 1);
+        stats.add(at(95), Duration::from_millis(20));
+        assert_eq!(stats.count(), 1);
+        assert_eq!(stats.max(), Some(Duration::from_millis(20)));
+
+        stats.clear();
+        assert_eq!(stats.count(), 0);
+        assert_eq!(stats.all().count(), 0);
+    }
+
+    #[test]
+    fn decayed_durations_fade() {
+        let mut stats = TimedStats::new(&stats_config(StatsScope::Decayed));
+        for i in 0..10 {
+            stats.add(at(i), Duration::from_millis(100));
+        }
+        assert!(close(stats.mean(), 100e6, 1e-9));
+        let weight: f64 = (0..10).map(|i| 0.5f64.powf(i as f64 / 10.0)).sum();
+        assert!(close(stats.decayed().weight(), weight, 1e-9));
+
+        // Ten half-lives later, the old durations weigh a thousandth of what they did
+        stats.add(at(109), Duration::from_millis(10));
+        let old = weight / 1024.0;
+        assert!(close(stats.decayed().weight(), old + 1.0, 1e-9));
+        assert!(close(
+            stats.mean(),
+            (old * 100e6 + 10e6) / (old + 1.0),
+            1e-9
+        ));
+        // Counts and quantiles come from the window, which only has the new duration
+        assert_eq!(stats.count(), 1);
+        assert!(close(stats.quantile(0.5).as_secs_f64(), 0.01, 0.02));
+
+        // Durations older than the newest one are added with their decayed weight
+        let mut late = DecayedStats::new(Duration::from_secs(10));
+        late.add(at(10), 10.0);
+        late.add(at(0), 20.0);
+        assert!(close(late.weight(), 1.5, 1e-9));
+        assert!(close(late.mean(), 40.0 / 3.0, 1e-9));
+        late.clear();
+        assert_eq!(late.weight(), 0.0);
+    }
+
+    #[test]
+    fn cusum() {
+        let mut cusum = Cusum::new(3.0);
+        for _ in 0..3 {
+            assert!(cusum.add(2.0, 1.0, -1.0).is_none());
+        }
+        assert_eq!(cusum.add(4.0, 1.0, -1.0), Some((Shift::Increase, 2.5)));
+        // It starts over after a change, and sums don't go below zero
+        assert!(cusum.add(1.0, -5.0, 2.0).is_none());
+        assert!(cusum.add(1.0, 1.0, 0.5).is_none());
+        match cusum.add(3.0, 1.0, 1.0) {
+            Some((Shift::Decrease, mean)) => assert!(close(mean, 5.0 / 3.0, 1e-12)),
+            other => panic!("Expected a decrease but got {:?}", other),
+        }
+    }
+
+    fn at(secs: i64) -> NaiveDateTime {
+        NaiveDateTime::from_timestamp_opt(secs, 0).unwrap()
+    }
+
+    /// Durations spread evenly between 9ms and 11ms
+    fn baseline(i: usize) -> Duration {
+        Duration::from_micros(9_000 + (i % 21) as u64 * 100)
+    }
+
+    #[test]
+    fn tracks_mean_changes() {
+        let config = ChangeConfig {
+            warmup: 42,
+            threshold: 5.0,
+            tail_quantile: 0.95,
+        };
+        let mut tracker = ChangeTracker::new(&config);
+        let mut time = 0;
+        for i in 0..63 {
+            time += 1;
+            let changes = tracker.add(at(time), baseline(i));
+            assert!(changes.iter().all(|c| c.metric != ChangeMetric::Mean));
+        }
+        let mut found = None;
+        for _ in 0..10 {
+            time += 1;
+            let changes = tracker.add(at(time), Duration::from_millis(15));
+            if let Some(c) = changes.into_iter().find(|c| c.metric == ChangeMetric::Mean) {
+                found = Some(c);
+                break;
+            }
+        }
+        let change = found.expect("No change of the mean");
+        assert_eq!(change.shift, Shift::Increase);
+        assert!(close(change.before, 10e6, 1e-9));
+        assert_eq!(change.time, at(64));
+        // The mean since the change started includes durations from before it that were above
+        // the baseline mean
+        assert!(change.after > 10e6 && change.after <= 15e6);
+        assert!(change.ratio() > 1.0);
+
+        // The durations after a change are the new baseline
+        for _ in 0..config.warmup {
+            time += 1;
+            assert!(tracker.add(at(time), Duration::from_millis(15)).is_empty());
+        }
+        let mut decreased = false;
+        for _ in 0..10 {
+            time += 1;
+            decreased |= tracker
+                .add(at(time), Duration::from_millis(5))
+                .iter()
+                .any(|c| c.metric == ChangeMetric::Mean && c.shift == Shift::Decrease);
+        }
+        assert!(decreased);
+    }
+
+    #[test]
+    fn tracks_tail_changes() {
+        let config = ChangeConfig {
+            warmup: 42,
+            threshold: 5.0,
+            tail_quantile: 0.95,
+        };
+        let mut tracker = ChangeTracker::new(&config);
+        for i in 0..42 {
+            tracker.add(at(i as i64), baseline(i));
+        }
+        // More durations are slower than the baseline ever was, but as many are faster, so the
+        // mean stays the same
+        let mut changes = Vec::new();
+        for i in 0..100 {
+            let duration = match i % 5 {
+                0 | 2 => Duration::from_micros(11_600),
+                1 | 3 => Duration::from_micros(8_400),
+                _ => baseline(i),
+            };
+            changes.extend(tracker.add(at(100 + i as i64), duration));
+            if !changes.is_empty() {
+                break;
+            }
+        }
+        let change = changes
+            .iter()
+            .find(|c| c.metric == ChangeMetric::Tail)
+            .expect("No change of the tail");
+        assert_eq!(change.shift, Shift::Increase);
+        assert!(close(change.before, 0.05, 1e-9));
+        assert!(change.after > 0.1);
+    }
+}
@@ -0,0 +1,735 @@
+/*
+This source code is licensed under the BSD-style license found in the
+LICENSE file in the root directory of this source tree.
+
+Copyright (c) 2022, Diagnosis and Control of Clouds Laboratory
+All rights reserved.
+*/
+
+//! Append-only on-disk store of traces.
+//!
+//! An archive is a directory with two files. `traces.bin` has the traces one after the other in a
+//! compact binary encoding, and `index.bin` has one entry per trace with its base id, request
+//! type, start time, duration and place in `traces.bin`. The index is small enough to keep in
+//! memory, so queries only read the traces they return.
+//!
+//! Each encoded trace starts with a table of the strings it uses (tracepoint ids, keys and string
+//! values) and refers to them by position, so a tracepoint id is stored once per trace.
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use chrono::NaiveDateTime;
+use petgraph::graph::NodeIndex;
+use petgraph::stable_graph::StableGraph;
+use uuid::Uuid;
+
+use pythia_common::RequestType;
+
+use crate::trace::{DAGEdge, EdgeType, Event, EventType, Trace, TracepointID, Value};
+
+const DATA_FILE: &str = "traces.bin";
+const INDEX_FILE: &str = "index.bin";
+const MAGIC: &[u8; 8] = b"PYTRACE1";
+/// Stands for a missing start or end node
+const NO_NODE: u32 = u32::MAX;
+/// Flags of events. Archives from before the error flag only have the synthetic flag.
+const SYNTHETIC: u8 = 1;
+const ERROR: u8 = 2;
+
+/// What the index knows about an archived trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveEntry {
+    pub base_id: Uuid,
+    pub request_type: RequestType,
+    /// Timestamp of the start node
+    pub start_time: NaiveDateTime,
+    pub duration: Duration,
+    offset: u64,
+    length: u32,
+}
+
+/// Which traces to get from an archive. Fields that are `None` match every trace.
+#[derive(Debug, Clone, Default)]
+pub struct ArchiveQuery {
+    pub request_type: Option<RequestType>,
+    pub start_after: Option<NaiveDateTime>,
+    pub start_before: Option<NaiveDateTime>,
+    pub min_duration: Option<Duration>,
+    pub max_duration: Option<Duration>,
+}
+
+impl ArchiveQuery {
+    pub fn matches(&self, entry: &ArchiveEntry) -> bool {
+        self.request_type.iter().all(|&r| r == entry.request_type)
+            && self.start_after.iter().all(|&t| entry.start_time >= t)
+            && self.start_before.iter().all(|&t| entry.start_time < t)
+            && self.min_duration.iter().all(|&d| entry.duration >= d)
+            && self.max_duration.iter().all(|&d| entry.duration <= d)
+    }
+}
+
+pub struct ArchiveWriter {
+    data: BufWriter<File>,
+    index: BufWriter<File>,
+    /// Where the next trace goes in the data file
+    offset: u64,
+    base_ids: HashSet<Uuid>,
+}
+
+impl ArchiveWriter {
+    /// Opens the archive in `dir` for appending, creating it if needed. Anything after the last
+    /// complete trace, e.g., from a crash in the middle of an append, is dropped.
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut data = open_file(&dir.join(DATA_FILE), true)?;
+        let mut index = open_file(&dir.join(INDEX_FILE), true)?;
+        let data_len = data.metadata()?.len();
+        let mut index_len = MAGIC.len() as u64;
+        let mut offset = MAGIC.len() as u64;
+        let mut base_ids = HashSet::new();
+        for (entry, entry_end) in read_index(&mut index)? {
+            let end = entry.offset + entry.length as u64;
+            if end > data_len {
+                break;
+            }
+            base_ids.insert(entry.base_id);
+            index_len = entry_end;
+            offset = end;
+        }
+        data.set_len(offset)?;
+        index.set_len(index_len)?;
+        data.seek(SeekFrom::End(0))?;
+        index.seek(SeekFrom::End(0))?;
+        Ok(ArchiveWriter {
+            data: BufWriter::new(data),
+            index: BufWriter::new(index),
+            offset,
+            base_ids,
+        })
+    }
+
+    /// Appends a trace, unless a trace with the same base id is already in the archive. Returns
+    /// whether the trace was appended.
+    pub fn append(&mut self, trace: &Trace) -> io::Result<bool> {
+        if self.base_ids.contains(&trace.base_id) {
+            return Ok(false);
+        }
+        let record = encode_trace(trace)?;
+        let entry = ArchiveEntry {
+            base_id: trace.base_id,
+            request_type: trace.request_type,
+            start_time: start_time(trace),
+            duration: trace.duration,
+            offset: self.offset,
+            length: record.len() as u32,
+        };
+        self.data.write_all(&record)?;
+        write_entry(&mut self.index, &entry)?;
+        self.offset += record.len() as u64;
+        self.base_ids.insert(trace.base_id);
+        Ok(true)
+    }
+
+    /// Writes out buffered traces. Traces are written before their index entries, so a crash
+    /// never leaves an index entry without its trace.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.data.flush()?;
+        self.index.flush()
+    }
+}
+
+pub struct ArchiveReader {
+    data: BufReader<File>,
+    entries: Vec<ArchiveEntry>,
+    by_base_id: HashMap<Uuid, usize>,
+    /// Positions in `entries` for each request type, sorted by start time
+    by_request_type: HashMap<RequestType, Vec<usize>>,
+    /// Positions in `entries`, sorted by start time
+    by_start_time: Vec<usize>,
+}
+
+impl ArchiveReader {
+    pub fn open(dir: &Path) -> io::Result<Self> {
+        let data = open_file(&dir.join(DATA_FILE), false)?;
+        let mut index = open_file(&dir.join(INDEX_FILE), false)?;
+        let data_len = data.metadata()?.len();
+        let entries: Vec<ArchiveEntry> = read_index(&mut index)?
+            .into_iter()
+            .map(|(entry, _)| entry)
+            .take_while(|e| e.offset + e.length as u64 <= data_len)
+            .collect();
+
+        let mut by_start_time: Vec<usize> = (0..entries.len()).collect();
+        by_start_time.sort_by_key(|&i| entries[i].start_time);
+        let mut by_base_id = HashMap::new();
+        let mut by_request_type: HashMap<RequestType, Vec<usize>> = HashMap::new();
+        for &i in by_start_time.iter() {
+            by_base_id.insert(entries[i].base_id, i);
+            by_request_type
+                .entry(entries[i].request_type)
+                .or_default()
+                .push(i);
+        }
+        Ok(ArchiveReader {
+            data: BufReader::new(data),
+            entries,
+            by_base_id,
+            by_request_type,
+            by_start_time,
+        })
+    }
+
+    /// Index entries in the order the traces were appended
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    pub fn entry(&self, base_id: &Uuid) -> Option<&ArchiveEntry> {
+        self.by_base_id.get(base_id).map(|&i| &self.entries[i])
+    }
+
+    /// Index entries that match the query, sorted by start time
+    pub fn query(&self, query: &ArchiveQuery) -> Vec<&ArchiveEntry> {
+        let candidates = match &query.request_type {
+            Some(r) => match self.by_request_type.get(r) {
+                Some(positions) => positions,
+                None => return Vec::new(),
+            },
+            None => &self.by_start_time,
+        };
+        candidates
+            .iter()
+            .map(|&i| &self.entries[i])
+            .filter(|e| query.matches(e))
+            .collect()
+    }
+
+    pub fn read(&mut self, entry: &ArchiveEntry) -> io::Result<Trace> {
+        let mut record = vec![0; entry.length as usize];
+        self.data.seek(SeekFrom::Start(entry.offset))?;
+        self.data.read_exact(&mut record)?;
+        decode_trace(&record)
+    }
+
+    pub fn get(&mut self, base_id: &Uuid) -> io::Result<Option<Trace>> {
+        match self.entry(base_id).cloned() {
+            Some(entry) => self.read(&entry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the traces that match the query, sorted by start time
+    pub fn read_query(&mut self, query: &ArchiveQuery) -> io::Result<Vec<Trace>> {
+        let entries: Vec<ArchiveEntry> = self.query(query).into_iter().cloned().collect();
+        entries.iter().map(|e| self.read(e)).collect()
+    }
+}
+
+/// Opens a file of the archive and checks its header. Files opened for writing are created with
+/// a header if they don't exist.
+fn open_file(path: &Path, write: bool) -> io::Result<File> {
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(write)
+        .create(write)
+        .open(path)?;
+    if write && file.metadata()?.len() == 0 {
+        file.write_all(MAGIC)?;
+        return Ok(file);
+    }
+    let mut magic = [0; 8];
+    file.read_exact(&mut magic)
+        .map_err(|_| invalid_data(format!("{} is not a trace archive", path.display())))?;
+    if &magic != MAGIC {
+        return Err(invalid_data(format!(
+            "{} is not a trace archive",
+            path.display()
+        )));
+    }
+    Ok(file)
+}
+
+/// Reads all complete entries of the index, along with where each entry ends in the file. A
+/// partially written last entry is ignored.
+fn read_index(index: &mut File) -> io::Result<Vec<(ArchiveEntry, u64)>> {
+    let mut buf = Vec::new();
+    index.seek(SeekFrom::Start(MAGIC.len() as u64))?;
+    index.read_to_end(&mut buf)?;
+    let mut result = Vec::new();
+    let mut remaining = &buf[..];
+    while !remaining.is_empty() {
+        match read_entry(&mut remaining) {
+            Ok(entry) => {
+                let end = MAGIC.len() + buf.len() - remaining.len();
+                result.push((entry, end as u64));
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(result)
+}
+
+fn write_entry<W: Write>(w: &mut W, entry: &ArchiveEntry) -> io::Result<()> {
+    w.write_u64::<LittleEndian>(entry.offset)?;
+    w.write_u32::<LittleEndian>(entry.length)?;
+    w.write_all(entry.base_id.as_bytes())?;
+    write_str(w, &entry.request_type.to_string())?;
+    write_timestamp(w, &entry.start_time)?;
+    write_duration(w, entry.duration)
+}
+
+fn read_entry<R: Read>(r: &mut R) -> io::Result<ArchiveEntry> {
+    let offset = r.read_u64::<LittleEndian>()?;
+    let length = r.read_u32::<LittleEndian>()?;
+    let base_id = read_uuid(r)?;
+    let request_type = RequestType::new(&read_string(r)?);
+    Ok(ArchiveEntry {
+        base_id,
+        request_type,
+        start_time: read_timestamp(r)?,
+        duration: read_duration(r)?,
+        offset,
+        length,
+    })
+}
+
+/// Timestamp of the start node, or of the earliest event if the start node is not set
+fn start_time(trace: &Trace) -> NaiveDateTime {
+    match trace.g.node_weight(trace.start_node) {
+        Some(event) => event.timestamp,
+        None => trace
+            .g
+            .node_indices()
+            .map(|n| trace.g[n].timestamp)
+            .min()
+            .unwrap_or_else(|| NaiveDateTime::from_timestamp_opt(0, 0).unwrap()),
+    }
+}
+
+/// Strings of a trace, in the order they are first used
+#[derive(Default)]
+struct StringTable {
+    positions: HashMap<String, u32>,
+    strings: Vec<String>,
+}
+
+impl StringTable {
+    fn position(&mut self, s: &str) -> u32 {
+        if let Some(&p) = self.positions.get(s) {
+            return p;
+        }
+        let p = self.strings.len() as u32;
+        self.positions.insert(s.to_string(), p);
+        self.strings.push(s.to_string());
+        p
+    }
+}
+
+fn encode_trace(trace: &Trace) -> io::Result<Vec<u8>> {
+    let mut strings = StringTable::default();
+    let mut body = Vec::new();
+    body.write_all(trace.base_id.as_bytes())?;
+    body.write_u32::<LittleEndian>(strings.position(&trace.request_type.to_string()))?;
+    write_duration(&mut body, trace.duration)?;
+    body.write_u32::<LittleEndian>(trace.keys.len() as u32)?;
+    for key in trace.keys.iter() {
+        body.write_u32::<LittleEndian>(strings.position(key))?;
+    }
+
+    // Nodes are numbered in order, so the gaps left by removed nodes are not stored
+    let mut positions = HashMap::new();
+    body.write_u32::<LittleEndian>(trace.g.node_count() as u32)?;
+    for (position, nidx) in trace.g.node_indices().enumerate() {
+        positions.insert(nidx, position as u32);
+        write_event(&mut body, &trace.g[nidx], &mut strings)?;
+    }
+    for nidx in [trace.start_node, trace.end_node].iter() {
+        body.write_u32::<LittleEndian>(*positions.get(nidx).unwrap_or(&NO_NODE))?;
+    }
+    body.write_u32::<LittleEndian>(trace.g.edge_count() as u32)?;
+    for eidx in trace.g.edge_indices() {
+        let (source, target) = trace.g.edge_endpoints(eidx).unwrap();
+        body.write_u32::<LittleEndian>(positions[&source])?;
+        body.write_u32::<LittleEndian>(positions[&target])?;
+        let edge = &trace.g[eidx];
+        write_duration(&mut body, edge.duration)?;
+        body.write_u8(match edge.variant {
+            EdgeType::ChildOf => 0,
+            EdgeType::FollowsFrom => 1,
+        })?;
+    }
+
+    let mut record = Vec::with_capacity(body.len());
+    record.write_u32::<LittleEndian>(strings.strings.len() as u32)?;
+    for s in strings.strings.iter() {
+        write_str(&mut record, s)?;
+    }
+    record.extend(body);
+    Ok(record)
+}
+
+fn decode_trace(record: &[u8]) -> io::Result<Trace> {
+    let mut r = record;
+    let string_count = r.read_u32::<LittleEndian>()?;
+    let mut strings = Vec::new();
+    for _ in 0..string_count {
+        strings.push(read_string(&mut r)?);
+    }
+    let string = |r: &mut &[u8]| -> io::Result<&String> {
+        let position = r.read_u32::<LittleEndian>()?;
+        strings
+            .get(position as usize)
+            .ok_or_else(|| invalid_data(format!("No string at position {}", position)))
+    };
+
+    let mut trace = Trace::new(&read_uuid(&mut r)?);
+    trace.request_type = RequestType::new(string(&mut r)?);
+    trace.duration = read_duration(&mut r)?;
+    let key_count = r.read_u32::<LittleEndian>()?;
+    for _ in 0..key_count {
+        trace.keys.push(string(&mut r)?.clone());
+    }
+
+    let node_count = r.read_u32::<LittleEndian>()?;
+    let mut g = StableGraph::new();
+    let mut nodes = Vec::new();
+    for _ in 0..node_count {
+        let trace_id = read_uuid(&mut r)?;
+        let tracepoint_id = TracepointID::from_str(string(&mut r)?);
+        let timestamp = read_timestamp(&mut r)?;
+        let flags = r.read_u8()?;
+        let variant = match r.read_u8()? {
+            0 => EventType::Entry,
+            1 => EventType::Exit,
+            2 => EventType::Annotation,
+            v => return Err(invalid_data(format!("Unknown event type {}", v))),
+        };
+        let mut key_value_pair = HashMap::new();
+        let pair_count = r.read_u32::<LittleEndian>()?;
+        for _ in 0..pair_count {
+            let key = string(&mut r)?.clone();
+            key_value_pair.insert(key, read_value(&mut r, &strings)?);
+        }
+        nodes.push(g.add_node(Event {
+            trace_id,
+            tracepoint_id,
+            timestamp,
+            is_synthetic: flags & SYNTHETIC != 0,
+            variant,
+            key_value_pair,
+            is_error: flags & ERROR != 0,
+        }));
+    }
+    let node = |r: &mut &[u8]| -> io::Result<NodeIndex> {
+        match r.read_u32::<LittleEndian>()? {
+            NO_NODE => Ok(NodeIndex::end()),
+            p => nodes
+                .get(p as usize)
+                .cloned()
+                .ok_or_else(|| invalid_data(format!("No node at position {}", p))),
+        }
+    };
+    trace.start_node = node(&mut r)?;
+    trace.end_node = node(&mut r)?;
+    let edge_count = r.read_u32::<LittleEndian>()?;
+    for _ in 0..edge_count {
+        let source = node(&mut r)?;
+        let target = node(&mut r)?;
+        let duration = read_duration(&mut r)?;
+        let variant = match r.read_u8()? {
+            0 => EdgeType::ChildOf,
+            1 => EdgeType::FollowsFrom,
+            v => return Err(invalid_data(format!("Unknown edge type {}", v))),
+        };
+        g.add_edge(source, target, DAGEdge { duration, variant });
+    }
+    trace.g = g;
+    trace.mark_errors();
+    Ok(trace)
+}
+
+fn write_event<W: Write>(w: &mut W, event: &Event, strings: &mut StringTable) -> io::Result<()> {
+    w.write_all(event.trace_id.as_bytes())?;
+    w.write_u32::<LittleEndian>(strings.position(&event.tracepoint_id.to_string()))?;
+    write_timestamp(w, &event.timestamp)?;
+    let mut flags = 0;
+    if event.is_synthetic {
+        flags |= SYNTHETIC;
+    }
+    if event.is_error {
+        flags |= ERROR;
+    }
+    w.write_u8(flags)?;
+    w.write_u8(match event.variant {
+        EventType::Entry => 0,
+        EventType::Exit => 1,
+        EventType::Annotation => 2,
+    })?;
+    w.write_u32::<LittleEndian>(event.key_value_pair.len() as u32)?;
+    for (key, value) in event.key_value_pair.iter() {
+        w.write_u32::<LittleEndian>(strings.position(key))?;
+        write_value(w, value, strings)?;
+    }
+    Ok(())
+}
+
+fn write_value<W: Write>(w: &mut W, value: &Value, strings: &mut StringTable) -> io::Result<()> {
+    match value {
+        Value::UnsignedInt(v) => {
+            w.write_u8(0)?;
+            w.write_u64::<LittleEndian>(*v)?;
+        }
+        Value::Str(s) => {
+            w.write_u8(1)?;
+            w.write_u32::<LittleEndian>(strings.position(s))?;
+        }
+        Value::SignedInt(v) => {
+            w.write_u8(2)?;
+            w.write_i64::<LittleEndian>(*v)?;
+        }
+        Value::Float(v) => {
+            w.write_u8(3)?;
+            w.write_f64::<LittleEndian>(*v)?;
+        }
+        Value::Bool(v) => {
+            w.write_u8(4)?;
+            w.write_u8(*v as u8)?;
+        }
+        Value::List(list) => {
+            w.write_u8(5)?;
+            w.write_u32::<LittleEndian>(list.len() as u32)?;
+            for v in list.iter() {
+                write_value(w, v, strings)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_value(r: &mut &[u8], strings: &[String]) -> io::Result<Value> {
+    Ok(match r.read_u8()? {
+        0 => Value::UnsignedInt(r.read_u64::<LittleEndian>()?),
+        1 => {
+            let position = r.read_u32::<LittleEndian>()?;
+            Value::Str(
+                strings
+                    .get(position as usize)
+                    .ok_or_else(|| invalid_data(format!("No string at position {}", position)))?
+                    .clone(),
+            )
+        }
+        2 => Value::SignedInt(r.read_i64::<LittleEndian>()?),
+        3 => Value::Float(r.read_f64::<LittleEndian>()?),
+        4 => Value::Bool(r.read_u8()? != 0),
+        5 => {
+            let len = r.read_u32::<LittleEndian>()?;
+            let mut list = Vec::new();
+            for _ in 0..len {
+                list.push(read_value(r, strings)?);
+            }
+            Value::List(list)
+        }
+        v => return Err(invalid_data(format!("Unknown value type {}", v))),
+    })
+}
+
+fn write_str<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    w.write_u32::<LittleEndian>(s.len() as u32)?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_string<R: Read>(r: &mut R) -> io::Result<String> {
+    let len = r.read_u32::<LittleEndian>()?;
+    let mut buf = vec![0; len as usize];
+    r.read_exact(&mut buf)?;
+    String::from_utf8(buf).map_err(|e| invalid_data(e.to_string()))
+}
+
+fn read_uuid<R: Read>(r: &mut R) -> io::Result<Uuid> {
+    let mut buf = [0; 16];
+    r.read_exact(&mut buf)?;
+    Ok(Uuid::from_bytes(buf))
+}
+
+fn write_timestamp<W: Write>(w: &mut W, t: &NaiveDateTime) -> io::Result<()> {
+    w.write_i64::<LittleEndian>(t.timestamp())?;
+    w.write_u32::<LittleEndian>(t.timestamp_subsec_nanos())
+}
+
+fn read_timestamp<R: Read>(r: &mut R) -> io::Result<NaiveDateTime> {
+    let secs = r.read_i64::<LittleEndian>()?;
+    let nanos = r.read_u32::<LittleEndian>()?;
+    NaiveDateTime::from_timestamp_opt(secs, nanos)
+        .ok_or_else(|| invalid_data(format!("Invalid timestamp {}.{:09}", secs, nanos)))
+}
+
+fn write_duration<W: Write>(w: &mut W, d: Duration) -> io::Result<()> {
+    w.write_u64::<LittleEndian>(d.as_secs())?;
+    w.write_u32::<LittleEndian>(d.subsec_nanos())
+}
+
+fn read_duration<R: Read>(r: &mut R) -> io::Result<Duration> {
+    let secs = r.read_u64::<LittleEndian>()?;
+    let nanos = r.read_u32::<LittleEndian>()?;
+    Ok(Duration::new(secs, nanos))
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for an archive
+    fn archive_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("pythia-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn event(span: Uuid, tracepoint: &str, secs: i64, variant: EventType) -> Event {
+        Event {
+            trace_id: span,
+            tracepoint_id: TracepointID::from_str(tracepoint),
+            timestamp: NaiveDateTime::from_timestamp_opt(secs, 0).unwrap(),
+            is_synthetic: false,
+            variant,
+            key_value_pair: HashMap::new(),
+            is_error: false,
+        }
+    }
+
+    /// A trace with one span that starts at `start` and takes `millis`
+    fn trace(request_type: &str, start: i64, millis: u64) -> Trace {
+        let span = Uuid::new_v4();
+        let mut trace = Trace::new(&Uuid::new_v4());
+        let mut entry = event(span, "api:handle", start, EventType::Entry);
+        entry
+            .key_value_pair
+            .insert("host".to_string(), Value::Str("web-1".to_string()));
+        let mut exit = event(span, "api:handle", start, EventType::Exit);
+        exit.timestamp += chrono::Duration::milliseconds(millis as i64);
+        exit.is_error = true;
+        exit.key_value_pair
+            .insert("status".to_string(), Value::UnsignedInt(500));
+        trace.start_node = trace.g.add_node(entry);
+        trace.end_node = trace.g.add_node(exit);
+        trace.duration = Duration::from_millis(millis);
+        trace.g.add_edge(
+            trace.start_node,
+            trace.end_node,
+            DAGEdge {
+                duration: trace.duration,
+                variant: EdgeType::ChildOf,
+            },
+        );
+        trace.request_type = RequestType::new(request_type);
+        trace.mark_errors();
+        trace
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = archive_dir("round-trip");
+        let traces = vec![
+            trace("ArchiveRead", 20, 100),
+            trace("ArchiveWrite", 10, 300),
+            trace("ArchiveRead", 30, 200),
+        ];
+        let mut writer = ArchiveWriter::open(&dir).unwrap();
+        for t in traces.iter() {
+            assert!(writer.append(t).unwrap());
+        }
+        assert!(!writer.append(&traces[0]).unwrap());
+        writer.flush().unwrap();
+
+        let mut reader = ArchiveReader::open(&dir).unwrap();
+        assert_eq!(reader.entries().len(), 3);
+        let read = reader.get(&traces[1].base_id).unwrap().unwrap();
+        assert_eq!(read.request_type, traces[1].request_type);
+        assert_eq!(read.duration, Duration::from_millis(300));
+        assert!(read.is_error);
+        assert_eq!(read.g.node_count(), 2);
+        assert_eq!(read.g.edge_count(), 1);
+        let start = &read.g[read.start_node];
+        assert_eq!(start.tracepoint_id, TracepointID::from_str("api:handle"));
+        assert_eq!(start.variant, EventType::Entry);
+        assert_eq!(
+            start.key_value_pair.get("host"),
+            Some(&Value::Str("web-1".to_string()))
+        );
+        let end = &read.g[read.end_node];
+        assert!(end.is_error);
+        assert_eq!(
+            end.timestamp - start.timestamp,
+            chrono::Duration::milliseconds(300)
+        );
+
+        let reads = reader
+            .read_query(&ArchiveQuery {
+                request_type: Some(RequestType::new("ArchiveRead")),
+                ..Default::default()
+            })
+            .unwrap();
+        let ids: Vec<Uuid> = reads.iter().map(|t| t.base_id).collect();
+        assert_eq!(ids, vec![traces[0].base_id, traces[2].base_id]);
+        let slow = reader.query(&ArchiveQuery {
+            min_duration: Some(Duration::from_millis(200)),
+            start_before: Some(NaiveDateTime::from_timestamp_opt(30, 0).unwrap()),
+            ..Default::default()
+        });
+        assert_eq!(slow.len(), 1);
+        assert_eq!(slow[0].base_id, traces[1].base_id);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn drops_truncated_tail() {
+        let dir = archive_dir("truncated-tail");
+        let first = trace("ArchiveRead", 10, 100);
+        let second = trace("ArchiveRead", 20, 100);
+        let mut writer = ArchiveWriter::open(&dir).unwrap();
+        writer.append(&first).unwrap();
+        writer.append(&second).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        // A crash in the middle of writing the second trace
+        let data = OpenOptions::new()
+            .write(true)
+            .open(dir.join(DATA_FILE))
+            .unwrap();
+        let len = data.metadata().unwrap().len();
+        data.set_len(len - 10).unwrap();
+        drop(data);
+
+        let mut reader = ArchiveReader::open(&dir).unwrap();
+        assert_eq!(reader.entries().len(), 1);
+        assert!(reader.get(&second.base_id).unwrap().is_none());
+        assert!(reader.get(&first.base_id).unwrap().is_some());
+
+        // Reopening for writing drops the partial trace and its index entry, so the trace can be
+        // appended again
+        let mut writer = ArchiveWriter::open(&dir).unwrap();
+        assert!(writer.append(&second).unwrap());
+        writer.flush().unwrap();
+        let mut reader = ArchiveReader::open(&dir).unwrap();
+        assert_eq!(reader.entries().len(), 2);
+        let read = reader.get(&second.base_id).unwrap().unwrap();
+        assert_eq!(read.g.node_count(), 2);
+        fs::remove_dir_all(&dir).ok();
+    }
+}
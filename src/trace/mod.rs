@@ -9,6 +9,8 @@ All rights reserved.
 //! General trace implementation
 //!
 
+mod archive;
+
 use std::fmt;
 use std::fmt::Debug;
 use std::fmt::Display;
@@ -31,13 +33,73 @@ use pythia_common::RequestType;
 
 use std::collections::HashMap;
 
+pub use crate::trace::archive::{ArchiveEntry, ArchiveQuery, ArchiveReader, ArchiveWriter};
+
 //The enum Value contains variants which are added depending on the type of key-value pairs needed
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub enum Value {
     UnsignedInt(u64),
     Str(String),
     SignedInt(i64),
-    //float(f64),
+    Float(f64),
+    Bool(bool),
+    List(Vec<Value>),
+}
+
+impl Value {
+    /// Keeps the type of a JSON value. Objects and nulls are kept as their JSON text.
+    pub fn from_json(value: &serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::String(s) => Value::Str(s.clone()),
+            serde_json::Value::Bool(b) => Value::Bool(*b),
+            serde_json::Value::Number(n) => {
+                if let Some(i) = n.as_u64() {
+                    Value::UnsignedInt(i)
+                } else if let Some(i) = n.as_i64() {
+                    Value::SignedInt(i)
+                } else {
+                    Value::Float(n.as_f64().unwrap())
+                }
+            }
+            serde_json::Value::Array(list) => {
+                Value::List(list.iter().map(Value::from_json).collect())
+            }
+            other => Value::Str(other.to_string()),
+        }
+    }
+
+    /// Numeric values as `f64`, e.g., to correlate them with latency
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::UnsignedInt(v) => Some(*v as f64),
+            Value::SignedInt(v) => Some(*v as f64),
+            Value::Float(v) => Some(*v),
+            Value::Bool(v) => Some(if *v { 1.0 } else { 0.0 }),
+            Value::Str(_) | Value::List(_) => None,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::UnsignedInt(v) => write!(f, "{}", v),
+            Value::Str(v) => write!(f, "{}", v),
+            Value::SignedInt(v) => write!(f, "{}", v),
+            Value::Float(v) => write!(f, "{}", v),
+            Value::Bool(v) => write!(f, "{}", v),
+            Value::List(list) => {
+                write!(f, "[")?;
+                for (i, v) in list.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
 }
 
 /// A general-purpose trace which does not contain application-specific things
@@ -51,6 +113,9 @@ pub struct Trace {
     pub duration: Duration,
     /// used by osprofiler to find keys to delete from redis
     pub keys: Vec<String>,
+    /// The request failed, i.e., some event reports an error, see `mark_errors`
+    #[serde(default)]
+    pub is_error: bool,
 }
 
 impl Trace {
@@ -60,12 +125,19 @@ impl Trace {
             base_id: base_id.clone(),
             start_node: NodeIndex::end(),
             end_node: NodeIndex::end(),
-            request_type: RequestType::Unknown,
+            request_type: RequestType::UNKNOWN,
             duration: Duration::new(0, 0),
             keys: Vec::new(),
+            is_error: false,
         }
     }
 
+    /// Sets `is_error` if some event reports an error. Readers call this once the trace is
+    /// complete.
+    pub fn mark_errors(&mut self) {
+        self.is_error = self.g.node_indices().any(|n| self.g[n].is_error);
+    }
+
     pub fn to_file(&self, file: &Path) {
         let writer = std::fs::File::create(file).unwrap();
         serde_json::to_writer(writer, self).ok();
@@ -205,6 +277,9 @@ pub struct Event {
     pub is_synthetic: bool,
     pub variant: EventType,
     pub key_value_pair: HashMap<String, Value>,
+    /// The event reports an error, e.g., it's the exit of a span that failed
+    #[serde(default)]
+    pub is_error: bool,
    // pub variance: f64,
 }
 
@@ -254,13 +329,66 @@ impl Display for DAGEdge {
     }
 }
 
+/// Distinct values kept per key of a `TraceNode`, further values are only counted
+const MAX_NODE_VALUES: usize = 16;
+
+/// How many events of a node had each value of a key. At most `MAX_NODE_VALUES` distinct values
+/// are kept, so nodes of groups don't grow with the number of traces, e.g., for request ids.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ValueCounts {
+    /// Values in the order they were first seen, with their counts
+    pub values: Vec<(Value, usize)>,
+    /// Count of the values that were not kept
+    pub others: usize,
+}
+
+impl ValueCounts {
+    pub fn add(&mut self, value: &Value) {
+        if let Some((_, count)) = self.values.iter_mut().find(|(v, _)| v == value) {
+            *count += 1;
+        } else if self.values.len() < MAX_NODE_VALUES {
+            self.values.push((value.clone(), 1));
+        } else {
+            self.others += 1;
+        }
+    }
+
+    /// Number of events with a value
+    pub fn count(&self) -> usize {
+        self.values.iter().map(|(_, c)| c).sum::<usize>() + self.others
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+        self.others = 0;
+    }
+}
+
+impl Display for ValueCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (value, count)) in self.values.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{} x{}", value, count)?;
+        }
+        if self.others != 0 {
+            write!(f, ", {} others", self.others)?;
+        }
+        Ok(())
+    }
+}
+
 /// A trace node is an abstract node, so it doesn't have a timestamp or trace id, it just has a
 /// tracepoint id and variant.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct TraceNode {
     pub tracepoint_id: TracepointID,
     pub variant: EventType,
-    pub key_value_pair: HashMap<String, Vec<Value>>,
+    pub key_value_pair: HashMap<String, ValueCounts>,
+    /// An event of this node reported an error
+    #[serde(default)]
+    pub is_error: bool,
    // pub variance: f64,
 }
 
@@ -273,62 +401,38 @@ impl PartialEq for TraceNode {
 impl Eq for TraceNode {}
 
 impl TraceNode {
-    //building hashmap that contains key value pairs where key is a string, and value is a vector
-    //of Value
-    pub fn from_event(event: &Event) -> Self {
-        let mut map = HashMap::new();
-        let mut vec_value: Vec<Value> = Vec::new();
-        let mut vec_host: Vec<Value> = Vec::new();
-        let mut vec_agent: Vec<Value> = Vec::new();
-        let mut vec_hrt: Vec<Value> = Vec::new();
-        let mut vec_proc_id: Vec<Value> = Vec::new();
-        let mut vec_proc_name: Vec<Value> = Vec::new();
-        let mut vec_thread_id: Vec<Value> = Vec::new();
-        let mut vec_thread_name: Vec<Value> = Vec::new();
-        for (key, value) in event.key_value_pair.clone() {
-            if key == "lock_queue".to_string() {
-                vec_value.push(value);
-            } else if key == "host".to_string() {
-                vec_host.push(value);
-            } else if key == "agent".to_string() {
-                vec_agent.push(value);
-            } else if key == "hrt".to_string() {
-                vec_hrt.push(value);
-            } else if key == "process ID".to_string() {
-                vec_proc_id.push(value);
-            } else if key == "process name" {
-                vec_proc_name.push(value);
-            } else if key == "thread ID" {
-                vec_thread_id.push(value);
-            } else if key == "thread name" {
-                vec_thread_name.push(value);
-            }
-        }
-
-        map.insert("lock_queue".to_string(), vec_value);
-        map.insert("host".to_string(), vec_host);
-
-       // let mut var = variance()
-        TraceNode {
+    /// Keeps the values of the keys in `keys`. Other key-values of the event are dropped, and a
+    /// key the event doesn't have gets no values.
+    pub fn from_event(event: &Event, keys: &[String]) -> Self {
+        let mut result = TraceNode {
             tracepoint_id: event.tracepoint_id,
             variant: event.variant,
-            key_value_pair: map,
-           // variance: event.pairs_variance(),
+            key_value_pair: keys
+                .iter()
+                .map(|k| (k.clone(), ValueCounts::default()))
+                .collect(),
+            is_error: false,
+        };
+        result.add_event(event);
+        result
+    }
+
+    /// Adds the values of another event of this node, e.g., from another trace of a group
+    pub fn add_event(&mut self, event: &Event) {
+        self.is_error |= event.is_error;
+        for (key, values) in self.key_value_pair.iter_mut() {
+            if let Some(value) = event.key_value_pair.get(key) {
+                values.add(value);
+            }
         }
     }
-/*
-    pub fn pairs_variance(event: &Event) -> f64 {
-        let mut varian;
-        for (key, value) in event.key_value_pair.clone() {
-            varian = variance(event.value.iter().map(|x| x.duration.as_nanos()));
+
+    /// Forgets the values of the events so far, but keeps the keys
+    pub fn clear_values(&mut self) {
+        for values in self.key_value_pair.values_mut() {
+            values.clear();
         }
-          varian = variance(event.key_value_pair.iter().map(|x| x.duration.as_nanos()));
-        return varian;
-    }*/
-/*
-    pub fn get_key_values() -> HashMap<String, Vec<Value>> {
-        return Self{key_value_pair};
-    } */
+    }
 }
 
 impl Display for TraceNode {
@@ -433,3 +537,146 @@ impl<'de> Deserialize<'de> for TracepointID {
         d.deserialize_str(TracepointIDVisitor)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::critical::CriticalPath;
+
+    /// Drops the `is_error` fields everywhere, as in data saved before they existed
+    fn strip_errors(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                map.remove("is_error");
+                map.values_mut().for_each(strip_errors);
+            }
+            serde_json::Value::Array(list) => list.iter_mut().for_each(strip_errors),
+            _ => {}
+        }
+    }
+
+    fn event(span: Uuid, secs: i64, variant: EventType) -> Event {
+        Event {
+            trace_id: span,
+            tracepoint_id: TracepointID::from_str("api:handle"),
+            timestamp: NaiveDateTime::from_timestamp_opt(secs, 0).unwrap(),
+            is_synthetic: false,
+            variant,
+            key_value_pair: HashMap::new(),
+            is_error: true,
+        }
+    }
+
+    #[test]
+    fn reads_traces_without_errors() {
+        let span = Uuid::new_v4();
+        let mut trace = Trace::new(&Uuid::new_v4());
+        trace.start_node = trace.g.add_node(event(span, 10, EventType::Entry));
+        trace.end_node = trace.g.add_node(event(span, 11, EventType::Exit));
+        trace.duration = Duration::from_secs(1);
+        trace.g.add_edge(
+            trace.start_node,
+            trace.end_node,
+            DAGEdge {
+                duration: trace.duration,
+                variant: EdgeType::ChildOf,
+            },
+        );
+        trace.mark_errors();
+        let path = CriticalPath::from_trace(&trace).unwrap();
+        assert!(path.is_error);
+
+        let mut json = serde_json::to_value(&trace).unwrap();
+        strip_errors(&mut json);
+        let old: Trace = serde_json::from_value(json).unwrap();
+        assert!(!old.is_error);
+        assert!(old.g.node_indices().all(|n| !old.g[n].is_error));
+        assert_eq!(old.g.node_count(), 2);
+
+        let mut json = serde_json::to_value(&path).unwrap();
+        strip_errors(&mut json);
+        let old: CriticalPath = serde_json::from_value(json).unwrap();
+        assert!(!old.is_error && !old.g.is_error);
+        assert_eq!(old.duration, path.duration);
+    }
+
+    #[test]
+    fn values_from_json() {
+        let json: serde_json::Value = serde_json::from_str(
+            r#"{"s": "web-1", "b": true, "u": 3, "i": -3, "f": 0.25, "l": [1, "a", [false]],
+                "n": null, "o": {"k": 1}}"#,
+        )
+        .unwrap();
+        let value = |key: &str| Value::from_json(&json[key]);
+        assert_eq!(value("s"), Value::Str("web-1".to_string()));
+        assert_eq!(value("b"), Value::Bool(true));
+        assert_eq!(value("u"), Value::UnsignedInt(3));
+        assert_eq!(value("i"), Value::SignedInt(-3));
+        assert_eq!(value("f"), Value::Float(0.25));
+        assert_eq!(
+            value("l"),
+            Value::List(vec![
+                Value::UnsignedInt(1),
+                Value::Str("a".to_string()),
+                Value::List(vec![Value::Bool(false)])
+            ])
+        );
+        assert_eq!(value("n"), Value::Str("null".to_string()));
+        assert_eq!(value("o"), Value::Str(r#"{"k":1}"#.to_string()));
+        assert_eq!(value("l").to_string(), "[1, a, [false]]");
+        assert_eq!(value("i").as_f64(), Some(-3.0));
+        assert_eq!(value("s").as_f64(), None);
+    }
+
+    #[test]
+    fn keeps_configured_keys() {
+        let mut first = event(Uuid::new_v4(), 10, EventType::Entry);
+        first.is_error = false;
+        first
+            .key_value_pair
+            .insert("host".to_string(), Value::Str("web-1".to_string()));
+        first
+            .key_value_pair
+            .insert("args".to_string(), Value::Str("--force".to_string()));
+        let keys = vec!["host".to_string(), "lock_queue".to_string()];
+        let mut node = TraceNode::from_event(&first, &keys);
+        assert_eq!(node.key_value_pair.len(), 2);
+        assert!(!node.key_value_pair.contains_key("args"));
+        assert_eq!(node.key_value_pair["lock_queue"].count(), 0);
+        assert!(!node.is_error);
+
+        let mut second = first.clone();
+        second.is_error = true;
+        second
+            .key_value_pair
+            .insert("lock_queue".to_string(), Value::UnsignedInt(2));
+        node.add_event(&second);
+        node.add_event(&first);
+        let hosts = &node.key_value_pair["host"];
+        assert_eq!(hosts.values, vec![(Value::Str("web-1".to_string()), 3)]);
+        assert_eq!(node.key_value_pair["lock_queue"].count(), 1);
+        assert!(node.is_error);
+        assert_eq!(hosts.to_string(), "web-1 x3");
+
+        node.clear_values();
+        assert_eq!(node.key_value_pair.len(), 2);
+        assert_eq!(node.key_value_pair["host"].count(), 0);
+    }
+
+    #[test]
+    fn bounds_node_values() {
+        let keys = vec!["request_id".to_string()];
+        let mut e = event(Uuid::new_v4(), 10, EventType::Entry);
+        let mut node = TraceNode::from_event(&e, &keys);
+        for i in 0..MAX_NODE_VALUES + 10 {
+            e.key_value_pair
+                .insert("request_id".to_string(), Value::UnsignedInt(i as u64));
+            node.add_event(&e);
+        }
+        let ids = &node.key_value_pair["request_id"];
+        assert_eq!(ids.values.len(), MAX_NODE_VALUES);
+        assert_eq!(ids.others, 10);
+        assert_eq!(ids.count(), MAX_NODE_VALUES + 10);
+        assert!(ids.to_string().ends_with(", 10 others"));
+    }
+}